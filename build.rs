@@ -14,6 +14,10 @@ use std::process::Command;
 
 mod error {
     error_chain! {
+        links {
+            TlCodegen(::tl_codegen::Error, ::tl_codegen::ErrorKind);
+        }
+
         foreign_links {
             Io(::std::io::Error);
             SetLogger(::log::SetLoggerError);
@@ -22,11 +26,21 @@ mod error {
 }
 
 
-const TL_SCHEMA_DIR:       &'static str = "./tl";
-const TL_SCHEMA_LIST_FILE: &'static str = "./tl/tl-schema-list.txt";
-const RUST_SCHEMA_FILE:    &'static str = "./src/schema.rs";
-
-fn collect_input() -> error::Result<String> {
+const TL_SCHEMA_DIR:         &'static str = "./tl";
+const TL_SCHEMA_LIST_FILE:   &'static str = "./tl/tl-schema-list.txt";
+const RUST_SCHEMA_FILE:      &'static str = "./src/schema.rs";
+// Framing types which deliberately deviate from their official
+// definitions, so their ids can't be validated.
+const TL_MANUAL_SCHEMA_FILE: &'static str = "001-manual.tl";
+// Optional descriptions of constructors and methods, see
+// `tl_codegen::apply_doc_file`.
+const TL_DOCS_FILE:          &'static str = "./tl/docs.json";
+
+/// Read all schema files as `(file name, contents)` pairs.
+///
+/// Files may be `.tl` text or Telegram's `.json` schemas, see
+/// `tl_codegen::parser::parse_files`.
+fn collect_input() -> error::Result<Vec<(String, String)>> {
     let mut tl_files = BufReader::new(File::open(TL_SCHEMA_LIST_FILE)?).lines().filter_map(|line| {
         match line {
             Ok(ref line) if line.starts_with("//") => None,  // This line is a comment
@@ -39,10 +53,12 @@ fn collect_input() -> error::Result<String> {
     debug!("Files detected: {:?}", &tl_files);
     println!("cargo:rerun-if-changed={}", TL_SCHEMA_LIST_FILE);
 
-    let mut input = String::new();
+    let mut input = vec![];
     for tl_file in tl_files {
-        File::open(&tl_file)?.read_to_string(&mut input)?;
+        let mut contents = String::new();
+        File::open(&tl_file)?.read_to_string(&mut contents)?;
         println!("cargo:rerun-if-changed={}", tl_file.to_string_lossy());
+        input.push((tl_file.to_string_lossy().into_owned(), contents));
     }
 
     Ok(input)
@@ -52,7 +68,23 @@ fn run() -> error::Result<()> {
     env_logger::init()?;
 
     let input = collect_input()?;
-    let code = tl_codegen::generate_code_for(&input);
+    let files = || input.iter().map(|&(ref file, ref contents)| (file.as_str(), contents.as_str()));
+
+    let mut official_items = tl_codegen::parser::parse_files(files()
+        .filter(|&(file, _)| !file.ends_with(TL_MANUAL_SCHEMA_FILE)))?;
+    tl_codegen::validate_items(&mut official_items, tl_codegen::MissingIds::Allow)?;
+    debug!("Validated {} items", official_items.len());
+
+    let mut items = tl_codegen::parser::parse_files(files())?;
+    println!("cargo:rerun-if-changed={}", TL_DOCS_FILE);
+    if Path::new(TL_DOCS_FILE).exists() {
+        let mut docs = String::new();
+        File::open(TL_DOCS_FILE)?.read_to_string(&mut docs)?;
+        tl_codegen::apply_doc_file(&mut items, &docs)?;
+        debug!("Applied docs from {}", TL_DOCS_FILE);
+    }
+
+    let code = tl_codegen::generate_code_for_items(items)?;
     debug!("Code size: {} bytes", code.as_str().len());
 
     File::create(RUST_SCHEMA_FILE)?.write_all(code.as_str().as_bytes())?;
@@ -23,7 +23,7 @@ use byteorder::{ByteOrder, BigEndian};
 use futures::{Future, Stream};
 use mtproto::tl::dynamic::TLObject;
 use mtproto::rpc::{AppInfo, Session};
-use mtproto::rpc::encryption::asymm;
+use mtproto::rpc::encryption::{asymm, RsaPaddingScheme};
 use mtproto::rpc::message::{Message, MessageType};
 use mtproto::schema;
 use rand::Rng;
@@ -35,6 +35,10 @@ use serde_mtproto::MtProtoSized;
 use tokio_core::reactor::{Core, Handle};
 
 
+/// Padding scheme used to encrypt `P_Q_inner_data`.
+const RSA_PADDING_SCHEME: RsaPaddingScheme = RsaPaddingScheme::RsaPad;
+
+
 mod error {
     error_chain! {
         links {
@@ -141,23 +145,21 @@ fn auth(handle: Handle) -> Box<Future<Item = (), Error = error::Error>> {
         info!("Data to send: {:#?}", &p_q_inner_data);
         let p_q_inner_data_serialized = tryf!(serde_mtproto::to_bytes(&p_q_inner_data));
         info!("Data bytes to send: {:?}", &p_q_inner_data_serialized);
-        let known_sha1_fingerprints = tryf!(asymm::KNOWN_RAW_KEYS.iter()
-            .map(|raw_key| {
-                let sha1_fingerprint = raw_key.read()?.sha1_fingerprint()?;
+        let rsa_keys = tryf!(asymm::RsaKeyRegistry::new());
+        let known_sha1_fingerprints = tryf!(rsa_keys.keys().iter()
+            .map(|key| {
+                let sha1_fingerprint = key.sha1_fingerprint()?;
                 Ok(sha1_fingerprint.iter().map(|b| format!("{:02x}", b)).collect::<String>())
             })
             .collect::<error::Result<Vec<_>>>());
         info!("Known public key SHA1 fingerprints: {:?}", known_sha1_fingerprints);
-        let known_fingerprints = tryf!(asymm::KNOWN_RAW_KEYS.iter()
-            .map(|raw_key| Ok(raw_key.read()?.fingerprint()?))
-            .collect::<error::Result<Vec<_>>>());
-        info!("Known public key fingerprints: {:?}", known_fingerprints);
+        info!("Known public key fingerprints: {:?}", rsa_keys.fingerprints());
         let server_pk_fingerprints = res_pq.server_public_key_fingerprints.inner().as_slice();
         info!("Server public key fingerprints: {:?}", &server_pk_fingerprints);
         let (rsa_public_key, fingerprint) =
-            tryf!(asymm::find_first_key_fail_safe(server_pk_fingerprints));
+            tryf!(rsa_keys.find_first_fail_safe(server_pk_fingerprints));
         info!("RSA public key used: {:#?}", &rsa_public_key);
-        let encrypted_data = tryf!(rsa_public_key.encrypt(&p_q_inner_data_serialized));
+        let encrypted_data = tryf!(rsa_public_key.encrypt_with(RSA_PADDING_SCHEME, &p_q_inner_data_serialized));
         info!("Encrypted data: {:?}", encrypted_data.as_ref());
         let encrypted_data2 = tryf!(rsa_public_key.encrypt2(&p_q_inner_data_serialized));
         info!("Encrypted data 2: {:?}", &encrypted_data2);
@@ -0,0 +1,305 @@
+//! User authorization.
+//!
+//! Logging in with a phone number is a sequence of steps:
+//!
+//! 1. `auth.sendCode` delivers a confirmation code to the user.
+//! 2. `auth.signIn` checks the code. A new phone number has to be
+//!    registered with `auth.signUp` instead.
+//! 3. If the account has two-step verification enabled, the server
+//!    replies with `SESSION_PASSWORD_NEEDED` and the password is checked
+//!    with `auth.checkPassword`.
+//!
+//! Each step is represented by its own type, so the flow can be driven
+//! manually. Alternatively, `login` runs the whole flow and asks a
+//! `LoginPrompt` for the data it needs.
+//!
+//! Bots skip all of this and log in with a single call to
+//! `auth.importBotAuthorization`, see `bot_login`.
+//!
+//! More information about authorization:
+//! https://core.telegram.org/api/auth.
+
+use serde_bytes::ByteBuf;
+
+use crypto;
+use error::{self, ErrorKind};
+use rpc::{AppInfo, DcConnector, DcPool, RpcCaller};
+use schema;
+
+
+/// Request a confirmation code for `phone_number`.
+pub fn send_code<C: RpcCaller>(caller: &mut C, app_info: &AppInfo, phone_number: &str)
+    -> error::Result<SentCode>
+{
+    let sent_code = caller.call(schema::rpc::auth::sendCode {
+        allow_flashcall: false,
+        phone_number: phone_number.to_owned(),
+        current_number: None,
+        api_id: app_info.api_id,
+        api_hash: app_info.api_hash.clone(),
+    })?;
+
+    Ok(SentCode::new(phone_number.to_owned(), sent_code))
+}
+
+
+/// A confirmation code which has been sent to the user.
+#[derive(Clone, Debug)]
+pub struct SentCode {
+    phone_number: String,
+    phone_code_hash: String,
+    phone_registered: bool,
+    code_type: schema::auth::SentCodeType,
+    next_type: Option<schema::auth::CodeType>,
+    timeout: Option<i32>,
+}
+
+impl SentCode {
+    fn new(phone_number: String, sent_code: schema::auth::SentCode) -> SentCode {
+        SentCode {
+            phone_number: phone_number,
+            phone_code_hash: sent_code.phone_code_hash,
+            phone_registered: sent_code.phone_registered,
+            code_type: sent_code.type_,
+            next_type: sent_code.next_type,
+            timeout: sent_code.timeout,
+        }
+    }
+
+    /// The phone number the code has been sent to.
+    pub fn phone_number(&self) -> &str {
+        &self.phone_number
+    }
+
+    /// Whether the phone number is already registered.
+    ///
+    /// Unregistered numbers have to be signed up with
+    /// `SignUpRequest::sign_up` after entering the code.
+    pub fn phone_registered(&self) -> bool {
+        self.phone_registered
+    }
+
+    /// How the code has been delivered.
+    pub fn code_type(&self) -> &schema::auth::SentCodeType {
+        &self.code_type
+    }
+
+    /// How the code will be delivered after calling `resend`, if it can
+    /// be resent at all.
+    pub fn next_type(&self) -> Option<&schema::auth::CodeType> {
+        self.next_type.as_ref()
+    }
+
+    /// Number of seconds to wait before calling `resend`.
+    pub fn timeout(&self) -> Option<i32> {
+        self.timeout
+    }
+
+    /// Resend the code using `next_type`.
+    pub fn resend<C: RpcCaller>(&self, caller: &mut C) -> error::Result<SentCode> {
+        let sent_code = caller.call(schema::rpc::auth::resendCode {
+            phone_number: self.phone_number.clone(),
+            phone_code_hash: self.phone_code_hash.clone(),
+        })?;
+
+        Ok(SentCode::new(self.phone_number.clone(), sent_code))
+    }
+
+    /// Invalidate the code.
+    pub fn cancel<C: RpcCaller>(self, caller: &mut C) -> error::Result<()> {
+        caller.call(schema::rpc::auth::cancelCode {
+            phone_number: self.phone_number,
+            phone_code_hash: self.phone_code_hash,
+        })?;
+
+        Ok(())
+    }
+
+    /// Sign in with the code entered by the user.
+    pub fn sign_in<C: RpcCaller>(&self, caller: &mut C, phone_code: &str) -> error::Result<SignIn> {
+        if !self.phone_registered {
+            return Ok(SignIn::SignUpNeeded(self.sign_up_request(phone_code)));
+        }
+
+        let result = caller.call(schema::rpc::auth::signIn {
+            phone_number: self.phone_number.clone(),
+            phone_code_hash: self.phone_code_hash.clone(),
+            phone_code: phone_code.to_owned(),
+        });
+
+        let error = match result {
+            Ok(authorization) => return Ok(SignIn::Authorized(authorization.user)),
+            Err(e) => e,
+        };
+
+        match *error.kind() {
+            ErrorKind::RpcError(401, ref message) if message == "SESSION_PASSWORD_NEEDED" => (),
+            ErrorKind::RpcError(400, ref message) if message == "PHONE_NUMBER_UNOCCUPIED" => {
+                return Ok(SignIn::SignUpNeeded(self.sign_up_request(phone_code)));
+            },
+            _ => return Err(error),
+        }
+
+        debug!("Two-step verification is enabled for {}", self.phone_number);
+
+        match caller.call(schema::rpc::account::getPassword {})? {
+            schema::account::Password::password(password) => {
+                Ok(SignIn::PasswordNeeded(PasswordRequest {
+                    current_salt: password.current_salt.to_vec(),
+                    hint: password.hint,
+                    has_recovery: password.has_recovery,
+                }))
+            },
+            schema::account::Password::noPassword(_) => bail!(ErrorKind::NoPasswordSet),
+        }
+    }
+
+    fn sign_up_request(&self, phone_code: &str) -> SignUpRequest {
+        SignUpRequest {
+            phone_number: self.phone_number.clone(),
+            phone_code_hash: self.phone_code_hash.clone(),
+            phone_code: phone_code.to_owned(),
+        }
+    }
+}
+
+
+/// Outcome of `SentCode::sign_in`.
+#[derive(Clone, Debug)]
+pub enum SignIn {
+    /// The user is logged in.
+    Authorized(schema::User),
+    /// The account is protected with a two-step verification password.
+    PasswordNeeded(PasswordRequest),
+    /// The phone number isn't registered yet.
+    SignUpNeeded(SignUpRequest),
+}
+
+
+/// The second step of logging into an account with two-step
+/// verification enabled.
+#[derive(Clone, Debug)]
+pub struct PasswordRequest {
+    current_salt: Vec<u8>,
+    hint: String,
+    has_recovery: bool,
+}
+
+impl PasswordRequest {
+    /// The hint set by the user along with the password.
+    pub fn hint(&self) -> &str {
+        &self.hint
+    }
+
+    /// Whether a recovery email is set for the account.
+    pub fn has_recovery(&self) -> bool {
+        self.has_recovery
+    }
+
+    /// Compute the password hash expected by `auth.checkPassword`:
+    /// `SHA256(current_salt + password + current_salt)`.
+    pub fn password_hash(&self, password: &str) -> error::Result<Vec<u8>> {
+        crypto::sha256(&[&self.current_salt, password.as_bytes(), &self.current_salt])
+    }
+
+    /// Log in with the password entered by the user.
+    pub fn check<C: RpcCaller>(&self, caller: &mut C, password: &str) -> error::Result<schema::User> {
+        let authorization = caller.call(schema::rpc::auth::checkPassword {
+            password_hash: ByteBuf::from(self.password_hash(password)?),
+        })?;
+
+        Ok(authorization.user)
+    }
+}
+
+
+/// Registration of a new phone number.
+#[derive(Clone, Debug)]
+pub struct SignUpRequest {
+    phone_number: String,
+    phone_code_hash: String,
+    phone_code: String,
+}
+
+impl SignUpRequest {
+    /// Register a new account with the given name.
+    pub fn sign_up<C: RpcCaller>(self, caller: &mut C, first_name: String, last_name: String)
+        -> error::Result<schema::User>
+    {
+        let authorization = caller.call(schema::rpc::auth::signUp {
+            phone_number: self.phone_number,
+            phone_code_hash: self.phone_code_hash,
+            phone_code: self.phone_code,
+            first_name: first_name,
+            last_name: last_name,
+        })?;
+
+        Ok(authorization.user)
+    }
+}
+
+
+/// Data supplied by the application during `login`.
+pub trait LoginPrompt {
+    /// Ask the user for the code described by `sent_code`.
+    ///
+    /// Returning `None` requests the code to be resent.
+    fn code(&mut self, sent_code: &SentCode) -> error::Result<Option<String>>;
+
+    /// Ask the user for the two-step verification password.
+    fn password(&mut self, request: &PasswordRequest) -> error::Result<String>;
+
+    /// Ask the user for the first and last name to register a new
+    /// account with.
+    fn name(&mut self) -> error::Result<(String, String)>;
+}
+
+/// Run the whole login flow for `phone_number`.
+pub fn login<C, P>(caller: &mut C, app_info: &AppInfo, phone_number: &str, prompt: &mut P)
+    -> error::Result<schema::User>
+    where C: RpcCaller,
+          P: LoginPrompt
+{
+    let mut sent_code = send_code(caller, app_info, phone_number)?;
+
+    let phone_code = loop {
+        match prompt.code(&sent_code)? {
+            Some(phone_code) => break phone_code,
+            None => sent_code = sent_code.resend(caller)?,
+        }
+    };
+
+    match sent_code.sign_in(caller, &phone_code)? {
+        SignIn::Authorized(user) => Ok(user),
+        SignIn::PasswordNeeded(request) => {
+            let password = prompt.password(&request)?;
+            request.check(caller, &password)
+        },
+        SignIn::SignUpNeeded(request) => {
+            let (first_name, last_name) = prompt.name()?;
+            request.sign_up(caller, first_name, last_name)
+        },
+    }
+}
+
+
+/// Log in as a bot with a token obtained from @BotFather.
+///
+/// The bot account may reside on another datacenter, in which case the
+/// server replies with `USER_MIGRATE_X` and the home datacenter of the
+/// pool is changed accordingly.
+pub fn bot_login<C: DcConnector>(pool: &mut DcPool<C>, app_info: &AppInfo, bot_token: &str)
+    -> error::Result<schema::User>
+{
+    let authorization = pool.call(schema::rpc::auth::importBotAuthorization {
+        flags: 0,
+        api_id: app_info.api_id,
+        api_hash: app_info.api_hash.clone(),
+        bot_auth_token: bot_token.to_owned(),
+    })?;
+
+    info!("Logged in as a bot on DC {}", pool.home_dc());
+    pool.mark_home_authorized();
+
+    Ok(authorization.user)
+}
@@ -0,0 +1,338 @@
+//! Cryptographic primitives used throughout the crate.
+//!
+//! The primitives are provided by a `CryptoBackend` chosen at compile
+//! time. OpenSSL is used by default (the `openssl` feature), while
+//! building with `--no-default-features --features pure-rust-crypto`
+//! swaps in pure-Rust implementations which don't need any system
+//! libraries. OpenSSL takes precedence if both features are enabled.
+
+use std::cmp::Ordering;
+
+use error::{self, ErrorKind};
+
+#[cfg(feature = "openssl")]
+mod openssl_backend;
+#[cfg(all(feature = "pure-rust-crypto", not(feature = "openssl")))]
+mod rust_backend;
+
+#[cfg(feature = "openssl")]
+pub use self::openssl_backend::OpenSsl;
+#[cfg(all(feature = "pure-rust-crypto", not(feature = "openssl")))]
+pub use self::rust_backend::RustCrypto;
+
+
+/// Backend used by the crate.
+#[cfg(feature = "openssl")]
+pub type DefaultBackend = OpenSsl;
+/// Backend used by the crate.
+#[cfg(not(feature = "openssl"))]
+pub type DefaultBackend = RustCrypto;
+
+/// Incremental MD5 hasher of the default backend.
+pub type Md5 = <DefaultBackend as CryptoBackend>::Md5;
+
+
+/// A hash function computed incrementally.
+pub trait Hasher: Sized {
+    fn new() -> error::Result<Self>;
+
+    fn update(&mut self, data: &[u8]) -> error::Result<()>;
+
+    fn finish(self) -> error::Result<Vec<u8>>;
+
+    /// Hash a concatenation of `parts`.
+    fn digest(parts: &[&[u8]]) -> error::Result<Vec<u8>> {
+        let mut hasher = Self::new()?;
+        for part in parts {
+            hasher.update(part)?;
+        }
+
+        hasher.finish()
+    }
+}
+
+/// Cryptographic primitives required by the crate.
+///
+/// All big numbers are passed as big-endian byte strings.
+pub trait CryptoBackend {
+    type Md5: Hasher;
+    type Sha1: Hasher;
+    type Sha256: Hasher;
+
+    /// Encrypt `input` with AES-256 in IGE mode.
+    ///
+    /// Fails with `ErrorKind::AesInputNotAligned` unless the length of
+    /// `input` is divisible by 16. The first half of `iv` is XORed with
+    /// the input of the block cipher, the second half is XORed with its
+    /// output.
+    fn aes_ige_encrypt(key: &[u8; 32], iv: &[u8; 32], input: &[u8]) -> error::Result<Vec<u8>>;
+
+    /// Decrypt `input` with AES-256 in IGE mode.
+    fn aes_ige_decrypt(key: &[u8; 32], iv: &[u8; 32], input: &[u8]) -> error::Result<Vec<u8>>;
+
+    /// Encrypt or decrypt `input` with AES-256 in CTR mode.
+    ///
+    /// The whole 16-byte `iv` is treated as a big-endian counter.
+    fn aes_ctr(key: &[u8; 32], iv: &[u8; 16], input: &[u8]) -> error::Result<Vec<u8>>;
+
+    /// Compute `base ^ exponent mod modulus`.
+    ///
+    /// The result has no leading zero bytes.
+    fn mod_exp(base: &[u8], exponent: &[u8], modulus: &[u8]) -> error::Result<Vec<u8>>;
+
+    /// Check whether `n` is a prime with the probability of error not
+    /// exceeding `4 ^ -rounds`.
+    fn is_prime(n: &[u8], rounds: u32) -> error::Result<bool>;
+}
+
+
+pub fn sha1(parts: &[&[u8]]) -> error::Result<Vec<u8>> {
+    <DefaultBackend as CryptoBackend>::Sha1::digest(parts)
+}
+
+pub fn sha256(parts: &[&[u8]]) -> error::Result<Vec<u8>> {
+    <DefaultBackend as CryptoBackend>::Sha256::digest(parts)
+}
+
+pub fn aes_ige_encrypt(key: &[u8; 32], iv: &[u8; 32], input: &[u8]) -> error::Result<Vec<u8>> {
+    DefaultBackend::aes_ige_encrypt(key, iv, input)
+}
+
+pub fn aes_ige_decrypt(key: &[u8; 32], iv: &[u8; 32], input: &[u8]) -> error::Result<Vec<u8>> {
+    DefaultBackend::aes_ige_decrypt(key, iv, input)
+}
+
+pub fn aes_ctr(key: &[u8; 32], iv: &[u8; 16], input: &[u8]) -> error::Result<Vec<u8>> {
+    DefaultBackend::aes_ctr(key, iv, input)
+}
+
+pub fn mod_exp(base: &[u8], exponent: &[u8], modulus: &[u8]) -> error::Result<Vec<u8>> {
+    DefaultBackend::mod_exp(base, exponent, modulus)
+}
+
+pub fn is_prime(n: &[u8], rounds: u32) -> error::Result<bool> {
+    DefaultBackend::is_prime(n, rounds)
+}
+
+
+// Helpers for big-endian numbers which don't need a bignum library
+
+/// Strip leading zero bytes of a big-endian number.
+pub fn strip_leading_zeros(n: &[u8]) -> &[u8] {
+    let start = n.iter().position(|&byte| byte != 0).unwrap_or(n.len());
+    &n[start..]
+}
+
+/// Compare two big-endian numbers of possibly different lengths.
+pub fn cmp_be(a: &[u8], b: &[u8]) -> Ordering {
+    let a = strip_leading_zeros(a);
+    let b = strip_leading_zeros(b);
+
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+/// Number of significant bits of a big-endian number.
+pub fn num_bits_be(n: &[u8]) -> usize {
+    let n = strip_leading_zeros(n);
+
+    match n.first() {
+        Some(&first) => n.len() * 8 - first.leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+/// Subtract 1 from a positive big-endian number.
+///
+/// # Panics
+///
+/// Panics if `n` is zero.
+pub fn sub_one_be(n: &[u8]) -> Vec<u8> {
+    let mut result = n.to_vec();
+
+    for byte in result.iter_mut().rev() {
+        if *byte == 0 {
+            *byte = 0xff;
+        } else {
+            *byte -= 1;
+            return result;
+        }
+    }
+
+    panic!("cannot subtract 1 from zero");
+}
+
+/// Subtract a big-endian number `b` from `a`.
+///
+/// # Panics
+///
+/// Panics if `b` is greater than `a`.
+pub fn sub_be(a: &[u8], b: &[u8]) -> Vec<u8> {
+    assert!(cmp_be(a, b) != Ordering::Less, "cannot subtract a bigger number");
+
+    let mut result = a.to_vec();
+    let mut borrow = 0;
+
+    for (i, byte) in result.iter_mut().rev().enumerate() {
+        let subtrahend = if i < b.len() { b[b.len() - 1 - i] as u16 } else { 0 };
+        let difference = (*byte as u16).wrapping_sub(subtrahend + borrow);
+
+        *byte = difference as u8;
+        borrow = if difference > 0xff { 1 } else { 0 };
+    }
+
+    result
+}
+
+/// Remainder of the division of a big-endian number by a small one.
+pub fn rem_small_be(n: &[u8], divisor: u32) -> u32 {
+    n.iter().fold(0, |rem, &byte| ((rem as u64 * 256 + byte as u64) % divisor as u64) as u32)
+}
+
+/// Shift a big-endian number right by 1 bit.
+pub fn shr_one_be(n: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(n.len());
+    let mut carry = 0;
+
+    for &byte in n {
+        result.push((byte >> 1) | carry);
+        carry = byte << 7;
+    }
+
+    result
+}
+
+/// Fail unless `input` consists of whole 16-byte AES blocks, as required
+/// by the IGE mode.
+fn check_aes_blocks(input: &[u8]) -> error::Result<()> {
+    if input.len() % 16 != 0 {
+        bail!(ErrorKind::AesInputNotAligned(input.len()));
+    }
+
+    Ok(())
+}
+
+/// Increment a 128-bit big-endian counter in place, wrapping around on
+/// overflow.
+#[cfg(all(feature = "pure-rust-crypto", not(feature = "openssl")))]
+fn increment_counter(counter: &mut [u8; 16]) {
+    for byte in counter.iter_mut().rev() {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{CryptoBackend, DefaultBackend, Hasher};
+    use error::ErrorKind;
+
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        hex.as_bytes().chunks(2)
+            .map(|pair| u8::from_str_radix(::std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn range_bytes(start: u8, len: usize) -> Vec<u8> {
+        (0..len).map(|i| start + i as u8).collect()
+    }
+
+    fn key_32(bytes: &[u8]) -> [u8; 32] {
+        let mut key = [0; 32];
+        key.copy_from_slice(bytes);
+        key
+    }
+
+    fn iv_16(bytes: &[u8]) -> [u8; 16] {
+        let mut iv = [0; 16];
+        iv.copy_from_slice(bytes);
+        iv
+    }
+
+
+    #[test]
+    fn test_hashes() {
+        type Sha1 = <DefaultBackend as CryptoBackend>::Sha1;
+        type Sha256 = <DefaultBackend as CryptoBackend>::Sha256;
+        type Md5 = <DefaultBackend as CryptoBackend>::Md5;
+
+        assert_eq!(Sha1::digest(&[b"abc"]).unwrap(), from_hex("a9993e364706816aba3e25717850c26c9cd0d89d"));
+        assert_eq!(Sha1::digest(&[b"ab", b"c"]).unwrap(), from_hex("a9993e364706816aba3e25717850c26c9cd0d89d"));
+        assert_eq!(Sha256::digest(&[b"abc"]).unwrap(),
+                   from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(Md5::digest(&[b"abc"]).unwrap(), from_hex("900150983cd24fb0d6963f7d28e17f72"));
+    }
+
+    #[test]
+    fn test_aes_ige() {
+        let key = key_32(&range_bytes(0, 32));
+        let iv = key_32(&range_bytes(32, 32));
+        let plaintext = range_bytes(100, 48);
+        let ciphertext = from_hex("2aeabf6e67e57b6f0d1bec11bec6e31b41aa6fb1910a291e3fccf0e0\
+                                   11a1bceb3970a3eafa203740a4e9266325deaee0");
+
+        assert_eq!(DefaultBackend::aes_ige_encrypt(&key, &iv, &plaintext).unwrap(), ciphertext);
+        assert_eq!(DefaultBackend::aes_ige_decrypt(&key, &iv, &ciphertext).unwrap(), plaintext);
+
+        // Partial blocks are rejected rather than padded
+        match *DefaultBackend::aes_ige_decrypt(&key, &iv, &ciphertext[..17]).unwrap_err().kind() {
+            ErrorKind::AesInputNotAligned(17) => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_aes_ctr() {
+        // NIST SP 800-38A, F.5.5
+        let key = key_32(&from_hex("603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4"));
+        let iv = iv_16(&from_hex("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff"));
+        let plaintext = from_hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51");
+        let ciphertext = from_hex("601ec313775789a5b7a7f504bbf3d228f443e3ca4d62b59aca84e990cacaf5c5");
+
+        assert_eq!(DefaultBackend::aes_ctr(&key, &iv, &plaintext).unwrap(), ciphertext);
+        assert_eq!(DefaultBackend::aes_ctr(&key, &iv, &ciphertext).unwrap(), plaintext);
+
+        // Counter carries into the upper bytes, partial last block
+        let key = key_32(&range_bytes(0, 32));
+        let iv = iv_16(&from_hex("000000000000000000000000ffffffff"));
+        let plaintext = range_bytes(100, 40);
+        let ciphertext = from_hex("dda715ff78a2cb3cc593047ffc1f24ed10686c4df8c7b58d\
+                                   8c8ef1e8e47c4d15b2fb7eaf00b8dfff");
+
+        assert_eq!(DefaultBackend::aes_ctr(&key, &iv, &plaintext).unwrap(), ciphertext);
+    }
+
+    #[test]
+    fn test_mod_exp() {
+        assert_eq!(DefaultBackend::mod_exp(&[4], &[13], &[0x01, 0xf1]).unwrap(), vec![0x01, 0xbd]);    // 445
+        assert_eq!(DefaultBackend::mod_exp(&[5], &[0], &[7]).unwrap(), vec![1]);
+        assert_eq!(DefaultBackend::mod_exp(&[7], &[2], &[7]).unwrap(), vec![]);
+
+        let base = range_bytes(1, 64);
+        let exponent = [0x01, 0x00, 0x01];
+        let mut modulus = vec![0xff; 66];
+        modulus[0] = 0x01;    // 2^521 - 1
+        let result = from_hex("017f32ee17f0a07995d8928db0f0c87b38a748c9109fd5d4e94870548493d0af\
+                               456aed6febd7fe9ff44852bfacef32bd63cf45ee4ad20db7bde5e6639360228e\
+                               f9a8");
+
+        assert_eq!(DefaultBackend::mod_exp(&base, &exponent, &modulus).unwrap(), result);
+    }
+
+    #[test]
+    fn test_is_prime() {
+        let mut mersenne_521 = vec![0xff; 66];
+        mersenne_521[0] = 0x01;
+        let mut mersenne_523 = vec![0xff; 66];
+        mersenne_523[0] = 0x07;
+
+        assert!(DefaultBackend::is_prime(&[2], 20).unwrap());
+        assert!(DefaultBackend::is_prime(&mersenne_521, 20).unwrap());
+        assert!(!DefaultBackend::is_prime(&mersenne_523, 20).unwrap());
+        assert!(!DefaultBackend::is_prime(&[0x01, 0xed], 20).unwrap());    // 493 = 17 * 29
+        assert!(!DefaultBackend::is_prime(&[1], 20).unwrap());
+    }
+}
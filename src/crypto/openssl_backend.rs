@@ -0,0 +1,98 @@
+//! Primitives backed by OpenSSL.
+
+use openssl::{aes, bn, hash, symm};
+
+use error;
+
+use super::{CryptoBackend, Hasher, check_aes_blocks};
+
+
+/// OpenSSL backend.
+#[derive(Clone, Copy, Debug)]
+pub enum OpenSsl {}
+
+impl CryptoBackend for OpenSsl {
+    type Md5 = Md5;
+    type Sha1 = Sha1;
+    type Sha256 = Sha256;
+
+    fn aes_ige_encrypt(key: &[u8; 32], iv: &[u8; 32], input: &[u8]) -> error::Result<Vec<u8>> {
+        check_aes_blocks(input)?;
+
+        // key is 256-bit, so can unwrap here
+        let key = aes::AesKey::new_encrypt(key).unwrap();
+        Ok(run_ige(&key, iv, input, symm::Mode::Encrypt))
+    }
+
+    fn aes_ige_decrypt(key: &[u8; 32], iv: &[u8; 32], input: &[u8]) -> error::Result<Vec<u8>> {
+        check_aes_blocks(input)?;
+
+        // Same here
+        let key = aes::AesKey::new_decrypt(key).unwrap();
+        Ok(run_ige(&key, iv, input, symm::Mode::Decrypt))
+    }
+
+    fn aes_ctr(key: &[u8; 32], iv: &[u8; 16], input: &[u8]) -> error::Result<Vec<u8>> {
+        // Encryption and decryption are the same in CTR mode
+        let output = symm::encrypt(symm::Cipher::aes_256_ctr(), key, Some(iv), input)?;
+
+        Ok(output)
+    }
+
+    fn mod_exp(base: &[u8], exponent: &[u8], modulus: &[u8]) -> error::Result<Vec<u8>> {
+        let mut ctx = bn::BigNumContext::new()?;
+        let base = bn::BigNum::from_slice(base)?;
+        let exponent = bn::BigNum::from_slice(exponent)?;
+        let modulus = bn::BigNum::from_slice(modulus)?;
+
+        let mut result = bn::BigNum::new()?;
+        result.mod_exp(&base, &exponent, &modulus, &mut ctx)?;
+
+        Ok(result.to_vec())
+    }
+
+    fn is_prime(n: &[u8], rounds: u32) -> error::Result<bool> {
+        let mut ctx = bn::BigNumContext::new()?;
+        let n = bn::BigNum::from_slice(n)?;
+
+        Ok(n.is_prime(rounds as i32, &mut ctx)?)
+    }
+}
+
+fn run_ige(key: &aes::AesKey, iv: &[u8; 32], input: &[u8], mode: symm::Mode) -> Vec<u8> {
+    let mut iv = *iv;
+    let mut output = vec![0; input.len()];
+
+    // Must not panic because:
+    // - input.len() == output.len() by declaration of output
+    // - input.len() % 16 == 0
+    // - iv.len() == 32 >= 32
+    aes::aes_ige(input, &mut output, key, &mut iv, mode);
+
+    output
+}
+
+
+macro_rules! impl_hasher {
+    ($name:ident, $digest:expr) => {
+        pub struct $name(hash::Hasher);
+
+        impl Hasher for $name {
+            fn new() -> error::Result<$name> {
+                Ok($name(hash::Hasher::new($digest)?))
+            }
+
+            fn update(&mut self, data: &[u8]) -> error::Result<()> {
+                Ok(self.0.update(data)?)
+            }
+
+            fn finish(mut self) -> error::Result<Vec<u8>> {
+                Ok(self.0.finish2()?.to_vec())
+            }
+        }
+    };
+}
+
+impl_hasher!(Md5, hash::MessageDigest::md5());
+impl_hasher!(Sha1, hash::MessageDigest::sha1());
+impl_hasher!(Sha256, hash::MessageDigest::sha256());
@@ -0,0 +1,196 @@
+//! Primitives implemented in pure Rust.
+
+use aes_soft::Aes256;
+use block_cipher_trait::BlockCipher;
+use digest::Digest;
+use generic_array::GenericArray;
+use md5;
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+use rand::{self, Rng};
+use sha1;
+use sha2;
+
+use error;
+
+use super::{CryptoBackend, Hasher, check_aes_blocks, increment_counter};
+
+
+/// Pure-Rust backend.
+#[derive(Clone, Copy, Debug)]
+pub enum RustCrypto {}
+
+impl CryptoBackend for RustCrypto {
+    type Md5 = Md5;
+    type Sha1 = Sha1;
+    type Sha256 = Sha256;
+
+    fn aes_ige_encrypt(key: &[u8; 32], iv: &[u8; 32], input: &[u8]) -> error::Result<Vec<u8>> {
+        check_aes_blocks(input)?;
+
+        let cipher = Aes256::new(GenericArray::from_slice(&key[..]));
+        let mut output = Vec::with_capacity(input.len());
+        let mut prev_x = [0; 16];
+        let mut prev_y = [0; 16];
+        prev_y.copy_from_slice(&iv[0..16]);
+        prev_x.copy_from_slice(&iv[16..32]);
+
+        for x in input.chunks(16) {
+            let mut block = GenericArray::clone_from_slice(x);
+            xor_in_place(&mut block, &prev_y);
+            cipher.encrypt_block(&mut block);
+            xor_in_place(&mut block, &prev_x);
+
+            prev_x.copy_from_slice(x);
+            prev_y.copy_from_slice(&block);
+            output.extend_from_slice(&block);
+        }
+
+        Ok(output)
+    }
+
+    fn aes_ige_decrypt(key: &[u8; 32], iv: &[u8; 32], input: &[u8]) -> error::Result<Vec<u8>> {
+        check_aes_blocks(input)?;
+
+        let cipher = Aes256::new(GenericArray::from_slice(&key[..]));
+        let mut output = Vec::with_capacity(input.len());
+        let mut prev_x = [0; 16];
+        let mut prev_y = [0; 16];
+        prev_x.copy_from_slice(&iv[0..16]);
+        prev_y.copy_from_slice(&iv[16..32]);
+
+        for x in input.chunks(16) {
+            let mut block = GenericArray::clone_from_slice(x);
+            xor_in_place(&mut block, &prev_y);
+            cipher.decrypt_block(&mut block);
+            xor_in_place(&mut block, &prev_x);
+
+            prev_x.copy_from_slice(x);
+            prev_y.copy_from_slice(&block);
+            output.extend_from_slice(&block);
+        }
+
+        Ok(output)
+    }
+
+    fn aes_ctr(key: &[u8; 32], iv: &[u8; 16], input: &[u8]) -> error::Result<Vec<u8>> {
+        let cipher = Aes256::new(GenericArray::from_slice(&key[..]));
+        let mut output = Vec::with_capacity(input.len());
+        let mut counter = *iv;
+
+        for chunk in input.chunks(16) {
+            let mut keystream = GenericArray::clone_from_slice(&counter);
+            cipher.encrypt_block(&mut keystream);
+            increment_counter(&mut counter);
+
+            output.extend(chunk.iter().zip(keystream.iter()).map(|(a, b)| a ^ b));
+        }
+
+        Ok(output)
+    }
+
+    fn mod_exp(base: &[u8], exponent: &[u8], modulus: &[u8]) -> error::Result<Vec<u8>> {
+        let base = BigUint::from_bytes_be(base);
+        let exponent = BigUint::from_bytes_be(exponent);
+        let modulus = BigUint::from_bytes_be(modulus);
+
+        Ok(to_bytes_be(&base.modpow(&exponent, &modulus)))
+    }
+
+    fn is_prime(n: &[u8], rounds: u32) -> error::Result<bool> {
+        Ok(miller_rabin(&BigUint::from_bytes_be(n), rounds))
+    }
+}
+
+fn xor_in_place(block: &mut [u8], other: &[u8; 16]) {
+    for (a, b) in block.iter_mut().zip(other) {
+        *a ^= *b;
+    }
+}
+
+/// Same as `BigUint::to_bytes_be`, but zero is represented as an empty
+/// vector like in OpenSSL.
+fn to_bytes_be(n: &BigUint) -> Vec<u8> {
+    if n.is_zero() {
+        Vec::new()
+    } else {
+        n.to_bytes_be()
+    }
+}
+
+fn miller_rabin(n: &BigUint, rounds: u32) -> bool {
+    let one = BigUint::one();
+    let two = &one + &one;
+    let three = &two + &one;
+
+    if *n < two {
+        return false;
+    }
+    if *n <= three {
+        return true;
+    }
+    if (n % &two).is_zero() {
+        return false;
+    }
+
+    // n - 1 == d * 2^s
+    let n_minus_one = n - &one;
+    let mut d = n_minus_one.clone();
+    let mut s = 0;
+    while (&d % &two).is_zero() {
+        d = d >> 1;
+        s += 1;
+    }
+
+    let mut rng = rand::thread_rng();
+    let n_bytes = n.to_bytes_be().len();
+    let n_minus_three = n - &three;
+
+    'witness: for _ in 0..rounds {
+        // Random witness in [2, n - 2]
+        let random_bytes: Vec<u8> = rng.gen_iter().take(n_bytes).collect();
+        let a = BigUint::from_bytes_be(&random_bytes) % &n_minus_three + &two;
+
+        let mut x = a.modpow(&d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+
+        for _ in 1..s {
+            x = x.modpow(&two, n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+
+        return false;
+    }
+
+    true
+}
+
+
+macro_rules! impl_hasher {
+    ($name:ident, $digest:ty) => {
+        pub struct $name($digest);
+
+        impl Hasher for $name {
+            fn new() -> error::Result<$name> {
+                Ok($name(<$digest>::default()))
+            }
+
+            fn update(&mut self, data: &[u8]) -> error::Result<()> {
+                self.0.input(data);
+                Ok(())
+            }
+
+            fn finish(self) -> error::Result<Vec<u8>> {
+                Ok(self.0.result().to_vec())
+            }
+        }
+    };
+}
+
+impl_hasher!(Md5, md5::Md5);
+impl_hasher!(Sha1, sha1::Sha1);
+impl_hasher!(Sha256, sha2::Sha256);
@@ -6,10 +6,11 @@ error_chain! {
     }
 
     foreign_links {
+        Base64(::base64::DecodeError);
         Envy(::envy::Error);
         FromUtf8(::std::string::FromUtf8Error);
         Io(::std::io::Error);
-        OpenSsl(::openssl::error::ErrorStack);
+        OpenSsl(::openssl::error::ErrorStack) #[cfg(feature = "openssl")];
         TomlDeserialize(::toml::de::Error);
     }
 
@@ -47,6 +48,47 @@ error_chain! {
                 fingerprints)
         }
 
+        RsaPadDataTooLong(expected_max_len: usize, found_len: usize) {
+            description("Data is too long to be encrypted with RSA_PAD")
+            display("Data is too long to be encrypted with RSA_PAD (expected maximum {} bytes, found {})",
+                expected_max_len, found_len)
+        }
+
+        MalformedRsaPublicKey {
+            description("Malformed RSA public key")
+            display("Malformed RSA public key")
+        }
+
+        AuthKeyExchangeOutOfOrder(request: &'static str) {
+            description("Unexpected request during the key exchange")
+            display("Unexpected request during the key exchange: {}", request)
+        }
+
+        AuthKeyExchangeNonceMismatch {
+            description("Nonces don't match during the key exchange")
+            display("Nonces don't match during the key exchange")
+        }
+
+        AuthKeyExchangeInvalidData(what: &'static str) {
+            description("Invalid data received during the key exchange")
+            display("Invalid data received during the key exchange: {}", what)
+        }
+
+        MalformedRsaPrivateKey {
+            description("Malformed RSA private key")
+            display("Malformed RSA private key")
+        }
+
+        RsaDecryptionFailure {
+            description("Failed to decrypt RSA-encrypted data")
+            display("Failed to decrypt RSA-encrypted data")
+        }
+
+        AesInputNotAligned(len: usize) {
+            description("AES input length is not a multiple of the block size")
+            display("AES input length {} is not a multiple of the block size", len)
+        }
+
         NoModulus {
             description("No modulus found from a RSA key")
             display("No modulus found from a RSA key")
@@ -77,6 +119,16 @@ error_chain! {
             display("Authorization key not found")
         }
 
+        UnencryptedMessage {
+            description("Expected an encrypted message, found a plain-text one")
+            display("Expected an encrypted message, found a plain-text one")
+        }
+
+        MalformedMessage(what: &'static str) {
+            description("Malformed message")
+            display("Malformed message: {}", what)
+        }
+
         NoEncryptedDataLengthProvided {
             description("No encrypted data length provided to deserialize an encrypted message")
             display("No encrypted data length provided to deserialize an encrypted message")
@@ -86,5 +138,153 @@ error_chain! {
             description("Unknown constructor id found while deserializing")
             display("Unknown constructor id found while deserializing {}: {:#x}", type_or_variant, ctor_id)
         }
+
+        TlSchemaParse(description: String) {
+            description("Failed to parse a TL schema")
+            display("Failed to parse a TL schema: {}", description)
+        }
+
+        UnknownTlType(name: String) {
+            description("Unknown TL type")
+            display("Unknown TL type: {}", name)
+        }
+
+        MissingFlagsField(name: String) {
+            description("Optional field refers to an unknown flags field")
+            display("Optional field refers to an unknown flags field: {}", name)
+        }
+
+        NotEnoughData(needed: usize, available: usize) {
+            description("Not enough data to decode a TL value")
+            display("Not enough data to decode a TL value (needed {} bytes, {} available)", needed, available)
+        }
+
+        TlValueTooDeep(max_depth: usize) {
+            description("TL value is nested too deeply")
+            display("TL value is nested deeper than {} levels", max_depth)
+        }
+
+        TlValueMismatch(description: String) {
+            description("TL value does not match its schema")
+            display("TL value does not match its schema: {}", description)
+        }
+
+        UnexpectedJson(description: String) {
+            description("Unexpected JSON value")
+            display("Unexpected JSON value: {}", description)
+        }
+
+        RpcError(error_code: i32, error_message: String) {
+            description("RPC call returned an error")
+            display("RPC call returned an error {}: {}", error_code, error_message)
+        }
+
+        NoRpcReply(request_msg_id: i64) {
+            description("No reply received for an RPC call")
+            display("No reply received for an RPC call in message {}", request_msg_id)
+        }
+
+        NoDcEndpoint(dc_id: i32) {
+            description("No suitable endpoint found for a datacenter")
+            display("No suitable endpoint found for datacenter {}", dc_id)
+        }
+
+        FilePartNotSaved(file_part: i32) {
+            description("The server refused to save a file part")
+            display("The server refused to save file part {}", file_part)
+        }
+
+        FileTooBig(size: u64, max_size: u64) {
+            description("File is too big to be uploaded")
+            display("File of {} bytes is too big to be uploaded (maximum {} bytes)", size, max_size)
+        }
+
+        FileSizeMismatch(expected: u64, found: u64) {
+            description("File size differs from the declared one")
+            display("File size differs from the declared one (expected {} bytes, found {})", expected, found)
+        }
+
+        MalformedCdnRedirect {
+            description("CDN redirect has wrong encryption key or IV length")
+            display("CDN redirect has wrong encryption key or IV length")
+        }
+
+        CdnHashMissing(offset: i32) {
+            description("No hash found for a CDN file part")
+            display("No hash found for a CDN file part at offset {}", offset)
+        }
+
+        CdnHashMismatch(offset: i32) {
+            description("CDN file part doesn't match its hash")
+            display("CDN file part at offset {} doesn't match its hash", offset)
+        }
+
+        TooManyCdnReuploads(offset: i32) {
+            description("CDN kept asking to reupload a file part")
+            display("CDN kept asking to reupload the file part at offset {}", offset)
+        }
+
+        NoPasswordSet {
+            description("Two-step verification password was requested but isn't set")
+            display("Two-step verification password was requested but isn't set")
+        }
+
+        SecretChatNotFound(chat_id: i32) {
+            description("Secret chat not found")
+            display("Secret chat {} not found", chat_id)
+        }
+
+        NoDhConfig {
+            description("No Diffie-Hellman parameters received from the server")
+            display("No Diffie-Hellman parameters received from the server")
+        }
+
+        UnsafeDhPrime {
+            description("Diffie-Hellman prime is not a safe 2048-bit prime")
+            display("Diffie-Hellman prime is not a safe 2048-bit prime")
+        }
+
+        UnsafeDhGenerator(g: i32) {
+            description("Diffie-Hellman generator doesn't generate a subgroup of prime order")
+            display("Diffie-Hellman generator {} doesn't generate a subgroup of prime order", g)
+        }
+
+        DhValueOutOfRange {
+            description("Diffie-Hellman value is outside of the safe range")
+            display("Diffie-Hellman value is outside of the safe range")
+        }
+
+        SecretKeyFingerprintMismatch(expected: i64, found: i64) {
+            description("Secret chat key fingerprint differs from the one computed locally")
+            display("Secret chat key fingerprint differs from the one computed locally (expected {}, found {})",
+                expected, found)
+        }
+
+        SecretMessageTooShort(len: usize) {
+            description("Encrypted secret chat message is too short")
+            display("Encrypted secret chat message is too short ({} bytes)", len)
+        }
+
+        SecretMessageInvalidLength(len: usize) {
+            description("Encrypted secret chat message doesn't consist of whole AES blocks")
+            display("Encrypted secret chat message doesn't consist of whole AES blocks ({} bytes)", len)
+        }
+
+        SecretMessageKeyMismatch {
+            description("Message key of a secret chat message doesn't match its contents")
+            display("Message key of a secret chat message doesn't match its contents")
+        }
+
+        SecretChatSeqGap(chat_id: i32, expected: i32, found: i32) {
+            description("Gap in secret chat messages sequence")
+            display("Gap in secret chat {} messages sequence (expected seq_no {}, found {})",
+                chat_id, expected, found)
+        }
+
+        TooManyMigrations(last_dc_id: i32) {
+            description("Too many datacenter migrations while performing a call")
+            display("Too many datacenter migrations while performing a call (last datacenter: {})",
+                last_dc_id)
+        }
     }
 }
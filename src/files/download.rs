@@ -0,0 +1,257 @@
+//! Downloading files in parts with `upload.getFile`, following CDN
+//! redirects.
+//!
+//! Files served from CDN datacenters are encrypted with AES-256-CTR
+//! using the key and IV from `upload.fileCdnRedirect`. Every decrypted
+//! part is checked against hashes obtained with
+//! `upload.getCdnFileHashes` from the master datacenter.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use byteorder::{BigEndian, ByteOrder};
+use serde::de::DeserializeOwned;
+
+use crypto;
+use error::{self, ErrorKind};
+use rpc::{DcConnector, DcKind, DcPool, RpcFunction};
+use schema;
+use tl::TLObject;
+
+use super::should_retry;
+
+
+/// Default number of bytes requested at once.
+pub const DEFAULT_PART_SIZE: i32 = 512 * 1024;
+
+/// Maximum number of bytes which can be requested at once.
+pub const MAX_PART_SIZE: i32 = 1024 * 1024;
+
+const DEFAULT_MAX_RETRIES: usize = 3;
+
+/// Maximum number of `upload.reuploadCdnFile` calls for a single part.
+const MAX_CDN_REUPLOADS: usize = 3;
+
+
+/// Downloads files part by part into any `Write`.
+#[derive(Clone, Debug)]
+pub struct Downloader {
+    part_size: i32,
+    max_retries: usize,
+}
+
+impl Downloader {
+    /// Construct a `Downloader` with default settings: 512 KB parts and
+    /// 3 retries per part.
+    pub fn new() -> Downloader {
+        Downloader {
+            part_size: DEFAULT_PART_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+
+    /// Set the number of bytes requested at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `part_size` is divisible by 4 KB and 1 MB is
+    /// divisible by `part_size`, as required by the server.
+    pub fn part_size(mut self, part_size: i32) -> Downloader {
+        assert!(part_size > 0 && part_size % 4096 == 0 && MAX_PART_SIZE % part_size == 0,
+                "invalid part size: {}", part_size);
+        self.part_size = part_size;
+        self
+    }
+
+    /// Set how many times a single part is retried on failure.
+    pub fn max_retries(mut self, max_retries: usize) -> Downloader {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Download a file stored on `dc_id` into `dest`.
+    ///
+    /// Returns the number of bytes written.
+    pub fn download<C, W>(&self,
+                          pool: &mut DcPool<C>,
+                          dc_id: i32,
+                          location: schema::InputFileLocation,
+                          dest: &mut W)
+                         -> error::Result<u64>
+        where C: DcConnector,
+              W: Write
+    {
+        let mut offset = 0;
+
+        loop {
+            let get_file = schema::rpc::upload::getFile {
+                location: location.clone(),
+                offset: offset,
+                limit: self.part_size,
+            };
+
+            match self.call_with_retries(pool, dc_id, DcKind::Media, get_file)? {
+                schema::upload::File::file(file) => {
+                    let len = file.bytes.len() as i32;    // Never exceeds `self.part_size`
+                    dest.write_all(&file.bytes)?;
+                    offset += len;
+
+                    if len < self.part_size {
+                        break;
+                    }
+                },
+                schema::upload::File::fileCdnRedirect(redirect) => {
+                    info!("File redirected to CDN DC {}", redirect.dc_id);
+                    let cdn_written = self.download_cdn(pool, dc_id, redirect, offset, dest)?;
+
+                    return Ok(offset as u64 + cdn_written);
+                },
+            }
+        }
+
+        Ok(offset as u64)
+    }
+
+    fn download_cdn<C, W>(&self,
+                          pool: &mut DcPool<C>,
+                          master_dc_id: i32,
+                          redirect: schema::upload::fileCdnRedirect,
+                          start_offset: i32,
+                          dest: &mut W)
+                         -> error::Result<u64>
+        where C: DcConnector,
+              W: Write
+    {
+        let mut hashes = BTreeMap::new();
+        let mut offset = start_offset;
+        let mut reuploads = 0;
+
+        loop {
+            let get_cdn_file = schema::rpc::upload::getCdnFile {
+                file_token: redirect.file_token.clone(),
+                offset: offset,
+                limit: self.part_size,
+            };
+
+            match self.call_with_retries(pool, redirect.dc_id, DcKind::Cdn, get_cdn_file)? {
+                schema::upload::CdnFile::cdnFileReuploadNeeded(reupload) => {
+                    debug!("CDN asked to reupload the file at offset {}", offset);
+
+                    reuploads += 1;
+                    if reuploads > MAX_CDN_REUPLOADS {
+                        bail!(ErrorKind::TooManyCdnReuploads(offset));
+                    }
+
+                    let reupload_cdn_file = schema::rpc::upload::reuploadCdnFile {
+                        file_token: redirect.file_token.clone(),
+                        request_token: reupload.request_token,
+                    };
+
+                    self.call_with_retries(pool, master_dc_id, DcKind::Main, reupload_cdn_file)?;
+                },
+                schema::upload::CdnFile::cdnFile(cdn_file) => {
+                    let bytes = decrypt_cdn_part(&redirect, offset, &cdn_file.bytes)?;
+                    self.verify_cdn_part(pool, master_dc_id, &redirect, &mut hashes, offset, &bytes)?;
+
+                    let len = bytes.len() as i32;    // Never exceeds `self.part_size`
+                    dest.write_all(&bytes)?;
+                    offset += len;
+                    reuploads = 0;
+
+                    if len < self.part_size {
+                        break;
+                    }
+                },
+            }
+        }
+
+        Ok((offset - start_offset) as u64)
+    }
+
+    fn verify_cdn_part<C>(&self,
+                          pool: &mut DcPool<C>,
+                          master_dc_id: i32,
+                          redirect: &schema::upload::fileCdnRedirect,
+                          hashes: &mut BTreeMap<i32, schema::CdnFileHash>,
+                          offset: i32,
+                          bytes: &[u8])
+                         -> error::Result<()>
+        where C: DcConnector
+    {
+        let mut checked = 0;
+
+        while checked < bytes.len() {
+            let chunk_offset = offset + checked as i32;
+
+            if !hashes.contains_key(&chunk_offset) {
+                let get_hashes = schema::rpc::upload::getCdnFileHashes {
+                    file_token: redirect.file_token.clone(),
+                    offset: chunk_offset,
+                };
+
+                let new_hashes = self.call_with_retries(pool, master_dc_id, DcKind::Main, get_hashes)?;
+                hashes.extend(new_hashes.into_inner().into_iter().map(|h| (h.offset, h)));
+            }
+
+            let hash = hashes.remove(&chunk_offset)
+                .ok_or(error::Error::from(ErrorKind::CdnHashMissing(chunk_offset)))?;
+
+            let end = cmp::min(checked + hash.limit as usize, bytes.len());
+            let sha256 = crypto::sha256(&[&bytes[checked..end]])?;
+
+            if &sha256[..] != &hash.hash[..] {
+                bail!(ErrorKind::CdnHashMismatch(chunk_offset));
+            }
+
+            checked = end;
+        }
+
+        Ok(())
+    }
+
+    fn call_with_retries<C, F>(&self, pool: &mut DcPool<C>, dc_id: i32, kind: DcKind, function: F)
+        -> error::Result<F::Reply>
+        where C: DcConnector,
+              F: Clone + RpcFunction + TLObject + fmt::Debug,
+              F::Reply: DeserializeOwned
+    {
+        let mut attempt = 0;
+
+        loop {
+            let error = match pool.call_on(dc_id, kind, function.clone()) {
+                Ok(reply) => return Ok(reply),
+                Err(e) => e,
+            };
+
+            attempt += 1;
+            if !should_retry(&error, attempt, self.max_retries) {
+                return Err(error);
+            }
+
+            debug!("Retrying {:?} (attempt {}): {}", function, attempt, error);
+        }
+    }
+}
+
+
+/// Decrypt a part of a CDN file with AES-256-CTR.
+///
+/// The last 4 bytes of the IV are replaced with `offset / 16` in
+/// big-endian.
+fn decrypt_cdn_part(redirect: &schema::upload::fileCdnRedirect, offset: i32, bytes: &[u8])
+    -> error::Result<Vec<u8>>
+{
+    let mut key = [0; 32];
+    let mut iv = [0; 16];
+    if redirect.encryption_key.len() != key.len() || redirect.encryption_iv.len() != iv.len() {
+        bail!(ErrorKind::MalformedCdnRedirect);
+    }
+
+    key.copy_from_slice(&redirect.encryption_key);
+    iv.copy_from_slice(&redirect.encryption_iv);
+    BigEndian::write_i32(&mut iv[12..16], offset / 16);
+
+    crypto::aes_ctr(&key, &iv, bytes)
+}
@@ -0,0 +1,53 @@
+//! Transferring files to and from Telegram servers.
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+use error::{self, ErrorKind};
+
+pub mod download;
+pub mod upload;
+
+pub use self::download::Downloader;
+pub use self::upload::Uploader;
+
+
+/// Fill `buf` from `source` until it's full or the source is exhausted.
+///
+/// Returns the number of bytes read.
+fn read_part<R: Read>(source: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match source.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Decide whether a failed call should be retried and wait if the
+/// server asked to (`FLOOD_WAIT_X`).
+fn should_retry(error: &error::Error, attempt: usize, max_retries: usize) -> bool {
+    if attempt >= max_retries {
+        return false;
+    }
+
+    match *error.kind() {
+        ErrorKind::RpcError(420, ref message) if message.starts_with("FLOOD_WAIT_") => {
+            let seconds = message["FLOOD_WAIT_".len()..].parse().unwrap_or(1);
+            warn!("Flood wait for {} seconds", seconds);
+            thread::sleep(Duration::from_secs(seconds));
+
+            true
+        },
+        // Errors like 400 BAD_REQUEST won't go away on retry
+        ErrorKind::RpcError(code, _) => code >= 500,
+        _ => true,
+    }
+}
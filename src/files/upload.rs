@@ -0,0 +1,279 @@
+//! Uploading files in parts with `upload.saveFilePart` and
+//! `upload.saveBigFilePart`.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+
+use rand::{self, Rng};
+use serde_bytes::ByteBuf;
+
+use crypto::{self, Hasher};
+use error::{self, ErrorKind};
+use rpc::RpcCaller;
+use schema;
+
+use super::{read_part, should_retry};
+
+
+/// Default size of a single file part.
+pub const DEFAULT_PART_SIZE: usize = 512 * 1024;
+
+/// Files larger than this are uploaded with `upload.saveBigFilePart`.
+pub const BIG_FILE_THRESHOLD: u64 = 10 * 1024 * 1024;
+
+/// Maximum number of parts a single file can consist of.
+pub const MAX_PARTS: u64 = 3000;
+
+const DEFAULT_MAX_RETRIES: usize = 3;
+
+
+/// Uploads files in parts and produces `InputFile`s for them.
+#[derive(Clone, Debug)]
+pub struct Uploader {
+    part_size: usize,
+    max_retries: usize,
+}
+
+impl Uploader {
+    /// Construct an `Uploader` with default settings: 512 KB parts and
+    /// 3 retries per part.
+    pub fn new() -> Uploader {
+        Uploader {
+            part_size: DEFAULT_PART_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+
+    /// Set the size of a single part.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `part_size` is divisible by 1 KB and 512 KB is
+    /// divisible by `part_size`, as required by the server.
+    pub fn part_size(mut self, part_size: usize) -> Uploader {
+        assert!(part_size > 0 && part_size % 1024 == 0 && DEFAULT_PART_SIZE % part_size == 0,
+                "invalid part size: {}", part_size);
+        self.part_size = part_size;
+        self
+    }
+
+    /// Set how many times a single part is retried on failure.
+    pub fn max_retries(mut self, max_retries: usize) -> Uploader {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Upload a file from the filesystem.
+    pub fn upload_file<C, P>(&self, caller: &mut C, path: P) -> error::Result<schema::InputFile>
+        where C: RpcCaller,
+              P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+        self.upload(caller, name, size, file)
+    }
+
+    /// Upload `size` bytes from `source` part by part over a single
+    /// connection.
+    pub fn upload<C, R>(&self, caller: &mut C, name: String, size: u64, mut source: R)
+        -> error::Result<schema::InputFile>
+        where C: RpcCaller,
+              R: Read
+    {
+        let plan = self.plan(size)?;
+        let mut md5 = crypto::Md5::new()?;
+        let mut read_total = 0;
+
+        for file_part in 0..plan.parts {
+            let bytes = self.read_next_part(&mut source, &mut md5, &mut read_total)?;
+            save_part(caller, &plan, file_part, bytes, self.max_retries)?;
+        }
+
+        self.finish(plan, name, size, read_total, md5)
+    }
+
+    /// Upload `size` bytes from `source` using several connections at
+    /// once.
+    ///
+    /// Each connection is driven by its own thread, so the number of
+    /// parts uploaded in parallel is bounded by the number of
+    /// connections. The connections are given back after the upload.
+    pub fn upload_parallel<C, R>(&self, callers: Vec<C>, name: String, size: u64, mut source: R)
+        -> error::Result<(schema::InputFile, Vec<C>)>
+        where C: RpcCaller + Send + 'static,
+              R: Read
+    {
+        assert!(!callers.is_empty(), "at least one connection is required");
+
+        let plan = self.plan(size)?;
+        let mut md5 = crypto::Md5::new()?;
+        let mut read_total = 0;
+
+        let (part_tx, part_rx) = mpsc::sync_channel::<(i32, Vec<u8>)>(callers.len());
+        let part_rx = Arc::new(Mutex::new(part_rx));
+        let (error_tx, error_rx) = mpsc::channel::<error::Error>();
+
+        let workers: Vec<_> = callers.into_iter().map(|mut caller| {
+            let part_rx = part_rx.clone();
+            let error_tx = error_tx.clone();
+            let plan = plan.clone();
+            let max_retries = self.max_retries;
+
+            thread::spawn(move || {
+                loop {
+                    // Release the lock before uploading so that others can proceed
+                    let next_part = part_rx.lock().unwrap().recv();
+
+                    let (file_part, bytes) = match next_part {
+                        Ok(part) => part,
+                        Err(_) => break,    // All parts are sent
+                    };
+
+                    if let Err(e) = save_part(&mut caller, &plan, file_part, bytes, max_retries) {
+                        // The receiver outlives all workers
+                        error_tx.send(e).unwrap();
+                        break;
+                    }
+                }
+
+                caller
+            })
+        }).collect();
+
+        let mut read_result = Ok(());
+        for file_part in 0..plan.parts {
+            let bytes = match self.read_next_part(&mut source, &mut md5, &mut read_total) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    read_result = Err(e);
+                    break;
+                },
+            };
+
+            if part_tx.send((file_part, bytes)).is_err() {
+                break;    // All workers have failed
+            }
+        }
+
+        drop(part_tx);
+        let callers = workers.into_iter()
+            .map(|worker| worker.join().expect("upload worker thread panicked"))
+            .collect();
+
+        read_result?;
+        if let Ok(e) = error_rx.try_recv() {
+            return Err(e);
+        }
+
+        let input_file = self.finish(plan, name, size, read_total, md5)?;
+
+        Ok((input_file, callers))
+    }
+
+    fn plan(&self, size: u64) -> error::Result<UploadPlan> {
+        let part_size = self.part_size as u64;
+        let parts = (size + part_size - 1) / part_size;
+
+        if parts > MAX_PARTS {
+            bail!(ErrorKind::FileTooBig(size, MAX_PARTS * part_size));
+        }
+
+        Ok(UploadPlan {
+            file_id: rand::thread_rng().gen(),
+            parts: parts as i32,    // Checked above
+            is_big: size > BIG_FILE_THRESHOLD,
+        })
+    }
+
+    fn read_next_part<R: Read>(&self, source: &mut R, md5: &mut crypto::Md5, read_total: &mut u64)
+        -> error::Result<Vec<u8>>
+    {
+        let mut bytes = vec![0; self.part_size];
+        let len = read_part(source, &mut bytes)?;
+        bytes.truncate(len);
+
+        md5.update(&bytes)?;
+        *read_total += len as u64;
+
+        Ok(bytes)
+    }
+
+    fn finish(&self, plan: UploadPlan, name: String, size: u64, read_total: u64, md5: crypto::Md5)
+        -> error::Result<schema::InputFile>
+    {
+        if read_total != size {
+            bail!(ErrorKind::FileSizeMismatch(size, read_total));
+        }
+
+        let input_file = if plan.is_big {
+            schema::InputFile::inputFileBig(schema::inputFileBig {
+                id: plan.file_id,
+                parts: plan.parts,
+                name: name,
+            })
+        } else {
+            let md5_checksum = md5.finish()?.iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+
+            schema::InputFile::inputFile(schema::inputFile {
+                id: plan.file_id,
+                parts: plan.parts,
+                name: name,
+                md5_checksum: md5_checksum,
+            })
+        };
+
+        Ok(input_file)
+    }
+}
+
+
+#[derive(Clone, Debug)]
+struct UploadPlan {
+    file_id: i64,
+    parts: i32,
+    is_big: bool,
+}
+
+fn save_part<C: RpcCaller>(caller: &mut C, plan: &UploadPlan, file_part: i32, bytes: Vec<u8>, max_retries: usize)
+    -> error::Result<()>
+{
+    let mut attempt = 0;
+
+    loop {
+        let result = if plan.is_big {
+            caller.call(schema::rpc::upload::saveBigFilePart {
+                file_id: plan.file_id,
+                file_part: file_part,
+                file_total_parts: plan.parts,
+                bytes: ByteBuf::from(bytes.clone()),
+            })
+        } else {
+            caller.call(schema::rpc::upload::saveFilePart {
+                file_id: plan.file_id,
+                file_part: file_part,
+                bytes: ByteBuf::from(bytes.clone()),
+            })
+        };
+
+        let error = match result {
+            Ok(true) => return Ok(()),
+            Ok(false) => error::Error::from(ErrorKind::FilePartNotSaved(file_part)),
+            Err(e) => e,
+        };
+
+        attempt += 1;
+        if !should_retry(&error, attempt, max_retries) {
+            return Err(error);
+        }
+
+        debug!("Retrying file part {} (attempt {}): {}", file_part, attempt, error);
+    }
+}
@@ -1,35 +1,66 @@
 // `error_chain!` can nest quite deeply
 #![recursion_limit = "128"]
 
+#[cfg(feature = "pure-rust-crypto")]
+extern crate aes_soft;
+extern crate base64;
+#[cfg(feature = "pure-rust-crypto")]
+extern crate block_cipher_trait;
 extern crate byteorder;
 extern crate chrono;
+#[cfg(feature = "pure-rust-crypto")]
+extern crate digest;
 extern crate envy;
 extern crate erased_serde;
 #[macro_use]
 extern crate error_chain;
 extern crate extprim;
+#[cfg(feature = "pure-rust-crypto")]
+extern crate generic_array;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "pure-rust-crypto")]
+extern crate md5;
+#[cfg(feature = "pure-rust-crypto")]
+extern crate num_bigint;
 extern crate num_traits;
+#[cfg(feature = "openssl")]
 extern crate openssl;
 extern crate rand;
 extern crate serde;
 extern crate serde_bytes;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(feature = "runtime-schema")]
+extern crate serde_json;
 extern crate serde_mtproto;
 #[macro_use]
 extern crate serde_mtproto_derive;
+#[cfg(feature = "pure-rust-crypto")]
+extern crate sha1;
+#[cfg(feature = "pure-rust-crypto")]
+extern crate sha2;
+#[cfg(feature = "runtime-schema")]
+extern crate tl_codegen;
 extern crate toml;
 
 
+#[cfg(not(any(feature = "openssl", feature = "pure-rust-crypto")))]
+compile_error!("either the `openssl` or the `pure-rust-crypto` feature must be enabled");
+
+
 mod manual_types;
 mod utils;
 
+pub mod auth;
+pub(crate) mod crypto;
 pub mod error;
+pub mod files;
 pub mod rpc;
 pub mod schema;
+pub mod secret;
 pub mod tl;
+pub mod updates;
 
 
 pub use error::{Error, ErrorKind, Result, ResultExt};
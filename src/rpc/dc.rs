@@ -0,0 +1,408 @@
+//! Datacenters: their addresses and connections to them.
+//!
+//! Telegram spreads users and files across several datacenters (DCs).
+//! `DcRegistry` keeps track of known DC endpoints (usually obtained
+//! with `help.getConfig`) and `DcPool` maintains a connection per DC,
+//! transparently following `*_MIGRATE_X` errors and transferring
+//! authorization between DCs when needed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+
+use error::{self, ErrorKind};
+use schema;
+use tl::TLObject;
+
+use super::{RpcCaller, RpcFunction};
+
+
+/// Maximum number of consecutive migrations followed by a single call.
+const MAX_MIGRATIONS: usize = 5;
+
+
+/// A single address through which a datacenter can be reached.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DcEndpoint {
+    pub dc_id: i32,
+    pub ip_address: String,
+    pub port: i32,
+    pub ipv6: bool,
+    pub media_only: bool,
+    pub tcpo_only: bool,
+    pub cdn: bool,
+    pub is_static: bool,
+}
+
+impl DcEndpoint {
+    /// Construct a regular IPv4 endpoint.
+    pub fn new(dc_id: i32, ip_address: String, port: i32) -> DcEndpoint {
+        DcEndpoint {
+            dc_id: dc_id,
+            ip_address: ip_address,
+            port: port,
+            ipv6: false,
+            media_only: false,
+            tcpo_only: false,
+            cdn: false,
+            is_static: false,
+        }
+    }
+
+    fn kind(&self) -> DcKind {
+        if self.cdn {
+            DcKind::Cdn
+        } else if self.media_only {
+            DcKind::Media
+        } else {
+            DcKind::Main
+        }
+    }
+}
+
+impl<'a> From<&'a schema::DcOption> for DcEndpoint {
+    fn from(dc_option: &'a schema::DcOption) -> DcEndpoint {
+        DcEndpoint {
+            dc_id: dc_option.id,
+            ip_address: dc_option.ip_address.clone(),
+            port: dc_option.port,
+            ipv6: dc_option.ipv6,
+            media_only: dc_option.media_only,
+            tcpo_only: dc_option.tcpo_only,
+            cdn: dc_option.cdn,
+            is_static: dc_option.static_,
+        }
+    }
+}
+
+/// Purpose for which a datacenter connection is used.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum DcKind {
+    /// Regular API calls.
+    Main,
+    /// Uploading and downloading files, falls back to `Main` endpoints.
+    Media,
+    /// Downloading files from CDN datacenters.
+    Cdn,
+}
+
+
+/// Set of known datacenter endpoints.
+#[derive(Clone, Debug, Default)]
+pub struct DcRegistry {
+    endpoints: BTreeMap<i32, Vec<DcEndpoint>>,
+    this_dc: Option<i32>,
+    prefer_ipv6: bool,
+}
+
+impl DcRegistry {
+    /// Construct an empty registry.
+    pub fn new() -> DcRegistry {
+        DcRegistry::default()
+    }
+
+    /// Construct a registry filled with `dc_options` of a `Config`.
+    pub fn from_config(config: &schema::Config) -> DcRegistry {
+        let mut registry = DcRegistry::new();
+        registry.update_from_config(config);
+
+        registry
+    }
+
+    /// Replace all known endpoints with ones found in a `Config`.
+    pub fn update_from_config(&mut self, config: &schema::Config) {
+        self.endpoints.clear();
+        self.this_dc = Some(config.this_dc);
+
+        for dc_option in config.dc_options.inner() {
+            self.add(DcEndpoint::from(dc_option));
+        }
+    }
+
+    /// Register a new endpoint, replacing the same one if present.
+    pub fn add(&mut self, endpoint: DcEndpoint) {
+        let endpoints = self.endpoints.entry(endpoint.dc_id).or_insert_with(Vec::new);
+
+        endpoints.retain(|e| e.ip_address != endpoint.ip_address || e.port != endpoint.port);
+        endpoints.push(endpoint);
+    }
+
+    /// Prefer IPv6 endpoints over IPv4 ones when both are available.
+    pub fn set_prefer_ipv6(&mut self, prefer_ipv6: bool) {
+        self.prefer_ipv6 = prefer_ipv6;
+    }
+
+    /// The datacenter the `Config` was received from, if known.
+    pub fn this_dc(&self) -> Option<i32> {
+        self.this_dc
+    }
+
+    /// Ids of all known datacenters.
+    pub fn dc_ids(&self) -> Vec<i32> {
+        self.endpoints.keys().cloned().collect()
+    }
+
+    /// All known endpoints of a datacenter.
+    pub fn endpoints(&self, dc_id: i32) -> &[DcEndpoint] {
+        self.endpoints.get(&dc_id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Select the most suitable endpoint of a datacenter for the given
+    /// purpose.
+    pub fn select(&self, dc_id: i32, kind: DcKind) -> Option<&DcEndpoint> {
+        let endpoints = self.endpoints(dc_id);
+        let prefer_ipv6 = self.prefer_ipv6;
+
+        let find = |kind: DcKind| {
+            let mut fallback = None;
+
+            for endpoint in endpoints.iter().filter(|e| e.kind() == kind) {
+                if endpoint.ipv6 == prefer_ipv6 {
+                    return Some(endpoint);
+                }
+
+                fallback = fallback.or(Some(endpoint));
+            }
+
+            fallback
+        };
+
+        match kind {
+            DcKind::Main => find(DcKind::Main),
+            DcKind::Media => find(DcKind::Media).or_else(|| find(DcKind::Main)),
+            DcKind::Cdn => find(DcKind::Cdn),
+        }
+    }
+}
+
+
+/// Opens connections to datacenters.
+pub trait DcConnector {
+    type Connection: RpcCaller;
+
+    /// Connect to a datacenter through `endpoint`.
+    ///
+    /// The returned connection must be ready to perform calls, i.e.
+    /// have an authorization key and a session.
+    fn connect(&mut self, endpoint: &DcEndpoint, kind: DcKind) -> error::Result<Self::Connection>;
+}
+
+/// Kinds of migration errors returned by the server.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Migration {
+    /// `PHONE_MIGRATE_X`, `NETWORK_MIGRATE_X` or `USER_MIGRATE_X`: the
+    /// home datacenter has to be changed.
+    Home(i32),
+    /// `FILE_MIGRATE_X`: the call has to be repeated on another
+    /// datacenter.
+    File(i32),
+}
+
+impl Migration {
+    /// Extract a migration request from an error, if it's one.
+    pub fn from_error(error: &error::Error) -> Option<Migration> {
+        let message = match *error.kind() {
+            ErrorKind::RpcError(303, ref message) => message,
+            _ => return None,
+        };
+
+        let parse_dc_id = |prefix: &str| {
+            if message.starts_with(prefix) {
+                message[prefix.len()..].parse().ok()
+            } else {
+                None
+            }
+        };
+
+        if let Some(dc_id) = parse_dc_id("FILE_MIGRATE_") {
+            return Some(Migration::File(dc_id));
+        }
+
+        parse_dc_id("PHONE_MIGRATE_")
+            .or_else(|| parse_dc_id("NETWORK_MIGRATE_"))
+            .or_else(|| parse_dc_id("USER_MIGRATE_"))
+            .map(Migration::Home)
+    }
+}
+
+
+/// A pool of connections to datacenters.
+pub struct DcPool<C: DcConnector> {
+    connector: C,
+    registry: DcRegistry,
+    home_dc: i32,
+    connections: BTreeMap<(i32, DcKind), C::Connection>,
+    authorized: BTreeSet<(i32, DcKind)>,
+}
+
+impl<C: DcConnector> DcPool<C> {
+    /// Construct a new pool with no connections established yet.
+    pub fn new(connector: C, registry: DcRegistry, home_dc: i32) -> DcPool<C> {
+        DcPool {
+            connector: connector,
+            registry: registry,
+            home_dc: home_dc,
+            connections: BTreeMap::new(),
+            authorized: BTreeSet::new(),
+        }
+    }
+
+    /// The datacenter where the user account resides.
+    pub fn home_dc(&self) -> i32 {
+        self.home_dc
+    }
+
+    pub fn registry(&self) -> &DcRegistry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut DcRegistry {
+        &mut self.registry
+    }
+
+    /// Mark the main connection to the home datacenter as authorized,
+    /// e.g. after successful sign in.
+    pub fn mark_home_authorized(&mut self) {
+        let home_dc = self.home_dc;
+        self.authorized.insert((home_dc, DcKind::Main));
+    }
+
+    /// Returns `true` if the connection to the datacenter has an
+    /// authorized user.
+    pub fn is_authorized(&self, dc_id: i32, kind: DcKind) -> bool {
+        self.authorized.contains(&(dc_id, kind))
+    }
+
+    /// Fetch `help.getConfig` from the home datacenter and refresh the
+    /// registry with it.
+    pub fn refresh_config(&mut self) -> error::Result<()> {
+        let config = self.call(schema::rpc::help::getConfig {})?;
+        self.registry.update_from_config(&config);
+
+        Ok(())
+    }
+
+    /// Get a connection to a datacenter, establishing it if needed.
+    pub fn connection(&mut self, dc_id: i32, kind: DcKind) -> error::Result<&mut C::Connection> {
+        if !self.connections.contains_key(&(dc_id, kind)) {
+            let connection = {
+                let endpoint = self.registry.select(dc_id, kind)
+                    .ok_or(error::Error::from(ErrorKind::NoDcEndpoint(dc_id)))?;
+
+                debug!("Connecting to DC {} ({:?}) through {:?}", dc_id, kind, endpoint);
+                self.connector.connect(endpoint, kind)?
+            };
+
+            self.connections.insert((dc_id, kind), connection);
+        }
+
+        Ok(self.connections.get_mut(&(dc_id, kind)).unwrap()) // Inserted above
+    }
+
+    /// Drop the connection to a datacenter.
+    pub fn disconnect(&mut self, dc_id: i32, kind: DcKind) -> Option<C::Connection> {
+        self.connections.remove(&(dc_id, kind))
+    }
+
+    /// Perform a call on the home datacenter.
+    ///
+    /// If the server asks to migrate to another datacenter, the home
+    /// datacenter is changed and the call is repeated there.
+    pub fn call<F>(&mut self, function: F) -> error::Result<F::Reply>
+        where F: Clone + RpcFunction + TLObject + fmt::Debug,
+              F::Reply: DeserializeOwned
+    {
+        for _ in 0..MAX_MIGRATIONS {
+            let home_dc = self.home_dc;
+            let result = self.connection(home_dc, DcKind::Main)?.call(function.clone());
+            let migration = result.as_ref().err().and_then(Migration::from_error);
+
+            match migration {
+                Some(Migration::Home(dc_id)) => {
+                    info!("Migrating home DC from {} to {}", home_dc, dc_id);
+                    self.home_dc = dc_id;
+                },
+                Some(Migration::File(dc_id)) => return self.call_on(dc_id, DcKind::Main, function),
+                None => return result,
+            }
+        }
+
+        bail!(ErrorKind::TooManyMigrations(self.home_dc))
+    }
+
+    /// Perform a call on a specific datacenter.
+    ///
+    /// Authorization is transferred from the home datacenter first if
+    /// the user is authorized there. `FILE_MIGRATE_X` errors are
+    /// followed by repeating the call on the datacenter `X`.
+    pub fn call_on<F>(&mut self, dc_id: i32, kind: DcKind, function: F) -> error::Result<F::Reply>
+        where F: Clone + RpcFunction + TLObject + fmt::Debug,
+              F::Reply: DeserializeOwned
+    {
+        let mut dc_id = dc_id;
+
+        for _ in 0..MAX_MIGRATIONS {
+            if kind != DcKind::Cdn {
+                self.ensure_authorized(dc_id, kind)?;
+            }
+
+            let result = self.connection(dc_id, kind)?.call(function.clone());
+            let migration = result.as_ref().err().and_then(Migration::from_error);
+
+            match migration {
+                Some(Migration::File(new_dc_id)) => {
+                    info!("Repeating call on DC {} instead of {}", new_dc_id, dc_id);
+                    dc_id = new_dc_id;
+                },
+                _ => return result,
+            }
+        }
+
+        bail!(ErrorKind::TooManyMigrations(dc_id))
+    }
+
+    /// Transfer authorization from the home datacenter to the `kind`
+    /// connection to `dc_id` with `auth.exportAuthorization` and
+    /// `auth.importAuthorization`.
+    ///
+    /// Does nothing if that connection is already authorized or if the
+    /// home datacenter isn't. Other connections to the home datacenter
+    /// share its authorization, since servers reject exporting it to
+    /// the same datacenter with `DC_ID_INVALID`.
+    pub fn ensure_authorized(&mut self, dc_id: i32, kind: DcKind) -> error::Result<()> {
+        let home_dc = self.home_dc;
+
+        if self.authorized.contains(&(dc_id, kind)) || !self.authorized.contains(&(home_dc, DcKind::Main)) {
+            return Ok(());
+        }
+
+        if dc_id == home_dc {
+            self.authorized.insert((dc_id, kind));
+            return Ok(());
+        }
+
+        let exported = self.connection(home_dc, DcKind::Main)?
+            .call(schema::rpc::auth::exportAuthorization { dc_id: dc_id })?;
+
+        self.connection(dc_id, kind)?
+            .call(schema::rpc::auth::importAuthorization {
+                id: exported.id,
+                bytes: exported.bytes,
+            })?;
+
+        debug!("Authorization exported from DC {} to DC {} ({:?})", home_dc, dc_id, kind);
+        self.authorized.insert((dc_id, kind));
+
+        Ok(())
+    }
+}
+
+impl<C: DcConnector> RpcCaller for DcPool<C> {
+    fn call<F>(&mut self, function: F) -> error::Result<F::Reply>
+        where F: Clone + RpcFunction + TLObject + fmt::Debug,
+              F::Reply: DeserializeOwned
+    {
+        DcPool::call(self, function)
+    }
+}
@@ -1,13 +1,20 @@
 //! Asymmetric-key operations and facilities around them.
 
+use std::cmp::Ordering;
 use std::fmt;
-
-use byteorder::{LittleEndian, ByteOrder};
-use openssl::{bn, hash, rsa};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{self, AtomicUsize, ATOMIC_USIZE_INIT};
+
+use base64;
+use byteorder::{BigEndian, LittleEndian, ByteOrder};
+use rand::{self, Rng};
 use serde_bytes::ByteBuf;
 use serde_mtproto;
 
-use error::{self, ErrorKind};
+use crypto;
+use error::{self, ErrorKind, ResultExt};
 use utils::safe_int_cast;
 
 use super::symm::AuthKey;
@@ -38,38 +45,34 @@ HwIDAQAB\n\
 
 impl<'a> RsaRawPublicKeyRef<'a> {
     pub fn read(&self) -> error::Result<RsaPublicKey> {
-        let key = rsa::Rsa::public_key_from_pem(&self.0)?;
-        Ok(RsaPublicKey(key))
+        RsaPublicKey::from_pem(self.0)
     }
 }
 
 
 /// "Cooked" RSA key.
-pub struct RsaPublicKey(rsa::Rsa);
+#[derive(Clone, Eq, PartialEq)]
+pub struct RsaPublicKey {
+    n: Vec<u8>,
+    e: Vec<u8>,
+}
 
 impl fmt::Debug for RsaPublicKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         struct RsaRepr<'a> {
-            n: Option<&'a bn::BigNumRef>,
-            e: Option<&'a bn::BigNumRef>,
+            n: &'a [u8],
+            e: &'a [u8],
         }
 
         impl<'a> fmt::Debug for RsaRepr<'a> {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                let debug_option_big_num = |opt_big_num: Option<&bn::BigNumRef>| {
-                    match opt_big_num {
-                        Some(big_num) => match big_num.to_hex_str() {
-                            Ok(hex_str) => hex_str.to_lowercase(),
-                            Err(_) => big_num.to_vec().iter()
-                                .map(|byte| format!("{:02x}", byte)).collect::<String>(),
-                        },
-                        None => "(None)".to_owned(),
-                    }
+                let to_hex = |big_num: &[u8]| {
+                    big_num.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()
                 };
 
                 f.debug_struct("RsaRepr")
-                    .field("n", &DisplayStr(&debug_option_big_num(self.n)))
-                    .field("e", &DisplayStr(&debug_option_big_num(self.e)))
+                    .field("n", &DisplayStr(&to_hex(self.n)))
+                    .field("e", &DisplayStr(&to_hex(self.e)))
                     .finish()
             }
         }
@@ -83,8 +86,8 @@ impl fmt::Debug for RsaPublicKey {
         }
 
         let rsa_repr = RsaRepr {
-            n: self.0.n(),
-            e: self.0.e(),
+            n: &self.n,
+            e: &self.e,
         };
 
         f.debug_tuple("RsaPublicKey")
@@ -95,20 +98,53 @@ impl fmt::Debug for RsaPublicKey {
 
 
 impl RsaPublicKey {
+    /// Construct a key from its big-endian modulus and exponent.
+    pub fn new(n: Vec<u8>, e: Vec<u8>) -> RsaPublicKey {
+        RsaPublicKey { n: n, e: e }
+    }
+
+    /// Read a key from a PEM string containing either a
+    /// SubjectPublicKeyInfo (`BEGIN PUBLIC KEY`) or a PKCS#1
+    /// RSAPublicKey (`BEGIN RSA PUBLIC KEY`).
+    pub fn from_pem(pem: &[u8]) -> error::Result<RsaPublicKey> {
+        RsaPublicKey::from_der(&pem_to_der(pem)?)
+    }
+
+    /// Read a key from DER-encoded SubjectPublicKeyInfo or PKCS#1
+    /// RSAPublicKey.
+    pub fn from_der(der: &[u8]) -> error::Result<RsaPublicKey> {
+        let (contents, _) = der_read(der, DER_SEQUENCE)?;
+
+        let rsa_public_key = if contents.first() == Some(&DER_SEQUENCE) {
+            // SubjectPublicKeyInfo: skip the algorithm identifier
+            let (_, rest) = der_read(contents, DER_SEQUENCE)?;
+            let (bit_string, _) = der_read(rest, DER_BIT_STRING)?;
+
+            // The first byte is the number of unused bits
+            match bit_string.split_first() {
+                Some((&0, rsa_public_key)) => der_read(rsa_public_key, DER_SEQUENCE)?.0,
+                _ => bail!(ErrorKind::MalformedRsaPublicKey),
+            }
+        } else {
+            contents
+        };
+
+        let (n, rest) = der_read(rsa_public_key, DER_INTEGER)
+            .chain_err(|| ErrorKind::NoModulus)?;
+        let (e, _) = der_read(rest, DER_INTEGER)
+            .chain_err(|| ErrorKind::NoExponent)?;
+
+        Ok(RsaPublicKey::new(crypto::strip_leading_zeros(n).to_vec(), crypto::strip_leading_zeros(e).to_vec()))
+    }
+
     pub fn sha1_fingerprint(&self) -> error::Result<Vec<u8>> {
         let mut buf = Vec::new();
 
-        let n_bytes = self.0.n().ok_or(error::Error::from(ErrorKind::NoModulus))?.to_vec();
-        let e_bytes = self.0.e().ok_or(error::Error::from(ErrorKind::NoExponent))?.to_vec();
-
         // Need to allocate new space, so use `&mut buf` instead of `buf.as_mut_slice()`
-        serde_mtproto::to_writer(&mut buf, &ByteBuf::from(n_bytes))?;
-        serde_mtproto::to_writer(&mut buf, &ByteBuf::from(e_bytes))?;
+        serde_mtproto::to_writer(&mut buf, &ByteBuf::from(self.n.clone()))?;
+        serde_mtproto::to_writer(&mut buf, &ByteBuf::from(self.e.clone()))?;
 
-        let mut hasher = hash::Hasher::new(hash::MessageDigest::sha1())?;
-        hasher.update(&buf)?;
-
-        Ok(hasher.finish2().map(|b| b.to_vec())?)
+        crypto::sha1(&[&buf])
     }
 
     pub fn fingerprint(&self) -> error::Result<i64> {
@@ -117,34 +153,381 @@ impl RsaPublicKey {
         Ok(LittleEndian::read_i64(&sha1_fingerprint[12..20]))
     }
 
-    /// Encrypts using the internal RSA key.
+    /// Encrypts using the internal RSA key with the legacy padding
+    /// scheme.
     pub fn encrypt(&self, input: &[u8]) -> error::Result<[u8; 256]> {
-        let mut padded_input = sha1_and_or_pad(input, true, Padding::Total255Random)?;
-        padded_input.insert(0, 0);    // OpenSSL requires exactly 256 bytes
-        debug!("Padded input: {:?}", &padded_input);
-
-        let mut output = [0; 256];
-        self.0.public_encrypt(&padded_input, &mut output, rsa::NO_PADDING)?;
-
-        Ok(output)
+        left_pad_256(&self.encrypt2(input)?)
     }
 
-    /// Other implementation of RSA encryption, just to verify that we are on the right track with
-    /// `encrypt()`. Also can be served as a drop-in replacement in case if we abandon OpenSSL
-    /// dependency after rewriting this method to use `num_bigint::BigUInt`.
+    /// Same as `encrypt()`, but the result is not padded with leading
+    /// zeros to 256 bytes.
     pub fn encrypt2(&self, input: &[u8]) -> error::Result<Vec<u8>> {
         let padded_input = sha1_and_or_pad(input, true, Padding::Total255Random)?;
         debug!("Padded input: {:?}", &padded_input);
 
-        let n = self.0.n().ok_or(error::Error::from(ErrorKind::NoModulus))?;
-        let e = self.0.e().ok_or(error::Error::from(ErrorKind::NoExponent))?;
+        crypto::mod_exp(&padded_input, &self.e, &self.n)
+    }
+
+    /// Encrypts using the internal RSA key with the specified padding
+    /// scheme.
+    pub fn encrypt_with(&self, scheme: RsaPaddingScheme, input: &[u8]) -> error::Result<[u8; 256]> {
+        match scheme {
+            RsaPaddingScheme::Legacy => self.encrypt(input),
+            RsaPaddingScheme::RsaPad => self.encrypt_rsa_pad(input),
+        }
+    }
+
+    /// Encrypts using the internal RSA key with the RSA_PAD scheme.
+    ///
+    /// See https://core.telegram.org/mtproto/auth_key#presenting-proof-of-work-server-authentication
+    /// for the description of the scheme.
+    pub fn encrypt_rsa_pad(&self, input: &[u8]) -> error::Result<[u8; 256]> {
+        if input.len() > RSA_PAD_MAX_DATA_LEN {
+            bail!(ErrorKind::RsaPadDataTooLong(RSA_PAD_MAX_DATA_LEN, input.len()));
+        }
+
+        // `key_aes_encrypted` is exactly 256 bytes long, so it can never
+        // be less than a shorter modulus
+        if self.n.len() != 256 {
+            bail!(ErrorKind::MalformedRsaPublicKey);
+        }
+
+        let mut rng = rand::thread_rng();
+
+        let mut data_with_padding = [0; RSA_PAD_DATA_WITH_PADDING_LEN];
+        data_with_padding[..input.len()].copy_from_slice(input);
+        rng.fill_bytes(&mut data_with_padding[input.len()..]);
 
-        let bn_padded_input = bn::BigNum::from_slice(&padded_input)?;
-        let mut output = bn::BigNum::new()?;
-        let mut context = bn::BigNumContext::new()?;
-        output.mod_exp(&bn_padded_input, e, n, &mut context)?;
+        let mut data_pad_reversed = data_with_padding;
+        data_pad_reversed.reverse();
 
-        Ok(output.to_vec())
+        loop {
+            let mut temp_key = [0; 32];
+            rng.fill_bytes(&mut temp_key);
+
+            let mut data_with_hash = data_pad_reversed.to_vec();
+            data_with_hash.extend(crypto::sha256(&[&temp_key, &data_with_padding])?);
+
+            let aes_encrypted = crypto::aes_ige_encrypt(&temp_key, &[0; 32], &data_with_hash)?;
+            let aes_encrypted_hash = crypto::sha256(&[&aes_encrypted])?;
+
+            let mut key_aes_encrypted = Vec::with_capacity(256);
+            key_aes_encrypted.extend(temp_key.iter().zip(&aes_encrypted_hash).map(|(a, b)| a ^ b));
+            key_aes_encrypted.extend(aes_encrypted);
+
+            if crypto::cmp_be(&key_aes_encrypted, &self.n) != Ordering::Less {
+                debug!("RSA_PAD: key_aes_encrypted is not less than modulus, retrying");
+                continue;
+            }
+
+            return left_pad_256(&crypto::mod_exp(&key_aes_encrypted, &self.e, &self.n)?);
+        }
+    }
+}
+
+
+/// RSA private key used on the server side to decrypt
+/// `P_Q_inner_data`.
+#[derive(Clone)]
+pub struct RsaPrivateKey {
+    public_key: RsaPublicKey,
+    d: Vec<u8>,
+}
+
+impl fmt::Debug for RsaPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Never print the private exponent
+        f.debug_struct("RsaPrivateKey")
+            .field("public_key", &self.public_key)
+            .finish()
+    }
+}
+
+impl RsaPrivateKey {
+    /// Construct a key from its big-endian modulus, public exponent
+    /// and private exponent.
+    pub fn new(n: Vec<u8>, e: Vec<u8>, d: Vec<u8>) -> RsaPrivateKey {
+        RsaPrivateKey {
+            public_key: RsaPublicKey::new(n, e),
+            d: d,
+        }
+    }
+
+    /// Read a key from a PEM string containing either a PKCS#1
+    /// RSAPrivateKey (`BEGIN RSA PRIVATE KEY`) or an unencrypted PKCS#8
+    /// PrivateKeyInfo (`BEGIN PRIVATE KEY`).
+    pub fn from_pem(pem: &[u8]) -> error::Result<RsaPrivateKey> {
+        RsaPrivateKey::from_der(&pem_to_der(pem)?)
+    }
+
+    /// Read a key from DER-encoded PKCS#1 RSAPrivateKey or unencrypted
+    /// PKCS#8 PrivateKeyInfo.
+    pub fn from_der(der: &[u8]) -> error::Result<RsaPrivateKey> {
+        let read_key = || -> error::Result<RsaPrivateKey> {
+            let (contents, _) = der_read(der, DER_SEQUENCE)?;
+            let (_version, rest) = der_read(contents, DER_INTEGER)?;
+
+            if rest.first() == Some(&DER_SEQUENCE) {
+                // PrivateKeyInfo: skip the algorithm identifier
+                let (_, rest) = der_read(rest, DER_SEQUENCE)?;
+                let (rsa_private_key, _) = der_read(rest, DER_OCTET_STRING)?;
+
+                return RsaPrivateKey::from_der(rsa_private_key);
+            }
+
+            let (n, rest) = der_read(rest, DER_INTEGER)?;
+            let (e, rest) = der_read(rest, DER_INTEGER)?;
+            let (d, _) = der_read(rest, DER_INTEGER)?;
+
+            Ok(RsaPrivateKey::new(crypto::strip_leading_zeros(n).to_vec(),
+                                  crypto::strip_leading_zeros(e).to_vec(),
+                                  crypto::strip_leading_zeros(d).to_vec()))
+        };
+
+        read_key().chain_err(|| ErrorKind::MalformedRsaPrivateKey)
+    }
+
+    pub fn public_key(&self) -> &RsaPublicKey {
+        &self.public_key
+    }
+
+    /// Decrypts a 256-byte block without removing any padding.
+    pub fn decrypt(&self, input: &[u8]) -> error::Result<[u8; 256]> {
+        if input.len() != 256 || crypto::cmp_be(input, &self.public_key.n) != Ordering::Less {
+            bail!(ErrorKind::RsaDecryptionFailure);
+        }
+
+        left_pad_256(&crypto::mod_exp(input, &self.d, &self.public_key.n)?)
+    }
+
+    /// Decrypts data encrypted with the legacy padding scheme.
+    ///
+    /// Returns SHA-1 of the data followed by the data and random
+    /// padding (255 bytes in total). The length of the data isn't
+    /// stored anywhere, so checking the hash is left to the caller.
+    pub fn decrypt_legacy(&self, input: &[u8]) -> error::Result<Vec<u8>> {
+        let decrypted = self.decrypt(input)?;
+
+        if decrypted[0] != 0 {
+            bail!(ErrorKind::RsaDecryptionFailure);
+        }
+
+        Ok(decrypted[1..].to_vec())
+    }
+
+    /// Decrypts data encrypted with the RSA_PAD scheme.
+    ///
+    /// Returns the data with random padding (192 bytes in total).
+    pub fn decrypt_rsa_pad(&self, input: &[u8]) -> error::Result<Vec<u8>> {
+        let key_aes_encrypted = self.decrypt(input)?;
+        let (temp_key_xor, aes_encrypted) = key_aes_encrypted.split_at(32);
+
+        let aes_encrypted_hash = crypto::sha256(&[aes_encrypted])?;
+        let mut temp_key = [0; 32];
+        for (i, byte) in temp_key.iter_mut().enumerate() {
+            *byte = temp_key_xor[i] ^ aes_encrypted_hash[i];
+        }
+
+        let data_with_hash = crypto::aes_ige_decrypt(&temp_key, &[0; 32], aes_encrypted)?;
+        let (data_pad_reversed, hash) = data_with_hash.split_at(RSA_PAD_DATA_WITH_PADDING_LEN);
+        let data_with_padding = data_pad_reversed.iter().rev().cloned().collect::<Vec<u8>>();
+
+        if &crypto::sha256(&[&temp_key, &data_with_padding])?[..] != hash {
+            bail!(ErrorKind::RsaDecryptionFailure);
+        }
+
+        Ok(data_with_padding)
+    }
+}
+
+
+/// Padding scheme used to encrypt `P_Q_inner_data` for `req_DH_params`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RsaPaddingScheme {
+    /// SHA-1 of data followed by data and random padding up to 255
+    /// bytes.
+    Legacy,
+    /// RSA_PAD: data padded to 192 bytes, reversed, hashed and
+    /// encrypted with a random temporary AES key.
+    RsaPad,
+}
+
+/// Maximum length of data which can be encrypted with RSA_PAD.
+pub const RSA_PAD_MAX_DATA_LEN: usize = 144;
+const RSA_PAD_DATA_WITH_PADDING_LEN: usize = 192;
+
+/// Restore leading zeros stripped by modular exponentiation.
+fn left_pad_256(encrypted: &[u8]) -> error::Result<[u8; 256]> {
+    if encrypted.len() > 256 {
+        bail!(ErrorKind::MalformedRsaPublicKey);
+    }
+
+    let mut output = [0; 256];
+    output[256 - encrypted.len()..].copy_from_slice(encrypted);
+
+    Ok(output)
+}
+
+
+const DER_INTEGER: u8 = 0x02;
+const DER_BIT_STRING: u8 = 0x03;
+const DER_OCTET_STRING: u8 = 0x04;
+const DER_SEQUENCE: u8 = 0x30;
+
+/// Extract DER contents of a PEM string.
+fn pem_to_der(pem: &[u8]) -> error::Result<Vec<u8>> {
+    let pem = String::from_utf8(pem.to_vec())?;
+    let base64_body = pem.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect::<String>();
+
+    Ok(base64::decode(&base64_body)?)
+}
+
+/// Read a single DER element with the given tag.
+///
+/// Returns contents of the element and the remaining input.
+fn der_read(input: &[u8], tag: u8) -> error::Result<(&[u8], &[u8])> {
+    if input.len() < 2 || input[0] != tag {
+        bail!(ErrorKind::MalformedRsaPublicKey);
+    }
+
+    let (len, header_len) = match input[1] {
+        len @ 0x00...0x7f => (len as usize, 2),
+        len_of_len @ 0x81...0x84 => {
+            let len_of_len = (len_of_len & 0x7f) as usize;
+            if input.len() < 2 + len_of_len {
+                bail!(ErrorKind::MalformedRsaPublicKey);
+            }
+
+            let len = input[2..2 + len_of_len].iter().fold(0, |len, &byte| (len << 8) | byte as usize);
+            (len, 2 + len_of_len)
+        },
+        _ => bail!(ErrorKind::MalformedRsaPublicKey),
+    };
+
+    if input.len() - header_len < len {
+        bail!(ErrorKind::MalformedRsaPublicKey);
+    }
+
+    Ok((&input[header_len..header_len + len], &input[header_len + len..]))
+}
+
+/// Set of RSA public keys used to select a key during the key exchange.
+///
+/// Fingerprints are computed once when a key is added. A registry
+/// created with `new()` contains keys from `KNOWN_RAW_KEYS`; use
+/// `empty()` to talk to servers which don't accept any of them (test
+/// DCs or private MTProto servers).
+#[derive(Clone, Debug)]
+pub struct RsaKeyRegistry {
+    keys: Vec<RsaPublicKey>,
+    fingerprints: Vec<i64>,
+}
+
+impl RsaKeyRegistry {
+    /// Create a registry with built-in keys.
+    pub fn new() -> error::Result<RsaKeyRegistry> {
+        let mut registry = RsaKeyRegistry::empty();
+        for raw_key in KNOWN_RAW_KEYS {
+            registry.add(raw_key.read()?)?;
+        }
+
+        Ok(registry)
+    }
+
+    /// Create a registry without any keys.
+    pub fn empty() -> RsaKeyRegistry {
+        RsaKeyRegistry {
+            keys: Vec::new(),
+            fingerprints: Vec::new(),
+        }
+    }
+
+    /// Add a key and return its fingerprint.
+    ///
+    /// A key with the same fingerprint is replaced.
+    pub fn add(&mut self, key: RsaPublicKey) -> error::Result<i64> {
+        let fingerprint = key.fingerprint()?;
+
+        match self.fingerprints.iter().position(|&f| f == fingerprint) {
+            Some(index) => self.keys[index] = key,
+            None => {
+                self.keys.push(key);
+                self.fingerprints.push(fingerprint);
+            },
+        }
+
+        Ok(fingerprint)
+    }
+
+    /// Add a key from a PEM string.
+    pub fn add_pem(&mut self, pem: &[u8]) -> error::Result<i64> {
+        self.add(RsaPublicKey::from_pem(pem)?)
+    }
+
+    /// Add a key from a PEM file.
+    pub fn add_pem_file<P: AsRef<Path>>(&mut self, path: P) -> error::Result<i64> {
+        let mut pem = Vec::new();
+        File::open(path)?.read_to_end(&mut pem)?;
+
+        self.add_pem(&pem)
+    }
+
+    /// Add a key from its big-endian modulus and exponent.
+    pub fn add_raw(&mut self, n: Vec<u8>, e: Vec<u8>) -> error::Result<i64> {
+        self.add(RsaPublicKey::new(n, e))
+    }
+
+    /// Remove a key by its fingerprint.
+    pub fn remove(&mut self, fingerprint: i64) -> Option<RsaPublicKey> {
+        self.fingerprints.iter().position(|&f| f == fingerprint).map(|index| {
+            self.fingerprints.remove(index);
+            self.keys.remove(index)
+        })
+    }
+
+    /// Get a key by its fingerprint.
+    pub fn get(&self, fingerprint: i64) -> Option<&RsaPublicKey> {
+        self.fingerprints.iter().position(|&f| f == fingerprint).map(|index| &self.keys[index])
+    }
+
+    /// Fingerprints of all keys in the order they were added.
+    pub fn fingerprints(&self) -> &[i64] {
+        &self.fingerprints
+    }
+
+    /// All keys in the order they were added.
+    pub fn keys(&self) -> &[RsaPublicKey] {
+        &self.keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Find the first key (in the order they were added) fingerprint
+    /// of which can be found in the supplied sequence of fingerprints.
+    pub fn find_first(&self, of_fingerprints: &[i64]) -> Option<(&RsaPublicKey, i64)> {
+        self.keys.iter()
+            .zip(self.fingerprints.iter())
+            .find(|&(_, fingerprint)| of_fingerprints.contains(fingerprint))
+            .map(|(key, &fingerprint)| (key, fingerprint))
+    }
+
+    /// Flat version of `find_first` method.
+    ///
+    /// Upon failure to find a suitable key, it returns an error rather
+    /// than `None`.
+    pub fn find_first_fail_safe(&self, of_fingerprints: &[i64]) -> error::Result<(RsaPublicKey, i64)> {
+        self.find_first(of_fingerprints)
+            .map(|(key, fingerprint)| (key.clone(), fingerprint))
+            .ok_or(ErrorKind::NoRsaPublicKeyForFingerprints(of_fingerprints.to_vec()).into())
     }
 }
 
@@ -153,49 +536,65 @@ impl RsaPublicKey {
 /// Upon failure to find a suitable key, it returns an error rather than
 /// `None`.
 pub fn find_first_key_fail_safe(of_fingerprints: &[i64]) -> error::Result<(RsaPublicKey, i64)> {
-    find_first_key(of_fingerprints)?
-        .ok_or(ErrorKind::NoRsaPublicKeyForFingerprints(of_fingerprints.to_vec()).into())
+    builtin_registry()?.find_first_fail_safe(of_fingerprints)
 }
 
-/// Find a key fingerprint of which can be found in the supplied
-/// sequence of fingerprints.
+/// Find a key among built-in ones fingerprint of which can be found in
+/// the supplied sequence of fingerprints.
+///
+/// Use `RsaKeyRegistry` to select from a custom set of keys.
 pub fn find_first_key(of_fingerprints: &[i64]) -> error::Result<Option<(RsaPublicKey, i64)>> {
-    for raw_key in KNOWN_RAW_KEYS {
-        let key = raw_key.read()?;
-        let fingerprint = key.fingerprint()?;
+    let registry = builtin_registry()?;
+
+    Ok(registry.find_first(of_fingerprints).map(|(key, fingerprint)| (key.clone(), fingerprint)))
+}
 
-        if of_fingerprints.contains(&fingerprint) {
-            return Ok(Some((key, fingerprint)));
+/// Registry with built-in keys shared by `find_first_key` and
+/// `find_first_key_fail_safe`.
+///
+/// It's created on first successful use and lives until the program
+/// exits.
+fn builtin_registry() -> error::Result<&'static RsaKeyRegistry> {
+    static REGISTRY: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    let mut registry = REGISTRY.load(atomic::Ordering::Acquire) as *const RsaKeyRegistry;
+    if registry.is_null() {
+        let created = Box::into_raw(Box::new(RsaKeyRegistry::new()?));
+
+        // Another thread may have been creating it at the same time
+        match REGISTRY.compare_exchange(0, created as usize, atomic::Ordering::AcqRel, atomic::Ordering::Acquire) {
+            Ok(_) => registry = created,
+            Err(existing) => {
+                drop(unsafe { Box::from_raw(created) });
+                registry = existing as *const RsaKeyRegistry;
+            },
         }
     }
 
-    Ok(None)
+    // Once stored, the registry is never modified nor freed
+    Ok(unsafe { &*registry })
 }
 
 pub fn calculate_auth_key(g: u32, dh_prime: &[u8], g_a: &[u8]) -> error::Result<(AuthKey, Vec<u8>)> {
-    let mut ctx = bn::BigNumContext::new()?;
-    let g = bn::BigNum::from_u32(g)?;
-    let dh_prime = bn::BigNum::from_slice(dh_prime)?;
-    let g_a = bn::BigNum::from_slice(g_a)?;
+    let mut g_bytes = [0; 4];
+    BigEndian::write_u32(&mut g_bytes, g);
 
     loop {
-        let mut b = bn::BigNum::new()?;
-        b.rand(2048, bn::MSB_MAYBE_ZERO, false)?;
-        let mut g_b = bn::BigNum::new()?;
-        g_b.mod_exp(&g, &b, &dh_prime, &mut ctx)?;
-        // .num_bytes() returns i32 and AUTH_KEY_SIZE is usize, so use u64 since it embraces
-        // both i32 and usize (until 128-bit machines are in the wild)
-        if g_b.num_bytes() as u64 != super::AUTH_KEY_SIZE as u64 || g_b >= dh_prime {
+        let mut b = [0; 256];
+        rand::thread_rng().fill_bytes(&mut b);
+
+        let g_b = crypto::mod_exp(&g_bytes, &b, dh_prime)?;
+        if g_b.len() != super::AUTH_KEY_SIZE || crypto::cmp_be(&g_b, dh_prime) != Ordering::Less {
             continue;
         }
-        let mut auth_key = bn::BigNum::new()?;
-        auth_key.mod_exp(&g_a, &b, &dh_prime, &mut ctx)?;
-        // Same here
-        if auth_key.num_bytes() as u64 != super::AUTH_KEY_SIZE as u64 {
+
+        let auth_key = crypto::mod_exp(g_a, &b, dh_prime)?;
+        if auth_key.len() != super::AUTH_KEY_SIZE {
             continue;
         }
-        let auth_key = AuthKey::new(&auth_key.to_vec())?;
-        return Ok((auth_key, g_b.to_vec()));
+
+        let auth_key = AuthKey::new(&auth_key)?;
+        return Ok((auth_key, g_b));
     }
 }
 
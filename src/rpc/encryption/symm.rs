@@ -4,10 +4,9 @@ use std::fmt;
 
 use byteorder::{ByteOrder, LittleEndian};
 use extprim::i128::i128;
-use openssl::{aes, symm};
 
+use crypto;
 use error::{self, ErrorKind};
-use rpc::utils::sha1_bytes;
 
 use super::AUTH_KEY_SIZE;
 use super::utils::{Padding, sha1_and_or_pad, set_slice_parts};
@@ -22,49 +21,55 @@ pub struct AesParams {
 impl AesParams {
     pub fn ige_encrypt(self, decrypted: &[u8], prepend_sha1: bool) -> error::Result<Vec<u8>> {
         let input = sha1_and_or_pad(decrypted, prepend_sha1, Padding::Mod16)?;
-        self.run_ige(&input, symm::Mode::Encrypt)
+        crypto::aes_ige_encrypt(&self.key, &self.iv, &input)
     }
 
     pub fn ige_decrypt(self, encrypted: &[u8]) -> error::Result<Vec<u8>> {
-        self.run_ige(encrypted, symm::Mode::Decrypt)
+        crypto::aes_ige_decrypt(&self.key, &self.iv, encrypted)
     }
 
-    fn run_ige(mut self, input: &[u8], mode: symm::Mode) -> error::Result<Vec<u8>> {
-        let key = match mode {
-            // self.key is 256-bit, so can unwrap here
-            symm::Mode::Encrypt => aes::AesKey::new_encrypt(&self.key).unwrap(),
-            symm::Mode::Decrypt => aes::AesKey::new_decrypt(&self.key).unwrap(),
-        };
-
-        let mut output = vec![0; input.len()];
+    /// Derive temporary AES parameters used during the key exchange
+    /// from serialized `new_nonce` (32 bytes) and `server_nonce` (16
+    /// bytes).
+    pub fn from_nonces(new_nonce: &[u8], server_nonce: &[u8]) -> error::Result<AesParams> {
+        let sha1_a = crypto::sha1(&[new_nonce, server_nonce])?;
+        let sha1_b = crypto::sha1(&[server_nonce, new_nonce])?;
+        let sha1_c = crypto::sha1(&[new_nonce, new_nonce])?;
 
-        // Must not panic because:
-        // - input.len() == output.len() by declaration of output
-        // - input.len() % 16 == 0
-        // - iv.len() == 32 >= 32
-        aes::aes_ige(input, &mut output, &key, &mut self.iv, mode);
+        let mut ret: AesParams = Default::default();
+        set_slice_parts(&mut ret.key, &[&sha1_a[..], &sha1_b[..12]]);
+        set_slice_parts(&mut ret.iv, &[&sha1_b[12..], &sha1_c[..], &new_nonce[..4]]);
 
-        Ok(output)
+        Ok(ret)
     }
+}
 
-    // TODO: uncomment after getting rpc functions working
-    /*pub fn from_pq_inner_data(data: &P_Q_inner_data) -> Result<AesParams> {
-        let new_nonce = data.new_nonce();
-        let server_nonce = data.server_nonce();
-
-        let sha1_a = sha1_nonces(&[new_nonce.0, new_nonce.1, server_nonce])?;
-        let sha1_b = sha1_nonces(&[server_nonce, new_nonce.0, new_nonce.1])?;
-        let sha1_c = sha1_nonces(&[new_nonce.0, new_nonce.1, new_nonce.0, new_nonce.1])?;
 
-        let mut tmp = [0u8; 8];
-        LittleEndian::write_i64(&mut tmp, (new_nonce.0).0);
+/// The side which has produced an encrypted message.
+///
+/// Message keys are derived from different parts of the authorization
+/// key depending on the direction of a message. Secret chats use
+/// `Client` in both directions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageOrigin {
+    Client,
+    Server,
+}
 
-        let mut ret: AesParams = Default::default();
-        set_slice_parts(&mut ret.key, &[&sha1_a, &sha1_b[..12]]);
-        set_slice_parts(&mut ret.iv, &[&sha1_b[12..], &sha1_c, &tmp[..4]]);
+impl MessageOrigin {
+    /// The other side of a connection.
+    pub fn peer(self) -> MessageOrigin {
+        match self {
+            MessageOrigin::Client => MessageOrigin::Server,
+            MessageOrigin::Server => MessageOrigin::Client,
+        }
+    }
+}
 
-        Ok(ret)
-    }*/
+impl Default for MessageOrigin {
+    fn default() -> MessageOrigin {
+        MessageOrigin::Client
+    }
 }
 
 
@@ -135,7 +140,7 @@ impl AuthKey {
             bail!(ErrorKind::AuthKeyTooLong(AUTH_KEY_SIZE, key_in.to_vec()));
         }
 
-        let sha1 = sha1_bytes(&[&key])?;
+        let sha1 = crypto::sha1(&[&key])?;
         let aux_hash = LittleEndian::read_i64(&sha1[0..8]);
         let fingerprint = LittleEndian::read_i64(&sha1[12..20]);
 
@@ -152,9 +157,19 @@ impl AuthKey {
     /// Returns an authorization key ID, message key and encrypted data,
     /// respectively.
     pub fn encrypt_message_bytes(&self, message_bytes: &[u8]) -> error::Result<(i64, i128, Vec<u8>)> {
+        self.encrypt_message_bytes_from(MessageOrigin::Client, message_bytes)
+    }
+
+    /// Encrypts a sequence of bytes sent by `origin`.
+    ///
+    /// Returns an authorization key ID, message key and encrypted data,
+    /// respectively.
+    pub fn encrypt_message_bytes_from(&self, origin: MessageOrigin, message_bytes: &[u8])
+        -> error::Result<(i64, i128, Vec<u8>)>
+    {
         let auth_key_id = self.fingerprint;
 
-        let message_hash = sha1_bytes(&[message_bytes])?;
+        let message_hash = crypto::sha1(&[message_bytes])?;
         let mut message_key_bytes = [0; 16];
         message_key_bytes.copy_from_slice(&message_hash[4..20]);
 
@@ -162,7 +177,7 @@ impl AuthKey {
         let message_key_hi = LittleEndian::read_i64(&message_key_bytes[8..16]);
         let message_key = i128::from_parts(message_key_hi, message_key_lo);
 
-        let aes = self.generate_message_aes_params(message_key, symm::Mode::Encrypt)?;
+        let aes = self.generate_message_aes_params(message_key, origin)?;
         let encrypted_data = aes.ige_encrypt(message_bytes, false)?;
 
         Ok((auth_key_id, message_key, encrypted_data))
@@ -174,22 +189,43 @@ impl AuthKey {
                                  message_key: i128,
                                  message_bytes: &[u8])
                                 -> error::Result<Vec<u8>> {
+        self.decrypt_message_bytes_from(MessageOrigin::Server, auth_key_id, message_key, message_bytes)
+    }
+
+    /// Decrypts a sequence of bytes sent by `origin` and returns
+    /// decrypted raw data.
+    pub fn decrypt_message_bytes_from(&self,
+                                      origin: MessageOrigin,
+                                      auth_key_id: i64,
+                                      message_key: i128,
+                                      message_bytes: &[u8])
+                                     -> error::Result<Vec<u8>> {
         if auth_key_id != self.fingerprint {
             bail!(ErrorKind::WrongFingerprint(self.fingerprint, auth_key_id));
         }
 
-        let aes = self.generate_message_aes_params(message_key, symm::Mode::Decrypt)?;
+        let aes = self.generate_message_aes_params(message_key, origin)?;
         aes.ige_decrypt(message_bytes)
     }
 
-    fn generate_message_aes_params(&self, msg_key: i128, mode: symm::Mode) -> error::Result<AesParams> {
+    /// The 64 lower-order bits of SHA1 of the key.
+    pub fn fingerprint(&self) -> i64 {
+        self.fingerprint
+    }
+
+    /// The 64 higher-order bits of SHA1 of the key.
+    pub fn aux_hash(&self) -> i64 {
+        self.aux_hash
+    }
+
+    fn generate_message_aes_params(&self, msg_key: i128, origin: MessageOrigin) -> error::Result<AesParams> {
         let mut msg_key_bytes = [0; 16];
         LittleEndian::write_u64(&mut msg_key_bytes[0..8], msg_key.low64());
         LittleEndian::write_i64(&mut msg_key_bytes[8..16], msg_key.high64());
 
-        let mut pos = match mode {
-            symm::Mode::Encrypt => 0,
-            symm::Mode::Decrypt => 8,
+        let mut pos = match origin {
+            MessageOrigin::Client => 0,
+            MessageOrigin::Server => 8,
         };
 
         let mut auth_key_take = |len| {
@@ -198,10 +234,10 @@ impl AuthKey {
             ret
         };
 
-        let sha1_a = sha1_bytes(&[&msg_key_bytes, auth_key_take(32)])?;
-        let sha1_b = sha1_bytes(&[auth_key_take(16), &msg_key_bytes, auth_key_take(16)])?;
-        let sha1_c = sha1_bytes(&[auth_key_take(32), &msg_key_bytes])?;
-        let sha1_d = sha1_bytes(&[&msg_key_bytes, auth_key_take(32)])?;
+        let sha1_a = crypto::sha1(&[&msg_key_bytes, auth_key_take(32)])?;
+        let sha1_b = crypto::sha1(&[auth_key_take(16), &msg_key_bytes, auth_key_take(16)])?;
+        let sha1_c = crypto::sha1(&[auth_key_take(32), &msg_key_bytes])?;
+        let sha1_d = crypto::sha1(&[&msg_key_bytes, auth_key_take(32)])?;
 
         let mut ret: AesParams = Default::default();
         set_slice_parts(&mut ret.key, &[&sha1_a[0..8], &sha1_b[8..20], &sha1_c[4..16]]);
@@ -2,7 +2,7 @@ use std::io::{Cursor, Write};
 
 use error::{self, ErrorKind};
 use rand::{self, Rng};
-use rpc::utils::sha1_bytes;
+use crypto;
 
 
 pub(super) enum Padding {
@@ -12,7 +12,7 @@ pub(super) enum Padding {
 
 pub(super) fn sha1_and_or_pad(input: &[u8], prepend_sha1: bool, padding: Padding) -> error::Result<Vec<u8>> {
     let mut result = if prepend_sha1 {
-        sha1_bytes(&[input])?
+        crypto::sha1(&[input])?
     } else {
         vec![]
     };
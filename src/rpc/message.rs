@@ -10,7 +10,7 @@ use serde_mtproto::{self, Boxed, Identifiable, MtProtoSized, WithSize, UnsizedBy
 
 use error::{self, ErrorKind};
 
-use super::encryption::AuthKey;
+use super::encryption::{AuthKey, MessageOrigin};
 use super::utils::EitherRef;
 
 
@@ -52,6 +52,10 @@ pub struct DecryptedData<T> {
     #[serde(skip)]
     #[mtproto_sized(skip)]
     pub(super) key: AuthKey,
+    /// The side which encrypts (or has encrypted) this data.
+    #[serde(skip)]
+    #[mtproto_sized(skip)]
+    pub(super) origin: MessageOrigin,
 }
 
 #[derive(Debug, Serialize)]
@@ -98,6 +102,14 @@ impl<T: MtProtoSized> MtProtoSized for Message<T> {
 
 
 impl<T: Identifiable + MtProtoSized> Message<T> {
+    /// The id of the message, either plain-text or encrypted.
+    pub fn message_id(&self) -> i64 {
+        match *self {
+            Message::PlainText { message_id, .. } => message_id,
+            Message::Decrypted { ref decrypted_data } => decrypted_data.message_id,
+        }
+    }
+
     /// Returns `Some(body)` if the message was plain-text.
     /// Otherwise returns `None`.
     pub fn into_plain_text_body(self) -> Option<T> {
@@ -152,7 +164,7 @@ impl<T> Message<T> {
                 debug!("Serialized data to be encrypted: {:?}", &decrypted_data_serialized);
 
                 let (auth_key_id, msg_key, encrypted_data) = decrypted_data.key
-                    .encrypt_message_bytes(&decrypted_data_serialized)?;
+                    .encrypt_message_bytes_from(decrypted_data.origin, &decrypted_data_serialized)?;
 
                 RawMessage::Encrypted {
                     auth_key_id: auth_key_id,
@@ -168,7 +180,8 @@ impl<T> Message<T> {
     }
 
     fn from_raw_message<'msg>(raw_message: RawMessage<'msg, T>,
-                              opt_key: Option<AuthKey>)
+                              opt_key: Option<AuthKey>,
+                              origin: MessageOrigin)
                              -> error::Result<Message<T>>
         where T: fmt::Debug + DeserializeOwned
     {
@@ -184,13 +197,14 @@ impl<T> Message<T> {
             RawMessage::Encrypted { auth_key_id, msg_key, encrypted_data } => {
                 let key = opt_key.ok_or(ErrorKind::NoAuthKey)?;
                 let decrypted_data_serialized = key
-                    .decrypt_message_bytes(auth_key_id, msg_key, &encrypted_data.into_inner())?;
+                    .decrypt_message_bytes_from(origin, auth_key_id, msg_key, &encrypted_data.into_inner())?;
                 debug!("Decrypted data to be deserialized: {:?}", &decrypted_data_serialized);
 
                 let mut decrypted_data: DecryptedData<T> =
                     serde_mtproto::from_reader(decrypted_data_serialized.as_slice(), None)?;
 
                 decrypted_data.key = key;
+                decrypted_data.origin = origin;
 
                 Message::Decrypted {
                     decrypted_data: decrypted_data,
@@ -220,14 +234,25 @@ impl<T: fmt::Debug + Serialize> Serialize for Message<T> {
 pub struct MessageSeed<T> {
     opt_key: Option<AuthKey>,
     encrypted_data_len: Option<u32>,
+    origin: MessageOrigin,
     phantom: PhantomData<T>,
 }
 
 impl<T: DeserializeOwned> MessageSeed<T> {
+    /// Create a seed for messages sent by the server.
     pub fn new(opt_key: Option<AuthKey>, encrypted_data_len: Option<u32>) -> MessageSeed<T> {
+        MessageSeed::new_from(MessageOrigin::Server, opt_key, encrypted_data_len)
+    }
+
+    /// Create a seed for messages sent by `origin`.
+    pub fn new_from(origin: MessageOrigin,
+                    opt_key: Option<AuthKey>,
+                    encrypted_data_len: Option<u32>)
+                   -> MessageSeed<T> {
         MessageSeed {
             opt_key: opt_key,
             encrypted_data_len: encrypted_data_len,
+            origin: origin,
             phantom: PhantomData,
         }
     }
@@ -242,6 +267,7 @@ impl<'de, T: fmt::Debug + DeserializeOwned> DeserializeSeed<'de> for MessageSeed
         struct MessageVisitor<T> {
             opt_key: Option<AuthKey>,
             encrypted_data_len: Option<u32>,
+            origin: MessageOrigin,
             phantom: PhantomData<T>,
         }
 
@@ -293,7 +319,7 @@ impl<'de, T: fmt::Debug + DeserializeOwned> DeserializeSeed<'de> for MessageSeed
                     raw_message
                 };
 
-                let message = Message::from_raw_message(raw_message, self.opt_key)
+                let message = Message::from_raw_message(raw_message, self.opt_key, self.origin)
                     .map_err(A::Error::custom)?;
 
                 Ok(message)
@@ -303,6 +329,7 @@ impl<'de, T: fmt::Debug + DeserializeOwned> DeserializeSeed<'de> for MessageSeed
         let visitor = MessageVisitor {
             opt_key: self.opt_key,
             encrypted_data_len: self.encrypted_data_len,
+            origin: self.origin,
             phantom: PhantomData,
         };
 
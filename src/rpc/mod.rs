@@ -1,5 +1,6 @@
 //! RPC essentials.
 
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
@@ -8,6 +9,7 @@ use chrono::{DateTime, TimeZone, Utc};
 use envy;
 use erased_serde::Serialize as ErasedSerialize;
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use toml;
 
 use error;
@@ -15,12 +17,19 @@ use schema::FutureSalt;
 use tl::dynamic::TLObject;
 
 
+pub mod dc;
 pub mod encryption;
 pub mod message;
+#[cfg(feature = "mock-server")]
+pub mod mock;
+pub mod ordered;
+pub mod server;
 pub mod session;
 mod utils;
 
+pub use self::dc::{DcConnector, DcEndpoint, DcKind, DcPool, DcRegistry, Migration};
 pub use self::message::{Message, MessageType};
+pub use self::ordered::Ordered;
 pub use self::session::Session;
 
 
@@ -28,6 +37,18 @@ pub trait RpcFunction: ErasedSerialize {
     type Reply: TLObject + 'static;
 }
 
+/// Anything capable of invoking RPC functions on a single datacenter
+/// and waiting for their replies.
+pub trait RpcCaller {
+    /// Send `function` to the server and return its reply.
+    ///
+    /// Errors reported by the server with `rpc_error` must be returned
+    /// as `ErrorKind::RpcError`.
+    fn call<F>(&mut self, function: F) -> error::Result<F::Reply>
+        where F: Clone + RpcFunction + TLObject + fmt::Debug,
+              F::Reply: DeserializeOwned;
+}
+
 
 /// Telegram application information required for authorization.
 ///
@@ -0,0 +1,109 @@
+//! Ordered execution of RPC queries.
+//!
+//! MTProto provides `invokeAfterMsg` and `invokeAfterMsgs` wrappers
+//! which instruct the server to run the wrapped query only after the
+//! messages with given ids have been processed. This module builds on
+//! them to allow chaining requests in a guaranteed order.
+
+use serde::ser::{self, Serialize};
+use serde_mtproto::{self, Boxed, Identifiable, MtProtoSized};
+
+use schema::rpc::{invokeAfterMsg, invokeAfterMsgs};
+
+use super::RpcFunction;
+
+
+/// Wrap `query` so that it is executed after the message with
+/// `msg_id` id.
+pub fn invoke_after_msg<T>(msg_id: i64, query: T) -> invokeAfterMsg<T> {
+    invokeAfterMsg {
+        msg_id: msg_id,
+        query: query,
+    }
+}
+
+/// Wrap `query` so that it is executed after all messages with ids
+/// found in `msg_ids`.
+pub fn invoke_after_msgs<T>(msg_ids: Vec<i64>, query: T) -> invokeAfterMsgs<T> {
+    invokeAfterMsgs {
+        msg_ids: Boxed::new(msg_ids),
+        query: query,
+    }
+}
+
+
+/// A query which is either sent as-is or wrapped to be executed after
+/// preceding messages.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ordered<T> {
+    /// The query doesn't depend on any other message.
+    Standalone(T),
+    /// The query is executed after a single preceding message.
+    AfterMsg(invokeAfterMsg<T>),
+    /// The query is executed after several preceding messages.
+    AfterMsgs(invokeAfterMsgs<T>),
+}
+
+impl<T> Ordered<T> {
+    /// Construct an `Ordered` query which runs after `msg_id` if it's
+    /// present or standalone otherwise.
+    pub fn new(prev_msg_id: Option<i64>, query: T) -> Ordered<T> {
+        match prev_msg_id {
+            Some(msg_id) => Ordered::AfterMsg(invoke_after_msg(msg_id, query)),
+            None => Ordered::Standalone(query),
+        }
+    }
+
+    /// Returns the wrapped query.
+    pub fn into_query(self) -> T {
+        match self {
+            Ordered::Standalone(query) => query,
+            Ordered::AfterMsg(wrapper) => wrapper.query,
+            Ordered::AfterMsgs(wrapper) => wrapper.query,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Ordered<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: ser::Serializer
+    {
+        match *self {
+            Ordered::Standalone(ref query) => query.serialize(serializer),
+            Ordered::AfterMsg(ref wrapper) => wrapper.serialize(serializer),
+            Ordered::AfterMsgs(ref wrapper) => wrapper.serialize(serializer),
+        }
+    }
+}
+
+impl<T: Identifiable> Identifiable for Ordered<T> {
+    fn type_id(&self) -> u32 {
+        match *self {
+            Ordered::Standalone(ref query) => query.type_id(),
+            Ordered::AfterMsg(ref wrapper) => wrapper.type_id(),
+            Ordered::AfterMsgs(ref wrapper) => wrapper.type_id(),
+        }
+    }
+
+    fn enum_variant_id(&self) -> Option<&'static str> {
+        match *self {
+            Ordered::Standalone(ref query) => query.enum_variant_id(),
+            Ordered::AfterMsg(ref wrapper) => wrapper.enum_variant_id(),
+            Ordered::AfterMsgs(ref wrapper) => wrapper.enum_variant_id(),
+        }
+    }
+}
+
+impl<T: MtProtoSized> MtProtoSized for Ordered<T> {
+    fn size_hint(&self) -> serde_mtproto::Result<usize> {
+        match *self {
+            Ordered::Standalone(ref query) => query.size_hint(),
+            Ordered::AfterMsg(ref wrapper) => wrapper.size_hint(),
+            Ordered::AfterMsgs(ref wrapper) => wrapper.size_hint(),
+        }
+    }
+}
+
+impl<T: RpcFunction + Serialize> RpcFunction for Ordered<T> {
+    type Reply = T::Reply;
+}
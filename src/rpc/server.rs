@@ -0,0 +1,387 @@
+//! Server-side part of the authorization key exchange.
+//!
+//! `AuthKeyResponder` answers `req_pq`, `req_DH_params` and
+//! `set_client_DH_params` in that order, after which both parties share
+//! the same `AuthKey`. It is meant for test doubles and internal
+//! services speaking MTProto, so only a single exchange at a time is
+//! supported and `dh_gen_retry` is never sent.
+//!
+//! More information about the exchange:
+//! https://core.telegram.org/mtproto/auth_key.
+
+use std::cmp::Ordering;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use chrono::Utc;
+use extprim::i128::i128;
+use rand::{self, Rng};
+use serde::de::DeserializeOwned;
+use serde_bytes::ByteBuf;
+use serde_mtproto::{self, Boxed, Identifiable, MtProtoSized};
+
+use crypto;
+use error::{self, ErrorKind};
+use schema;
+
+use super::encryption::{AesParams, AuthKey, RsaPrivateKey};
+
+
+/// 2048-bit safe prime used by Telegram servers.
+pub const DEFAULT_DH_PRIME: &'static [u8] = &[
+    0xc7, 0x1c, 0xae, 0xb9, 0xc6, 0xb1, 0xc9, 0x04, 0x8e, 0x6c, 0x52, 0x2f, 0x70, 0xf1, 0x3f, 0x73,
+    0x98, 0x0d, 0x40, 0x23, 0x8e, 0x3e, 0x21, 0xc1, 0x49, 0x34, 0xd0, 0x37, 0x56, 0x3d, 0x93, 0x0f,
+    0x48, 0x19, 0x8a, 0x0a, 0xa7, 0xc1, 0x40, 0x58, 0x22, 0x94, 0x93, 0xd2, 0x25, 0x30, 0xf4, 0xdb,
+    0xfa, 0x33, 0x6f, 0x6e, 0x0a, 0xc9, 0x25, 0x13, 0x95, 0x43, 0xae, 0xd4, 0x4c, 0xce, 0x7c, 0x37,
+    0x20, 0xfd, 0x51, 0xf6, 0x94, 0x58, 0x70, 0x5a, 0xc6, 0x8c, 0xd4, 0xfe, 0x6b, 0x6b, 0x13, 0xab,
+    0xdc, 0x97, 0x46, 0x51, 0x29, 0x69, 0x32, 0x84, 0x54, 0xf1, 0x8f, 0xaf, 0x8c, 0x59, 0x5f, 0x64,
+    0x24, 0x77, 0xfe, 0x96, 0xbb, 0x2a, 0x94, 0x1d, 0x5b, 0xcd, 0x1d, 0x4a, 0xc8, 0xcc, 0x49, 0x88,
+    0x07, 0x08, 0xfa, 0x9b, 0x37, 0x8e, 0x3c, 0x4f, 0x3a, 0x90, 0x60, 0xbe, 0xe6, 0x7c, 0xf9, 0xa4,
+    0xa4, 0xa6, 0x95, 0x81, 0x10, 0x51, 0x90, 0x7e, 0x16, 0x27, 0x53, 0xb5, 0x6b, 0x0f, 0x6b, 0x41,
+    0x0d, 0xba, 0x74, 0xd8, 0xa8, 0x4b, 0x2a, 0x14, 0xb3, 0x14, 0x4e, 0x0e, 0xf1, 0x28, 0x47, 0x54,
+    0xfd, 0x17, 0xed, 0x95, 0x0d, 0x59, 0x65, 0xb4, 0xb9, 0xdd, 0x46, 0x58, 0x2d, 0xb1, 0x17, 0x8d,
+    0x16, 0x9c, 0x6b, 0xc4, 0x65, 0xb0, 0xd6, 0xff, 0x9c, 0xa3, 0x92, 0x8f, 0xef, 0x5b, 0x9a, 0xe4,
+    0xe4, 0x18, 0xfc, 0x15, 0xe8, 0x3e, 0xbe, 0xa0, 0xf8, 0x7f, 0xa9, 0xff, 0x5e, 0xed, 0x70, 0x05,
+    0x0d, 0xed, 0x28, 0x49, 0xf4, 0x7b, 0xf9, 0x59, 0xd9, 0x56, 0x85, 0x0c, 0xe9, 0x29, 0x85, 0x1f,
+    0x0d, 0x81, 0x15, 0xf6, 0x35, 0xb1, 0x05, 0xee, 0x2e, 0x4e, 0x15, 0xd0, 0x4b, 0x24, 0x54, 0xbf,
+    0x6f, 0x4f, 0xad, 0xf0, 0x34, 0xb1, 0x04, 0x03, 0x11, 0x9c, 0xd8, 0xe3, 0xb9, 0x2f, 0xcc, 0x5b,
+];
+
+/// Generator used with `DEFAULT_DH_PRIME`.
+pub const DEFAULT_DH_G: i32 = 3;
+
+/// Factors of `pq` are chosen from `[PQ_FACTOR_MIN, PQ_FACTOR_MIN +
+/// PQ_FACTOR_RANGE)` so that `pq` fits into 63 bits and clients using
+/// Fermat's factorization method find them quickly.
+const PQ_FACTOR_MIN: u32 = 0x7f00_0000;
+const PQ_FACTOR_RANGE: u32 = 0x0100_0000;
+
+const PRIME_CHECKS: u32 = 20;
+const DH_SECRET_LENGTH: usize = 256;
+
+
+/// Result of a successful key exchange.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NegotiatedKey {
+    pub auth_key: AuthKey,
+    /// Salt to be used in the first encrypted messages.
+    pub server_salt: i64,
+}
+
+#[derive(Debug)]
+enum State {
+    Idle,
+    /// `resPQ` has been sent.
+    PqSent {
+        nonce: i128,
+        server_nonce: i128,
+        pq: u64,
+        p: u32,
+        q: u32,
+    },
+    /// `server_DH_params_ok` has been sent.
+    DhParamsSent {
+        nonce: i128,
+        server_nonce: i128,
+        new_nonce: (i128, i128),
+        secret: Vec<u8>,
+    },
+}
+
+/// Answers key exchange requests of a single client.
+#[derive(Debug)]
+pub struct AuthKeyResponder {
+    rsa_key: RsaPrivateKey,
+    fingerprint: i64,
+    g: i32,
+    dh_prime: Vec<u8>,
+    state: State,
+}
+
+impl AuthKeyResponder {
+    /// Create a responder which decrypts `P_Q_inner_data` with
+    /// `rsa_key` and uses the default DH parameters.
+    pub fn new(rsa_key: RsaPrivateKey) -> error::Result<AuthKeyResponder> {
+        let fingerprint = rsa_key.public_key().fingerprint()?;
+
+        Ok(AuthKeyResponder {
+            rsa_key: rsa_key,
+            fingerprint: fingerprint,
+            g: DEFAULT_DH_G,
+            dh_prime: DEFAULT_DH_PRIME.to_vec(),
+            state: State::Idle,
+        })
+    }
+
+    /// Use custom DH parameters.
+    ///
+    /// The parameters are not checked, clients are expected to do it.
+    pub fn dh_params(mut self, g: i32, dh_prime: Vec<u8>) -> AuthKeyResponder {
+        self.g = g;
+        self.dh_prime = dh_prime;
+        self
+    }
+
+    /// Answer `req_pq` and start a new exchange.
+    pub fn handle_req_pq(&mut self, request: &schema::rpc::req_pq) -> error::Result<schema::ResPQ> {
+        let mut rng = rand::thread_rng();
+
+        let p = generate_pq_factor(&mut rng)?;
+        let q = loop {
+            let q = generate_pq_factor(&mut rng)?;
+            if q != p {
+                break q;
+            }
+        };
+        let (p, q) = if p < q { (p, q) } else { (q, p) };
+        let pq = p as u64 * q as u64;
+
+        let mut pq_bytes = vec![0; 8];
+        BigEndian::write_u64(&mut pq_bytes, pq);
+
+        let server_nonce = rng.gen();
+
+        self.state = State::PqSent {
+            nonce: request.nonce,
+            server_nonce: server_nonce,
+            pq: pq,
+            p: p,
+            q: q,
+        };
+
+        Ok(schema::ResPQ {
+            nonce: request.nonce,
+            server_nonce: server_nonce,
+            pq: ByteBuf::from(pq_bytes),
+            server_public_key_fingerprints: Boxed::new(vec![self.fingerprint]),
+        })
+    }
+
+    /// Answer `req_DH_params` with `server_DH_params_ok`.
+    ///
+    /// Both the legacy and RSA_PAD encryption of `P_Q_inner_data` are
+    /// accepted.
+    pub fn handle_req_dh_params(&mut self, request: &schema::rpc::req_DH_params)
+        -> error::Result<schema::Server_DH_Params>
+    {
+        let (nonce, server_nonce, pq, p, q) = match self.state {
+            State::PqSent { nonce, server_nonce, pq, p, q } => (nonce, server_nonce, pq, p, q),
+            _ => bail!(ErrorKind::AuthKeyExchangeOutOfOrder("req_DH_params")),
+        };
+
+        check_nonces(nonce, server_nonce, request.nonce, request.server_nonce)?;
+        if be_to_u64(&request.p) != Some(p as u64) || be_to_u64(&request.q) != Some(q as u64) {
+            bail!(ErrorKind::AuthKeyExchangeInvalidData("p and q don't match pq"));
+        }
+        if request.public_key_fingerprint != self.fingerprint {
+            bail!(ErrorKind::NoRsaPublicKeyForFingerprints(vec![request.public_key_fingerprint]));
+        }
+
+        let (inner_pq, inner_nonce, inner_server_nonce, new_nonce) = match self.decrypt_inner_data(&request.encrypted_data)? {
+            schema::P_Q_inner_data::p_q_inner_data(data) => (data.pq, data.nonce, data.server_nonce, data.new_nonce),
+            schema::P_Q_inner_data::p_q_inner_data_temp(data) => (data.pq, data.nonce, data.server_nonce, data.new_nonce),
+        };
+
+        check_nonces(nonce, server_nonce, inner_nonce, inner_server_nonce)?;
+        if be_to_u64(&inner_pq) != Some(pq) {
+            bail!(ErrorKind::AuthKeyExchangeInvalidData("pq in P_Q_inner_data doesn't match"));
+        }
+
+        let (secret, g_a) = self.generate_dh_secret()?;
+
+        let server_dh_inner_data = schema::Server_DH_inner_data {
+            nonce: nonce,
+            server_nonce: server_nonce,
+            g: self.g,
+            dh_prime: ByteBuf::from(self.dh_prime.clone()),
+            g_a: ByteBuf::from(g_a),
+            server_time: Utc::now().timestamp() as i32,
+        };
+        debug!("Server DH inner data: {:?}", &server_dh_inner_data);
+
+        let answer = serde_mtproto::to_bytes(&Boxed::new(server_dh_inner_data))?;
+        let encrypted_answer = tmp_aes_params(new_nonce, server_nonce)?.ige_encrypt(&answer, true)?;
+
+        self.state = State::DhParamsSent {
+            nonce: nonce,
+            server_nonce: server_nonce,
+            new_nonce: new_nonce,
+            secret: secret,
+        };
+
+        Ok(schema::Server_DH_Params::server_DH_params_ok(schema::server_DH_params_ok {
+            nonce: nonce,
+            server_nonce: server_nonce,
+            encrypted_answer: ByteBuf::from(encrypted_answer),
+        }))
+    }
+
+    /// Answer `set_client_DH_params` with `dh_gen_ok` and finish the
+    /// exchange.
+    pub fn handle_set_client_dh_params(&mut self, request: &schema::rpc::set_client_DH_params)
+        -> error::Result<(schema::Set_client_DH_params_answer, NegotiatedKey)>
+    {
+        let (nonce, server_nonce, new_nonce, secret) = match self.state {
+            State::DhParamsSent { nonce, server_nonce, new_nonce, ref secret } => {
+                (nonce, server_nonce, new_nonce, secret.clone())
+            },
+            _ => bail!(ErrorKind::AuthKeyExchangeOutOfOrder("set_client_DH_params")),
+        };
+
+        check_nonces(nonce, server_nonce, request.nonce, request.server_nonce)?;
+
+        // Sent by the client, so it may be anything rather than whole
+        // AES blocks
+        let encrypted_data = &request.encrypted_data;
+        if encrypted_data.is_empty() || encrypted_data.len() % 16 != 0 {
+            bail!(ErrorKind::AuthKeyExchangeInvalidData("Client_DH_Inner_Data"));
+        }
+
+        let decrypted = tmp_aes_params(new_nonce, server_nonce)?.ige_decrypt(encrypted_data)?;
+        let client_dh_inner_data: schema::Client_DH_Inner_Data =
+            read_with_sha1(&decrypted, "Client_DH_Inner_Data")?;
+
+        check_nonces(nonce, server_nonce, client_dh_inner_data.nonce, client_dh_inner_data.server_nonce)?;
+
+        let g_b = &client_dh_inner_data.g_b;
+        let p_minus_one = crypto::sub_one_be(&self.dh_prime);
+        if crypto::cmp_be(g_b, &[1]) != Ordering::Greater || crypto::cmp_be(g_b, &p_minus_one) != Ordering::Less {
+            bail!(ErrorKind::DhValueOutOfRange);
+        }
+
+        let auth_key = AuthKey::new(&crypto::mod_exp(g_b, &secret, &self.dh_prime)?)?;
+        let new_nonce_hash1 = new_nonce_hash(new_nonce, 1, &auth_key)?;
+        let server_salt = initial_server_salt(new_nonce, server_nonce)?;
+
+        self.state = State::Idle;
+
+        let answer = schema::Set_client_DH_params_answer::dh_gen_ok(schema::dh_gen_ok {
+            nonce: nonce,
+            server_nonce: server_nonce,
+            new_nonce_hash1: new_nonce_hash1,
+        });
+
+        let negotiated_key = NegotiatedKey {
+            auth_key: auth_key,
+            server_salt: server_salt,
+        };
+
+        Ok((answer, negotiated_key))
+    }
+
+    fn decrypt_inner_data(&self, encrypted_data: &[u8]) -> error::Result<schema::P_Q_inner_data> {
+        // RSA_PAD data can be verified without parsing it, so try it first
+        if let Ok(data_with_padding) = self.rsa_key.decrypt_rsa_pad(encrypted_data) {
+            let inner_data: Boxed<schema::P_Q_inner_data> =
+                serde_mtproto::from_reader(data_with_padding.as_slice(), None)?;
+
+            return Ok(inner_data.into_inner());
+        }
+
+        let decrypted = self.rsa_key.decrypt_legacy(encrypted_data)?;
+        read_with_sha1(&decrypted, "P_Q_inner_data")
+    }
+
+    fn generate_dh_secret(&self) -> error::Result<(Vec<u8>, Vec<u8>)> {
+        let mut g = [0; 4];
+        BigEndian::write_i32(&mut g, self.g);
+
+        let p_minus_one = crypto::sub_one_be(&self.dh_prime);
+
+        loop {
+            let mut secret = vec![0; DH_SECRET_LENGTH];
+            rand::thread_rng().fill_bytes(&mut secret);
+
+            let g_a = crypto::mod_exp(&g, &secret, &self.dh_prime)?;
+            if crypto::cmp_be(&g_a, &[1]) == Ordering::Greater && crypto::cmp_be(&g_a, &p_minus_one) == Ordering::Less {
+                return Ok((secret, g_a));
+            }
+        }
+    }
+}
+
+
+/// Compute `new_nonce_hash1`, `new_nonce_hash2` or `new_nonce_hash3`
+/// (for `number` equal to 1, 2 or 3 respectively) sent in answers to
+/// `set_client_DH_params`.
+pub fn new_nonce_hash(new_nonce: (i128, i128), number: u8, auth_key: &AuthKey) -> error::Result<i128> {
+    let new_nonce_bytes = serde_mtproto::to_bytes(&new_nonce)?;
+    let mut aux_hash = [0; 8];
+    LittleEndian::write_i64(&mut aux_hash, auth_key.aux_hash());
+
+    // The lower-order 128 bits of SHA1
+    let sha1 = crypto::sha1(&[&new_nonce_bytes, &[number], &aux_hash])?;
+    let lo = LittleEndian::read_u64(&sha1[4..12]);
+    let hi = LittleEndian::read_i64(&sha1[12..20]);
+
+    Ok(i128::from_parts(hi, lo))
+}
+
+/// Compute the salt used right after the key exchange:
+/// `substr(new_nonce, 0, 8) XOR substr(server_nonce, 0, 8)`.
+pub fn initial_server_salt(new_nonce: (i128, i128), server_nonce: i128) -> error::Result<i64> {
+    let new_nonce_bytes = serde_mtproto::to_bytes(&new_nonce)?;
+    let server_nonce_bytes = serde_mtproto::to_bytes(&server_nonce)?;
+
+    Ok(LittleEndian::read_i64(&new_nonce_bytes[0..8]) ^ LittleEndian::read_i64(&server_nonce_bytes[0..8]))
+}
+
+/// Derive AES parameters used to encrypt `Server_DH_inner_data` and
+/// `Client_DH_Inner_Data`.
+pub fn tmp_aes_params(new_nonce: (i128, i128), server_nonce: i128) -> error::Result<AesParams> {
+    let new_nonce_bytes = serde_mtproto::to_bytes(&new_nonce)?;
+    let server_nonce_bytes = serde_mtproto::to_bytes(&server_nonce)?;
+
+    AesParams::from_nonces(&new_nonce_bytes, &server_nonce_bytes)
+}
+
+
+fn generate_pq_factor<R: Rng>(rng: &mut R) -> error::Result<u32> {
+    loop {
+        let candidate = (PQ_FACTOR_MIN + rng.gen_range(0, PQ_FACTOR_RANGE)) | 1;
+
+        let mut candidate_bytes = [0; 4];
+        BigEndian::write_u32(&mut candidate_bytes, candidate);
+
+        if crypto::is_prime(&candidate_bytes, PRIME_CHECKS)? {
+            return Ok(candidate);
+        }
+    }
+}
+
+fn be_to_u64(bytes: &[u8]) -> Option<u64> {
+    let bytes = crypto::strip_leading_zeros(bytes);
+
+    if bytes.len() > 8 {
+        None
+    } else {
+        Some(bytes.iter().fold(0, |n, &byte| (n << 8) | byte as u64))
+    }
+}
+
+fn check_nonces(nonce: i128, server_nonce: i128, found_nonce: i128, found_server_nonce: i128) -> error::Result<()> {
+    if nonce != found_nonce || server_nonce != found_server_nonce {
+        bail!(ErrorKind::AuthKeyExchangeNonceMismatch);
+    }
+
+    Ok(())
+}
+
+/// Read a boxed value preceded by SHA1 of its serialized form and
+/// followed by arbitrary padding.
+fn read_with_sha1<T>(data_with_hash: &[u8], what: &'static str) -> error::Result<T>
+    where T: DeserializeOwned + Identifiable + MtProtoSized
+{
+    if data_with_hash.len() < 20 {
+        bail!(ErrorKind::AuthKeyExchangeInvalidData(what));
+    }
+
+    let (hash, data) = data_with_hash.split_at(20);
+    let value: Boxed<T> = serde_mtproto::from_reader(data, None)?;
+    let len = value.size_hint()?;
+
+    if len > data.len() || &crypto::sha1(&[&data[..len]])?[..] != hash {
+        bail!(ErrorKind::AuthKeyExchangeInvalidData(what));
+    }
+
+    Ok(value.into_inner())
+}
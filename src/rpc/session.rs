@@ -13,16 +13,23 @@ use manual_types::Object;
 use tl::TLObject;
 
 use super::{AppInfo, Salt};
-use super::encryption::AuthKey;
+use super::encryption::{AuthKey, MessageOrigin};
 use super::message::{DecryptedData, Message, MessageSeed};
+use super::ordered::Ordered;
 
 
-fn next_message_id() -> i64 {
+fn next_message_id(origin: MessageOrigin) -> i64 {
     let time = Utc::now();
     let timestamp = time.timestamp();
     let nano = time.nanosecond() as i64; // from u32
 
-    ((timestamp << 32) | (nano & 0x_ffff_fffc))
+    let message_id = (timestamp << 32) | (nano & 0x_ffff_fffc);
+
+    // Client message IDs are divisible by 4, server ones give 1 modulo 4
+    match origin {
+        MessageOrigin::Client => message_id,
+        MessageOrigin::Server => message_id | 1,
+    }
 }
 
 
@@ -36,6 +43,11 @@ enum MessagePurpose {
 // by trying to match representations we can synchronize the range of allowed values
 /// Represents a session attached to the client device and user key ID.
 ///
+/// A session is either client-side (created with `new`) or server-side
+/// (created with `new_server` or `accept`). Server-side sessions
+/// encrypt outgoing messages with server-to-client message keys and
+/// expect incoming messages to be sent by a client.
+///
 /// More information about sessions:
 /// https://core.telegram.org/mtproto#high-level-component-rpc-query-language-api.
 #[derive(Debug)]
@@ -46,12 +58,49 @@ pub struct Session {
     seq_no: i32,
     auth_key: Option<AuthKey>,
     to_ack: Vec<i64>,
-    app_info: AppInfo,
+    app_info: Option<AppInfo>,
+    ordered_queue: bool,
+    last_ordered_msg_id: Option<i64>,
+    origin: MessageOrigin,
 }
 
 impl Session {
     /// Construct a new `Session` from a unique session ID and app info.
     pub fn new(session_id: i64, app_info: AppInfo) -> Session {
+        Session::new_from(MessageOrigin::Client, session_id, Some(app_info))
+    }
+
+    /// Construct a new server-side `Session` from a session ID chosen
+    /// by a client.
+    ///
+    /// Salts added by `add_server_salts` are used for outgoing messages
+    /// just like in client-side sessions.
+    pub fn new_server(session_id: i64) -> Session {
+        Session::new_from(MessageOrigin::Server, session_id, None)
+    }
+
+    /// Accept the first encrypted message of a client and create a
+    /// server-side `Session` for it.
+    ///
+    /// The session ID is taken from the message, and `auth_key` is
+    /// adopted by the returned session.
+    pub fn accept<T>(auth_key: AuthKey, message_bytes: &[u8], encrypted_data_len: u32)
+        -> error::Result<(Session, Message<T>)>
+        where T: fmt::Debug + DeserializeOwned
+    {
+        let mut session = Session::new_server(0);
+        session.adopt_key(auth_key);
+
+        let message: Message<T> = session.process_message(message_bytes, Some(encrypted_data_len))?;
+        match message {
+            Message::PlainText { .. } => bail!(ErrorKind::UnencryptedMessage),
+            Message::Decrypted { ref decrypted_data } => session.session_id = decrypted_data.session_id,
+        }
+
+        Ok((session, message))
+    }
+
+    fn new_from(origin: MessageOrigin, session_id: i64, app_info: Option<AppInfo>) -> Session {
         Session {
             session_id: session_id,
             server_salts: Vec::new(),
@@ -59,9 +108,37 @@ impl Session {
             auth_key: None,
             to_ack: Vec::new(),
             app_info: app_info,
+            ordered_queue: false,
+            last_ordered_msg_id: None,
+            origin: origin,
         }
     }
 
+    /// The side of a connection this session belongs to.
+    pub fn origin(&self) -> MessageOrigin {
+        self.origin
+    }
+
+    pub fn session_id(&self) -> i64 {
+        self.session_id
+    }
+
+    /// Enable or disable the ordered queue mode.
+    ///
+    /// In this mode every message created with
+    /// `create_encrypted_message_ordered` is wrapped in
+    /// `invokeAfterMsg` so that the server executes it only after the
+    /// previous one. Toggling the mode starts a new chain.
+    pub fn set_ordered_queue(&mut self, enabled: bool) {
+        self.ordered_queue = enabled;
+        self.last_ordered_msg_id = None;
+    }
+
+    /// Returns `true` if the ordered queue mode is enabled.
+    pub fn is_ordered_queue(&self) -> bool {
+        self.ordered_queue
+    }
+
     fn next_seq_no(&mut self, purpose: MessagePurpose) -> i32 {
         match purpose {
             MessagePurpose::Content => {
@@ -126,7 +203,7 @@ impl Session {
         where T: TLObject
     {
         Ok(Message::PlainText {
-            message_id: next_message_id(),
+            message_id: next_message_id(self.origin),
             body: WithSize::new(Boxed::new(body))?,
         })
     }
@@ -166,12 +243,12 @@ impl Session {
         let msg_container = ::schema::manual::MessageContainer {
             messages: vec![
                 ::schema::manual::Message {
-                    msg_id: next_message_id(),
+                    msg_id: next_message_id(self.origin),
                     seqno: self.next_seq_no(MessagePurpose::NonContent),
                     body: WithSize::new(Boxed::new(Box::new(acks) as Object))?,
                 },
                 ::schema::manual::Message {
-                    msg_id: next_message_id(),
+                    msg_id: next_message_id(self.origin),
                     seqno: self.next_seq_no(MessagePurpose::Content),
                     body: WithSize::new(Boxed::new(Box::new(body) as Object))?,
                 }
@@ -189,17 +266,38 @@ impl Session {
         Ok(Some(message))
     }
 
+    /// Create an encrypted message which respects the ordered queue
+    /// mode.
+    ///
+    /// If the mode is enabled, the body is wrapped to be executed after
+    /// the message previously created by this method (the first message
+    /// in a chain is sent as-is). Otherwise the body is always sent
+    /// as-is.
+    pub fn create_encrypted_message_ordered<T>(&mut self, body: T) -> error::Result<Message<Ordered<T>>>
+        where T: TLObject
+    {
+        let prev_msg_id = if self.ordered_queue { self.last_ordered_msg_id } else { None };
+        let message = self.impl_create_decrypted_message(Ordered::new(prev_msg_id, body), MessagePurpose::Content)?;
+
+        if self.ordered_queue {
+            self.last_ordered_msg_id = Some(message.message_id());
+        }
+
+        Ok(message)
+    }
+
     fn impl_create_decrypted_message<T>(&mut self, body: T, purpose: MessagePurpose) -> error::Result<Message<T>>
         where T: Identifiable + MtProtoSized
     {
         let decrypted_data = DecryptedData {
             salt: self.latest_server_salt()?,
             session_id: self.session_id,
-            message_id: next_message_id(),
+            message_id: next_message_id(self.origin),
             seq_no: self.next_seq_no(purpose),
             body: WithSize::new(Boxed::new(body))?,
 
             key: self.fresh_auth_key()?,
+            origin: self.origin,
         };
 
         let message = Message::Decrypted {
@@ -216,7 +314,7 @@ impl Session {
         use serde_mtproto::Deserializer;
 
         let mut deserializer = Deserializer::new(message_bytes, None);
-        let seed = MessageSeed::new(self.auth_key.clone(), encrypted_data_len);
+        let seed = MessageSeed::new_from(self.origin.peer(), self.auth_key.clone(), encrypted_data_len);
 
         seed.deserialize(&mut deserializer).map_err(Into::into)
     }
@@ -0,0 +1,736 @@
+//! Secret chats.
+//!
+//! Secret chats are end-to-end encrypted: the server only relays
+//! opaque `EncryptedMessage`s between two clients. The key of a chat is
+//! established with a Diffie-Hellman exchange using the parameters from
+//! `messages.getDhConfig`. Payloads are `DecryptedMessageLayer`s
+//! encrypted with AES-IGE in the same manner as regular messages, with
+//! the message key derived from the client part of the shared key in
+//! both directions.
+//!
+//! Every message carries `in_seq_no` and `out_seq_no` so that lost
+//! messages can be detected. The key is periodically replaced with a
+//! new one negotiated through `decryptedMessageActionRequestKey`,
+//! `decryptedMessageActionAcceptKey` and
+//! `decryptedMessageActionCommitKey` service messages.
+//!
+//! More information about secret chats:
+//! https://core.telegram.org/api/end-to-end.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use extprim::i128::i128;
+use rand::{self, Rng};
+use serde_bytes::ByteBuf;
+use serde_mtproto::{self, Boxed};
+
+use crypto;
+use error::{self, ErrorKind};
+use rpc::RpcCaller;
+use rpc::encryption::{AuthKey, MessageOrigin};
+use schema;
+
+
+/// Secret chat layer implemented by this module.
+pub const SECRET_LAYER: i32 = 20;
+
+/// Number of messages encrypted with a single key after which it is
+/// replaced.
+pub const REKEY_AFTER_MESSAGES: u32 = 100;
+
+const DH_RANDOM_LENGTH: usize = 256;
+const DH_PRIME_BITS: usize = 2048;
+const DH_PRIME_CHECKS: u32 = 64;
+/// `g_a` and `g_b` must be within `2^(2048 - 64)` of both 1 and `p`.
+const DH_SAFETY_MARGIN_BITS: usize = 64;
+const MIN_LAYER_RANDOM_BYTES: usize = 15;
+const MESSAGE_HEADER_LEN: usize = 8 + 16;    // key_fingerprint + msg_key
+
+
+/// Diffie-Hellman parameters obtained with `messages.getDhConfig`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct DhParams {
+    g: i32,
+    p: Vec<u8>,
+    version: i32,
+}
+
+impl DhParams {
+    /// Check that `p` is a safe 2048-bit prime and `g` generates a
+    /// cyclic subgroup of prime order `(p - 1) / 2`.
+    fn check(&self) -> error::Result<()> {
+        let q = crypto::shr_one_be(&self.p);
+
+        if crypto::num_bits_be(&self.p) != DH_PRIME_BITS
+            || !crypto::is_prime(&self.p, DH_PRIME_CHECKS)?
+            || !crypto::is_prime(&q, DH_PRIME_CHECKS)?
+        {
+            bail!(ErrorKind::UnsafeDhPrime);
+        }
+
+        // By quadratic reciprocity, `g` is a quadratic residue modulo `p`
+        // exactly when `p` has one of these residues modulo a small number
+        let is_generator = match self.g {
+            2 => crypto::rem_small_be(&self.p, 8) == 7,
+            3 => crypto::rem_small_be(&self.p, 3) == 2,
+            4 => true,
+            5 => [1, 4].contains(&crypto::rem_small_be(&self.p, 5)),
+            6 => [19, 23].contains(&crypto::rem_small_be(&self.p, 24)),
+            7 => [3, 5, 6].contains(&crypto::rem_small_be(&self.p, 7)),
+            _ => false,
+        };
+
+        if !is_generator {
+            bail!(ErrorKind::UnsafeDhGenerator(self.g));
+        }
+
+        Ok(())
+    }
+
+    /// Generate a secret exponent and the corresponding public value
+    /// `g^x mod p`.
+    fn generate(&self, server_random: &[u8]) -> error::Result<(Vec<u8>, Vec<u8>)> {
+        let mut g = [0; 4];
+        BigEndian::write_i32(&mut g, self.g);
+
+        loop {
+            let mut secret = vec![0; DH_RANDOM_LENGTH];
+            rand::thread_rng().fill_bytes(&mut secret);
+            for (byte, server_byte) in secret.iter_mut().zip(server_random) {
+                *byte ^= *server_byte;
+            }
+
+            let g_x = crypto::mod_exp(&g, &secret, &self.p)?;
+            if self.check_public(&g_x).is_ok() {
+                return Ok((secret, g_x));
+            }
+        }
+    }
+
+    /// Check that `2^(2048 - 64) < g_x < p - 2^(2048 - 64)`.
+    fn check_public(&self, g_x: &[u8]) -> error::Result<()> {
+        let mut margin = vec![0; (DH_PRIME_BITS - DH_SAFETY_MARGIN_BITS) / 8 + 1];
+        margin[0] = 1;
+        let upper_bound = crypto::sub_be(&self.p, &margin);
+
+        if crypto::cmp_be(g_x, &margin) != Ordering::Greater || crypto::cmp_be(g_x, &upper_bound) != Ordering::Less {
+            bail!(ErrorKind::DhValueOutOfRange);
+        }
+
+        Ok(())
+    }
+
+    /// Compute the shared key `(g_y)^x mod p`.
+    fn compute_key(&self, secret: &[u8], g_y: &[u8]) -> error::Result<AuthKey> {
+        self.check_public(g_y)?;
+
+        AuthKey::new(&crypto::mod_exp(g_y, secret, &self.p)?)
+    }
+}
+
+
+/// State of a key exchange initiated by either party.
+#[derive(Clone, Debug)]
+enum KeyExchange {
+    /// We have sent `decryptedMessageActionRequestKey`.
+    Requested { exchange_id: i64, secret: Vec<u8> },
+    /// We have sent `decryptedMessageActionAcceptKey` and wait for
+    /// `decryptedMessageActionCommitKey`.
+    Accepted { exchange_id: i64, key: AuthKey },
+    /// We are about to send `decryptedMessageActionCommitKey`, after
+    /// which `key` is used.
+    Committed { exchange_id: i64, key: AuthKey },
+}
+
+
+/// An established secret chat.
+#[derive(Clone, Debug)]
+pub struct SecretChat {
+    id: i32,
+    access_hash: i64,
+    is_admin: bool,
+    peer_layer: i32,
+    dh_params: DhParams,
+    key: AuthKey,
+    prev_key: Option<AuthKey>,
+    in_count: i32,
+    out_count: i32,
+    messages_since_rekey: u32,
+    key_exchange: Option<KeyExchange>,
+}
+
+impl SecretChat {
+    fn new(id: i32, access_hash: i64, is_admin: bool, dh_params: DhParams, key: AuthKey) -> SecretChat {
+        SecretChat {
+            id: id,
+            access_hash: access_hash,
+            is_admin: is_admin,
+            peer_layer: 0,
+            dh_params: dh_params,
+            key: key,
+            prev_key: None,
+            in_count: 0,
+            out_count: 0,
+            messages_since_rekey: 0,
+            key_exchange: None,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// Returns `true` if the chat has been created by us.
+    pub fn is_admin(&self) -> bool {
+        self.is_admin
+    }
+
+    /// Layer supported by the other party, as reported by
+    /// `decryptedMessageActionNotifyLayer`.
+    pub fn peer_layer(&self) -> i32 {
+        self.peer_layer
+    }
+
+    /// Fingerprint of the current key.
+    pub fn key_fingerprint(&self) -> i64 {
+        self.key.fingerprint()
+    }
+
+    pub fn input_peer(&self) -> schema::InputEncryptedChat {
+        schema::InputEncryptedChat {
+            chat_id: self.id,
+            access_hash: self.access_hash,
+        }
+    }
+
+    /// Returns `true` if the current key has been used long enough to
+    /// be replaced.
+    pub fn needs_rekey(&self) -> bool {
+        self.key_exchange.is_none() && self.messages_since_rekey >= REKEY_AFTER_MESSAGES
+    }
+
+    // The chat creator sends odd sequence numbers, the other party sends
+    // even ones.
+    fn out_seq_no(&self) -> i32 {
+        2 * self.out_count + self.is_admin as i32
+    }
+
+    fn in_seq_no(&self) -> i32 {
+        2 * self.in_count + !self.is_admin as i32
+    }
+
+    /// Encrypt a message into the `data` expected by
+    /// `messages.sendEncrypted`.
+    fn encrypt(&mut self, message: schema::DecryptedMessage) -> error::Result<Vec<u8>> {
+        let committed_exchange_id = match message {
+            schema::DecryptedMessage::decryptedMessageService(ref service) => match service.action {
+                schema::DecryptedMessageAction::decryptedMessageActionCommitKey(ref commit) => {
+                    Some(commit.exchange_id)
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let mut rng = rand::thread_rng();
+        let random_bytes_len = rng.gen_range(MIN_LAYER_RANDOM_BYTES, MIN_LAYER_RANDOM_BYTES + 16);
+        let random_bytes: Vec<u8> = rng.gen_iter().take(random_bytes_len).collect();
+
+        let layer = schema::DecryptedMessageLayer {
+            random_bytes: ByteBuf::from(random_bytes),
+            layer: SECRET_LAYER,
+            in_seq_no: self.in_seq_no(),
+            out_seq_no: self.out_seq_no(),
+            message: message,
+        };
+
+        let layer_serialized = serde_mtproto::to_bytes(&Boxed::new(layer))?;
+        let mut plaintext = vec![0; 4];
+        LittleEndian::write_i32(&mut plaintext, layer_serialized.len() as i32);
+        plaintext.extend(layer_serialized);
+
+        let (key_fingerprint, msg_key, encrypted_data) =
+            self.key.encrypt_message_bytes_from(MessageOrigin::Client, &plaintext)?;
+
+        let mut data = vec![0; MESSAGE_HEADER_LEN];
+        LittleEndian::write_i64(&mut data[0..8], key_fingerprint);
+        LittleEndian::write_u64(&mut data[8..16], msg_key.low64());
+        LittleEndian::write_i64(&mut data[16..24], msg_key.high64());
+        data.extend(encrypted_data);
+
+        self.out_count += 1;
+        self.messages_since_rekey += 1;
+
+        // The commit message itself is encrypted with the old key, the
+        // new one is used starting with the next message
+        let commits_key = match self.key_exchange {
+            Some(KeyExchange::Committed { exchange_id, .. }) => committed_exchange_id == Some(exchange_id),
+            _ => false,
+        };
+
+        if commits_key {
+            if let Some(KeyExchange::Committed { key, .. }) = self.key_exchange.take() {
+                self.switch_key(key);
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Decrypt the `bytes` of an `EncryptedMessage`.
+    fn decrypt(&self, data: &[u8]) -> error::Result<schema::DecryptedMessageLayer> {
+        if data.len() < MESSAGE_HEADER_LEN + 16 {
+            bail!(ErrorKind::SecretMessageTooShort(data.len()));
+        }
+        if (data.len() - MESSAGE_HEADER_LEN) % 16 != 0 {
+            bail!(ErrorKind::SecretMessageInvalidLength(data.len()));
+        }
+
+        let key_fingerprint = LittleEndian::read_i64(&data[0..8]);
+        let msg_key_bytes = &data[8..24];
+        let msg_key = i128::from_parts(LittleEndian::read_i64(&msg_key_bytes[8..16]),
+                                       LittleEndian::read_u64(&msg_key_bytes[0..8]));
+
+        let key = match self.prev_key {
+            Some(ref prev_key) if prev_key.fingerprint() == key_fingerprint => prev_key,
+            _ => &self.key,
+        };
+
+        let plaintext = key.decrypt_message_bytes_from(
+            MessageOrigin::Client, key_fingerprint, msg_key, &data[MESSAGE_HEADER_LEN..])?;
+
+        let len = LittleEndian::read_i32(&plaintext[0..4]);
+        if len < 0 || 4 + len as usize > plaintext.len() {
+            bail!(ErrorKind::SecretMessageKeyMismatch);
+        }
+
+        let plaintext = &plaintext[..4 + len as usize];
+        if &crypto::sha1(&[plaintext])?[4..20] != msg_key_bytes {
+            bail!(ErrorKind::SecretMessageKeyMismatch);
+        }
+
+        let layer: Boxed<schema::DecryptedMessageLayer> = serde_mtproto::from_reader(&plaintext[4..], None)?;
+
+        Ok(layer.into_inner())
+    }
+
+    fn switch_key(&mut self, key: AuthKey) {
+        debug!("Switching secret chat {} to key {}", self.id, key.fingerprint());
+
+        let old_key = ::std::mem::replace(&mut self.key, key);
+        self.prev_key = Some(old_key);
+        self.messages_since_rekey = 0;
+    }
+}
+
+
+/// Keeps track of secret chats and their keys.
+#[derive(Debug, Default)]
+pub struct SecretChatManager {
+    dh_params: Option<DhParams>,
+    chats: BTreeMap<i32, SecretChat>,
+    /// Chats requested by us which haven't been accepted yet.
+    requested: BTreeMap<i32, (DhParams, Vec<u8>)>,
+    /// Service messages to be sent by `flush`.
+    outgoing: VecDeque<(i32, schema::DecryptedMessage)>,
+}
+
+impl SecretChatManager {
+    pub fn new() -> SecretChatManager {
+        Default::default()
+    }
+
+    pub fn chat(&self, chat_id: i32) -> Option<&SecretChat> {
+        self.chats.get(&chat_id)
+    }
+
+    pub fn chat_ids(&self) -> Vec<i32> {
+        self.chats.keys().cloned().collect()
+    }
+
+    /// Request a new secret chat with `user`.
+    ///
+    /// Returns the id of the chat which becomes usable after the other
+    /// party accepts it, see `handle_encrypted_chat`.
+    pub fn request<C: RpcCaller>(&mut self, caller: &mut C, user: schema::InputUser) -> error::Result<i32> {
+        let (dh_params, server_random) = self.fetch_dh_params(caller)?;
+        let (secret, g_a) = dh_params.generate(&server_random)?;
+
+        let chat = caller.call(schema::rpc::messages::requestEncryption {
+            user_id: user,
+            random_id: rand::thread_rng().gen(),
+            g_a: ByteBuf::from(g_a),
+        })?;
+
+        let chat_id = chat.id();
+        info!("Requested secret chat {}", chat_id);
+        self.requested.insert(chat_id, (dh_params, secret));
+
+        Ok(chat_id)
+    }
+
+    /// Accept a secret chat requested by another user.
+    pub fn accept<C: RpcCaller>(&mut self, caller: &mut C, requested: &schema::encryptedChatRequested)
+        -> error::Result<()>
+    {
+        let (dh_params, server_random) = self.fetch_dh_params(caller)?;
+        let (secret, g_b) = dh_params.generate(&server_random)?;
+        let key = dh_params.compute_key(&secret, &requested.g_a)?;
+
+        caller.call(schema::rpc::messages::acceptEncryption {
+            peer: schema::InputEncryptedChat {
+                chat_id: requested.id,
+                access_hash: requested.access_hash,
+            },
+            g_b: ByteBuf::from(g_b),
+            key_fingerprint: key.fingerprint(),
+        })?;
+
+        info!("Accepted secret chat {}", requested.id);
+        let chat = SecretChat::new(requested.id, requested.access_hash, false, dh_params, key);
+        self.add_chat(chat);
+
+        Ok(())
+    }
+
+    /// Apply an `EncryptedChat` from `updateEncryption`.
+    ///
+    /// Chats requested by us are established once accepted by the
+    /// other party, discarded chats are forgotten. Incoming requests
+    /// must be accepted explicitly with `accept`.
+    pub fn handle_encrypted_chat(&mut self, chat: &schema::EncryptedChat) -> error::Result<()> {
+        match *chat {
+            schema::EncryptedChat::encryptedChat(ref chat) => {
+                if let Some((dh_params, secret)) = self.requested.remove(&chat.id) {
+                    let key = dh_params.compute_key(&secret, &chat.g_a_or_b)?;
+                    if key.fingerprint() != chat.key_fingerprint {
+                        bail!(ErrorKind::SecretKeyFingerprintMismatch(key.fingerprint(), chat.key_fingerprint));
+                    }
+
+                    info!("Secret chat {} has been accepted", chat.id);
+                    self.add_chat(SecretChat::new(chat.id, chat.access_hash, true, dh_params, key));
+                }
+            },
+            schema::EncryptedChat::encryptedChatDiscarded(ref chat) => {
+                info!("Secret chat {} has been discarded", chat.id);
+                self.chats.remove(&chat.id);
+                self.requested.remove(&chat.id);
+                self.outgoing.retain(|&(chat_id, _)| chat_id != chat.id);
+            },
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Decrypt an incoming message.
+    ///
+    /// Key exchange service messages are processed internally and their
+    /// replies are queued for `flush`. Returns `None` for messages which
+    /// have already been received.
+    pub fn decrypt(&mut self, message: &schema::EncryptedMessage)
+        -> error::Result<Option<schema::DecryptedMessage>>
+    {
+        let chat_id = message.chat_id();
+        let layer = {
+            let chat = self.chats.get_mut(&chat_id)
+                .ok_or(error::Error::from(ErrorKind::SecretChatNotFound(chat_id)))?;
+
+            let layer = chat.decrypt(message.bytes())?;
+            let expected_seq_no = chat.in_seq_no();
+
+            if layer.out_seq_no < expected_seq_no {
+                debug!("Ignoring duplicate secret message {} in chat {}", layer.out_seq_no, chat_id);
+                return Ok(None);
+            }
+
+            if layer.out_seq_no > expected_seq_no {
+                bail!(ErrorKind::SecretChatSeqGap(chat_id, expected_seq_no, layer.out_seq_no));
+            }
+
+            chat.in_count += 1;
+            chat.peer_layer = layer.layer;
+
+            layer
+        };
+
+        if let schema::DecryptedMessage::decryptedMessageService(ref service) = layer.message {
+            self.handle_action(chat_id, &service.action)?;
+        }
+
+        Ok(Some(layer.message))
+    }
+
+    /// Start replacing the key of a chat.
+    ///
+    /// Does nothing if a key exchange is already in progress.
+    pub fn start_rekey(&mut self, chat_id: i32) -> error::Result<()> {
+        let request = {
+            let chat = self.chat_mut(chat_id)?;
+            if chat.key_exchange.is_some() {
+                return Ok(());
+            }
+
+            let (secret, g_a) = chat.dh_params.generate(&[])?;
+            let exchange_id = rand::thread_rng().gen();
+            chat.key_exchange = Some(KeyExchange::Requested { exchange_id: exchange_id, secret: secret });
+
+            schema::DecryptedMessageAction::decryptedMessageActionRequestKey(
+                schema::decryptedMessageActionRequestKey {
+                    exchange_id: exchange_id,
+                    g_a: ByteBuf::from(g_a),
+                })
+        };
+
+        debug!("Requesting a new key for secret chat {}", chat_id);
+        self.queue_action(chat_id, request);
+
+        Ok(())
+    }
+
+    /// Send a message to a secret chat.
+    ///
+    /// Queued service messages are sent first and a key exchange is
+    /// started if the current key has been used for too long.
+    pub fn send<C: RpcCaller>(&mut self, caller: &mut C, chat_id: i32, message: schema::DecryptedMessage)
+        -> error::Result<schema::messages::SentEncryptedMessage>
+    {
+        if self.chat_mut(chat_id)?.needs_rekey() {
+            self.start_rekey(chat_id)?;
+        }
+
+        self.flush(caller)?;
+        self.send_now(caller, chat_id, message)
+    }
+
+    /// Send all queued service messages.
+    pub fn flush<C: RpcCaller>(&mut self, caller: &mut C) -> error::Result<()> {
+        while let Some((chat_id, message)) = self.outgoing.pop_front() {
+            self.send_now(caller, chat_id, message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Discard a secret chat.
+    pub fn discard<C: RpcCaller>(&mut self, caller: &mut C, chat_id: i32) -> error::Result<()> {
+        caller.call(schema::rpc::messages::discardEncryption { chat_id: chat_id })?;
+
+        self.chats.remove(&chat_id);
+        self.requested.remove(&chat_id);
+        self.outgoing.retain(|&(id, _)| id != chat_id);
+
+        Ok(())
+    }
+
+    fn fetch_dh_params<C: RpcCaller>(&mut self, caller: &mut C) -> error::Result<(DhParams, Vec<u8>)> {
+        let dh_config = caller.call(schema::rpc::messages::getDhConfig {
+            version: self.dh_params.as_ref().map_or(0, |params| params.version),
+            random_length: DH_RANDOM_LENGTH as i32,
+        })?;
+
+        match dh_config {
+            schema::messages::DhConfig::dhConfig(config) => {
+                let dh_params = DhParams {
+                    g: config.g,
+                    p: config.p.to_vec(),
+                    version: config.version,
+                };
+
+                dh_params.check()?;
+                self.dh_params = Some(dh_params.clone());
+
+                Ok((dh_params, config.random.to_vec()))
+            },
+            schema::messages::DhConfig::dhConfigNotModified(config) => {
+                let dh_params = self.dh_params.clone().ok_or(error::Error::from(ErrorKind::NoDhConfig))?;
+
+                Ok((dh_params, config.random.to_vec()))
+            },
+        }
+    }
+
+    fn add_chat(&mut self, chat: SecretChat) {
+        let chat_id = chat.id;
+        self.chats.insert(chat_id, chat);
+
+        let notify_layer = schema::DecryptedMessageAction::decryptedMessageActionNotifyLayer(
+            schema::decryptedMessageActionNotifyLayer { layer: SECRET_LAYER });
+        self.queue_action(chat_id, notify_layer);
+    }
+
+    fn chat_mut(&mut self, chat_id: i32) -> error::Result<&mut SecretChat> {
+        self.chats.get_mut(&chat_id).ok_or(ErrorKind::SecretChatNotFound(chat_id).into())
+    }
+
+    fn queue_action(&mut self, chat_id: i32, action: schema::DecryptedMessageAction) {
+        let message = schema::DecryptedMessage::decryptedMessageService(schema::decryptedMessageService {
+            random_id: rand::thread_rng().gen(),
+            action: action,
+        });
+
+        self.outgoing.push_back((chat_id, message));
+    }
+
+    fn send_now<C: RpcCaller>(&mut self, caller: &mut C, chat_id: i32, message: schema::DecryptedMessage)
+        -> error::Result<schema::messages::SentEncryptedMessage>
+    {
+        let random_id = message.random_id();
+        let is_service = match message {
+            schema::DecryptedMessage::decryptedMessageService(_) => true,
+            schema::DecryptedMessage::decryptedMessage(_) => false,
+        };
+
+        let (peer, data) = {
+            let chat = self.chat_mut(chat_id)?;
+            (chat.input_peer(), ByteBuf::from(chat.encrypt(message)?))
+        };
+
+        if is_service {
+            caller.call(schema::rpc::messages::sendEncryptedService {
+                peer: peer,
+                random_id: random_id,
+                data: data,
+            })
+        } else {
+            caller.call(schema::rpc::messages::sendEncrypted {
+                peer: peer,
+                random_id: random_id,
+                data: data,
+            })
+        }
+    }
+
+    fn handle_action(&mut self, chat_id: i32, action: &schema::DecryptedMessageAction) -> error::Result<()> {
+        let reply = {
+            let chat = self.chat_mut(chat_id)?;
+
+            match *action {
+                schema::DecryptedMessageAction::decryptedMessageActionNotifyLayer(ref notify) => {
+                    chat.peer_layer = notify.layer;
+                    None
+                },
+                schema::DecryptedMessageAction::decryptedMessageActionRequestKey(ref request) => {
+                    handle_request_key(chat, request)?
+                },
+                schema::DecryptedMessageAction::decryptedMessageActionAcceptKey(ref accept) => {
+                    handle_accept_key(chat, accept)?
+                },
+                schema::DecryptedMessageAction::decryptedMessageActionCommitKey(ref commit) => {
+                    handle_commit_key(chat, commit)
+                },
+                schema::DecryptedMessageAction::decryptedMessageActionAbortKey(ref abort) => {
+                    handle_abort_key(chat, abort);
+                    None
+                },
+                _ => None,
+            }
+        };
+
+        if let Some(reply) = reply {
+            self.queue_action(chat_id, reply);
+        }
+
+        Ok(())
+    }
+}
+
+
+fn handle_request_key(chat: &mut SecretChat, request: &schema::decryptedMessageActionRequestKey)
+    -> error::Result<Option<schema::DecryptedMessageAction>>
+{
+    match chat.key_exchange {
+        // When both parties start an exchange simultaneously, the one
+        // with the bigger id wins
+        Some(KeyExchange::Requested { exchange_id, .. }) if exchange_id > request.exchange_id => {
+            return Ok(None);
+        },
+        Some(KeyExchange::Requested { .. }) | None => (),
+        // Another exchange is in progress
+        Some(_) => return Ok(None),
+    }
+
+    let (secret, g_b) = chat.dh_params.generate(&[])?;
+    let key = chat.dh_params.compute_key(&secret, &request.g_a)?;
+    let key_fingerprint = key.fingerprint();
+
+    chat.key_exchange = Some(KeyExchange::Accepted { exchange_id: request.exchange_id, key: key });
+
+    let accept = schema::decryptedMessageActionAcceptKey {
+        exchange_id: request.exchange_id,
+        g_b: ByteBuf::from(g_b),
+        key_fingerprint: key_fingerprint,
+    };
+
+    Ok(Some(schema::DecryptedMessageAction::decryptedMessageActionAcceptKey(accept)))
+}
+
+fn handle_accept_key(chat: &mut SecretChat, accept: &schema::decryptedMessageActionAcceptKey)
+    -> error::Result<Option<schema::DecryptedMessageAction>>
+{
+    let key = match chat.key_exchange {
+        Some(KeyExchange::Requested { exchange_id, ref secret }) if exchange_id == accept.exchange_id => {
+            chat.dh_params.compute_key(secret, &accept.g_b)?
+        },
+        _ => return Ok(None),
+    };
+
+    if key.fingerprint() != accept.key_fingerprint {
+        warn!("Key fingerprint mismatch in secret chat {}, aborting key exchange", chat.id);
+        chat.key_exchange = None;
+
+        let abort = schema::decryptedMessageActionAbortKey { exchange_id: accept.exchange_id };
+        return Ok(Some(schema::DecryptedMessageAction::decryptedMessageActionAbortKey(abort)));
+    }
+
+    let key_fingerprint = key.fingerprint();
+    chat.key_exchange = Some(KeyExchange::Committed { exchange_id: accept.exchange_id, key: key });
+
+    let commit = schema::decryptedMessageActionCommitKey {
+        exchange_id: accept.exchange_id,
+        key_fingerprint: key_fingerprint,
+    };
+
+    Ok(Some(schema::DecryptedMessageAction::decryptedMessageActionCommitKey(commit)))
+}
+
+fn handle_commit_key(chat: &mut SecretChat, commit: &schema::decryptedMessageActionCommitKey)
+    -> Option<schema::DecryptedMessageAction>
+{
+    let matches = match chat.key_exchange {
+        Some(KeyExchange::Accepted { exchange_id, ref key }) => {
+            exchange_id == commit.exchange_id && key.fingerprint() == commit.key_fingerprint
+        },
+        _ => return None,
+    };
+
+    let exchange = chat.key_exchange.take();
+    if !matches {
+        warn!("Key fingerprint mismatch in secret chat {}, aborting key exchange", chat.id);
+
+        let abort = schema::decryptedMessageActionAbortKey { exchange_id: commit.exchange_id };
+        return Some(schema::DecryptedMessageAction::decryptedMessageActionAbortKey(abort));
+    }
+
+    if let Some(KeyExchange::Accepted { key, .. }) = exchange {
+        chat.switch_key(key);
+    }
+
+    // Confirm the switch with a message encrypted with the new key
+    Some(schema::DecryptedMessageAction::decryptedMessageActionNoop)
+}
+
+fn handle_abort_key(chat: &mut SecretChat, abort: &schema::decryptedMessageActionAbortKey) {
+    let aborted = match chat.key_exchange {
+        Some(KeyExchange::Requested { exchange_id, .. }) |
+        Some(KeyExchange::Accepted { exchange_id, .. }) => exchange_id == abort.exchange_id,
+        _ => false,
+    };
+
+    if aborted {
+        debug!("Key exchange {} aborted in secret chat {}", abort.exchange_id, chat.id);
+        chat.key_exchange = None;
+    }
+}
@@ -0,0 +1,483 @@
+//! JSON representation of TL values.
+//!
+//! Objects are represented as JSON objects with the full constructor name
+//! stored under the `_` key, e.g.
+//! `{"_": "future_salt", "valid_since": 1, "valid_until": 2, "salt": 3}`.
+//! `bytes` are encoded with base64, `int128` and `int256` as decimal
+//! strings and `true` fields as booleans. Optional fields which are not
+//! set are omitted, and flags fields are recomputed from the optional
+//! fields present when converting back from JSON.
+//!
+//! Conversion goes through the wire format and a `RuntimeSchema` loaded
+//! with the definitions `schema` was generated from, so it works for any
+//! schema type as well as for `Box<TLObject>`.
+
+use base64;
+use byteorder::{LittleEndian, WriteBytesExt};
+use extprim::i128::i128;
+use serde::de::{DeserializeOwned, DeserializeSeed};
+use serde::ser::Serialize;
+use serde_json::{Map, Number, Value};
+use serde_mtproto::{self, Boxed, Identifiable};
+use tl_codegen::ast::{Constructor, Field, Type};
+
+use error::{self, ErrorKind};
+use schema;
+
+use super::dynamic::{TLConstructorsMap, TLObject};
+use super::runtime::{self, RuntimeSchema};
+use super::value::TLValue;
+
+
+/// Key under which constructor names are stored.
+pub const CONSTRUCTOR_KEY: &'static str = "_";
+
+/// Maximum nesting of JSON objects and arrays, same as for TL payloads.
+const MAX_DEPTH: usize = 64;
+
+
+thread_local! {
+    static COMPILED_SCHEMA: RuntimeSchema = RuntimeSchema::compiled();
+}
+
+
+/// Convert a value of any schema type (including `Box<TLObject>`) to
+/// JSON.
+pub fn to_json<T: Serialize + Identifiable>(value: &T) -> error::Result<Value> {
+    let mut bytes = Vec::new();
+    bytes.write_u32::<LittleEndian>(Identifiable::type_id(value))?;
+    bytes.extend(serde_mtproto::to_bytes(value)?);
+
+    let tl_value = COMPILED_SCHEMA.with(|schema| schema.decode(&bytes))?;
+
+    Ok(tl_value.to_json())
+}
+
+/// Convert JSON produced by `to_json` back to a value of a schema type.
+pub fn from_json<T: DeserializeOwned + Identifiable>(json: &Value) -> error::Result<T> {
+    let bytes = COMPILED_SCHEMA.with(|schema| encode_json(schema, json))?;
+    let boxed: Boxed<T> = serde_mtproto::from_reader(bytes.as_slice(), None)?;
+
+    Ok(boxed.into_inner())
+}
+
+/// Convert JSON to a dynamically-typed object using the constructor
+/// named by its `_` key.
+pub fn object_from_json(json: &Value) -> error::Result<Box<TLObject>> {
+    let bytes = COMPILED_SCHEMA.with(|schema| encode_json(schema, json))?;
+
+    let mut cmap = TLConstructorsMap::new();
+    schema::register_ctors(&mut cmap);
+
+    let mut deserializer = serde_mtproto::Deserializer::new(bytes.as_slice(), None);
+    let object = cmap.deserialize(&mut deserializer)?;
+
+    Ok(object)
+}
+
+fn encode_json(schema: &RuntimeSchema, json: &Value) -> error::Result<Vec<u8>> {
+    let tl_value = schema.value_from_json(json)?;
+    schema.encode(&tl_value)
+}
+
+
+impl TLValue {
+    /// JSON representation of this value.
+    pub fn to_json(&self) -> Value {
+        match *self {
+            TLValue::Int(i) => Value::from(i),
+            TLValue::Long(l) => Value::from(l),
+            TLValue::Double(d) => Number::from_f64(d).map(Value::Number).unwrap_or(Value::Null),
+            TLValue::Int128(i) => Value::String(i.to_string()),
+            TLValue::Int256(lo, hi) => Value::String(int256_to_string(lo, hi)),
+            TLValue::Bool(b) => Value::Bool(b),
+            TLValue::True => Value::Bool(true),
+            TLValue::String(ref s) => Value::String(s.clone()),
+            TLValue::Bytes(ref b) => Value::String(base64::encode(b)),
+            TLValue::Vector(ref elements) => Value::Array(elements.iter().map(TLValue::to_json).collect()),
+            TLValue::Object { ref name, ref fields, .. } => {
+                let mut map = Map::new();
+                map.insert(CONSTRUCTOR_KEY.to_owned(), Value::String(name.clone()));
+
+                for &(ref field_name, ref value) in fields {
+                    map.insert(field_name.clone(), value.to_json());
+                }
+
+                Value::Object(map)
+            },
+        }
+    }
+}
+
+
+impl RuntimeSchema {
+    /// Convert JSON with a `_` constructor name to a boxed `TLValue`
+    /// using field types of this schema.
+    pub fn value_from_json(&self, json: &Value) -> error::Result<TLValue> {
+        JsonConverter { schema: self }.boxed_from_json(json, 0)
+    }
+}
+
+
+struct JsonConverter<'s> {
+    schema: &'s RuntimeSchema,
+}
+
+impl<'s> JsonConverter<'s> {
+    fn boxed_from_json(&self, json: &Value, depth: usize) -> error::Result<TLValue> {
+        match *json {
+            Value::Bool(b) => Ok(TLValue::Bool(b)),
+            Value::Object(ref map) => {
+                let name = map.get(CONSTRUCTOR_KEY).and_then(Value::as_str)
+                    .ok_or_else(|| unexpected("an object with a constructor name", json))?;
+                let constructor = self.schema.constructor_by_name(name)
+                    .ok_or(error::Error::from(ErrorKind::UnknownTlType(name.to_owned())))?;
+
+                self.object_from_json(constructor, map, depth)
+            },
+            _ => Err(unexpected("an object", json)),
+        }
+    }
+
+    fn object_from_json(&self,
+                        constructor: &'s Constructor,
+                        map: &Map<String, Value>,
+                        depth: usize)
+                       -> error::Result<TLValue>
+    {
+        if depth > MAX_DEPTH {
+            bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+        }
+
+        let mut fields = Vec::with_capacity(constructor.fields().len());
+
+        for (index, field) in constructor.fields().iter().enumerate() {
+            let name = field.name().map(ToOwned::to_owned).unwrap_or_else(|| index.to_string());
+
+            let value = match *field.ty() {
+                Type::Int => {
+                    // Keep unknown bits and set the others according to
+                    // the optional fields present
+                    let mut flags = match map.get(&name) {
+                        Some(json) => json_to_i32(json)? as u32, // from i32
+                        None => 0,
+                    };
+
+                    for flagged in constructor.fields() {
+                        if let Type::Flagged(ref flags_name, bit, ref ty) = *flagged.ty() {
+                            if *flags_name != name || bit >= 32 {
+                                continue;
+                            }
+
+                            if flagged.name().and_then(|n| map.get(n)).map_or(false, |json| is_present(json, ty)) {
+                                flags |= 1 << bit;
+                            } else {
+                                flags &= !(1 << bit);
+                            }
+                        }
+                    }
+
+                    TLValue::Int(flags as i32) // from u32
+                },
+                Type::Flagged(_, _, ref ty) => match map.get(&name) {
+                    Some(json) if is_present(json, ty) => self.type_from_json(ty, json, constructor, depth)?,
+                    _ => continue,
+                },
+                ref ty => {
+                    let json = map.get(&name).ok_or_else(|| {
+                        let description = format!("missing field `{}` of `{}`",
+                            name, runtime::full_name(constructor.variant()));
+                        error::Error::from(ErrorKind::UnexpectedJson(description))
+                    })?;
+
+                    self.type_from_json(ty, json, constructor, depth)?
+                },
+            };
+
+            fields.push((name, value));
+        }
+
+        Ok(TLValue::Object {
+            name: runtime::full_name(constructor.variant()),
+            id: constructor.tl_id().unwrap_or(0),
+            fields: fields,
+        })
+    }
+
+    fn type_from_json(&self,
+                      ty: &Type,
+                      json: &Value,
+                      constructor: &'s Constructor,
+                      depth: usize)
+                     -> error::Result<TLValue>
+    {
+        match *ty {
+            Type::Int => json_to_i32(json).map(TLValue::Int),
+            Type::TypeParameter(_) => self.boxed_from_json(json, depth + 1),
+            Type::Named(ref names) => self.named_from_json(names, json, constructor, depth),
+            Type::Generic(ref names, ref tys) => {
+                let container = names.join(".");
+                let ty = runtime::container_argument(&container, tys)?;
+
+                match container.as_str() {
+                    "Vector" | "vector" => {
+                        let elements = json.as_array().ok_or_else(|| unexpected("an array", json))?;
+
+                        if depth > MAX_DEPTH {
+                            bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+                        }
+
+                        elements.iter()
+                            .map(|element| self.type_from_json(ty, element, constructor, depth + 1))
+                            .collect::<error::Result<Vec<_>>>()
+                            .map(TLValue::Vector)
+                    },
+                    "WithSize" => self.type_from_json(ty, json, constructor, depth),
+                    _ => bail!(ErrorKind::UnknownTlType(container.clone())),
+                }
+            },
+            Type::Flagged(..) => bail!(ErrorKind::UnknownTlType("nested optional field".to_owned())),
+            Type::Repeated(ref fields) => {
+                let elements = json.as_array().ok_or_else(|| unexpected("an array", json))?;
+
+                if depth > MAX_DEPTH {
+                    bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+                }
+
+                elements.iter()
+                    .map(|element| self.repeated_element_from_json(fields, element, constructor, depth + 1))
+                    .collect::<error::Result<Vec<_>>>()
+                    .map(TLValue::Vector)
+            },
+        }
+    }
+
+    /// Elements of repeated fields with several fields are arrays of
+    /// their field values.
+    fn repeated_element_from_json(&self,
+                                  fields: &[Field],
+                                  json: &Value,
+                                  constructor: &'s Constructor,
+                                  depth: usize)
+                                 -> error::Result<TLValue>
+    {
+        if fields.len() == 1 {
+            return self.type_from_json(fields[0].ty(), json, constructor, depth);
+        }
+
+        let values = match json.as_array() {
+            Some(values) if values.len() == fields.len() => values,
+            _ => return Err(unexpected(&format!("an array of {} values", fields.len()), json)),
+        };
+
+        fields.iter().zip(values)
+            .map(|(field, value)| self.type_from_json(field.ty(), value, constructor, depth))
+            .collect::<error::Result<Vec<_>>>()
+            .map(TLValue::Vector)
+    }
+
+    fn named_from_json(&self,
+                       names: &[String],
+                       json: &Value,
+                       constructor: &'s Constructor,
+                       depth: usize)
+                      -> error::Result<TLValue>
+    {
+        let name = names.join(".");
+
+        let value = match name.as_str() {
+            "int" => TLValue::Int(json_to_i32(json)?),
+            "long" => TLValue::Long(json_to_i64(json)?),
+            "double" => TLValue::Double(json.as_f64().ok_or_else(|| unexpected("a number", json))?),
+            "int128" => {
+                let int128 = json_to_string(json)?.parse::<i128>().ok()
+                    .ok_or_else(|| unexpected("an int128 decimal string", json))?;
+
+                TLValue::Int128(int128)
+            },
+            "int256" => {
+                let (lo, hi) = int256_from_str(&json_to_string(json)?)
+                    .ok_or_else(|| unexpected("an int256 decimal string", json))?;
+
+                TLValue::Int256(lo, hi)
+            },
+            "string" => TLValue::String(json.as_str().ok_or_else(|| unexpected("a string", json))?.to_owned()),
+            "bytes" => {
+                let encoded = json.as_str().ok_or_else(|| unexpected("a base64 string", json))?;
+                TLValue::Bytes(base64::decode(encoded)?)
+            },
+            "true" => TLValue::True,
+            "Bool" => TLValue::Bool(json.as_bool().ok_or_else(|| unexpected("a boolean", json))?),
+            "Object" => self.boxed_from_json(json, depth + 1)?,
+            _ if constructor.type_parameters().iter().any(|p| p.name() == Some(name.as_str())) => {
+                self.boxed_from_json(json, depth + 1)?
+            },
+            _ if runtime::starts_with_uppercase(names.last().map(String::as_str).unwrap_or("")) => {
+                self.boxed_from_json(json, depth + 1)?
+            },
+            _ => {
+                // A bare constructor, its name is optional
+                let bare = self.schema.constructor_by_name(&name)
+                    .ok_or(error::Error::from(ErrorKind::UnknownTlType(name.clone())))?;
+                let map = json.as_object().ok_or_else(|| unexpected("an object", json))?;
+
+                self.object_from_json(bare, map, depth + 1)?
+            },
+        };
+
+        Ok(value)
+    }
+}
+
+
+/// `null` and `false` for `true` fields both mean that an optional field
+/// is not set.
+fn is_present(json: &Value, ty: &Type) -> bool {
+    match (json, ty) {
+        (&Value::Null, _) => false,
+        (&Value::Bool(false), &Type::Named(ref names)) => !(names.len() == 1 && names[0] == "true"),
+        _ => true,
+    }
+}
+
+fn unexpected(expected: &str, found: &Value) -> error::Error {
+    ErrorKind::UnexpectedJson(format!("expected {}, found {}", expected, found)).into()
+}
+
+fn json_to_string(json: &Value) -> error::Result<String> {
+    match *json {
+        Value::String(ref s) => Ok(s.clone()),
+        Value::Number(ref n) => Ok(n.to_string()),
+        _ => Err(unexpected("a string", json)),
+    }
+}
+
+/// Large integers are also accepted as strings since not every JSON
+/// implementation handles 64-bit numbers.
+fn json_to_i64(json: &Value) -> error::Result<i64> {
+    let integer = match *json {
+        Value::Number(ref n) => n.as_i64(),
+        Value::String(ref s) => s.parse().ok(),
+        _ => None,
+    };
+
+    integer.ok_or_else(|| unexpected("an integer", json))
+}
+
+/// Both signed and unsigned 32-bit integers are accepted, the latter is
+/// common for flags.
+fn json_to_i32(json: &Value) -> error::Result<i32> {
+    let integer = json_to_i64(json)?;
+
+    if integer < ::std::i32::MIN as i64 || integer > ::std::u32::MAX as i64 { // from i32, u32
+        return Err(unexpected("a 32-bit integer", json));
+    }
+
+    Ok(integer as i32) // from i64
+}
+
+
+/// 256-bit integers are represented as `(lo, hi)` pairs with `lo` being
+/// stored on the wire first. Convert them to little-endian 32-bit limbs.
+fn int256_to_limbs(lo: i128, hi: i128) -> [u32; 8] {
+    let words = [lo.low64(), lo.high64() as u64, hi.low64(), hi.high64() as u64]; // from i64
+    let mut limbs = [0; 8];
+
+    for (i, word) in words.iter().enumerate() {
+        limbs[2 * i] = *word as u32; // from u64
+        limbs[2 * i + 1] = (*word >> 32) as u32; // from u64
+    }
+
+    limbs
+}
+
+fn limbs_to_int256(limbs: &[u32; 8]) -> (i128, i128) {
+    let word = |i: usize| limbs[2 * i] as u64 | (limbs[2 * i + 1] as u64) << 32; // from u32
+
+    (i128::from_parts(word(1) as i64, word(0)), i128::from_parts(word(3) as i64, word(2))) // from u64
+}
+
+/// Two's complement negation.
+fn negate_limbs(limbs: &mut [u32; 8]) {
+    let mut carry = 1;
+
+    for limb in limbs.iter_mut() {
+        let sum = (!*limb) as u64 + carry; // from u32
+        *limb = sum as u32; // from u64
+        carry = sum >> 32;
+    }
+}
+
+fn int256_to_string(lo: i128, hi: i128) -> String {
+    const CHUNK: u64 = 1_000_000_000;
+
+    let mut limbs = int256_to_limbs(lo, hi);
+    let negative = limbs[7] & 0x8000_0000 != 0;
+    if negative {
+        negate_limbs(&mut limbs);
+    }
+
+    // Decimal digits in chunks of 9, least significant first
+    let mut chunks = Vec::new();
+    while limbs.iter().any(|&limb| limb != 0) {
+        let mut remainder = 0u64;
+
+        for limb in limbs.iter_mut().rev() {
+            let current = remainder << 32 | *limb as u64; // from u32
+            *limb = (current / CHUNK) as u32; // from u64
+            remainder = current % CHUNK;
+        }
+
+        chunks.push(remainder);
+    }
+
+    let mut result = if negative { "-".to_owned() } else { String::new() };
+    match chunks.pop() {
+        Some(chunk) => result.push_str(&chunk.to_string()),
+        None => result.push('0'),
+    }
+    for chunk in chunks.iter().rev() {
+        result.push_str(&format!("{:09}", chunk));
+    }
+
+    result
+}
+
+fn int256_from_str(s: &str) -> Option<(i128, i128)> {
+    let (negative, digits) = if s.starts_with('-') { (true, &s[1..]) } else { (false, s) };
+    if digits.is_empty() {
+        return None;
+    }
+
+    let mut limbs = [0u32; 8];
+    for digit in digits.bytes() {
+        let mut carry = match digit {
+            b'0'...b'9' => (digit - b'0') as u64, // from u8
+            _ => return None,
+        };
+
+        for limb in limbs.iter_mut() {
+            let current = *limb as u64 * 10 + carry; // from u32
+            *limb = current as u32; // from u64
+            carry = current >> 32;
+        }
+
+        if carry != 0 {
+            return None;
+        }
+    }
+
+    if limbs[7] & 0x8000_0000 != 0 {
+        // Only -2^255 has its highest bit set before negation
+        let is_min = limbs[7] == 0x8000_0000 && limbs[..7].iter().all(|&limb| limb == 0);
+        if !(negative && is_min) {
+            return None;
+        }
+    }
+
+    if negative {
+        negate_limbs(&mut limbs);
+    }
+
+    Some(limbs_to_int256(&limbs))
+}
@@ -1,5 +1,13 @@
 //! Definitions to represent Type Language concepts in Rust.
 
 pub mod dynamic;
+#[cfg(feature = "runtime-schema")]
+pub mod json;
+#[cfg(feature = "runtime-schema")]
+pub mod runtime;
+pub mod value;
 
 pub use self::dynamic::{TLConstructorsMap, TLObject};
+#[cfg(feature = "runtime-schema")]
+pub use self::runtime::RuntimeSchema;
+pub use self::value::TLValue;
@@ -0,0 +1,754 @@
+//! TL schemas loaded at runtime.
+//!
+//! Unlike `TLConstructorsMap` which only knows constructors compiled into
+//! `schema`, `RuntimeSchema` parses `.tl` files with the same parser as
+//! `tl_codegen` and decodes payloads into `TLValue` trees. This is handy
+//! to inspect traffic of layers newer than the compiled one.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use extprim::i128::i128;
+use tl_codegen::ast::{Constructor, Delimiter, Field, Item, Type};
+use tl_codegen::parser;
+
+use error::{self, ErrorKind};
+
+use super::value::TLValue;
+
+
+const VECTOR_ID: u32 = 0x1cb5c415;
+const BOOL_FALSE_ID: u32 = 0xbc799737;
+const BOOL_TRUE_ID: u32 = 0x997275b5;
+
+/// Maximum nesting of objects and vectors; deeper payloads are most
+/// likely malicious.
+const MAX_DEPTH: usize = 64;
+
+/// Schema files `schema` is generated from.
+const COMPILED_SCHEMA_FILES: &'static [&'static str] = &[
+    include_str!("../../tl/000-scheme.tl"),
+    include_str!("../../tl/001-manual.tl"),
+    include_str!("../../tl/002-secret.tl"),
+];
+
+
+/// Constructors and functions of a TL schema.
+#[derive(Debug, Default)]
+pub struct RuntimeSchema {
+    layer: Option<u32>,
+    constructors: HashMap<u32, Constructor>,
+    ids_by_name: HashMap<String, u32>,
+    functions: HashSet<u32>,
+}
+
+impl RuntimeSchema {
+    /// Create a schema without any constructors.
+    pub fn new() -> RuntimeSchema {
+        Default::default()
+    }
+
+    /// Parse a schema from its textual representation.
+    pub fn parse(input: &str) -> error::Result<RuntimeSchema> {
+        let mut schema = RuntimeSchema::new();
+        schema.extend_from_str(input)?;
+
+        Ok(schema)
+    }
+
+    /// Schema with the same definitions `schema` was generated from.
+    pub fn compiled() -> RuntimeSchema {
+        let mut schema = RuntimeSchema::new();
+        for input in COMPILED_SCHEMA_FILES {
+            schema.extend_from_str(input).expect("compiled TL schema must be valid");
+        }
+
+        schema
+    }
+
+    /// Read and parse a `.tl` file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> error::Result<RuntimeSchema> {
+        let mut schema = RuntimeSchema::new();
+        schema.extend_from_file(path)?;
+
+        Ok(schema)
+    }
+
+    /// Add constructors and functions defined in `input`.
+    ///
+    /// Definitions with already known IDs or names replace the old ones,
+    /// so schemas can be layered on top of each other. Definitions
+    /// without an explicit ID are skipped.
+    pub fn extend_from_str(&mut self, input: &str) -> error::Result<()> {
+        let items = parser::parse_string(input)
+            .map_err(|e| ErrorKind::TlSchemaParse(e.to_string()))?;
+
+        let mut delimiter = Delimiter::Types;
+
+        for item in items {
+            match item {
+                Item::Layer(layer) => self.layer = Some(layer),
+                Item::Delimiter(d) => delimiter = d,
+                Item::Constructor(mut constructor) => {
+                    let id = match constructor.tl_id() {
+                        // Vectors are handled natively
+                        Some(VECTOR_ID) => continue,
+                        Some(id) => id,
+                        None => {
+                            debug!("Skipping constructor without an ID: {:?}", constructor.variant());
+                            continue;
+                        },
+                    };
+
+                    // Keep in sync with the types of generated fields
+                    constructor.fixup_binary_strings();
+                    constructor.fixup_repeated_fields();
+
+                    if delimiter == Delimiter::Functions {
+                        self.functions.insert(id);
+                    } else {
+                        self.functions.remove(&id);
+                    }
+
+                    self.ids_by_name.insert(full_name(constructor.variant()), id);
+                    self.constructors.insert(id, constructor);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a `.tl` file and add definitions from it.
+    pub fn extend_from_file<P: AsRef<Path>>(&mut self, path: P) -> error::Result<()> {
+        let mut input = String::new();
+        File::open(path)?.read_to_string(&mut input)?;
+
+        self.extend_from_str(&input)
+    }
+
+    /// The layer declared with `// LAYER N`, if any.
+    pub fn layer(&self) -> Option<u32> {
+        self.layer
+    }
+
+    /// Number of known constructors and functions.
+    pub fn len(&self) -> usize {
+        self.constructors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constructors.is_empty()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.constructors.contains_key(&id)
+    }
+
+    /// Returns `true` if `id` belongs to a function rather than to a
+    /// constructor of a type.
+    pub fn is_function(&self, id: u32) -> bool {
+        self.functions.contains(&id)
+    }
+
+    /// Full name of a constructor, e.g. `auth.sentCode`.
+    pub fn name_of(&self, id: u32) -> Option<String> {
+        self.constructors.get(&id).map(|c| full_name(c.variant()))
+    }
+
+    /// ID of a constructor by its full name.
+    pub fn id_of(&self, name: &str) -> Option<u32> {
+        self.ids_by_name.get(name).cloned()
+    }
+
+    /// Decode a boxed value from the beginning of `data`.
+    ///
+    /// Trailing bytes (e.g. padding of decrypted messages) are ignored.
+    pub fn decode(&self, data: &[u8]) -> error::Result<TLValue> {
+        self.decode_partial(data).map(|(value, _)| value)
+    }
+
+    /// Decode a boxed value and return it along with the number of bytes
+    /// it occupies.
+    pub fn decode_partial(&self, data: &[u8]) -> error::Result<(TLValue, usize)> {
+        let mut decoder = Decoder {
+            schema: self,
+            reader: Reader { data: data, pos: 0 },
+        };
+
+        let value = decoder.decode_boxed(0)?;
+
+        Ok((value, decoder.reader.pos))
+    }
+
+    /// Decode a bare value of constructor `name`, i.e. without a leading
+    /// constructor ID.
+    pub fn decode_bare(&self, name: &str, data: &[u8]) -> error::Result<TLValue> {
+        let constructor = self.ids_by_name.get(name)
+            .and_then(|id| self.constructors.get(id))
+            .ok_or(error::Error::from(ErrorKind::UnknownTlType(name.to_owned())))?;
+
+        let mut decoder = Decoder {
+            schema: self,
+            reader: Reader { data: data, pos: 0 },
+        };
+
+        decoder.decode_object(constructor, 0)
+    }
+
+    /// Encode a value as a boxed object, the reverse of `decode`.
+    ///
+    /// Optional fields are written according to the flags field of the
+    /// value which is therefore expected to be consistent with them.
+    pub fn encode(&self, value: &TLValue) -> error::Result<Vec<u8>> {
+        let mut encoder = Encoder {
+            schema: self,
+            buf: Vec::new(),
+        };
+
+        encoder.encode_boxed(value, 0)?;
+
+        Ok(encoder.buf)
+    }
+
+    pub(crate) fn constructor_by_name(&self, name: &str) -> Option<&Constructor> {
+        self.ids_by_name.get(name).and_then(|id| self.constructors.get(id))
+    }
+}
+
+
+struct Decoder<'s, 'd> {
+    schema: &'s RuntimeSchema,
+    reader: Reader<'d>,
+}
+
+impl<'s, 'd> Decoder<'s, 'd> {
+    fn decode_boxed(&mut self, depth: usize) -> error::Result<TLValue> {
+        let id = self.reader.read_u32()?;
+
+        match id {
+            BOOL_FALSE_ID => Ok(TLValue::Bool(false)),
+            BOOL_TRUE_ID => Ok(TLValue::Bool(true)),
+            _ => {
+                let constructor = self.schema.constructors.get(&id)
+                    .ok_or(error::Error::from(ErrorKind::UnknownConstructorId("TLValue", id)))?;
+
+                self.decode_object(constructor, depth)
+            },
+        }
+    }
+
+    fn decode_object(&mut self, constructor: &'s Constructor, depth: usize) -> error::Result<TLValue> {
+        if depth > MAX_DEPTH {
+            bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+        }
+
+        let mut fields: Vec<(String, TLValue)> = Vec::with_capacity(constructor.fields().len());
+
+        for (index, field) in constructor.fields().iter().enumerate() {
+            let name = field.name().map(ToOwned::to_owned).unwrap_or_else(|| index.to_string());
+
+            let value = match *field.ty() {
+                Type::Flagged(ref flags_name, bit, ref ty) => {
+                    if !flag_is_set(&fields, flags_name, bit)? {
+                        continue;
+                    }
+
+                    self.decode_type(ty, constructor, depth)?
+                },
+                ref ty => self.decode_type(ty, constructor, depth)?,
+            };
+
+            fields.push((name, value));
+        }
+
+        Ok(TLValue::Object {
+            name: full_name(constructor.variant()),
+            id: constructor.tl_id().unwrap_or(0),
+            fields: fields,
+        })
+    }
+
+    fn decode_type(&mut self, ty: &Type, constructor: &'s Constructor, depth: usize) -> error::Result<TLValue> {
+        match *ty {
+            Type::Int => self.reader.read_i32().map(TLValue::Int),
+            Type::TypeParameter(_) => self.decode_boxed(depth + 1),
+            Type::Named(ref names) => self.decode_named(names, constructor, depth),
+            Type::Generic(ref names, ref tys) => {
+                let container = names.join(".");
+                let ty = container_argument(&container, tys)?;
+
+                match container.as_str() {
+                    "Vector" => {
+                        let id = self.reader.read_u32()?;
+                        if id != VECTOR_ID {
+                            bail!(ErrorKind::UnknownConstructorId("Vector", id));
+                        }
+
+                        self.decode_vector(ty, constructor, depth)
+                    },
+                    "vector" => self.decode_vector(ty, constructor, depth),
+                    "WithSize" => {
+                        self.reader.read_u32()?;
+                        self.decode_type(ty, constructor, depth)
+                    },
+                    _ => bail!(ErrorKind::UnknownTlType(container.clone())),
+                }
+            },
+            Type::Flagged(..) => bail!(ErrorKind::UnknownTlType("nested optional field".to_owned())),
+            Type::Repeated(ref fields) => self.decode_repeated(fields, constructor, depth),
+        }
+    }
+
+    fn decode_named(&mut self, names: &[String], constructor: &'s Constructor, depth: usize) -> error::Result<TLValue> {
+        let name = names.join(".");
+
+        let value = match name.as_str() {
+            "int" => TLValue::Int(self.reader.read_i32()?),
+            "long" => TLValue::Long(self.reader.read_i64()?),
+            "double" => TLValue::Double(self.reader.read_f64()?),
+            "int128" => TLValue::Int128(self.reader.read_i128()?),
+            "int256" => {
+                let lo = self.reader.read_i128()?;
+                let hi = self.reader.read_i128()?;
+                TLValue::Int256(lo, hi)
+            },
+            // Binary data is only expected in `bytes` fields (see
+            // `fixup_binary_strings`), so that values of `string` fields
+            // convert to JSON and back unchanged
+            "string" => TLValue::String(String::from_utf8_lossy(&self.reader.read_bytes()?).into_owned()),
+            "bytes" => TLValue::Bytes(self.reader.read_bytes()?),
+            "true" => TLValue::True,
+            "Bool" => match self.reader.read_u32()? {
+                BOOL_FALSE_ID => TLValue::Bool(false),
+                BOOL_TRUE_ID => TLValue::Bool(true),
+                id => bail!(ErrorKind::UnknownConstructorId("Bool", id)),
+            },
+            "Object" => self.decode_boxed(depth + 1)?,
+            _ if constructor.type_parameters().iter().any(|p| p.name() == Some(name.as_str())) => {
+                self.decode_boxed(depth + 1)?
+            },
+            _ if starts_with_uppercase(names.last().map(String::as_str).unwrap_or("")) => {
+                self.decode_boxed(depth + 1)?
+            },
+            _ => {
+                // A bare constructor
+                let bare = self.schema.ids_by_name.get(&name)
+                    .and_then(|id| self.schema.constructors.get(id))
+                    .ok_or(error::Error::from(ErrorKind::UnknownTlType(name.clone())))?;
+
+                self.decode_object(bare, depth + 1)?
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn decode_vector(&mut self, ty: &Type, constructor: &'s Constructor, depth: usize) -> error::Result<TLValue> {
+        if depth > MAX_DEPTH {
+            bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+        }
+
+        let count = self.reader.read_u32()? as usize; // from u32
+
+        // Every element except `true` occupies at least 4 bytes
+        let remaining = self.reader.remaining();
+        if count > remaining {
+            bail!(ErrorKind::NotEnoughData(count, remaining));
+        }
+
+        let mut elements = Vec::with_capacity(count);
+        for _ in 0..count {
+            elements.push(self.decode_type(ty, constructor, depth + 1)?);
+        }
+
+        Ok(TLValue::Vector(elements))
+    }
+
+    /// Decode a repeated field merged with its `#` count field.
+    ///
+    /// Elements with several fields are decoded as vectors of their
+    /// field values, like tuples in the generated code.
+    fn decode_repeated(&mut self, fields: &[Field], constructor: &'s Constructor, depth: usize)
+        -> error::Result<TLValue>
+    {
+        if depth > MAX_DEPTH {
+            bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+        }
+
+        let count = self.reader.read_u32()? as usize; // from u32
+
+        let remaining = self.reader.remaining();
+        if count > remaining {
+            bail!(ErrorKind::NotEnoughData(count, remaining));
+        }
+
+        let mut elements = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut values = Vec::with_capacity(fields.len());
+            for field in fields {
+                values.push(self.decode_type(field.ty(), constructor, depth + 1)?);
+            }
+
+            if values.len() == 1 {
+                elements.push(values.pop().unwrap()); // safe to unwrap
+            } else {
+                elements.push(TLValue::Vector(values));
+            }
+        }
+
+        Ok(TLValue::Vector(elements))
+    }
+}
+
+
+struct Encoder<'s> {
+    schema: &'s RuntimeSchema,
+    buf: Vec<u8>,
+}
+
+impl<'s> Encoder<'s> {
+    fn encode_boxed(&mut self, value: &TLValue, depth: usize) -> error::Result<()> {
+        match *value {
+            TLValue::Bool(b) => {
+                self.write_u32(if b { BOOL_TRUE_ID } else { BOOL_FALSE_ID });
+                Ok(())
+            },
+            TLValue::Object { id, ref fields, .. } => {
+                let constructor = self.schema.constructors.get(&id)
+                    .ok_or(error::Error::from(ErrorKind::UnknownConstructorId("TLValue", id)))?;
+
+                self.write_u32(id);
+                self.encode_object(constructor, fields, depth)
+            },
+            _ => bail!(mismatch("a boxed object", value)),
+        }
+    }
+
+    fn encode_object(&mut self,
+                     constructor: &'s Constructor,
+                     fields: &[(String, TLValue)],
+                     depth: usize)
+                    -> error::Result<()>
+    {
+        if depth > MAX_DEPTH {
+            bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+        }
+
+        for (index, field) in constructor.fields().iter().enumerate() {
+            let name = field.name().map(ToOwned::to_owned).unwrap_or_else(|| index.to_string());
+
+            let ty = match *field.ty() {
+                Type::Flagged(ref flags_name, bit, ref ty) => {
+                    if !flag_is_set(fields, flags_name, bit)? {
+                        continue;
+                    }
+
+                    ty
+                },
+                ref ty => ty,
+            };
+
+            let value = fields.iter()
+                .find(|&&(ref field_name, _)| *field_name == name)
+                .map(|&(_, ref value)| value)
+                .ok_or_else(|| {
+                    let description = format!("missing field `{}` of `{}`", name, full_name(constructor.variant()));
+                    error::Error::from(ErrorKind::TlValueMismatch(description))
+                })?;
+
+            self.encode_type(ty, value, constructor, depth)?;
+        }
+
+        Ok(())
+    }
+
+    fn encode_type(&mut self,
+                   ty: &Type,
+                   value: &TLValue,
+                   constructor: &'s Constructor,
+                   depth: usize)
+                  -> error::Result<()>
+    {
+        match *ty {
+            Type::Int => match *value {
+                TLValue::Int(i) => self.write_i32(i),
+                _ => bail!(mismatch("#", value)),
+            },
+            Type::TypeParameter(_) => self.encode_boxed(value, depth + 1)?,
+            Type::Named(ref names) => self.encode_named(names, value, constructor, depth)?,
+            Type::Generic(ref names, ref tys) => {
+                let container = names.join(".");
+                let ty = container_argument(&container, tys)?;
+
+                match container.as_str() {
+                    "Vector" => {
+                        self.write_u32(VECTOR_ID);
+                        self.encode_vector(ty, value, constructor, depth)?;
+                    },
+                    "vector" => self.encode_vector(ty, value, constructor, depth)?,
+                    "WithSize" => {
+                        let start = self.buf.len();
+                        self.write_u32(0);
+                        self.encode_type(ty, value, constructor, depth)?;
+
+                        let size = self.buf.len() - start - 4;
+                        LittleEndian::write_u32(&mut self.buf[start..start + 4], size as u32); // from usize
+                    },
+                    _ => bail!(ErrorKind::UnknownTlType(container.clone())),
+                }
+            },
+            Type::Flagged(..) => bail!(ErrorKind::UnknownTlType("nested optional field".to_owned())),
+            Type::Repeated(ref fields) => self.encode_repeated(fields, value, constructor, depth)?,
+        }
+
+        Ok(())
+    }
+
+    fn encode_named(&mut self,
+                    names: &[String],
+                    value: &TLValue,
+                    constructor: &'s Constructor,
+                    depth: usize)
+                   -> error::Result<()>
+    {
+        let name = names.join(".");
+
+        match (name.as_str(), value) {
+            ("int", &TLValue::Int(i)) => self.write_i32(i),
+            ("long", &TLValue::Long(l)) => self.write_i64(l),
+            ("double", &TLValue::Double(d)) => self.write_f64(d),
+            ("int128", &TLValue::Int128(i)) => self.write_i128(i),
+            ("int256", &TLValue::Int256(lo, hi)) => {
+                self.write_i128(lo);
+                self.write_i128(hi);
+            },
+            ("string", &TLValue::String(ref s)) |
+            ("bytes", &TLValue::String(ref s)) => self.write_bytes(s.as_bytes()),
+            ("string", &TLValue::Bytes(ref b)) |
+            ("bytes", &TLValue::Bytes(ref b)) => self.write_bytes(b),
+            ("true", &TLValue::True) => (),
+            ("Bool", &TLValue::Bool(b)) => self.write_u32(if b { BOOL_TRUE_ID } else { BOOL_FALSE_ID }),
+            ("int", _) | ("long", _) | ("double", _) | ("int128", _) | ("int256", _) |
+            ("string", _) | ("bytes", _) | ("true", _) | ("Bool", _) => bail!(mismatch(&name, value)),
+            ("Object", _) => self.encode_boxed(value, depth + 1)?,
+            _ if constructor.type_parameters().iter().any(|p| p.name() == Some(name.as_str())) => {
+                self.encode_boxed(value, depth + 1)?
+            },
+            _ if starts_with_uppercase(names.last().map(String::as_str).unwrap_or("")) => {
+                self.encode_boxed(value, depth + 1)?
+            },
+            (_, &TLValue::Object { ref fields, .. }) => {
+                // A bare constructor
+                let bare = self.schema.ids_by_name.get(&name)
+                    .and_then(|id| self.schema.constructors.get(id))
+                    .ok_or(error::Error::from(ErrorKind::UnknownTlType(name.clone())))?;
+
+                self.encode_object(bare, fields, depth + 1)?
+            },
+            _ => bail!(mismatch(&name, value)),
+        }
+
+        Ok(())
+    }
+
+    fn encode_vector(&mut self,
+                     ty: &Type,
+                     value: &TLValue,
+                     constructor: &'s Constructor,
+                     depth: usize)
+                    -> error::Result<()>
+    {
+        if depth > MAX_DEPTH {
+            bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+        }
+
+        let elements = match *value {
+            TLValue::Vector(ref elements) => elements,
+            _ => bail!(mismatch("a vector", value)),
+        };
+
+        self.write_u32(elements.len() as u32); // from usize
+        for element in elements {
+            self.encode_type(ty, element, constructor, depth + 1)?;
+        }
+
+        Ok(())
+    }
+
+    fn encode_repeated(&mut self,
+                       fields: &[Field],
+                       value: &TLValue,
+                       constructor: &'s Constructor,
+                       depth: usize)
+                      -> error::Result<()>
+    {
+        if depth > MAX_DEPTH {
+            bail!(ErrorKind::TlValueTooDeep(MAX_DEPTH));
+        }
+
+        let elements = match *value {
+            TLValue::Vector(ref elements) => elements,
+            _ => bail!(mismatch("a vector", value)),
+        };
+
+        self.write_u32(elements.len() as u32); // from usize
+        for element in elements {
+            if fields.len() == 1 {
+                self.encode_type(fields[0].ty(), element, constructor, depth + 1)?;
+                continue;
+            }
+
+            let values = match *element {
+                TLValue::Vector(ref values) if values.len() == fields.len() => values,
+                _ => bail!(mismatch(&format!("a vector of {} values", fields.len()), element)),
+            };
+
+            for (field, value) in fields.iter().zip(values) {
+                self.encode_type(field.ty(), value, constructor, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writing to a `Vec` never fails
+    fn write_u32(&mut self, value: u32) {
+        self.buf.write_u32::<LittleEndian>(value).unwrap();
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.buf.write_i32::<LittleEndian>(value).unwrap();
+    }
+
+    fn write_i64(&mut self, value: i64) {
+        self.buf.write_i64::<LittleEndian>(value).unwrap();
+    }
+
+    fn write_f64(&mut self, value: f64) {
+        self.buf.write_f64::<LittleEndian>(value).unwrap();
+    }
+
+    fn write_i128(&mut self, value: i128) {
+        self.buf.write_u64::<LittleEndian>(value.low64()).unwrap();
+        self.buf.write_i64::<LittleEndian>(value.high64()).unwrap();
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let header_len = if bytes.len() < 254 {
+            self.buf.push(bytes.len() as u8); // from usize
+            1
+        } else {
+            let len = bytes.len();
+            self.buf.extend_from_slice(&[254, len as u8, (len >> 8) as u8, (len >> 16) as u8]); // from usize
+            4
+        };
+
+        self.buf.extend_from_slice(bytes);
+
+        let padding = (4 - (header_len + bytes.len()) % 4) % 4;
+        self.buf.extend_from_slice(&[0; 3][..padding]);
+    }
+}
+
+
+struct Reader<'d> {
+    data: &'d [u8],
+    pos: usize,
+}
+
+impl<'d> Reader<'d> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, len: usize) -> error::Result<&'d [u8]> {
+        if self.remaining() < len {
+            bail!(ErrorKind::NotEnoughData(len, self.remaining()));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> error::Result<u32> {
+        self.take(4).map(LittleEndian::read_u32)
+    }
+
+    fn read_i32(&mut self) -> error::Result<i32> {
+        self.take(4).map(LittleEndian::read_i32)
+    }
+
+    fn read_i64(&mut self) -> error::Result<i64> {
+        self.take(8).map(LittleEndian::read_i64)
+    }
+
+    fn read_f64(&mut self) -> error::Result<f64> {
+        self.take(8).map(LittleEndian::read_f64)
+    }
+
+    fn read_i128(&mut self) -> error::Result<i128> {
+        let lo = self.take(8).map(LittleEndian::read_u64)?;
+        let hi = self.take(8).map(LittleEndian::read_i64)?;
+
+        Ok(i128::from_parts(hi, lo))
+    }
+
+    fn read_bytes(&mut self) -> error::Result<Vec<u8>> {
+        let first = self.take(1)?[0];
+
+        let (len, header_len) = if first < 254 {
+            (first as usize, 1) // from u8
+        } else {
+            let len_bytes = self.take(3)?;
+            let len = len_bytes[0] as usize | (len_bytes[1] as usize) << 8 | (len_bytes[2] as usize) << 16;
+            (len, 4)
+        };
+
+        let bytes = self.take(len)?.to_vec();
+        let padding = (4 - (header_len + len) % 4) % 4;
+        self.take(padding)?;
+
+        Ok(bytes)
+    }
+}
+
+
+pub(super) fn full_name(variant: &Type) -> String {
+    variant.names_vec().map(|names| names.join(".")).unwrap_or_default()
+}
+
+/// The only supported generic types are containers with one argument.
+pub(super) fn container_argument<'t>(container: &str, tys: &'t [Type]) -> error::Result<&'t Type> {
+    if tys.len() != 1 {
+        bail!(ErrorKind::UnknownTlType(format!("{} with {} type arguments", container, tys.len())));
+    }
+
+    Ok(&tys[0])
+}
+
+pub(super) fn starts_with_uppercase(name: &str) -> bool {
+    name.chars().next().map(char::is_uppercase).unwrap_or(false)
+}
+
+fn flag_is_set(fields: &[(String, TLValue)], flags_name: &str, bit: u32) -> error::Result<bool> {
+    let flags = fields.iter()
+        .filter(|&&(ref name, _)| name == flags_name)
+        .filter_map(|&(_, ref value)| match *value {
+            TLValue::Int(flags) => Some(flags as u32), // from i32
+            _ => None,
+        })
+        .next()
+        .ok_or(error::Error::from(ErrorKind::MissingFlagsField(flags_name.to_owned())))?;
+
+    Ok(bit < 32 && flags & (1 << bit) != 0)
+}
+
+fn mismatch(expected: &str, found: &TLValue) -> ErrorKind {
+    ErrorKind::TlValueMismatch(format!("expected {}, found {:?}", expected, found))
+}
@@ -0,0 +1,63 @@
+//! Untyped representation of TL values.
+
+use extprim::i128::i128;
+
+
+/// Any value which can be decoded with a `RuntimeSchema`.
+///
+/// Values of TL `string` type are always decoded as `String`, with
+/// invalid UTF-8 sequences replaced by `U+FFFD`. Constructors which
+/// store binary data in `string` fields (e.g. the ones used during the
+/// key exchange) are decoded as if the fields were `bytes`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TLValue {
+    Int(i32),
+    Long(i64),
+    Double(f64),
+    Int128(i128),
+    Int256(i128, i128),
+    Bool(bool),
+    /// Value of the `true` type which occupies no space.
+    True,
+    String(String),
+    Bytes(Vec<u8>),
+    Vector(Vec<TLValue>),
+    /// A constructor or a function together with its fields in
+    /// declaration order.
+    ///
+    /// Optional fields whose bits are not set in the corresponding flags
+    /// field are omitted.
+    Object {
+        name: String,
+        id: u32,
+        fields: Vec<(String, TLValue)>,
+    },
+}
+
+impl TLValue {
+    /// Name of the constructor if this value is an object.
+    pub fn name(&self) -> Option<&str> {
+        match *self {
+            TLValue::Object { ref name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Constructor ID if this value is an object.
+    pub fn id(&self) -> Option<u32> {
+        match *self {
+            TLValue::Object { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    /// Look up a field of an object by its name.
+    pub fn field(&self, field_name: &str) -> Option<&TLValue> {
+        match *self {
+            TLValue::Object { ref fields, .. } => {
+                fields.iter().find(|&&(ref name, _)| name == field_name).map(|&(_, ref value)| value)
+            },
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,726 @@
+//! Updates state machine.
+//!
+//! The server pushes `Updates` objects which must be applied in a
+//! strict order defined by several sequences: common `pts`, secret chat
+//! `qts`, `seq` of update containers and per-channel `pts`. When a gap
+//! is detected (e.g. after a reconnect) the missing updates are fetched
+//! with `updates.getDifference` or `updates.getChannelDifference`.
+//!
+//! More information about updates handling:
+//! https://core.telegram.org/api/updates.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::vec_deque;
+use std::time::{Duration, Instant};
+
+use error;
+use rpc::RpcCaller;
+use schema;
+
+
+/// How long to wait for missing updates before requesting a difference.
+pub const GAP_TIMEOUT_MILLIS: u64 = 500;
+
+/// Maximum number of updates requested at once with
+/// `updates.getChannelDifference`.
+const CHANNEL_DIFFERENCE_LIMIT: i32 = 100;
+
+
+/// Common updates state of an account.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct State {
+    pub pts: i32,
+    pub qts: i32,
+    pub seq: i32,
+    pub date: i32,
+}
+
+impl From<schema::updates::State> for State {
+    fn from(state: schema::updates::State) -> State {
+        State {
+            pts: state.pts,
+            qts: state.qts,
+            seq: state.seq,
+            date: state.date,
+        }
+    }
+}
+
+
+/// An update ready to be handled by the application.
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// A regular update.
+    Update(schema::Update),
+    /// A short private message.
+    ShortMessage(schema::updateShortMessage),
+    /// A short message in a group chat.
+    ShortChatMessage(schema::updateShortChatMessage),
+    /// A short reply to a sent message.
+    ShortSentMessage(schema::updateShortSentMessage),
+    /// A message received through `updates.getDifference` or
+    /// `updates.getChannelDifference`.
+    NewMessage(schema::Message),
+    /// A secret chat message received through `updates.getDifference`.
+    NewEncryptedMessage(schema::EncryptedMessage),
+    /// Users and chats mentioned in the updates following this event.
+    Entities {
+        users: Vec<schema::User>,
+        chats: Vec<schema::Chat>,
+    },
+}
+
+
+/// Sequence an update belongs to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Sequence {
+    None,
+    Pts { pts: i32, pts_count: i32 },
+    Qts { qts: i32 },
+    ChannelPts { channel_id: i32, pts: i32, pts_count: i32 },
+    ChannelTooLong { channel_id: i32 },
+}
+
+/// Outcome of checking an update against the local state.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Check {
+    Apply,
+    Duplicate,
+    Gap,
+}
+
+fn check_pts(local_pts: i32, pts: i32, pts_count: i32) -> Check {
+    if local_pts + pts_count == pts {
+        Check::Apply
+    } else if local_pts + pts_count > pts {
+        Check::Duplicate
+    } else {
+        Check::Gap
+    }
+}
+
+#[derive(Clone, Debug)]
+struct PendingUpdate {
+    sequence: Sequence,
+    event: Event,
+}
+
+#[derive(Clone, Debug)]
+struct PendingContainer {
+    seq_start: i32,
+    seq: i32,
+    date: i32,
+    updates: Vec<schema::Update>,
+    users: Vec<schema::User>,
+    chats: Vec<schema::Chat>,
+}
+
+
+/// Tracks updates state, detects gaps and yields updates in order.
+#[derive(Debug)]
+pub struct UpdatesManager {
+    state: Option<State>,
+    channel_pts: BTreeMap<i32, i32>,
+    channel_access_hashes: BTreeMap<i32, i64>,
+
+    pending_updates: Vec<PendingUpdate>,
+    pending_containers: Vec<PendingContainer>,
+    gap_since: Option<Instant>,
+    too_long: bool,
+    /// Channels with gaps which may still be filled, and since when.
+    channel_gaps_since: BTreeMap<i32, Instant>,
+    /// Channels which need `updates.getChannelDifference`.
+    channels_too_long: BTreeSet<i32>,
+
+    ready: VecDeque<Event>,
+}
+
+impl UpdatesManager {
+    /// Construct a manager which doesn't know the current state yet.
+    ///
+    /// Until the state is known (see `fetch_state` and `set_state`),
+    /// all updates are passed through without any checks.
+    pub fn new() -> UpdatesManager {
+        UpdatesManager {
+            state: None,
+            channel_pts: BTreeMap::new(),
+            channel_access_hashes: BTreeMap::new(),
+
+            pending_updates: Vec::new(),
+            pending_containers: Vec::new(),
+            gap_since: None,
+            too_long: false,
+            channel_gaps_since: BTreeMap::new(),
+            channels_too_long: BTreeSet::new(),
+
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Construct a manager resuming from a previously saved state.
+    pub fn with_state(state: State) -> UpdatesManager {
+        let mut manager = UpdatesManager::new();
+        manager.state = Some(state);
+
+        manager
+    }
+
+    /// Current common state, if known.
+    pub fn state(&self) -> Option<State> {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: State) {
+        self.state = Some(state);
+    }
+
+    /// Current `pts` of a channel, if known.
+    pub fn channel_pts(&self, channel_id: i32) -> Option<i32> {
+        self.channel_pts.get(&channel_id).cloned()
+    }
+
+    pub fn set_channel_pts(&mut self, channel_id: i32, pts: i32) {
+        self.channel_pts.insert(channel_id, pts);
+    }
+
+    /// Remember the access hash of a channel to be able to request its
+    /// difference.
+    ///
+    /// Access hashes of channels mentioned in updates are remembered
+    /// automatically.
+    pub fn set_channel_access_hash(&mut self, channel_id: i32, access_hash: i64) {
+        self.channel_access_hashes.insert(channel_id, access_hash);
+    }
+
+    /// Fetch the current state from the server with `updates.getState`.
+    pub fn fetch_state<C: RpcCaller>(&mut self, caller: &mut C) -> error::Result<()> {
+        let state = caller.call(schema::rpc::updates::getState {})?;
+        self.state = Some(State::from(state));
+
+        Ok(())
+    }
+
+    /// Take the next update in order, if any.
+    pub fn pop_event(&mut self) -> Option<Event> {
+        self.ready.pop_front()
+    }
+
+    /// Take all updates which are ready to be handled, in order.
+    pub fn events(&mut self) -> vec_deque::Drain<Event> {
+        self.ready.drain(..)
+    }
+
+    /// Returns `true` if some updates are missing and a difference has
+    /// to be requested.
+    ///
+    /// Small gaps, both common and of channels, are given
+    /// `GAP_TIMEOUT_MILLIS` to be filled by updates arriving out of
+    /// order before this method reports them.
+    pub fn needs_difference(&self) -> bool {
+        self.too_long || self.gap_timed_out() || !self.channels_needing_difference().is_empty()
+    }
+
+    fn gap_timed_out(&self) -> bool {
+        self.gap_since.map(timed_out).unwrap_or(false)
+    }
+
+    fn channels_needing_difference(&self) -> Vec<i32> {
+        let timed_out_gaps = self.channel_gaps_since.iter()
+            .filter(|&(_, &since)| timed_out(since))
+            .map(|(&channel_id, _)| channel_id);
+
+        let channels: BTreeSet<i32> = self.channels_too_long.iter().cloned().chain(timed_out_gaps).collect();
+        channels.into_iter().collect()
+    }
+
+    /// Request differences for all detected gaps which are due for
+    /// recovery.
+    pub fn recover_gaps<C: RpcCaller>(&mut self, caller: &mut C) -> error::Result<()> {
+        if !self.needs_difference() {
+            return Ok(());
+        }
+
+        if self.too_long || self.gap_timed_out() {
+            self.get_difference(caller)?;
+        }
+
+        for channel_id in self.channels_needing_difference() {
+            self.get_channel_difference(caller, channel_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Process an `Updates` object received from the server.
+    pub fn feed(&mut self, updates: schema::Updates) {
+        match updates {
+            schema::Updates::updatesTooLong => {
+                self.too_long = true;
+            },
+            schema::Updates::updateShortMessage(short) => {
+                let sequence = Sequence::Pts { pts: short.pts, pts_count: short.pts_count };
+                self.process(sequence, Event::ShortMessage(short));
+            },
+            schema::Updates::updateShortChatMessage(short) => {
+                let sequence = Sequence::Pts { pts: short.pts, pts_count: short.pts_count };
+                self.process(sequence, Event::ShortChatMessage(short));
+            },
+            schema::Updates::updateShortSentMessage(short) => {
+                let sequence = Sequence::Pts { pts: short.pts, pts_count: short.pts_count };
+                self.process(sequence, Event::ShortSentMessage(short));
+            },
+            schema::Updates::updateShort(short) => {
+                self.process_update(short.update);
+
+                if let Some(ref mut state) = self.state {
+                    state.date = short.date;
+                }
+            },
+            schema::Updates::updatesCombined(combined) => {
+                self.process_container(PendingContainer {
+                    seq_start: combined.seq_start,
+                    seq: combined.seq,
+                    date: combined.date,
+                    updates: combined.updates.into_inner(),
+                    users: combined.users.into_inner(),
+                    chats: combined.chats.into_inner(),
+                });
+            },
+            schema::Updates::updates_(container) => {
+                self.process_container(PendingContainer {
+                    seq_start: container.seq,
+                    seq: container.seq,
+                    date: container.date,
+                    updates: container.updates.into_inner(),
+                    users: container.users.into_inner(),
+                    chats: container.chats.into_inner(),
+                });
+            },
+        }
+    }
+
+    fn process_update(&mut self, update: schema::Update) {
+        let sequence = classify(&update);
+        self.process(sequence, Event::Update(update));
+    }
+
+    fn process(&mut self, sequence: Sequence, event: Event) {
+        let check = self.check(sequence);
+
+        match check {
+            Check::Apply => {
+                self.apply(sequence, event);
+                self.flush_pending_updates();
+            },
+            Check::Duplicate => {
+                debug!("Skipping duplicate update: {:?}", sequence);
+            },
+            Check::Gap => {
+                debug!("Gap detected before update: {:?}", sequence);
+
+                match sequence {
+                    Sequence::ChannelPts { channel_id, .. } => self.start_channel_gap(channel_id),
+                    Sequence::ChannelTooLong { channel_id } => {
+                        self.channels_too_long.insert(channel_id);
+                    },
+                    _ => self.start_gap(),
+                }
+
+                self.pending_updates.push(PendingUpdate {
+                    sequence: sequence,
+                    event: event,
+                });
+            },
+        }
+    }
+
+    fn check(&self, sequence: Sequence) -> Check {
+        match sequence {
+            Sequence::None => Check::Apply,
+            Sequence::Pts { pts, pts_count } => match self.state {
+                Some(state) => check_pts(state.pts, pts, pts_count),
+                None => Check::Apply,
+            },
+            Sequence::Qts { qts } => match self.state {
+                Some(state) => check_pts(state.qts, qts, 1),
+                None => Check::Apply,
+            },
+            Sequence::ChannelPts { channel_id, pts, pts_count } => match self.channel_pts.get(&channel_id) {
+                Some(&local_pts) => check_pts(local_pts, pts, pts_count),
+                None => Check::Apply,
+            },
+            Sequence::ChannelTooLong { .. } => Check::Gap,
+        }
+    }
+
+    fn apply(&mut self, sequence: Sequence, event: Event) {
+        match sequence {
+            Sequence::None | Sequence::ChannelTooLong { .. } => (),
+            Sequence::Pts { pts, .. } => {
+                if let Some(ref mut state) = self.state {
+                    state.pts = pts;
+                }
+            },
+            Sequence::Qts { qts } => {
+                if let Some(ref mut state) = self.state {
+                    state.qts = qts;
+                }
+            },
+            Sequence::ChannelPts { channel_id, pts, .. } => {
+                self.channel_pts.insert(channel_id, pts);
+            },
+        }
+
+        self.ready.push_back(event);
+    }
+
+    fn process_container(&mut self, container: PendingContainer) {
+        let local_seq = match self.state {
+            Some(state) if container.seq_start != 0 => state.seq,
+            _ => {
+                self.apply_container(container);
+                return;
+            },
+        };
+
+        if local_seq + 1 == container.seq_start {
+            self.apply_container(container);
+            self.flush_pending_containers();
+        } else if local_seq + 1 > container.seq_start {
+            debug!("Skipping duplicate updates container with seq {}", container.seq);
+        } else {
+            debug!("Gap detected before updates container with seq_start {}", container.seq_start);
+            self.start_gap();
+            self.pending_containers.push(container);
+        }
+    }
+
+    fn apply_container(&mut self, container: PendingContainer) {
+        self.remember_chats(&container.chats);
+
+        self.ready.push_back(Event::Entities {
+            users: container.users,
+            chats: container.chats,
+        });
+
+        for update in container.updates {
+            self.process_update(update);
+        }
+
+        if let Some(ref mut state) = self.state {
+            if container.seq != 0 {
+                state.seq = container.seq;
+            }
+            state.date = container.date;
+        }
+    }
+
+    fn flush_pending_updates(&mut self) {
+        loop {
+            let mut progress = false;
+            let pending = ::std::mem::replace(&mut self.pending_updates, Vec::new());
+
+            for pending_update in pending {
+                match self.check(pending_update.sequence) {
+                    Check::Apply => {
+                        self.apply(pending_update.sequence, pending_update.event);
+                        progress = true;
+                    },
+                    Check::Duplicate => (),
+                    Check::Gap => self.pending_updates.push(pending_update),
+                }
+            }
+
+            if !progress {
+                break;
+            }
+        }
+
+        self.update_gap();
+    }
+
+    fn flush_pending_containers(&mut self) {
+        self.pending_containers.sort_by_key(|c| c.seq_start);
+        let pending = ::std::mem::replace(&mut self.pending_containers, Vec::new());
+
+        for container in pending {
+            self.process_container(container);
+        }
+
+        self.update_gap();
+    }
+
+    fn start_gap(&mut self) {
+        if self.gap_since.is_none() {
+            self.gap_since = Some(Instant::now());
+        }
+    }
+
+    fn start_channel_gap(&mut self, channel_id: i32) {
+        self.channel_gaps_since.entry(channel_id).or_insert_with(Instant::now);
+    }
+
+    fn update_gap(&mut self) {
+        let has_common_gaps = !self.pending_containers.is_empty() || self.pending_updates.iter().any(|p| {
+            match p.sequence {
+                Sequence::Pts { .. } | Sequence::Qts { .. } => true,
+                _ => false,
+            }
+        });
+
+        if !has_common_gaps {
+            self.gap_since = None;
+        }
+
+        // Channels which still have gaps keep their timers, new ones are
+        // started for gaps remaining after a difference was received
+        let mut channel_gaps_since = BTreeMap::new();
+        for pending_update in &self.pending_updates {
+            if let Sequence::ChannelPts { channel_id, .. } = pending_update.sequence {
+                let since = self.channel_gaps_since.get(&channel_id).cloned().unwrap_or_else(Instant::now);
+                channel_gaps_since.insert(channel_id, since);
+            }
+        }
+
+        self.channel_gaps_since = channel_gaps_since;
+    }
+
+    fn remember_chats(&mut self, chats: &[schema::Chat]) {
+        for chat in chats {
+            match *chat {
+                schema::Chat::channel(ref channel) => {
+                    if let Some(access_hash) = channel.access_hash {
+                        self.channel_access_hashes.insert(channel.id, access_hash);
+                    }
+                },
+                schema::Chat::channelForbidden(ref channel) => {
+                    self.channel_access_hashes.insert(channel.id, channel.access_hash);
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// Fetch missing common updates with `updates.getDifference`.
+    pub fn get_difference<C: RpcCaller>(&mut self, caller: &mut C) -> error::Result<()> {
+        let mut state = match self.state {
+            Some(state) => state,
+            None => {
+                self.fetch_state(caller)?;
+                self.too_long = false;
+                self.gap_since = None;
+
+                return Ok(());
+            },
+        };
+
+        loop {
+            let difference = caller.call(schema::rpc::updates::getDifference {
+                pts: state.pts,
+                pts_total_limit: Some(i32::max_value()),
+                date: state.date,
+                qts: state.qts,
+            })?;
+
+            let is_final = match difference {
+                schema::updates::Difference::differenceEmpty(empty) => {
+                    state.date = empty.date;
+                    state.seq = empty.seq;
+
+                    true
+                },
+                schema::updates::Difference::difference(difference) => {
+                    self.apply_difference(
+                        difference.new_messages.into_inner(),
+                        difference.new_encrypted_messages.into_inner(),
+                        difference.other_updates.into_inner(),
+                        difference.users.into_inner(),
+                        difference.chats.into_inner());
+                    state = State::from(difference.state);
+
+                    true
+                },
+                schema::updates::Difference::differenceSlice(slice) => {
+                    self.apply_difference(
+                        slice.new_messages.into_inner(),
+                        slice.new_encrypted_messages.into_inner(),
+                        slice.other_updates.into_inner(),
+                        slice.users.into_inner(),
+                        slice.chats.into_inner());
+                    state = State::from(slice.intermediate_state);
+
+                    false
+                },
+                schema::updates::Difference::differenceTooLong(too_long) => {
+                    state.pts = too_long.pts;
+
+                    true
+                },
+            };
+
+            self.state = Some(state);
+
+            if is_final {
+                break;
+            }
+        }
+
+        self.too_long = false;
+        self.gap_since = None;
+        self.pending_containers.clear();
+        self.flush_pending_updates();
+
+        Ok(())
+    }
+
+    fn apply_difference(&mut self,
+                        new_messages: Vec<schema::Message>,
+                        new_encrypted_messages: Vec<schema::EncryptedMessage>,
+                        other_updates: Vec<schema::Update>,
+                        users: Vec<schema::User>,
+                        chats: Vec<schema::Chat>) {
+        self.remember_chats(&chats);
+        self.ready.push_back(Event::Entities { users: users, chats: chats });
+        self.ready.extend(new_messages.into_iter().map(Event::NewMessage));
+        self.ready.extend(new_encrypted_messages.into_iter().map(Event::NewEncryptedMessage));
+
+        for update in other_updates {
+            match classify(&update) {
+                Sequence::ChannelPts { .. } | Sequence::ChannelTooLong { .. } => self.process_update(update),
+                // Common sequences are already accounted for by the new state
+                _ => self.ready.push_back(Event::Update(update)),
+            }
+        }
+    }
+
+    /// Fetch missing updates of a channel with
+    /// `updates.getChannelDifference`.
+    pub fn get_channel_difference<C: RpcCaller>(&mut self, caller: &mut C, channel_id: i32) -> error::Result<()> {
+        let access_hash = match self.channel_access_hashes.get(&channel_id) {
+            Some(&access_hash) => access_hash,
+            None => {
+                warn!("Cannot get difference of channel {} without its access hash", channel_id);
+                self.channels_too_long.remove(&channel_id);
+                self.channel_gaps_since.remove(&channel_id);
+
+                return Ok(());
+            },
+        };
+
+        loop {
+            let pts = match self.channel_pts.get(&channel_id) {
+                Some(&pts) => pts,
+                None => 1,
+            };
+
+            let difference = caller.call(schema::rpc::updates::getChannelDifference {
+                force: false,
+                channel: schema::InputChannel::inputChannel(schema::inputChannel {
+                    channel_id: channel_id,
+                    access_hash: access_hash,
+                }),
+                filter: schema::ChannelMessagesFilter::channelMessagesFilterEmpty,
+                pts: pts,
+                limit: CHANNEL_DIFFERENCE_LIMIT,
+            })?;
+
+            let is_final = match difference {
+                schema::updates::ChannelDifference::channelDifferenceEmpty(empty) => {
+                    self.channel_pts.insert(channel_id, empty.pts);
+
+                    true
+                },
+                schema::updates::ChannelDifference::channelDifferenceTooLong(too_long) => {
+                    self.remember_chats(too_long.chats.inner());
+                    self.ready.push_back(Event::Entities {
+                        users: too_long.users.into_inner(),
+                        chats: too_long.chats.into_inner(),
+                    });
+                    self.ready.extend(too_long.messages.into_inner().into_iter().map(Event::NewMessage));
+                    self.channel_pts.insert(channel_id, too_long.pts);
+
+                    too_long.final_
+                },
+                schema::updates::ChannelDifference::channelDifference(difference) => {
+                    self.remember_chats(difference.chats.inner());
+                    self.ready.push_back(Event::Entities {
+                        users: difference.users.into_inner(),
+                        chats: difference.chats.into_inner(),
+                    });
+                    self.ready.extend(difference.new_messages.into_inner().into_iter().map(Event::NewMessage));
+                    self.ready.extend(difference.other_updates.into_inner().into_iter().map(Event::Update));
+                    self.channel_pts.insert(channel_id, difference.pts);
+
+                    difference.final_
+                },
+            };
+
+            if is_final {
+                break;
+            }
+        }
+
+        self.channels_too_long.remove(&channel_id);
+        self.channel_gaps_since.remove(&channel_id);
+        self.pending_updates.retain(|p| match p.sequence {
+            Sequence::ChannelTooLong { channel_id: id } => id != channel_id,
+            _ => true,
+        });
+        self.flush_pending_updates();
+
+        Ok(())
+    }
+}
+
+
+fn timed_out(since: Instant) -> bool {
+    since.elapsed() >= Duration::from_millis(GAP_TIMEOUT_MILLIS)
+}
+
+fn message_channel_id(message: &schema::Message) -> Option<i32> {
+    let to_id = match *message {
+        schema::Message::message(ref message) => &message.to_id,
+        schema::Message::messageService(ref message) => &message.to_id,
+        schema::Message::messageEmpty(_) => return None,
+    };
+
+    match *to_id {
+        schema::Peer::peerChannel(ref peer) => Some(peer.channel_id),
+        _ => None,
+    }
+}
+
+fn classify(update: &schema::Update) -> Sequence {
+    use schema::Update::*;
+
+    let pts = |pts, pts_count| Sequence::Pts { pts: pts, pts_count: pts_count };
+    let channel_pts = |channel_id, pts, pts_count| {
+        Sequence::ChannelPts { channel_id: channel_id, pts: pts, pts_count: pts_count }
+    };
+
+    match *update {
+        updateNewMessage(ref u) => pts(u.pts, u.pts_count),
+        updateDeleteMessages(ref u) => pts(u.pts, u.pts_count),
+        updateReadHistoryInbox(ref u) => pts(u.pts, u.pts_count),
+        updateReadHistoryOutbox(ref u) => pts(u.pts, u.pts_count),
+        updateWebPage(ref u) => pts(u.pts, u.pts_count),
+        updateReadMessagesContents(ref u) => pts(u.pts, u.pts_count),
+        updateEditMessage(ref u) => pts(u.pts, u.pts_count),
+
+        updateNewEncryptedMessage(ref u) => Sequence::Qts { qts: u.qts },
+
+        updateNewChannelMessage(ref u) => match message_channel_id(&u.message) {
+            Some(channel_id) => channel_pts(channel_id, u.pts, u.pts_count),
+            None => Sequence::None,
+        },
+        updateEditChannelMessage(ref u) => match message_channel_id(&u.message) {
+            Some(channel_id) => channel_pts(channel_id, u.pts, u.pts_count),
+            None => Sequence::None,
+        },
+        updateDeleteChannelMessages(ref u) => channel_pts(u.channel_id, u.pts, u.pts_count),
+        updateChannelWebPage(ref u) => channel_pts(u.channel_id, u.pts, u.pts_count),
+        updateChannelTooLong(ref u) => Sequence::ChannelTooLong { channel_id: u.channel_id },
+
+        _ => Sequence::None,
+    }
+}
@@ -0,0 +1,339 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate serde_bytes;
+extern crate test_logger;
+
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mtproto::{Error, ErrorKind, Result};
+use mtproto::auth::{self, LoginPrompt, PasswordRequest, SentCode, SignIn};
+use mtproto::rpc::{AppInfo, DcConnector, DcEndpoint, DcKind, DcPool, DcRegistry};
+use mtproto::rpc::mock;
+use mtproto::schema;
+use serde_bytes::ByteBuf;
+use test_logger::ensure_env_logger_initialized;
+
+
+const BOT_DC: i32 = 4;
+const BOT_USER_ID: i32 = 123456;
+const USER_ID: i32 = 654321;
+
+const PHONE_NUMBER: &'static str = "+15550001";
+const PHONE_CODE: &'static str = "12345";
+const PHONE_CODE_HASH: &'static str = "code-hash";
+
+/// `SHA256("salt" + "password" + "salt")`.
+const PASSWORD_HASH: [u8; 32] = [
+    0x14, 0x28, 0x42, 0xb4, 0xc7, 0x29, 0xfa, 0xe1, 0x9d, 0xa4, 0x7d, 0xce, 0xbb, 0xc7, 0x39, 0x56,
+    0x4a, 0xda, 0x2d, 0x9f, 0xbb, 0xe4, 0x01, 0x90, 0x5d, 0xdd, 0x3b, 0x96, 0x4f, 0x2b, 0xef, 0xab,
+];
+
+
+/// Servers of DC 2, which redirects bots to `BOT_DC`, and of `BOT_DC`,
+/// along with the bot tokens each of them received.
+fn bot_servers() -> (mock::MockServer, mock::MockServer, Rc<RefCell<Vec<(i32, String)>>>) {
+    let tokens = Rc::new(RefCell::new(Vec::new()));
+
+    let server_dc2 = mock::MockServer::new().unwrap();
+    {
+        let tokens = tokens.clone();
+        server_dc2.on(move |function: schema::rpc::auth::importBotAuthorization| {
+            tokens.borrow_mut().push((2, function.bot_auth_token));
+            Err(ErrorKind::RpcError(303, format!("USER_MIGRATE_{}", BOT_DC)).into())
+        });
+    }
+
+    let server_bot_dc = mock::MockServer::new().unwrap();
+    {
+        let tokens = tokens.clone();
+        server_bot_dc.on(move |function: schema::rpc::auth::importBotAuthorization| {
+            tokens.borrow_mut().push((BOT_DC, function.bot_auth_token));
+
+            Ok(schema::auth::Authorization {
+                tmp_sessions: None,
+                user: schema::User::userEmpty(schema::userEmpty { id: BOT_USER_ID }),
+            })
+        });
+    }
+
+    (server_dc2, server_bot_dc, tokens)
+}
+
+
+fn user() -> schema::User {
+    schema::User::userEmpty(schema::userEmpty { id: USER_ID })
+}
+
+fn authorization() -> schema::auth::Authorization {
+    schema::auth::Authorization {
+        tmp_sessions: None,
+        user: user(),
+    }
+}
+
+/// A server which sends codes to `PHONE_NUMBER` and signs in with
+/// `PHONE_CODE`, replying with `sign_in_error` if given.
+fn login_server(phone_registered: bool, sign_in_error: Option<(i32, &'static str)>) -> mock::MockServer {
+    let server = mock::MockServer::new().unwrap();
+
+    server.on(move |function: schema::rpc::auth::sendCode| {
+        assert_eq!(function.phone_number, PHONE_NUMBER);
+        assert_eq!(function.api_id, 9000);
+
+        Ok(schema::auth::SentCode {
+            phone_registered: phone_registered,
+            type_: schema::auth::SentCodeType::sentCodeTypeSms(schema::auth::sentCodeTypeSms { length: 5 }),
+            phone_code_hash: PHONE_CODE_HASH.to_owned(),
+            next_type: None,
+            timeout: None,
+        })
+    });
+
+    server.on(move |function: schema::rpc::auth::signIn| {
+        assert_eq!(function.phone_number, PHONE_NUMBER);
+        assert_eq!(function.phone_code_hash, PHONE_CODE_HASH);
+        assert_eq!(function.phone_code, PHONE_CODE);
+
+        match sign_in_error {
+            Some((code, message)) => Err(ErrorKind::RpcError(code, message.to_owned()).into()),
+            None => Ok(authorization()),
+        }
+    });
+
+    server
+}
+
+fn password_server(has_password: bool) -> mock::MockServer {
+    let server = login_server(true, Some((401, "SESSION_PASSWORD_NEEDED")));
+
+    server.on(move |_: schema::rpc::account::getPassword| {
+        if has_password {
+            Ok(schema::account::Password::password(schema::account::password {
+                current_salt: ByteBuf::from(b"salt".to_vec()),
+                new_salt: ByteBuf::from(b"new salt".to_vec()),
+                hint: "hint".to_owned(),
+                has_recovery: true,
+                email_unconfirmed_pattern: String::new(),
+            }))
+        } else {
+            Ok(schema::account::Password::noPassword(schema::account::noPassword {
+                new_salt: ByteBuf::from(b"new salt".to_vec()),
+                email_unconfirmed_pattern: String::new(),
+            }))
+        }
+    });
+
+    server.on(|function: schema::rpc::auth::checkPassword| {
+        if &function.password_hash[..] == &PASSWORD_HASH[..] {
+            Ok(authorization())
+        } else {
+            Err(ErrorKind::RpcError(400, "PASSWORD_HASH_INVALID".to_owned()).into())
+        }
+    });
+
+    server
+}
+
+fn connect(server: &mock::MockServer) -> mock::MockConnection {
+    mock::MockConnection::connect(server, AppInfo::new(9000, "random text".to_owned())).unwrap()
+}
+
+fn send_code(connection: &mut mock::MockConnection) -> SentCode {
+    auth::send_code(connection, &AppInfo::new(9000, "random text".to_owned()), PHONE_NUMBER).unwrap()
+}
+
+
+#[test]
+fn test_bot_login_user_migrate() {
+    ensure_env_logger_initialized();
+
+    let (server_dc2, server_bot_dc, tokens) = bot_servers();
+    let mut connector = mock::MockConnector::new(AppInfo::new(9000, "random text".to_owned()));
+    connector.add_server(2, server_dc2.clone());
+    connector.add_server(BOT_DC, server_bot_dc.clone());
+
+    let mut registry = DcRegistry::new();
+    for dc_id in 1..6 {
+        registry.add(DcEndpoint::new(dc_id, format!("127.0.0.{}", dc_id), 443));
+    }
+
+    let mut pool = DcPool::new(connector, registry, 2);
+    let app_info = AppInfo::new(9000, "random text".to_owned());
+
+    let user = auth::bot_login(&mut pool, &app_info, "123456:bot-token").unwrap();
+
+    assert_eq!(user, schema::User::userEmpty(schema::userEmpty { id: BOT_USER_ID }));
+    assert_eq!(pool.home_dc(), BOT_DC);
+    assert!(pool.is_authorized(BOT_DC, DcKind::Main));
+    assert!(!pool.is_authorized(2, DcKind::Main));
+
+    assert_eq!(*tokens.borrow(), vec![
+        (2, "123456:bot-token".to_owned()),
+        (BOT_DC, "123456:bot-token".to_owned()),
+    ]);
+    assert_eq!(server_dc2.auth_key_count(), 1);
+    assert_eq!(server_bot_dc.auth_key_count(), 1);
+}
+
+#[test]
+fn test_bot_login_error() {
+    ensure_env_logger_initialized();
+
+    struct FailingConnector;
+
+    impl DcConnector for FailingConnector {
+        type Connection = mock::MockConnection;
+
+        fn connect(&mut self, endpoint: &DcEndpoint, _kind: DcKind) -> Result<mock::MockConnection> {
+            Err(ErrorKind::NoDcEndpoint(endpoint.dc_id).into())
+        }
+    }
+
+    let mut registry = DcRegistry::new();
+    registry.add(DcEndpoint::new(2, "127.0.0.2".to_owned(), 443));
+
+    let mut pool = DcPool::new(FailingConnector, registry, 2);
+    let app_info = AppInfo::new(9000, "random text".to_owned());
+
+    let error: Error = auth::bot_login(&mut pool, &app_info, "123456:bot-token").unwrap_err();
+    match *error.kind() {
+        ErrorKind::NoDcEndpoint(2) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert!(!pool.is_authorized(2, DcKind::Main));
+}
+
+#[test]
+fn test_sign_in_with_code() {
+    ensure_env_logger_initialized();
+
+    let server = login_server(true, None);
+    let mut connection = connect(&server);
+
+    let sent_code = send_code(&mut connection);
+    assert_eq!(sent_code.phone_number(), PHONE_NUMBER);
+    assert!(sent_code.phone_registered());
+    assert!(sent_code.next_type().is_none());
+
+    match sent_code.sign_in(&mut connection, PHONE_CODE).unwrap() {
+        SignIn::Authorized(signed_in_user) => assert_eq!(signed_in_user, user()),
+        sign_in => panic!("unexpected sign in outcome: {:?}", sign_in),
+    }
+}
+
+#[test]
+fn test_sign_in_with_wrong_code() {
+    ensure_env_logger_initialized();
+
+    let server = login_server(true, Some((400, "PHONE_CODE_INVALID")));
+    let mut connection = connect(&server);
+
+    let error = send_code(&mut connection).sign_in(&mut connection, PHONE_CODE).unwrap_err();
+    match *error.kind() {
+        ErrorKind::RpcError(400, ref message) => assert_eq!(message, "PHONE_CODE_INVALID"),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn test_sign_in_unregistered() {
+    ensure_env_logger_initialized();
+
+    let server = login_server(false, None);
+    server.on(|function: schema::rpc::auth::signUp| {
+        assert_eq!(function.phone_code, PHONE_CODE);
+        assert_eq!((&*function.first_name, &*function.last_name), ("John", "Doe"));
+
+        Ok(authorization())
+    });
+    let mut connection = connect(&server);
+
+    match send_code(&mut connection).sign_in(&mut connection, PHONE_CODE).unwrap() {
+        SignIn::SignUpNeeded(request) => {
+            let signed_up_user = request.sign_up(&mut connection, "John".to_owned(), "Doe".to_owned()).unwrap();
+            assert_eq!(signed_up_user, user());
+        },
+        sign_in => panic!("unexpected sign in outcome: {:?}", sign_in),
+    }
+}
+
+#[test]
+fn test_sign_in_password_needed() {
+    ensure_env_logger_initialized();
+
+    let server = password_server(true);
+    let mut connection = connect(&server);
+
+    let request = match send_code(&mut connection).sign_in(&mut connection, PHONE_CODE).unwrap() {
+        SignIn::PasswordNeeded(request) => request,
+        sign_in => panic!("unexpected sign in outcome: {:?}", sign_in),
+    };
+
+    assert_eq!(request.hint(), "hint");
+    assert!(request.has_recovery());
+    assert_eq!(request.password_hash("password").unwrap(), PASSWORD_HASH.to_vec());
+
+    let error = request.check(&mut connection, "wrong password").unwrap_err();
+    match *error.kind() {
+        ErrorKind::RpcError(400, ref message) => assert_eq!(message, "PASSWORD_HASH_INVALID"),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    assert_eq!(request.check(&mut connection, "password").unwrap(), user());
+}
+
+#[test]
+fn test_sign_in_no_password_set() {
+    ensure_env_logger_initialized();
+
+    let server = password_server(false);
+    let mut connection = connect(&server);
+
+    let error = send_code(&mut connection).sign_in(&mut connection, PHONE_CODE).unwrap_err();
+    match *error.kind() {
+        ErrorKind::NoPasswordSet => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn test_login() {
+    ensure_env_logger_initialized();
+
+    struct Prompt {
+        codes_asked: usize,
+    }
+
+    impl LoginPrompt for Prompt {
+        fn code(&mut self, sent_code: &SentCode) -> Result<Option<String>> {
+            assert_eq!(sent_code.phone_number(), PHONE_NUMBER);
+            self.codes_asked += 1;
+
+            Ok(Some(PHONE_CODE.to_owned()))
+        }
+
+        fn password(&mut self, request: &PasswordRequest) -> Result<String> {
+            assert_eq!(request.hint(), "hint");
+            Ok("password".to_owned())
+        }
+
+        fn name(&mut self) -> Result<(String, String)> {
+            panic!("the phone number is registered");
+        }
+    }
+
+    let server = password_server(true);
+    let mut connection = connect(&server);
+    let mut prompt = Prompt { codes_asked: 0 };
+
+    let logged_in_user = auth::login(&mut connection,
+                                     &AppInfo::new(9000, "random text".to_owned()),
+                                     PHONE_NUMBER,
+                                     &mut prompt).unwrap();
+
+    assert_eq!(logged_in_user, user());
+    assert_eq!(prompt.codes_asked, 1);
+}
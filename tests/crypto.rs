@@ -0,0 +1,200 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+
+use mtproto::ErrorKind;
+use mtproto::rpc::encryption::{AesParams, RsaKeyRegistry, RsaPaddingScheme, RsaPrivateKey, RsaPublicKey};
+use mtproto::rpc::encryption::asymm;
+
+
+const KNOWN_KEY_FINGERPRINT: i64 = -4344800451088585951;    // 0xc3b42b026ce86b21
+
+const TEST_PKCS1_KEY: &'static [u8] = b"\
+-----BEGIN RSA PUBLIC KEY-----\n\
+MIGJAoGBAJ5dOyoYSZpZ1BWy6p1h+5PSKv24KqSrkOOaZAk2LhhWdj7Brrf2YB0Q\n\
+2Pn14vIYGdPSFTAd1HE20RBCJ13FL/Tl8dj9nzhhFYpoNp9JmnHsY1b8BzguiBr5\n\
+rNRz5r4ZnyJK9DvMSDL+aYPDCwWaHMFy6y6opP6xPClSNiClIqNHAgMBAAE=\n\
+-----END RSA PUBLIC KEY-----";
+const TEST_PKCS1_KEY_FINGERPRINT: i64 = -1214154576339732871;
+
+// 2048-bit key with a known private exponent to check encryption results
+const TEST_RSA_2048_KEY: &'static [u8] = b"\
+-----BEGIN RSA PUBLIC KEY-----\n\
+MIIBCgKCAQEAljrRIBwZXgRVRcWFgk2TUw6vfdJtDx0OizWx5ZAEpNKmsJqS+pss\n\
+UnMDBWVeRF9rQdTx5vHyWsQXYfiz1+coLzyxozj7f0DUkk/FEOrLbSKdN+mioTSJ\n\
+WT6mWiIwSd+4/kVBEOe/evUNlueWJGS84KefOXKTle6rgrYAXFObQjzr7/sqgKgH\n\
+2vd1QJ8e7zp3r7HYpoSMl+JeKuF2CkIw3wrBw9zN6AhSVSUNXwJPZINf30noykYG\n\
+PQhOOfN22mJiSPkwP2UhDFhgEHj5jdkK0E5lbCGpKvnMG5Z88eogI/MwJb4DFX/T\n\
+pR/V/uobyJoCYRAanisZyOKKF3tqMDnoZwIDAQAB\n\
+-----END RSA PUBLIC KEY-----";
+const TEST_RSA_2048_N: &'static str = "\
+963ad1201c195e045545c585824d93530eaf7dd26d0f1d0e8b35b1e59004a4d2\
+a6b09a92fa9b2c52730305655e445f6b41d4f1e6f1f25ac41761f8b3d7e7282f\
+3cb1a338fb7f40d4924fc510eacb6d229d37e9a2a13489593ea65a223049dfb8\
+fe454110e7bf7af50d96e7962464bce0a79f39729395eeab82b6005c539b423c\
+ebeffb2a80a807daf775409f1eef3a77afb1d8a6848c97e25e2ae1760a4230df\
+0ac1c3dccde8085255250d5f024f64835fdf49e8ca46063d084e39f376da6262\
+48f9303f65210c58601078f98dd90ad04e656c21a92af9cc1b967cf1ea2023f3\
+3025be03157fd3a51fd5feea1bc89a0261101a9e2b19c8e28a177b6a3039e867";
+const TEST_RSA_2048_D: &'static str = "\
+0de50bc19207c09bdafd348b28259faa2f7208cba51da03cdfa04c3f64c6212e\
+808f5604a11c2809ca2c6ddbd5cfb2199c01a38c0e35b1b2704d35f10057976e\
+6309350508b1d8dfb056a36df0a1770b1c0a7757fcdbfc961e06c9d05eb1fe78\
+c03fcc88513ef312e63a5e5a132ca4118d3f5b535f161f788a82e5dea436f875\
+c0be04c8184508f5060702642b4962cc17a5963f5dd71dbfc3db08a6b558ee39\
+579421fcfdea7c8603ced52e340d86be427b8904efc8dfe389d85ba57abaecef\
+29c76141eb62644001feaa657c5b4bc1fa9a19e443352f0de15504867cf7a70f\
+a50434aaca080bf1d8ead366ca93bd083692ae4076dd2100c7075ea8288e6bad";
+
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    hex.as_bytes().chunks(2)
+        .map(|pair| u8::from_str_radix(::std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+fn range_bytes(start: u8, len: usize) -> Vec<u8> {
+    (0..len).map(|i| start + i as u8).collect()
+}
+
+fn private_key_2048() -> RsaPrivateKey {
+    RsaPrivateKey::new(from_hex(TEST_RSA_2048_N), vec![0x01, 0x00, 0x01], from_hex(TEST_RSA_2048_D))
+}
+
+
+#[test]
+fn test_known_rsa_key_fingerprint() {
+    let key = asymm::KNOWN_RAW_KEYS[0].read().unwrap();
+    assert_eq!(key.fingerprint().unwrap(), KNOWN_KEY_FINGERPRINT);
+}
+
+#[test]
+fn test_rsa_key_registry_builtin() {
+    let registry = RsaKeyRegistry::new().unwrap();
+    assert_eq!(registry.fingerprints(), &[KNOWN_KEY_FINGERPRINT]);
+
+    let (key, fingerprint) = registry.find_first(&[1, KNOWN_KEY_FINGERPRINT]).unwrap();
+    assert_eq!(fingerprint, KNOWN_KEY_FINGERPRINT);
+    assert_eq!(key, &asymm::KNOWN_RAW_KEYS[0].read().unwrap());
+
+    assert!(RsaKeyRegistry::empty().find_first(&[KNOWN_KEY_FINGERPRINT]).is_none());
+}
+
+#[test]
+fn test_find_first_key() {
+    // The built-in registry is created once and reused afterwards
+    for _ in 0..2 {
+        let (key, fingerprint) = asymm::find_first_key(&[1, KNOWN_KEY_FINGERPRINT]).unwrap().unwrap();
+        assert_eq!(fingerprint, KNOWN_KEY_FINGERPRINT);
+        assert_eq!(key, asymm::KNOWN_RAW_KEYS[0].read().unwrap());
+    }
+
+    assert!(asymm::find_first_key(&[1]).unwrap().is_none());
+    assert!(asymm::find_first_key_fail_safe(&[1]).is_err());
+}
+
+#[test]
+fn test_rsa_key_registry_custom_keys() {
+    let mut registry = RsaKeyRegistry::empty();
+    assert_eq!(registry.add_pem(TEST_PKCS1_KEY).unwrap(), TEST_PKCS1_KEY_FINGERPRINT);
+
+    let raw_key = RsaPublicKey::new(vec![0xc5, 0x01, 0x23], vec![0x03]);
+    let raw_fingerprint = registry.add_raw(vec![0xc5, 0x01, 0x23], vec![0x03]).unwrap();
+    assert_eq!(raw_fingerprint, raw_key.fingerprint().unwrap());
+    assert_eq!(registry.get(raw_fingerprint), Some(&raw_key));
+
+    // Adding the same key again doesn't duplicate it
+    registry.add_pem(TEST_PKCS1_KEY).unwrap();
+    assert_eq!(registry.fingerprints(), &[TEST_PKCS1_KEY_FINGERPRINT, raw_fingerprint]);
+
+    let (_, fingerprint) = registry.find_first(&[raw_fingerprint, TEST_PKCS1_KEY_FINGERPRINT]).unwrap();
+    assert_eq!(fingerprint, TEST_PKCS1_KEY_FINGERPRINT);
+
+    assert_eq!(registry.remove(TEST_PKCS1_KEY_FINGERPRINT).map(|key| key.fingerprint().unwrap()),
+               Some(TEST_PKCS1_KEY_FINGERPRINT));
+    assert_eq!(registry.fingerprints(), &[raw_fingerprint]);
+
+    match registry.find_first_fail_safe(&[TEST_PKCS1_KEY_FINGERPRINT]) {
+        Err(e) => match *e.kind() {
+            ErrorKind::NoRsaPublicKeyForFingerprints(ref fingerprints) => {
+                assert_eq!(fingerprints, &[TEST_PKCS1_KEY_FINGERPRINT]);
+            },
+            ref kind => panic!("unexpected error: {:?}", kind),
+        },
+        Ok(found) => panic!("unexpected key: {:?}", found),
+    }
+}
+
+#[test]
+fn test_rsa_key_registry_pem_file() {
+    let path = env::temp_dir().join("mtproto-test-rsa-key.pem");
+    File::create(&path).unwrap().write_all(TEST_PKCS1_KEY).unwrap();
+
+    let mut registry = RsaKeyRegistry::new().unwrap();
+    let fingerprint = registry.add_pem_file(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(fingerprint.unwrap(), TEST_PKCS1_KEY_FINGERPRINT);
+    assert_eq!(registry.fingerprints(), &[KNOWN_KEY_FINGERPRINT, TEST_PKCS1_KEY_FINGERPRINT]);
+}
+
+#[test]
+fn test_rsa_pad_encryption() {
+    let key = private_key_2048();
+    let data = range_bytes(1, 100);
+
+    let encrypted = key.public_key().encrypt_with(RsaPaddingScheme::RsaPad, &data).unwrap();
+    let data_with_padding = key.decrypt_rsa_pad(&encrypted).unwrap();
+
+    assert_eq!(data_with_padding.len(), 192);
+    assert_eq!(&data_with_padding[..100], &data[..]);
+
+    // Tampering with the ciphertext breaks the hash check
+    let mut tampered = encrypted;
+    tampered[255] ^= 1;
+    assert!(key.decrypt_rsa_pad(&tampered).is_err());
+}
+
+#[test]
+fn test_rsa_pad_data_too_long() {
+    let key = RsaPublicKey::from_pem(TEST_RSA_2048_KEY).unwrap();
+
+    match key.encrypt_rsa_pad(&[0; 145]) {
+        Err(e) => match *e.kind() {
+            ErrorKind::RsaPadDataTooLong(144, 145) => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        },
+        Ok(_) => panic!("data longer than 144 bytes must not be encrypted"),
+    }
+}
+
+#[test]
+fn test_legacy_rsa_encryption() {
+    let key = private_key_2048();
+    let data = range_bytes(1, 100);
+
+    let encrypted = key.public_key().encrypt_with(RsaPaddingScheme::Legacy, &data).unwrap();
+    let decrypted = key.decrypt_legacy(&encrypted).unwrap();
+
+    assert_eq!(decrypted.len(), 255);
+    assert_eq!(&decrypted[..20], &from_hex("30563c6ffea0ca4f9bdb7e3ddeb163b5e75153cc")[..]);    // SHA-1
+    assert_eq!(&decrypted[20..120], &data[..]);
+}
+
+#[test]
+fn test_aes_ige() {
+    let aes_params = AesParams::from_nonces(&range_bytes(0, 32), &range_bytes(32, 16)).unwrap();
+    let data = range_bytes(100, 44);
+    let encrypted = from_hex("801c81f7be8df853094d42e0d9970f7e5099a07233de2a9f7bc5ae6233f960b1\
+                              6438be9a152b2c4d425cf37c7c4d920fd6e4d5a31c291f5f378a6fc26dd5da24");
+
+    assert_eq!(aes_params.ige_encrypt(&data, true).unwrap(), encrypted);
+
+    let decrypted = aes_params.ige_decrypt(&encrypted).unwrap();
+    assert_eq!(&decrypted[20..], &data[..]);
+}
@@ -0,0 +1,175 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate serde_bytes;
+extern crate test_logger;
+
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use mtproto::{Error, ErrorKind};
+use mtproto::rpc::{AppInfo, DcEndpoint, DcKind, DcPool, DcRegistry, Migration};
+use mtproto::rpc::mock::{MockConnector, MockServer};
+use mtproto::schema;
+use serde_bytes::ByteBuf;
+use test_logger::ensure_env_logger_initialized;
+
+
+fn endpoint(dc_id: i32, ip_address: &str, ipv6: bool, media_only: bool, cdn: bool) -> DcEndpoint {
+    DcEndpoint {
+        ipv6: ipv6,
+        media_only: media_only,
+        cdn: cdn,
+        ..DcEndpoint::new(dc_id, ip_address.to_owned(), 443)
+    }
+}
+
+fn rpc_error(code: i32, message: &str) -> Error {
+    ErrorKind::RpcError(code, message.to_owned()).into()
+}
+
+
+#[test]
+fn test_registry_select() {
+    let mut registry = DcRegistry::new();
+    registry.add(endpoint(1, "10.0.0.1", false, false, false));
+    registry.add(endpoint(1, "::1", true, false, false));
+    registry.add(endpoint(2, "10.0.0.2", false, false, false));
+    registry.add(endpoint(2, "10.0.1.2", false, true, false));
+    registry.add(endpoint(3, "10.0.0.3", false, false, true));
+
+    let select = |registry: &DcRegistry, dc_id, kind| {
+        registry.select(dc_id, kind).map(|e| e.ip_address.clone())
+    };
+
+    assert_eq!(select(&registry, 1, DcKind::Main), Some("10.0.0.1".to_owned()));
+    assert_eq!(select(&registry, 2, DcKind::Main), Some("10.0.0.2".to_owned()));
+    assert_eq!(select(&registry, 2, DcKind::Media), Some("10.0.1.2".to_owned()));
+    // No media endpoints in DC 1, so the main ones are used
+    assert_eq!(select(&registry, 1, DcKind::Media), Some("10.0.0.1".to_owned()));
+    // CDN endpoints are never used for anything else and vice versa
+    assert_eq!(select(&registry, 3, DcKind::Main), None);
+    assert_eq!(select(&registry, 3, DcKind::Media), None);
+    assert_eq!(select(&registry, 3, DcKind::Cdn), Some("10.0.0.3".to_owned()));
+    assert_eq!(select(&registry, 1, DcKind::Cdn), None);
+    assert_eq!(select(&registry, 4, DcKind::Main), None);
+
+    registry.set_prefer_ipv6(true);
+    assert_eq!(select(&registry, 1, DcKind::Main), Some("::1".to_owned()));
+    assert_eq!(select(&registry, 1, DcKind::Media), Some("::1".to_owned()));
+    // IPv4 is used when there's no IPv6 endpoint
+    assert_eq!(select(&registry, 2, DcKind::Main), Some("10.0.0.2".to_owned()));
+}
+
+#[test]
+fn test_registry_add_replaces_same_endpoint() {
+    let mut registry = DcRegistry::new();
+    registry.add(endpoint(1, "10.0.0.1", false, false, false));
+    registry.add(endpoint(1, "10.0.0.1", false, true, false));
+
+    assert_eq!(registry.endpoints(1).len(), 1);
+    assert!(registry.endpoints(1)[0].media_only);
+    assert_eq!(registry.dc_ids(), vec![1]);
+}
+
+#[test]
+fn test_migration_from_error() {
+    assert_eq!(Migration::from_error(&rpc_error(303, "FILE_MIGRATE_4")), Some(Migration::File(4)));
+    assert_eq!(Migration::from_error(&rpc_error(303, "PHONE_MIGRATE_2")), Some(Migration::Home(2)));
+    assert_eq!(Migration::from_error(&rpc_error(303, "NETWORK_MIGRATE_3")), Some(Migration::Home(3)));
+    assert_eq!(Migration::from_error(&rpc_error(303, "USER_MIGRATE_5")), Some(Migration::Home(5)));
+
+    // Only 303 errors with a valid DC id are migrations
+    assert_eq!(Migration::from_error(&rpc_error(400, "USER_MIGRATE_5")), None);
+    assert_eq!(Migration::from_error(&rpc_error(303, "USER_MIGRATE_")), None);
+    assert_eq!(Migration::from_error(&rpc_error(303, "USER_MIGRATE_X")), None);
+    assert_eq!(Migration::from_error(&rpc_error(303, "SOMETHING_ELSE_1")), None);
+    assert_eq!(Migration::from_error(&ErrorKind::NoDcEndpoint(1).into()), None);
+}
+
+#[test]
+fn test_call_on_authorizes_used_connection() {
+    ensure_env_logger_initialized();
+
+    let server_dc2 = MockServer::new().unwrap();
+    server_dc2.on(|function: schema::rpc::auth::exportAuthorization| {
+        assert_eq!(function.dc_id, 4);
+
+        Ok(schema::auth::ExportedAuthorization {
+            id: 42,
+            bytes: ByteBuf::from(vec![1, 2, 3]),
+        })
+    });
+
+    let imports = Rc::new(Cell::new(0));
+    let server_dc4 = MockServer::new().unwrap();
+    {
+        let imports = imports.clone();
+        server_dc4.on(move |function: schema::rpc::auth::importAuthorization| {
+            assert_eq!(function.id, 42);
+            imports.set(imports.get() + 1);
+
+            Ok(schema::auth::Authorization {
+                tmp_sessions: None,
+                user: schema::User::userEmpty(schema::userEmpty { id: 1 }),
+            })
+        });
+    }
+
+    let mut connector = MockConnector::new(AppInfo::new(9000, "random text".to_owned()));
+    connector.add_server(2, server_dc2.clone());
+    connector.add_server(4, server_dc4.clone());
+
+    let mut registry = DcRegistry::new();
+    registry.add(DcEndpoint::new(2, "127.0.0.2".to_owned(), 443));
+    registry.add(DcEndpoint::new(4, "127.0.0.4".to_owned(), 443));
+
+    let mut pool = DcPool::new(connector, registry, 2);
+    pool.mark_home_authorized();
+
+    pool.call_on(4, DcKind::Media, schema::rpc::ping { ping_id: 1 }).unwrap();
+    assert_eq!(imports.get(), 1);
+    assert!(pool.is_authorized(4, DcKind::Media));
+    assert!(!pool.is_authorized(4, DcKind::Main));
+    // Only the media connection has been opened on DC 4
+    assert_eq!(server_dc4.auth_key_count(), 1);
+
+    // The media connection stays authorized
+    pool.call_on(4, DcKind::Media, schema::rpc::ping { ping_id: 2 }).unwrap();
+    assert_eq!(imports.get(), 1);
+
+    // The main connection needs its own authorization
+    pool.call_on(4, DcKind::Main, schema::rpc::ping { ping_id: 3 }).unwrap();
+    assert_eq!(imports.get(), 2);
+    assert!(pool.is_authorized(4, DcKind::Main));
+    assert_eq!(server_dc4.auth_key_count(), 2);
+}
+
+#[test]
+fn test_call_on_home_dc_shares_authorization() {
+    ensure_env_logger_initialized();
+
+    let exports = Rc::new(Cell::new(0));
+    let server_dc2 = MockServer::new().unwrap();
+    {
+        let exports = exports.clone();
+        server_dc2.on(move |_: schema::rpc::auth::exportAuthorization| -> Result<schema::auth::ExportedAuthorization, Error> {
+            exports.set(exports.get() + 1);
+            Err(rpc_error(400, "DC_ID_INVALID"))
+        });
+    }
+
+    let mut connector = MockConnector::new(AppInfo::new(9000, "random text".to_owned()));
+    connector.add_server(2, server_dc2.clone());
+
+    let mut registry = DcRegistry::new();
+    registry.add(DcEndpoint::new(2, "127.0.0.2".to_owned(), 443));
+
+    let mut pool = DcPool::new(connector, registry, 2);
+    pool.mark_home_authorized();
+
+    pool.call_on(2, DcKind::Media, schema::rpc::ping { ping_id: 1 }).unwrap();
+    assert_eq!(exports.get(), 0);
+    assert!(pool.is_authorized(2, DcKind::Media));
+}
@@ -0,0 +1,318 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate serde_bytes;
+extern crate serde_mtproto;
+extern crate test_logger;
+
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use mtproto::ErrorKind;
+use mtproto::files::Downloader;
+use mtproto::rpc::{AppInfo, DcEndpoint, DcPool, DcRegistry};
+use mtproto::rpc::mock::{MockConnector, MockServer};
+use mtproto::schema;
+use serde_bytes::ByteBuf;
+use serde_mtproto::Boxed;
+use test_logger::ensure_env_logger_initialized;
+
+
+const MASTER_DC: i32 = 2;
+const CDN_DC: i32 = 5;
+const PART_SIZE: i32 = 4096;
+
+/// The CDN part is requested after a full part from the master DC.
+const CDN_OFFSET: i32 = PART_SIZE;
+
+const CDN_PLAIN_TEXT: &'static [u8] = b"Files served from CDN datacenters are encrypted with AES-256-CTR.";
+
+/// `CDN_PLAIN_TEXT` encrypted with AES-256-CTR using `cdn_redirect()`
+/// key and IV with the counter set to `CDN_OFFSET / 16`.
+const CDN_CIPHER_TEXT: [u8; 65] = [
+    0xbf, 0x59, 0x12, 0xa6, 0x9e, 0x1e, 0x3a, 0xe9, 0xa3, 0xc9, 0x5a, 0xba, 0x10, 0x08, 0x2d, 0xdf,
+    0x61, 0x96, 0x3d, 0x84, 0xcb, 0xb6, 0x36, 0xf3, 0x67, 0x07, 0x2d, 0x1a, 0x52, 0xcb, 0x71, 0xb9,
+    0x26, 0x67, 0x09, 0x1d, 0x00, 0x73, 0xbf, 0x6d, 0x5b, 0x98, 0x43, 0x6a, 0x65, 0x2d, 0x5c, 0xb3,
+    0xf1, 0x86, 0x5d, 0x2c, 0x75, 0x5c, 0xd3, 0xc8, 0x27, 0xab, 0xb8, 0x08, 0xf2, 0x42, 0x54, 0xd5,
+    0x3e,
+];
+
+/// SHA-256 of `CDN_PLAIN_TEXT`.
+const CDN_PLAIN_TEXT_SHA256: [u8; 32] = [
+    0x93, 0xde, 0xca, 0xaa, 0x03, 0xcf, 0xbc, 0xbb, 0xf0, 0x53, 0x83, 0xe9, 0xe9, 0x8a, 0xf3, 0x6e,
+    0xe1, 0x90, 0x54, 0xef, 0x8f, 0xc8, 0xa7, 0x5b, 0x2d, 0xc4, 0x69, 0x2e, 0xdc, 0xac, 0x2b, 0x39,
+];
+
+
+fn location() -> schema::InputFileLocation {
+    schema::InputFileLocation::inputFileLocation(schema::inputFileLocation {
+        volume_id: 1,
+        local_id: 2,
+        secret: 3,
+    })
+}
+
+fn file_part(bytes: Vec<u8>) -> schema::upload::File {
+    schema::upload::File::file(schema::upload::file {
+        type_: schema::storage::FileType::fileUnknown,
+        mtime: 0,
+        bytes: ByteBuf::from(bytes),
+    })
+}
+
+fn cdn_redirect() -> schema::upload::File {
+    schema::upload::File::fileCdnRedirect(schema::upload::fileCdnRedirect {
+        dc_id: CDN_DC,
+        file_token: ByteBuf::from(b"token".to_vec()),
+        encryption_key: ByteBuf::from((0..32).collect::<Vec<u8>>()),
+        encryption_iv: ByteBuf::from((0x10..0x20).collect::<Vec<u8>>()),
+    })
+}
+
+fn master_part() -> Vec<u8> {
+    (0..PART_SIZE).map(|i| i as u8).collect()
+}
+
+fn pool(master: &MockServer, cdn: &MockServer) -> DcPool<MockConnector> {
+    let mut connector = MockConnector::new(AppInfo::new(9000, "random text".to_owned()));
+    connector.add_server(MASTER_DC, master.clone());
+    connector.add_server(CDN_DC, cdn.clone());
+
+    let mut registry = DcRegistry::new();
+    registry.add(DcEndpoint::new(MASTER_DC, "127.0.0.2".to_owned(), 443));
+    registry.add(DcEndpoint { cdn: true, ..DcEndpoint::new(CDN_DC, "127.0.0.5".to_owned(), 443) });
+
+    DcPool::new(connector, registry, MASTER_DC)
+}
+
+/// A master DC which serves the first part itself, then redirects to
+/// the CDN and provides `hashes` for the CDN part.
+fn master_server(hashes: Vec<schema::CdnFileHash>) -> MockServer {
+    let server = MockServer::new().unwrap();
+
+    server.on(|function: schema::rpc::upload::getFile| {
+        if function.offset == 0 {
+            Ok(file_part(master_part()))
+        } else {
+            assert_eq!(function.offset, CDN_OFFSET);
+            Ok(cdn_redirect())
+        }
+    });
+
+    server.on(move |function: schema::rpc::upload::getCdnFileHashes| {
+        assert_eq!(&function.file_token[..], b"token");
+        assert_eq!(function.offset, CDN_OFFSET);
+
+        Ok(Boxed::new(hashes.clone()))
+    });
+
+    server
+}
+
+fn cdn_hash(hash: &[u8]) -> schema::CdnFileHash {
+    schema::CdnFileHash {
+        offset: CDN_OFFSET,
+        limit: 128 * 1024,
+        hash: ByteBuf::from(hash.to_vec()),
+    }
+}
+
+/// A CDN which asks to reupload the file `reuploads` times, then serves
+/// it.
+fn cdn_server(reuploads: usize) -> (MockServer, Rc<Cell<usize>>) {
+    let requests = Rc::new(Cell::new(0));
+    let server = MockServer::new().unwrap();
+
+    {
+        let requests = requests.clone();
+        server.on(move |function: schema::rpc::upload::getCdnFile| {
+            assert_eq!(function.offset, CDN_OFFSET);
+            requests.set(requests.get() + 1);
+
+            if requests.get() <= reuploads {
+                Ok(schema::upload::CdnFile::cdnFileReuploadNeeded(schema::upload::cdnFileReuploadNeeded {
+                    request_token: ByteBuf::from(b"request".to_vec()),
+                }))
+            } else {
+                Ok(schema::upload::CdnFile::cdnFile(schema::upload::cdnFile {
+                    bytes: ByteBuf::from(CDN_CIPHER_TEXT.to_vec()),
+                }))
+            }
+        });
+    }
+
+    (server, requests)
+}
+
+fn count_reuploads(master: &MockServer) -> Rc<Cell<usize>> {
+    let reuploads = Rc::new(Cell::new(0));
+
+    {
+        let reuploads = reuploads.clone();
+        master.on(move |function: schema::rpc::upload::reuploadCdnFile| {
+            assert_eq!(&function.request_token[..], b"request");
+            reuploads.set(reuploads.get() + 1);
+
+            Ok(true)
+        });
+    }
+
+    reuploads
+}
+
+
+#[test]
+fn test_download() {
+    ensure_env_logger_initialized();
+
+    let requests = Rc::new(Cell::new(0));
+    let master = MockServer::new().unwrap();
+    let (cdn, _) = cdn_server(0);
+
+    {
+        let requests = requests.clone();
+        master.on(move |function: schema::rpc::upload::getFile| {
+            assert_eq!(function.limit, PART_SIZE);
+            requests.set(requests.get() + 1);
+
+            // Two full parts and a short one
+            let len = if function.offset < 2 * PART_SIZE { PART_SIZE } else { 100 };
+            Ok(file_part(vec![(function.offset / PART_SIZE) as u8; len as usize]))
+        });
+    }
+
+    let mut dest = Vec::new();
+    let written = Downloader::new().part_size(PART_SIZE)
+        .download(&mut pool(&master, &cdn), MASTER_DC, location(), &mut dest)
+        .unwrap();
+
+    assert_eq!(written, 2 * PART_SIZE as u64 + 100);
+    assert_eq!(requests.get(), 3);
+    assert_eq!(dest.len(), written as usize);
+    assert!(dest[..PART_SIZE as usize].iter().all(|&b| b == 0));
+    assert!(dest[PART_SIZE as usize..2 * PART_SIZE as usize].iter().all(|&b| b == 1));
+    assert!(dest[2 * PART_SIZE as usize..].iter().all(|&b| b == 2));
+}
+
+#[test]
+fn test_download_cdn() {
+    ensure_env_logger_initialized();
+
+    let master = master_server(vec![cdn_hash(&CDN_PLAIN_TEXT_SHA256)]);
+    let (cdn, requests) = cdn_server(0);
+
+    let mut dest = Vec::new();
+    let written = Downloader::new().part_size(PART_SIZE)
+        .download(&mut pool(&master, &cdn), MASTER_DC, location(), &mut dest)
+        .unwrap();
+
+    assert_eq!(written, (PART_SIZE as usize + CDN_PLAIN_TEXT.len()) as u64);
+    assert_eq!(requests.get(), 1);
+    assert_eq!(&dest[..PART_SIZE as usize], &master_part()[..]);
+    // The part is decrypted with the counter depending on its offset
+    assert_eq!(&dest[PART_SIZE as usize..], CDN_PLAIN_TEXT);
+}
+
+#[test]
+fn test_download_cdn_hash_mismatch() {
+    ensure_env_logger_initialized();
+
+    let mut wrong_hash = CDN_PLAIN_TEXT_SHA256;
+    wrong_hash[0] ^= 1;
+
+    let master = master_server(vec![cdn_hash(&wrong_hash)]);
+    let (cdn, _) = cdn_server(0);
+
+    let error = Downloader::new().part_size(PART_SIZE)
+        .download(&mut pool(&master, &cdn), MASTER_DC, location(), &mut Vec::new())
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::CdnHashMismatch(CDN_OFFSET) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn test_download_cdn_hash_missing() {
+    ensure_env_logger_initialized();
+
+    let master = master_server(vec![]);
+    let (cdn, _) = cdn_server(0);
+
+    let error = Downloader::new().part_size(PART_SIZE)
+        .download(&mut pool(&master, &cdn), MASTER_DC, location(), &mut Vec::new())
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::CdnHashMissing(CDN_OFFSET) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn test_download_cdn_reupload() {
+    ensure_env_logger_initialized();
+
+    let master = master_server(vec![cdn_hash(&CDN_PLAIN_TEXT_SHA256)]);
+    let reuploads = count_reuploads(&master);
+    let (cdn, requests) = cdn_server(2);
+
+    let mut dest = Vec::new();
+    Downloader::new().part_size(PART_SIZE)
+        .download(&mut pool(&master, &cdn), MASTER_DC, location(), &mut dest)
+        .unwrap();
+
+    assert_eq!(reuploads.get(), 2);
+    assert_eq!(requests.get(), 3);
+    assert_eq!(&dest[PART_SIZE as usize..], CDN_PLAIN_TEXT);
+}
+
+#[test]
+fn test_download_cdn_too_many_reuploads() {
+    ensure_env_logger_initialized();
+
+    let master = master_server(vec![cdn_hash(&CDN_PLAIN_TEXT_SHA256)]);
+    let reuploads = count_reuploads(&master);
+    let (cdn, requests) = cdn_server(usize::max_value());
+
+    let error = Downloader::new().part_size(PART_SIZE)
+        .download(&mut pool(&master, &cdn), MASTER_DC, location(), &mut Vec::new())
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::TooManyCdnReuploads(CDN_OFFSET) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert_eq!(reuploads.get(), 3);
+    assert_eq!(requests.get(), 4);
+}
+
+#[test]
+fn test_download_cdn_malformed_redirect() {
+    ensure_env_logger_initialized();
+
+    let master = MockServer::new().unwrap();
+    master.on(|_: schema::rpc::upload::getFile| {
+        let redirect = match cdn_redirect() {
+            schema::upload::File::fileCdnRedirect(redirect) => redirect,
+            _ => unreachable!(),
+        };
+
+        Ok(schema::upload::File::fileCdnRedirect(schema::upload::fileCdnRedirect {
+            encryption_iv: ByteBuf::from(vec![0; 8]),
+            ..redirect
+        }))
+    });
+    let (cdn, _) = cdn_server(0);
+
+    let error = Downloader::new().part_size(PART_SIZE)
+        .download(&mut pool(&master, &cdn), MASTER_DC, location(), &mut Vec::new())
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::MalformedCdnRedirect => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
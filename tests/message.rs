@@ -10,8 +10,9 @@ extern crate test_logger;
 use std::thread::sleep;
 use std::time::Duration;
 
-use mtproto::rpc::{AppInfo, Message, Session};
+use mtproto::rpc::{AppInfo, Message, Ordered, Session};
 use mtproto::rpc::encryption::AuthKey;
+use mtproto::rpc::ordered::invoke_after_msg;
 use mtproto::schema::FutureSalt;
 use serde_mtproto::MtProtoSized;
 use test_logger::ensure_env_logger_initialized;
@@ -63,6 +64,40 @@ fn test_encrypted() {
     assert_eq!(message, msg);
 }
 
+#[test]
+fn test_ordered_queue() {
+    let mut session = Session::new(892103, AppInfo::new(9000, "random text".to_owned()));
+    session.adopt_key(AuthKey::new(&[0xf0, 0xe1, 0xd2, 0xc3, 0xb4, 0xa5, 0x96, 0x87]).unwrap());
+    session.add_server_salts(vec![FutureSalt {
+        valid_since: 0x0100_0000,
+        valid_until: 0x0fff_ffff,
+        salt: 0x1234_5678_90ab_cdef,
+    }]);
+    session.set_ordered_queue(true);
+
+    // The first message in a chain doesn't depend on anything
+    let first = session.create_encrypted_message_ordered(1).unwrap();
+    let first_id = first.message_id();
+    assert_eq!(first.unwrap_decrypted_body(), Ordered::Standalone(1));
+
+    let second = session.create_encrypted_message_ordered(2).unwrap();
+    let second_id = second.message_id();
+    assert!(second_id > first_id);
+    assert_eq!(second.unwrap_decrypted_body(), Ordered::AfterMsg(invoke_after_msg(first_id, 2)));
+
+    let third = session.create_encrypted_message_ordered(3).unwrap();
+    assert_eq!(third.unwrap_decrypted_body(), Ordered::AfterMsg(invoke_after_msg(second_id, 3)));
+
+    // Toggling the mode starts a new chain
+    session.set_ordered_queue(false);
+    let unordered = session.create_encrypted_message_ordered(4).unwrap();
+    assert_eq!(unordered.unwrap_decrypted_body(), Ordered::Standalone(4));
+
+    session.set_ordered_queue(true);
+    let restarted = session.create_encrypted_message_ordered(5).unwrap();
+    assert_eq!(restarted.unwrap_decrypted_body(), Ordered::Standalone(5));
+}
+
 #[test]
 fn test_next_message_id_monotonicity() {
     let session = Session::new(0, AppInfo::new(100, "foo hash".to_owned()));
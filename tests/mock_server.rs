@@ -0,0 +1,149 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate test_logger;
+
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use mtproto::ErrorKind;
+use mtproto::auth;
+use mtproto::rpc::{AppInfo, DcEndpoint, DcPool, DcRegistry, RpcCaller};
+use mtproto::rpc::mock::{MockConnection, MockConnector, MockServer};
+use mtproto::schema;
+use test_logger::ensure_env_logger_initialized;
+
+
+const BOT_USER_ID: i32 = 123456;
+
+
+fn app_info() -> AppInfo {
+    AppInfo::new(9000, "random text".to_owned())
+}
+
+
+#[test]
+fn test_connect_and_ping() {
+    ensure_env_logger_initialized();
+
+    let server = MockServer::new().unwrap();
+    let mut connection = MockConnection::connect(&server, app_info()).unwrap();
+
+    assert_eq!(server.auth_key_count(), 1);
+
+    let pong = connection.call(schema::rpc::ping { ping_id: 0x1234_5678 }).unwrap();
+    assert_eq!(pong.ping_id, 0x1234_5678);
+    assert!(pong.msg_id != 0);
+    assert_eq!(server.session_count(), 1);
+}
+
+#[test]
+fn test_get_future_salts() {
+    ensure_env_logger_initialized();
+
+    let server = MockServer::new().unwrap();
+    let mut connection = MockConnection::connect(&server, app_info()).unwrap();
+
+    let future_salts = connection.call(schema::rpc::get_future_salts { num: 3 }).unwrap();
+    assert_eq!(future_salts.salts.len(), 3);
+
+    for pair in future_salts.salts.windows(2) {
+        assert_eq!(pair[0].valid_until, pair[1].valid_since);
+    }
+
+    // The session keeps working with the current salt
+    connection.call(schema::rpc::ping { ping_id: 1 }).unwrap();
+}
+
+#[test]
+fn test_scripted_reply_and_acks() {
+    ensure_env_logger_initialized();
+
+    let phones = Rc::new(RefCell::new(Vec::new()));
+    let server = MockServer::new().unwrap();
+
+    {
+        let phones = phones.clone();
+        server.on(move |function: schema::rpc::auth::checkPhone| {
+            phones.borrow_mut().push(function.phone_number.clone());
+
+            Ok(schema::auth::CheckedPhone {
+                phone_registered: function.phone_number == "+15550001",
+            })
+        });
+    }
+
+    let mut connection = MockConnection::connect(&server, app_info()).unwrap();
+
+    let checked = connection.call(schema::rpc::auth::checkPhone { phone_number: "+15550001".to_owned() }).unwrap();
+    assert!(checked.phone_registered);
+    assert!(server.acked_msg_ids().is_empty());
+
+    // Replies to the first call are acknowledged along with the second one
+    let checked = connection.call(schema::rpc::auth::checkPhone { phone_number: "+15550002".to_owned() }).unwrap();
+    assert!(!checked.phone_registered);
+    assert_eq!(server.acked_msg_ids().len(), 2); // new_session_created and rpc_result
+
+    assert_eq!(*phones.borrow(), vec!["+15550001".to_owned(), "+15550002".to_owned()]);
+}
+
+#[test]
+fn test_rpc_errors() {
+    ensure_env_logger_initialized();
+
+    let server = MockServer::new().unwrap();
+    server.on(|_: schema::rpc::auth::checkPhone| -> mtproto::Result<schema::auth::CheckedPhone> {
+        Err(ErrorKind::RpcError(420, "FLOOD_WAIT_5".to_owned()).into())
+    });
+
+    let mut connection = MockConnection::connect(&server, app_info()).unwrap();
+
+    let error = connection.call(schema::rpc::auth::checkPhone { phone_number: "+15550001".to_owned() }).unwrap_err();
+    match *error.kind() {
+        ErrorKind::RpcError(420, ref message) => assert_eq!(message, "FLOOD_WAIT_5"),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    let error = connection.call(schema::rpc::help::getNearestDc {}).unwrap_err();
+    match *error.kind() {
+        ErrorKind::RpcError(400, ref message) => assert_eq!(message, "INPUT_METHOD_INVALID"),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_bot_login_through_pool() {
+    ensure_env_logger_initialized();
+
+    let server_dc2 = MockServer::new().unwrap();
+    server_dc2.on(|_: schema::rpc::auth::importBotAuthorization| -> mtproto::Result<schema::auth::Authorization> {
+        Err(ErrorKind::RpcError(303, "USER_MIGRATE_4".to_owned()).into())
+    });
+
+    let server_dc4 = MockServer::new().unwrap();
+    server_dc4.on(|function: schema::rpc::auth::importBotAuthorization| {
+        assert_eq!(function.bot_auth_token, "123456:bot-token");
+
+        Ok(schema::auth::Authorization {
+            tmp_sessions: None,
+            user: schema::User::userEmpty(schema::userEmpty { id: BOT_USER_ID }),
+        })
+    });
+
+    let mut connector = MockConnector::new(app_info());
+    connector.add_server(2, server_dc2.clone());
+    connector.add_server(4, server_dc4.clone());
+
+    let mut registry = DcRegistry::new();
+    registry.add(DcEndpoint::new(2, "127.0.0.2".to_owned(), 443));
+    registry.add(DcEndpoint::new(4, "127.0.0.4".to_owned(), 443));
+
+    let mut pool = DcPool::new(connector, registry, 2);
+    let user = auth::bot_login(&mut pool, &app_info(), "123456:bot-token").unwrap();
+
+    assert_eq!(user, schema::User::userEmpty(schema::userEmpty { id: BOT_USER_ID }));
+    assert_eq!(pool.home_dc(), 4);
+    assert_eq!(server_dc2.auth_key_count(), 1);
+    assert_eq!(server_dc4.auth_key_count(), 1);
+}
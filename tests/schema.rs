@@ -0,0 +1,51 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate serde_mtproto;
+
+
+use mtproto::schema;
+
+
+#[test]
+fn test_constructor_builder() {
+    let type_ = schema::auth::SentCodeType::sentCodeTypeSms(schema::auth::sentCodeTypeSms { length: 5 });
+    let sent_code = schema::auth::SentCode::builder(type_.clone(), "abc".to_owned())
+        .phone_registered(true)
+        .timeout(60)
+        .build();
+
+    assert_eq!(sent_code, schema::auth::SentCode {
+        phone_registered: true,
+        type_: type_,
+        phone_code_hash: "abc".to_owned(),
+        next_type: None,
+        timeout: Some(60),
+    });
+}
+
+#[test]
+fn test_function_builder() {
+    let send_message = schema::rpc::messages::sendMessage::builder(
+        schema::InputPeer::inputPeerSelf, "hi".to_owned(), 42)
+        .silent(true)
+        .reply_to_msg_id(7)
+        .build();
+
+    assert_eq!(send_message, schema::rpc::messages::sendMessage {
+        no_webpage: false,
+        silent: true,
+        background: false,
+        clear_draft: false,
+        peer: schema::InputPeer::inputPeerSelf,
+        reply_to_msg_id: Some(7),
+        message: "hi".to_owned(),
+        random_id: 42,
+        reply_markup: None,
+        entities: None,
+    });
+
+    // `silent` is bit 5 and `reply_to_msg_id` is bit 0 of the flags
+    let bytes = serde_mtproto::to_bytes(&send_message).unwrap();
+    assert_eq!(&bytes[..4], &[0x21, 0x00, 0x00, 0x00]);
+}
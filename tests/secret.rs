@@ -0,0 +1,527 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate serde_bytes;
+extern crate test_logger;
+
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use mtproto::{Error, ErrorKind};
+use mtproto::rpc::AppInfo;
+use mtproto::rpc::mock::{MockConnection, MockServer};
+use mtproto::rpc::server::{DEFAULT_DH_G, DEFAULT_DH_PRIME};
+use mtproto::schema;
+use mtproto::secret::{SECRET_LAYER, SecretChatManager};
+use serde_bytes::ByteBuf;
+use test_logger::ensure_env_logger_initialized;
+
+
+const CHAT_ID: i32 = 77;
+const ACCESS_HASH: i64 = 0x1234_5678;
+const ALICE_ID: i32 = 1;
+const BOB_ID: i32 = 2;
+
+
+/// A server relaying secret chat messages between Alice, who creates
+/// the chat, and Bob.
+struct Relay {
+    server: MockServer,
+    /// `g_a` sent with `messages.requestEncryption`.
+    g_a: Rc<RefCell<Vec<u8>>>,
+    /// `g_b` and the key fingerprint sent with
+    /// `messages.acceptEncryption`.
+    accepted: Rc<RefCell<Option<(Vec<u8>, i64)>>>,
+    /// `data` of sent messages in order.
+    sent: Rc<RefCell<VecDeque<Vec<u8>>>>,
+}
+
+impl Relay {
+    fn new(g: i32, p: Vec<u8>) -> Relay {
+        let relay = Relay {
+            server: MockServer::new().unwrap(),
+            g_a: Rc::new(RefCell::new(Vec::new())),
+            accepted: Rc::new(RefCell::new(None)),
+            sent: Rc::new(RefCell::new(VecDeque::new())),
+        };
+
+        relay.server.on(move |function: schema::rpc::messages::getDhConfig| {
+            Ok(schema::messages::DhConfig::dhConfig(schema::messages::dhConfig {
+                g: g,
+                p: ByteBuf::from(p.clone()),
+                version: 1,
+                random: ByteBuf::from(vec![0x5a; function.random_length as usize]),
+            }))
+        });
+
+        {
+            let g_a = relay.g_a.clone();
+            relay.server.on(move |function: schema::rpc::messages::requestEncryption| {
+                *g_a.borrow_mut() = function.g_a.to_vec();
+
+                Ok(schema::EncryptedChat::encryptedChatWaiting(schema::encryptedChatWaiting {
+                    id: CHAT_ID,
+                    access_hash: ACCESS_HASH,
+                    date: 0,
+                    admin_id: ALICE_ID,
+                    participant_id: BOB_ID,
+                }))
+            });
+        }
+
+        {
+            let accepted = relay.accepted.clone();
+            relay.server.on(move |function: schema::rpc::messages::acceptEncryption| {
+                *accepted.borrow_mut() = Some((function.g_b.to_vec(), function.key_fingerprint));
+
+                Ok(schema::EncryptedChat::encryptedChat(schema::encryptedChat {
+                    id: CHAT_ID,
+                    access_hash: ACCESS_HASH,
+                    date: 0,
+                    admin_id: ALICE_ID,
+                    participant_id: BOB_ID,
+                    g_a_or_b: function.g_b,
+                    key_fingerprint: function.key_fingerprint,
+                }))
+            });
+        }
+
+        {
+            let sent = relay.sent.clone();
+            relay.server.on(move |function: schema::rpc::messages::sendEncrypted| {
+                sent.borrow_mut().push_back(function.data.to_vec());
+                Ok(sent_encrypted_message())
+            });
+        }
+
+        {
+            let sent = relay.sent.clone();
+            relay.server.on(move |function: schema::rpc::messages::sendEncryptedService| {
+                sent.borrow_mut().push_back(function.data.to_vec());
+                Ok(sent_encrypted_message())
+            });
+        }
+
+        relay
+    }
+
+    fn connect(&self) -> MockConnection {
+        MockConnection::connect(&self.server, AppInfo::new(9000, "random text".to_owned())).unwrap()
+    }
+
+    /// The next message sent by anyone, as received by the other party.
+    fn next_message(&self) -> schema::EncryptedMessage {
+        let bytes = self.sent.borrow_mut().pop_front().expect("no messages sent");
+
+        schema::EncryptedMessage::encryptedMessage(schema::encryptedMessage {
+            random_id: 0,
+            chat_id: CHAT_ID,
+            date: 0,
+            bytes: ByteBuf::from(bytes),
+            file: schema::EncryptedFile::encryptedFileEmpty,
+        })
+    }
+}
+
+/// Both parties of an established secret chat.
+struct Chat {
+    relay: Relay,
+    alice: SecretChatManager,
+    alice_connection: MockConnection,
+    bob: SecretChatManager,
+    bob_connection: MockConnection,
+}
+
+impl Chat {
+    fn establish() -> Chat {
+        let relay = Relay::new(DEFAULT_DH_G, DEFAULT_DH_PRIME.to_vec());
+        let mut alice = SecretChatManager::new();
+        let mut alice_connection = relay.connect();
+        let mut bob = SecretChatManager::new();
+        let mut bob_connection = relay.connect();
+
+        let bob_user = schema::InputUser::inputUser(schema::inputUser { user_id: BOB_ID, access_hash: 0 });
+        assert_eq!(alice.request(&mut alice_connection, bob_user).unwrap(), CHAT_ID);
+
+        let g_a = relay.g_a.borrow().clone();
+        bob.accept(&mut bob_connection, &requested(g_a)).unwrap();
+
+        let (g_b, key_fingerprint) = relay.accepted.borrow().clone().unwrap();
+        alice.handle_encrypted_chat(&accepted(g_b, key_fingerprint)).unwrap();
+
+        Chat {
+            relay: relay,
+            alice: alice,
+            alice_connection: alice_connection,
+            bob: bob,
+            bob_connection: bob_connection,
+        }
+    }
+
+    /// Establish a chat and deliver `decryptedMessageActionNotifyLayer`
+    /// sent by both parties.
+    fn establish_and_notify() -> Chat {
+        let mut chat = Chat::establish();
+
+        chat.alice.flush(&mut chat.alice_connection).unwrap();
+        chat.to_bob().unwrap().unwrap();
+        chat.bob.flush(&mut chat.bob_connection).unwrap();
+        chat.to_alice().unwrap().unwrap();
+
+        chat
+    }
+
+    fn alice_sends(&mut self, text: &str) {
+        self.alice.send(&mut self.alice_connection, CHAT_ID, message(text)).unwrap();
+    }
+
+    fn bob_sends(&mut self, text: &str) {
+        self.bob.send(&mut self.bob_connection, CHAT_ID, message(text)).unwrap();
+    }
+
+    fn to_alice(&mut self) -> Result<Option<schema::DecryptedMessage>, Error> {
+        let message = self.relay.next_message();
+        self.alice.decrypt(&message)
+    }
+
+    fn to_bob(&mut self) -> Result<Option<schema::DecryptedMessage>, Error> {
+        let message = self.relay.next_message();
+        self.bob.decrypt(&message)
+    }
+
+    fn fingerprints(&self) -> (i64, i64) {
+        (self.alice.chat(CHAT_ID).unwrap().key_fingerprint(), self.bob.chat(CHAT_ID).unwrap().key_fingerprint())
+    }
+}
+
+fn requested(g_a: Vec<u8>) -> schema::encryptedChatRequested {
+    schema::encryptedChatRequested {
+        id: CHAT_ID,
+        access_hash: ACCESS_HASH,
+        date: 0,
+        admin_id: ALICE_ID,
+        participant_id: BOB_ID,
+        g_a: ByteBuf::from(g_a),
+    }
+}
+
+fn accepted(g_b: Vec<u8>, key_fingerprint: i64) -> schema::EncryptedChat {
+    schema::EncryptedChat::encryptedChat(schema::encryptedChat {
+        id: CHAT_ID,
+        access_hash: ACCESS_HASH,
+        date: 0,
+        admin_id: ALICE_ID,
+        participant_id: BOB_ID,
+        g_a_or_b: ByteBuf::from(g_b),
+        key_fingerprint: key_fingerprint,
+    })
+}
+
+fn sent_encrypted_message() -> schema::messages::SentEncryptedMessage {
+    schema::messages::SentEncryptedMessage::sentEncryptedMessage(schema::messages::sentEncryptedMessage { date: 0 })
+}
+
+fn message(text: &str) -> schema::DecryptedMessage {
+    schema::DecryptedMessage::decryptedMessage(schema::decryptedMessage {
+        random_id: text.len() as i64,
+        ttl: 0,
+        message: text.to_owned(),
+        media: schema::DecryptedMessageMedia::decryptedMessageMediaEmpty,
+    })
+}
+
+fn text(message: Option<schema::DecryptedMessage>) -> String {
+    match message {
+        Some(schema::DecryptedMessage::decryptedMessage(message)) => message.message,
+        message => panic!("unexpected message: {:?}", message),
+    }
+}
+
+fn action(message: Option<schema::DecryptedMessage>) -> schema::DecryptedMessageAction {
+    match message {
+        Some(schema::DecryptedMessage::decryptedMessageService(service)) => service.action,
+        message => panic!("unexpected message: {:?}", message),
+    }
+}
+
+/// `2^(2048 - 64)`.
+fn safety_margin() -> Vec<u8> {
+    let mut margin = vec![0; 249];
+    margin[0] = 1;
+    margin
+}
+
+/// `a - b` for `a >= b` of the same length.
+fn sub(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut result = a.to_vec();
+    let mut borrow = 0;
+
+    for i in (0..a.len()).rev() {
+        let difference = a[i] as i16 - b[i] as i16 - borrow;
+        result[i] = difference as u8;
+        borrow = if difference < 0 { 1 } else { 0 };
+    }
+
+    result
+}
+
+/// `a + 1`, assuming it doesn't overflow.
+fn add_one(a: &[u8]) -> Vec<u8> {
+    let mut result = a.to_vec();
+    for byte in result.iter_mut().rev() {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Left-pad a big-endian number to the length of `DEFAULT_DH_PRIME`.
+fn pad(a: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; DEFAULT_DH_PRIME.len() - a.len()];
+    result.extend_from_slice(a);
+    result
+}
+
+
+#[test]
+fn test_establish() {
+    ensure_env_logger_initialized();
+
+    let chat = Chat::establish();
+    let (alice_fingerprint, bob_fingerprint) = chat.fingerprints();
+
+    assert_eq!(alice_fingerprint, bob_fingerprint);
+    assert!(chat.alice.chat(CHAT_ID).unwrap().is_admin());
+    assert!(!chat.bob.chat(CHAT_ID).unwrap().is_admin());
+}
+
+#[test]
+fn test_round_trip() {
+    ensure_env_logger_initialized();
+
+    let mut chat = Chat::establish();
+
+    // Both parties announce their layer first
+    chat.alice.flush(&mut chat.alice_connection).unwrap();
+    match action(chat.to_bob().unwrap()) {
+        schema::DecryptedMessageAction::decryptedMessageActionNotifyLayer(notify) => {
+            assert_eq!(notify.layer, SECRET_LAYER);
+        },
+        action => panic!("unexpected action: {:?}", action),
+    }
+    assert_eq!(chat.bob.chat(CHAT_ID).unwrap().peer_layer(), SECRET_LAYER);
+
+    chat.bob.flush(&mut chat.bob_connection).unwrap();
+    chat.to_alice().unwrap().unwrap();
+    assert_eq!(chat.alice.chat(CHAT_ID).unwrap().peer_layer(), SECRET_LAYER);
+
+    chat.alice_sends("hello");
+    assert_eq!(text(chat.to_bob().unwrap()), "hello");
+
+    chat.bob_sends("hi");
+    assert_eq!(text(chat.to_alice().unwrap()), "hi");
+}
+
+#[test]
+fn test_seq_no_parity() {
+    ensure_env_logger_initialized();
+
+    let mut chat = Chat::establish_and_notify();
+
+    // Sequence numbers of both parties advance independently: Alice
+    // sends odd ones, Bob sends even ones
+    for i in 0..3 {
+        chat.alice_sends(&format!("alice {}", i));
+    }
+    chat.bob_sends("bob 0");
+
+    for i in 0..3 {
+        assert_eq!(text(chat.to_bob().unwrap()), format!("alice {}", i));
+    }
+    assert_eq!(text(chat.to_alice().unwrap()), "bob 0");
+
+    chat.bob_sends("bob 1");
+    chat.alice_sends("alice 3");
+    assert_eq!(text(chat.to_alice().unwrap()), "bob 1");
+    assert_eq!(text(chat.to_bob().unwrap()), "alice 3");
+}
+
+#[test]
+fn test_duplicates_and_gaps() {
+    ensure_env_logger_initialized();
+
+    let mut chat = Chat::establish_and_notify();
+
+    chat.alice_sends("first");
+    chat.alice_sends("second");
+    chat.alice_sends("third");
+
+    let first = chat.relay.next_message();
+    let second = chat.relay.next_message();
+    let third = chat.relay.next_message();
+
+    assert_eq!(text(chat.bob.decrypt(&first).unwrap()), "first");
+    assert_eq!(chat.bob.decrypt(&first).unwrap(), None);
+
+    // Alice's out_seq_no are 1 (the layer notification), 3, 5, 7
+    let error = chat.bob.decrypt(&third).unwrap_err();
+    match *error.kind() {
+        ErrorKind::SecretChatSeqGap(CHAT_ID, 5, 7) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    assert_eq!(text(chat.bob.decrypt(&second).unwrap()), "second");
+    assert_eq!(text(chat.bob.decrypt(&third).unwrap()), "third");
+    assert_eq!(chat.bob.decrypt(&second).unwrap(), None);
+}
+
+#[test]
+fn test_malformed_message() {
+    ensure_env_logger_initialized();
+
+    let mut chat = Chat::establish_and_notify();
+    chat.alice_sends("hello");
+
+    // A valid header followed by a partial AES block
+    let mut message = match chat.relay.next_message() {
+        schema::EncryptedMessage::encryptedMessage(message) => message,
+        message => panic!("unexpected message: {:?}", message),
+    };
+    let mut bytes = message.bytes.to_vec();
+    bytes.truncate(24 + 16 + 1);
+    message.bytes = ByteBuf::from(bytes);
+
+    let error = chat.bob.decrypt(&schema::EncryptedMessage::encryptedMessage(message)).unwrap_err();
+    match *error.kind() {
+        ErrorKind::SecretMessageInvalidLength(41) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn test_rekey() {
+    ensure_env_logger_initialized();
+
+    let mut chat = Chat::establish_and_notify();
+    let (old_fingerprint, _) = chat.fingerprints();
+
+    chat.alice.start_rekey(CHAT_ID).unwrap();
+    chat.alice.flush(&mut chat.alice_connection).unwrap();
+    let g_a = match action(chat.to_bob().unwrap()) {
+        schema::DecryptedMessageAction::decryptedMessageActionRequestKey(request) => request.g_a,
+        action => panic!("unexpected action: {:?}", action),
+    };
+    assert_eq!(g_a.len(), DEFAULT_DH_PRIME.len());
+
+    chat.bob.flush(&mut chat.bob_connection).unwrap();
+    let new_fingerprint = match action(chat.to_alice().unwrap()) {
+        schema::DecryptedMessageAction::decryptedMessageActionAcceptKey(accept) => accept.key_fingerprint,
+        action => panic!("unexpected action: {:?}", action),
+    };
+    assert!(new_fingerprint != old_fingerprint);
+
+    // The key is switched only after the commit message is encrypted
+    assert_eq!(chat.fingerprints(), (old_fingerprint, old_fingerprint));
+    chat.alice.flush(&mut chat.alice_connection).unwrap();
+    assert_eq!(chat.fingerprints(), (new_fingerprint, old_fingerprint));
+
+    match action(chat.to_bob().unwrap()) {
+        schema::DecryptedMessageAction::decryptedMessageActionCommitKey(commit) => {
+            assert_eq!(commit.key_fingerprint, new_fingerprint);
+        },
+        action => panic!("unexpected action: {:?}", action),
+    }
+    assert_eq!(chat.fingerprints(), (new_fingerprint, new_fingerprint));
+
+    // Bob confirms the switch with a message encrypted with the new key
+    chat.bob.flush(&mut chat.bob_connection).unwrap();
+    match action(chat.to_alice().unwrap()) {
+        schema::DecryptedMessageAction::decryptedMessageActionNoop => (),
+        action => panic!("unexpected action: {:?}", action),
+    }
+
+    chat.alice_sends("after rekey");
+    assert_eq!(text(chat.to_bob().unwrap()), "after rekey");
+    chat.bob_sends("after rekey too");
+    assert_eq!(text(chat.to_alice().unwrap()), "after rekey too");
+}
+
+#[test]
+fn test_unsafe_dh_params() {
+    ensure_env_logger_initialized();
+
+    let request = |g: i32, p: Vec<u8>| -> Error {
+        let relay = Relay::new(g, p);
+        let bob_user = schema::InputUser::inputUser(schema::inputUser { user_id: BOB_ID, access_hash: 0 });
+
+        SecretChatManager::new().request(&mut relay.connect(), bob_user).unwrap_err()
+    };
+
+    // Among 2..7, only 3, 4 and 7 generate the prime order subgroup for
+    // the default prime
+    for &g in &[2, 5, 6, 0, 8] {
+        match *request(g, DEFAULT_DH_PRIME.to_vec()).kind() {
+            ErrorKind::UnsafeDhGenerator(found) => assert_eq!(found, g),
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    let mut not_prime = DEFAULT_DH_PRIME.to_vec();
+    *not_prime.last_mut().unwrap() ^= 2;
+    match *request(DEFAULT_DH_G, not_prime).kind() {
+        ErrorKind::UnsafeDhPrime => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn test_dh_value_range() {
+    ensure_env_logger_initialized();
+
+    let relay = Relay::new(DEFAULT_DH_G, DEFAULT_DH_PRIME.to_vec());
+    let mut connection = relay.connect();
+
+    let margin = pad(&safety_margin());
+    let upper_bound = sub(DEFAULT_DH_PRIME, &margin);
+
+    let out_of_range = vec![
+        vec![1],
+        vec![2],
+        margin.clone(),
+        upper_bound.clone(),
+        add_one(&upper_bound),
+        sub(DEFAULT_DH_PRIME, &pad(&[1])),
+        DEFAULT_DH_PRIME.to_vec(),
+    ];
+
+    for g_a in out_of_range {
+        let error = SecretChatManager::new().accept(&mut connection, &requested(g_a)).unwrap_err();
+        match *error.kind() {
+            ErrorKind::DhValueOutOfRange => (),
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+    }
+    assert!(relay.accepted.borrow().is_none());
+
+    // The bounds themselves are excluded, but values right next to them
+    // are fine
+    for g_a in vec![add_one(&margin), sub(&upper_bound, &pad(&[1]))] {
+        SecretChatManager::new().accept(&mut connection, &requested(g_a)).unwrap();
+    }
+
+    // `g_b` is checked by the chat creator as well
+    let mut alice = SecretChatManager::new();
+    let bob_user = schema::InputUser::inputUser(schema::inputUser { user_id: BOB_ID, access_hash: 0 });
+    alice.request(&mut connection, bob_user).unwrap();
+
+    let error = alice.handle_encrypted_chat(&accepted(vec![2], 0)).unwrap_err();
+    match *error.kind() {
+        ErrorKind::DhValueOutOfRange => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
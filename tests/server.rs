@@ -0,0 +1,146 @@
+extern crate extprim;
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate serde_bytes;
+extern crate serde_mtproto;
+
+
+use extprim::i128::i128;
+use mtproto::ErrorKind;
+use mtproto::rpc::{AppInfo, Message, Session};
+use mtproto::rpc::encryption::{asymm, AuthKey, MessageOrigin, RsaPaddingScheme, RsaPrivateKey};
+use mtproto::rpc::mock::{MOCK_RSA_PRIVATE_KEY, MockConnection, MockServer};
+use mtproto::rpc::server::{AuthKeyResponder, NegotiatedKey};
+use mtproto::schema;
+use serde_bytes::ByteBuf;
+use serde_mtproto::Boxed;
+
+
+/// Perform the whole key exchange with a mock server and return keys
+/// obtained by both parties.
+fn exchange_keys(padding_scheme: RsaPaddingScheme) -> (AuthKey, NegotiatedKey) {
+    let server = MockServer::new().unwrap();
+    let app_info = AppInfo::new(9000, "random text".to_owned());
+    let connection = MockConnection::connect_with(&server, app_info, padding_scheme).unwrap();
+
+    let client_key = connection.auth_key().clone();
+    let negotiated_key = server.negotiated_key(client_key.fingerprint()).unwrap();
+
+    (client_key, negotiated_key)
+}
+
+
+#[test]
+fn test_key_exchange_rsa_pad() {
+    let (client_key, negotiated_key) = exchange_keys(RsaPaddingScheme::RsaPad);
+    assert_eq!(client_key, negotiated_key.auth_key);
+}
+
+#[test]
+fn test_key_exchange_legacy() {
+    let (client_key, negotiated_key) = exchange_keys(RsaPaddingScheme::Legacy);
+    assert_eq!(client_key, negotiated_key.auth_key);
+}
+
+#[test]
+fn test_key_exchange_out_of_order() {
+    let private_key = RsaPrivateKey::from_pem(MOCK_RSA_PRIVATE_KEY).unwrap();
+    let mut responder = AuthKeyResponder::new(private_key).unwrap();
+
+    let result = responder.handle_set_client_dh_params(&schema::rpc::set_client_DH_params {
+        nonce: i128::new(1),
+        server_nonce: i128::new(2),
+        encrypted_data: ByteBuf::new(),
+    });
+
+    match result {
+        Err(e) => match *e.kind() {
+            ErrorKind::AuthKeyExchangeOutOfOrder("set_client_DH_params") => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        },
+        Ok(answer) => panic!("unexpected answer: {:?}", answer),
+    }
+}
+
+#[test]
+fn test_set_client_dh_params_invalid_length() {
+    let private_key = RsaPrivateKey::from_pem(MOCK_RSA_PRIVATE_KEY).unwrap();
+    let public_key = private_key.public_key().clone();
+    let mut responder = AuthKeyResponder::new(private_key).unwrap();
+
+    let nonce = i128::new(1);
+    let res_pq = responder.handle_req_pq(&schema::rpc::req_pq { nonce: nonce }).unwrap();
+    let pq = res_pq.pq.iter().fold(0u64, |pq, &b| pq << 8 | b as u64);
+    let (p, q) = asymm::decompose_pq(pq).unwrap();
+    let to_bytes = |n: u32| ByteBuf::from(vec![(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+
+    let new_nonce = (i128::new(3), i128::new(4));
+    let p_q_inner_data = Boxed::new(schema::P_Q_inner_data::p_q_inner_data(schema::p_q_inner_data {
+        pq: res_pq.pq.clone(),
+        p: to_bytes(p),
+        q: to_bytes(q),
+        nonce: nonce,
+        server_nonce: res_pq.server_nonce,
+        new_nonce: new_nonce,
+    }));
+    let encrypted_data = public_key
+        .encrypt_with(RsaPaddingScheme::RsaPad, &serde_mtproto::to_bytes(&p_q_inner_data).unwrap())
+        .unwrap();
+
+    responder.handle_req_dh_params(&schema::rpc::req_DH_params {
+        nonce: nonce,
+        server_nonce: res_pq.server_nonce,
+        p: to_bytes(p),
+        q: to_bytes(q),
+        public_key_fingerprint: public_key.fingerprint().unwrap(),
+        encrypted_data: ByteBuf::from(encrypted_data.to_vec()),
+    }).unwrap();
+
+    // Not a whole number of AES blocks
+    let result = responder.handle_set_client_dh_params(&schema::rpc::set_client_DH_params {
+        nonce: nonce,
+        server_nonce: res_pq.server_nonce,
+        encrypted_data: ByteBuf::from(vec![0; 17]),
+    });
+
+    match result {
+        Err(e) => match *e.kind() {
+            ErrorKind::AuthKeyExchangeInvalidData("Client_DH_Inner_Data") => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        },
+        Ok(answer) => panic!("unexpected answer: {:?}", answer),
+    }
+}
+
+#[test]
+fn test_session_accept_and_reply() {
+    let (client_key, negotiated_key) = exchange_keys(RsaPaddingScheme::RsaPad);
+
+    let future_salt = schema::FutureSalt {
+        valid_since: 0x0100_0000,
+        valid_until: 0x7fff_ffff,
+        salt: negotiated_key.server_salt,
+    };
+
+    let mut client_session = Session::new(892103, AppInfo::new(9000, "random text".to_owned()));
+    client_session.adopt_key(client_key);
+    client_session.add_server_salts(vec![future_salt.clone()]);
+
+    let request = client_session.create_encrypted_message_no_acks(23).unwrap().unwrap();
+    let request_bytes = serde_mtproto::to_bytes(&request).unwrap();
+
+    let (mut server_session, received): (Session, Message<i32>) =
+        Session::accept(negotiated_key.auth_key, &request_bytes, (request_bytes.len() - 24) as u32).unwrap();
+    assert_eq!(server_session.origin(), MessageOrigin::Server);
+    assert_eq!(server_session.session_id(), 892103);
+    assert_eq!(received.unwrap_decrypted_body(), 23);
+
+    server_session.add_server_salts(vec![future_salt]);
+    let reply = server_session.create_encrypted_message_no_acks(true).unwrap().unwrap();
+    let reply_bytes = serde_mtproto::to_bytes(&reply).unwrap();
+
+    let received: Message<bool> =
+        client_session.process_message(&reply_bytes, Some((reply_bytes.len() - 24) as u32)).unwrap();
+    assert_eq!(received.unwrap_decrypted_body(), true);
+}
@@ -0,0 +1,150 @@
+extern crate extprim;
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+#[macro_use]
+extern crate serde_json;
+extern crate serde_bytes;
+extern crate serde_mtproto;
+
+
+use extprim::i128::i128;
+use mtproto::ErrorKind;
+use mtproto::schema;
+use mtproto::tl::{RuntimeSchema, TLObject};
+use mtproto::tl::json;
+use serde_bytes::ByteBuf;
+use serde_mtproto::Boxed;
+
+
+const NEW_THING: &'static str = "\
+---types---
+
+newThing#12345678 flags:# count:flags.0?int pinned:flags.1?true title:string = NewThing;
+";
+
+
+fn future_salts() -> schema::FutureSalts {
+    schema::FutureSalts {
+        req_msg_id: 0x0123_4567_89ab_cdef,
+        now: 1_500_000_000,
+        salts: vec![
+            schema::FutureSalt { valid_since: 1, valid_until: 2, salt: -3 },
+        ],
+    }
+}
+
+
+#[test]
+fn test_schema_type_to_json_and_back() {
+    let json = json::to_json(&future_salts()).unwrap();
+
+    assert_eq!(json, json!({
+        "_": "future_salts",
+        "req_msg_id": 0x0123_4567_89ab_cdefi64,
+        "now": 1_500_000_000,
+        "salts": [
+            { "_": "future_salt", "valid_since": 1, "valid_until": 2, "salt": -3 }
+        ]
+    }));
+
+    let future_salts_from_json: schema::FutureSalts = json::from_json(&json).unwrap();
+    assert_eq!(future_salts_from_json, future_salts());
+}
+
+#[test]
+fn test_bytes_and_big_integers() {
+    let res_pq = schema::ResPQ {
+        nonce: i128::from_parts(-1, 0),
+        server_nonce: i128::new(42),
+        pq: ByteBuf::from(vec![0x17, 0xed, 0x48, 0x94, 0x1a, 0x08, 0xf9, 0x81]),
+        server_public_key_fingerprints: Boxed::new(vec![-4344800451088585951]),
+    };
+
+    let json = json::to_json(&res_pq).unwrap();
+    assert_eq!(json, json!({
+        "_": "resPQ",
+        "nonce": "-18446744073709551616",
+        "server_nonce": "42",
+        "pq": "F+1IlBoI+YE=",
+        "server_public_key_fingerprints": [-4344800451088585951i64]
+    }));
+
+    let res_pq_from_json: schema::ResPQ = json::from_json(&json).unwrap();
+    assert_eq!(res_pq_from_json, res_pq);
+
+    let retry = schema::Set_client_DH_params_answer::dh_gen_retry(schema::dh_gen_retry {
+        nonce: i128::new(1),
+        server_nonce: i128::new(2),
+        new_nonce_hash2: i128::new(-3),
+    });
+    assert_eq!(json::to_json(&retry).unwrap(), json!({
+        "_": "dh_gen_retry",
+        "nonce": "1",
+        "server_nonce": "2",
+        "new_nonce_hash2": "-3"
+    }));
+}
+
+#[test]
+fn test_dynamic_object_to_json_and_back() {
+    let object: Box<TLObject> = Box::new(future_salts());
+    let json = json::to_json(&object).unwrap();
+    assert_eq!(json["_"], json!("future_salts"));
+
+    let object_from_json = json::object_from_json(&json).unwrap();
+    let future_salts_from_json = object_from_json.as_box_any().downcast::<schema::FutureSalts>().unwrap();
+    assert_eq!(*future_salts_from_json, future_salts());
+}
+
+#[test]
+fn test_flags_are_computed() {
+    let mut runtime_schema = RuntimeSchema::compiled();
+    runtime_schema.extend_from_str(NEW_THING).unwrap();
+
+    let value = runtime_schema.value_from_json(&json!({
+        "_": "newThing",
+        "count": 5,
+        "pinned": false,
+        "title": "abc"
+    })).unwrap();
+    assert_eq!(value.field("flags").unwrap().to_json(), json!(0b01));
+    assert_eq!(runtime_schema.decode(&runtime_schema.encode(&value).unwrap()).unwrap(), value);
+}
+
+#[test]
+fn test_non_utf8_string() {
+    let mut runtime_schema = RuntimeSchema::compiled();
+    runtime_schema.extend_from_str(NEW_THING).unwrap();
+
+    // newThing flags:0 title:"a\xff"
+    let bytes = [0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0, 2, b'a', 0xff, 0];
+    let value = runtime_schema.decode(&bytes).unwrap();
+
+    let json = value.to_json();
+    assert_eq!(json, json!({ "_": "newThing", "flags": 0, "title": "a\u{fffd}" }));
+
+    // Conversion to JSON and back doesn't change the value
+    assert_eq!(runtime_schema.value_from_json(&json).unwrap(), value);
+}
+
+#[test]
+fn test_invalid_json() {
+    let error = json::object_from_json(&json!({ "req_msg_id": 1 })).unwrap_err();
+    match *error.kind() {
+        ErrorKind::UnexpectedJson(_) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    let error = json::object_from_json(&json!({ "_": "noSuchConstructor" })).unwrap_err();
+    match *error.kind() {
+        ErrorKind::UnknownTlType(ref name) => assert_eq!(name, "noSuchConstructor"),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    let error = json::from_json::<schema::FutureSalts>(&json!({ "_": "future_salts", "now": 1 })).unwrap_err();
+    match *error.kind() {
+        ErrorKind::UnexpectedJson(_) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
@@ -0,0 +1,194 @@
+extern crate byteorder;
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate serde_mtproto;
+
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use mtproto::ErrorKind;
+use mtproto::schema;
+use mtproto::tl::{RuntimeSchema, TLValue};
+use serde_mtproto::{Boxed, MtProtoSized};
+
+
+const NEWER_LAYER: &'static str = "\
+// LAYER 200
+
+---types---
+
+newThing#12345678 flags:# count:flags.0?int pinned:flags.1?true title:string = NewThing;
+newThingEmpty#12345679 = NewThing;
+";
+
+const REPEATED_FIELDS: &'static str = "\
+---types---
+
+pairs#aabbccdd n:# [ a:int b:long ] = Pairs;
+numbers#aabbccde # [ int ] = Numbers;
+";
+
+
+fn compiled_schema() -> RuntimeSchema {
+    let mut runtime_schema = RuntimeSchema::from_file("tl/000-scheme.tl").unwrap();
+    runtime_schema.extend_from_file("tl/001-manual.tl").unwrap();
+    runtime_schema
+}
+
+
+#[test]
+fn test_decode_compiled_constructor() {
+    let future_salts = Boxed::new(schema::FutureSalts {
+        req_msg_id: 0x0123_4567_89ab_cdef,
+        now: 1_500_000_000,
+        salts: vec![
+            schema::FutureSalt { valid_since: 1, valid_until: 2, salt: 3 },
+            schema::FutureSalt { valid_since: 2, valid_until: 3, salt: 4 },
+        ],
+    });
+    let bytes = serde_mtproto::to_bytes(&future_salts).unwrap();
+
+    let runtime_schema = compiled_schema();
+    let (value, len) = runtime_schema.decode_partial(&bytes).unwrap();
+
+    assert_eq!(len, bytes.len());
+    assert_eq!(value.name(), Some("future_salts"));
+    assert_eq!(value.id(), Some(0xae500895));
+    assert_eq!(value.field("req_msg_id"), Some(&TLValue::Long(0x0123_4567_89ab_cdef)));
+
+    match value.field("salts") {
+        Some(&TLValue::Vector(ref salts)) => {
+            assert_eq!(salts.len(), 2);
+            assert_eq!(salts[1].name(), Some("future_salt"));
+            assert_eq!(salts[1].field("salt"), Some(&TLValue::Long(4)));
+        },
+        other => panic!("unexpected salts: {:?}", other),
+    }
+}
+
+#[test]
+fn test_decode_newer_layer_with_flags() {
+    let mut runtime_schema = compiled_schema();
+    runtime_schema.extend_from_str(NEWER_LAYER).unwrap();
+    assert_eq!(runtime_schema.layer(), Some(200));
+    assert!(!runtime_schema.is_function(0x12345678));
+
+    let mut bytes = vec![];
+    bytes.write_u32::<LittleEndian>(0x12345678).unwrap();
+    bytes.write_i32::<LittleEndian>(0b10).unwrap(); // only `pinned` is set
+    bytes.extend_from_slice(&[3, b'a', b'b', b'c']);
+
+    let value = runtime_schema.decode(&bytes).unwrap();
+
+    assert_eq!(value, TLValue::Object {
+        name: "newThing".to_owned(),
+        id: 0x12345678,
+        fields: vec![
+            ("flags".to_owned(), TLValue::Int(0b10)),
+            ("pinned".to_owned(), TLValue::True),
+            ("title".to_owned(), TLValue::String("abc".to_owned())),
+        ],
+    });
+}
+
+#[test]
+fn test_compiled_constructor_with_flags() {
+    let sent_code = schema::auth::SentCode {
+        phone_registered: true,
+        type_: schema::auth::SentCodeType::sentCodeTypeSms(schema::auth::sentCodeTypeSms { length: 5 }),
+        phone_code_hash: "abc".to_owned(),
+        next_type: None,
+        timeout: Some(60),
+    };
+    let bytes = serde_mtproto::to_bytes(&Boxed::new(sent_code.clone())).unwrap();
+    assert_eq!(sent_code.size_hint().unwrap() + 4, bytes.len());
+
+    let value = compiled_schema().decode(&bytes).unwrap();
+    assert_eq!(value.field("flags"), Some(&TLValue::Int(0b101)));
+    assert_eq!(value.field("phone_registered"), Some(&TLValue::True));
+    assert_eq!(value.field("next_type"), None);
+    assert_eq!(value.field("timeout"), Some(&TLValue::Int(60)));
+
+    let sent_code_from_bytes: Boxed<schema::auth::SentCode> =
+        serde_mtproto::from_reader(bytes.as_slice(), None).unwrap();
+    assert_eq!(sent_code_from_bytes.into_inner(), sent_code);
+}
+
+#[test]
+fn test_decode_unknown_constructor() {
+    let runtime_schema = compiled_schema();
+
+    let error = runtime_schema.decode(&[0x78, 0x56, 0x34, 0x12]).unwrap_err();
+    match *error.kind() {
+        ErrorKind::UnknownConstructorId(_, 0x12345678) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+
+    let error = runtime_schema.decode(&[0x95, 0x08, 0x50, 0xae, 0x00]).unwrap_err();
+    match *error.kind() {
+        ErrorKind::NotEnoughData(8, 1) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
+
+#[test]
+fn test_lookup_by_name() {
+    let runtime_schema = compiled_schema();
+
+    let id = runtime_schema.id_of("help.getConfig").unwrap();
+    assert!(runtime_schema.is_function(id));
+    assert_eq!(runtime_schema.name_of(id), Some("help.getConfig".to_owned()));
+    assert_eq!(runtime_schema.id_of("help.noSuchFunction"), None);
+}
+
+#[test]
+fn test_repeated_fields() {
+    let runtime_schema = RuntimeSchema::parse(REPEATED_FIELDS).unwrap();
+
+    // The `#` field holds the number of elements
+    let mut bytes = vec![];
+    bytes.write_u32::<LittleEndian>(0xaabbccdd).unwrap();
+    bytes.write_u32::<LittleEndian>(2).unwrap();
+    bytes.write_i32::<LittleEndian>(1).unwrap();
+    bytes.write_i64::<LittleEndian>(2).unwrap();
+    bytes.write_i32::<LittleEndian>(3).unwrap();
+    bytes.write_i64::<LittleEndian>(4).unwrap();
+
+    let value = runtime_schema.decode(&bytes).unwrap();
+    assert_eq!(value, TLValue::Object {
+        name: "pairs".to_owned(),
+        id: 0xaabbccdd,
+        fields: vec![
+            ("n".to_owned(), TLValue::Vector(vec![
+                TLValue::Vector(vec![TLValue::Int(1), TLValue::Long(2)]),
+                TLValue::Vector(vec![TLValue::Int(3), TLValue::Long(4)]),
+            ])),
+        ],
+    });
+    assert_eq!(runtime_schema.encode(&value).unwrap(), bytes);
+
+    // Elements with a single field aren't wrapped, anonymous counts
+    // are named `items`
+    let mut bytes = vec![];
+    bytes.write_u32::<LittleEndian>(0xaabbccde).unwrap();
+    bytes.write_u32::<LittleEndian>(3).unwrap();
+    for i in 7..10 {
+        bytes.write_i32::<LittleEndian>(i).unwrap();
+    }
+
+    let value = runtime_schema.decode(&bytes).unwrap();
+    assert_eq!(value.field("items"),
+               Some(&TLValue::Vector(vec![TLValue::Int(7), TLValue::Int(8), TLValue::Int(9)])));
+    assert_eq!(runtime_schema.encode(&value).unwrap(), bytes);
+
+    // Elements must have as many values as there are repeated fields
+    let value = TLValue::Object {
+        name: "pairs".to_owned(),
+        id: 0xaabbccdd,
+        fields: vec![("n".to_owned(), TLValue::Vector(vec![TLValue::Vector(vec![TLValue::Int(1)])]))],
+    };
+    match *runtime_schema.encode(&value).unwrap_err().kind() {
+        ErrorKind::TlValueMismatch(..) => (),
+        ref kind => panic!("unexpected error: {:?}", kind),
+    }
+}
@@ -0,0 +1,280 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate serde_mtproto;
+extern crate test_logger;
+
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread::sleep;
+use std::time::Duration;
+
+use mtproto::rpc::AppInfo;
+use mtproto::rpc::mock::{MockConnection, MockServer};
+use mtproto::schema;
+use mtproto::updates::{Event, GAP_TIMEOUT_MILLIS, State, UpdatesManager};
+use serde_mtproto::Boxed;
+use test_logger::ensure_env_logger_initialized;
+
+
+const CHANNEL_ID: i32 = 1000;
+
+
+fn state(pts: i32, seq: i32) -> State {
+    State { pts: pts, qts: 0, seq: seq, date: 0 }
+}
+
+fn delete_messages(pts: i32, pts_count: i32) -> schema::Update {
+    schema::Update::updateDeleteMessages(schema::updateDeleteMessages {
+        messages: Boxed::new(vec![]),
+        pts: pts,
+        pts_count: pts_count,
+    })
+}
+
+fn delete_channel_messages(pts: i32, pts_count: i32) -> schema::Update {
+    schema::Update::updateDeleteChannelMessages(schema::updateDeleteChannelMessages {
+        channel_id: CHANNEL_ID,
+        messages: Boxed::new(vec![]),
+        pts: pts,
+        pts_count: pts_count,
+    })
+}
+
+fn short(update: schema::Update) -> schema::Updates {
+    schema::Updates::updateShort(schema::updateShort { update: update, date: 0 })
+}
+
+fn container(seq: i32, updates: Vec<schema::Update>) -> schema::Updates {
+    schema::Updates::updates_(schema::updates_ {
+        updates: Boxed::new(updates),
+        users: Boxed::new(vec![]),
+        chats: Boxed::new(vec![]),
+        date: 0,
+        seq: seq,
+    })
+}
+
+/// `pts` of updates ready to be handled, skipping other events.
+fn ready_pts(manager: &mut UpdatesManager) -> Vec<i32> {
+    manager.events().filter_map(|event| match event {
+        Event::Update(schema::Update::updateDeleteMessages(u)) => Some(u.pts),
+        Event::Update(schema::Update::updateDeleteChannelMessages(u)) => Some(u.pts),
+        _ => None,
+    }).collect()
+}
+
+fn wait_for_gap_timeout() {
+    sleep(Duration::from_millis(GAP_TIMEOUT_MILLIS + 50));
+}
+
+
+#[test]
+fn test_apply_in_order() {
+    let mut manager = UpdatesManager::with_state(state(10, 0));
+
+    manager.feed(short(delete_messages(11, 1)));
+    manager.feed(short(delete_messages(13, 2)));
+
+    assert_eq!(ready_pts(&mut manager), vec![11, 13]);
+    assert_eq!(manager.state().unwrap().pts, 13);
+    assert!(!manager.needs_difference());
+}
+
+#[test]
+fn test_skip_duplicates() {
+    let mut manager = UpdatesManager::with_state(state(10, 0));
+
+    manager.feed(short(delete_messages(11, 1)));
+    manager.feed(short(delete_messages(11, 1)));
+    manager.feed(short(delete_messages(9, 1)));
+
+    assert_eq!(ready_pts(&mut manager), vec![11]);
+    assert_eq!(manager.state().unwrap().pts, 11);
+    assert!(!manager.needs_difference());
+}
+
+#[test]
+fn test_gap_filled_in_time() {
+    let mut manager = UpdatesManager::with_state(state(10, 0));
+
+    manager.feed(short(delete_messages(13, 1)));
+    assert_eq!(ready_pts(&mut manager), vec![]);
+    // The gap may still be filled
+    assert!(!manager.needs_difference());
+
+    manager.feed(short(delete_messages(12, 1)));
+    manager.feed(short(delete_messages(11, 1)));
+
+    assert_eq!(ready_pts(&mut manager), vec![11, 12, 13]);
+    assert_eq!(manager.state().unwrap().pts, 13);
+
+    wait_for_gap_timeout();
+    assert!(!manager.needs_difference());
+}
+
+#[test]
+fn test_gap_timeout() {
+    let mut manager = UpdatesManager::with_state(state(10, 0));
+
+    manager.feed(short(delete_messages(13, 1)));
+    assert!(!manager.needs_difference());
+
+    wait_for_gap_timeout();
+    assert!(manager.needs_difference());
+    assert_eq!(ready_pts(&mut manager), vec![]);
+}
+
+#[test]
+fn test_updates_too_long() {
+    let mut manager = UpdatesManager::with_state(state(10, 0));
+
+    manager.feed(schema::Updates::updatesTooLong);
+    assert!(manager.needs_difference());
+}
+
+#[test]
+fn test_channel_gap_filled_in_time() {
+    let mut manager = UpdatesManager::with_state(state(10, 0));
+    manager.set_channel_pts(CHANNEL_ID, 100);
+
+    manager.feed(short(delete_channel_messages(102, 1)));
+    // Channel gaps get the same grace period as common ones
+    assert!(!manager.needs_difference());
+
+    manager.feed(short(delete_channel_messages(101, 1)));
+    assert_eq!(ready_pts(&mut manager), vec![101, 102]);
+    assert_eq!(manager.channel_pts(CHANNEL_ID), Some(102));
+
+    wait_for_gap_timeout();
+    assert!(!manager.needs_difference());
+}
+
+#[test]
+fn test_channel_gap_timeout() {
+    let mut manager = UpdatesManager::with_state(state(10, 0));
+    manager.set_channel_pts(CHANNEL_ID, 100);
+
+    manager.feed(short(delete_channel_messages(102, 1)));
+    assert!(!manager.needs_difference());
+
+    wait_for_gap_timeout();
+    assert!(manager.needs_difference());
+    assert_eq!(manager.channel_pts(CHANNEL_ID), Some(100));
+}
+
+#[test]
+fn test_channel_too_long() {
+    let mut manager = UpdatesManager::with_state(state(10, 0));
+
+    manager.feed(short(schema::Update::updateChannelTooLong(schema::updateChannelTooLong {
+        channel_id: CHANNEL_ID,
+        pts: None,
+    })));
+
+    // No point in waiting, the server already says updates are missing
+    assert!(manager.needs_difference());
+}
+
+#[test]
+fn test_containers() {
+    let mut manager = UpdatesManager::with_state(state(10, 5));
+
+    manager.feed(container(7, vec![delete_messages(12, 1)]));
+    assert_eq!(ready_pts(&mut manager), vec![]);
+    assert!(!manager.needs_difference());
+
+    manager.feed(container(6, vec![delete_messages(11, 1)]));
+    assert_eq!(ready_pts(&mut manager), vec![11, 12]);
+    assert_eq!(manager.state(), Some(state(12, 7)));
+
+    // Duplicate container
+    manager.feed(container(7, vec![delete_messages(12, 1)]));
+    assert_eq!(ready_pts(&mut manager), vec![]);
+
+    // Containers with zero seq aren't ordered
+    manager.feed(container(0, vec![delete_messages(13, 1)]));
+    assert_eq!(ready_pts(&mut manager), vec![13]);
+    assert_eq!(manager.state(), Some(state(13, 7)));
+
+    manager.feed(container(9, vec![]));
+    wait_for_gap_timeout();
+    assert!(manager.needs_difference());
+}
+
+#[test]
+fn test_get_difference() {
+    ensure_env_logger_initialized();
+
+    let calls = Rc::new(Cell::new(0));
+    let server = MockServer::new().unwrap();
+
+    {
+        let calls = calls.clone();
+        server.on(move |function: schema::rpc::updates::getDifference| {
+            calls.set(calls.get() + 1);
+
+            let new_state = |pts| schema::updates::State { pts: pts, qts: 0, date: 0, seq: 5, unread_count: 0 };
+
+            if calls.get() == 1 {
+                assert_eq!(function.pts, 10);
+
+                Ok(schema::updates::Difference::differenceSlice(schema::updates::differenceSlice {
+                    new_messages: Boxed::new(vec![]),
+                    new_encrypted_messages: Boxed::new(vec![]),
+                    other_updates: Boxed::new(vec![delete_messages(11, 1)]),
+                    chats: Boxed::new(vec![]),
+                    users: Boxed::new(vec![]),
+                    intermediate_state: new_state(11),
+                }))
+            } else {
+                assert_eq!(function.pts, 11);
+
+                Ok(schema::updates::Difference::difference(schema::updates::difference {
+                    new_messages: Boxed::new(vec![]),
+                    new_encrypted_messages: Boxed::new(vec![]),
+                    other_updates: Boxed::new(vec![delete_messages(12, 1)]),
+                    chats: Boxed::new(vec![]),
+                    users: Boxed::new(vec![]),
+                    state: new_state(12),
+                }))
+            }
+        });
+    }
+
+    let mut connection = MockConnection::connect(&server, AppInfo::new(9000, "random text".to_owned())).unwrap();
+    let mut manager = UpdatesManager::with_state(state(10, 5));
+
+    // 12 arrives twice: as a pending update and within the difference
+    manager.feed(short(delete_messages(12, 1)));
+    manager.feed(short(delete_messages(13, 1)));
+    wait_for_gap_timeout();
+    assert!(manager.needs_difference());
+
+    manager.recover_gaps(&mut connection).unwrap();
+
+    assert_eq!(calls.get(), 2);
+    assert_eq!(ready_pts(&mut manager), vec![11, 12, 13]);
+    assert_eq!(manager.state(), Some(state(13, 5)));
+    assert!(!manager.needs_difference());
+}
+
+#[test]
+fn test_get_difference_too_long() {
+    ensure_env_logger_initialized();
+
+    let server = MockServer::new().unwrap();
+    server.on(|_: schema::rpc::updates::getDifference| {
+        Ok(schema::updates::Difference::differenceTooLong(schema::updates::differenceTooLong { pts: 50 }))
+    });
+
+    let mut connection = MockConnection::connect(&server, AppInfo::new(9000, "random text".to_owned())).unwrap();
+    let mut manager = UpdatesManager::with_state(state(10, 5));
+
+    manager.feed(schema::Updates::updatesTooLong);
+    manager.recover_gaps(&mut connection).unwrap();
+
+    assert_eq!(manager.state().unwrap().pts, 50);
+    assert!(!manager.needs_difference());
+}
@@ -0,0 +1,254 @@
+extern crate mtproto;
+#[macro_use]
+extern crate pretty_assertions;
+extern crate test_logger;
+
+
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use mtproto::{Error, ErrorKind};
+use mtproto::files::Uploader;
+use mtproto::files::upload::{BIG_FILE_THRESHOLD, MAX_PARTS};
+use mtproto::rpc::AppInfo;
+use mtproto::rpc::mock::{MockConnection, MockServer};
+use mtproto::schema;
+use test_logger::ensure_env_logger_initialized;
+
+
+/// Parts received by the server: `(file_part, total_parts, bytes)`,
+/// with `total_parts` only known for big files.
+type Parts = Rc<RefCell<Vec<(i32, Option<i32>, Vec<u8>)>>>;
+
+fn connect(server: &MockServer) -> MockConnection {
+    MockConnection::connect(server, AppInfo::new(9000, "random text".to_owned())).unwrap()
+}
+
+fn recording_server() -> (MockServer, Parts) {
+    let parts = Rc::new(RefCell::new(Vec::new()));
+    let server = MockServer::new().unwrap();
+
+    {
+        let parts = parts.clone();
+        server.on(move |function: schema::rpc::upload::saveFilePart| {
+            parts.borrow_mut().push((function.file_part, None, function.bytes.to_vec()));
+            Ok(true)
+        });
+    }
+
+    {
+        let parts = parts.clone();
+        server.on(move |function: schema::rpc::upload::saveBigFilePart| {
+            parts.borrow_mut().push((function.file_part, Some(function.file_total_parts), function.bytes.to_vec()));
+            Ok(true)
+        });
+    }
+
+    (server, parts)
+}
+
+/// A server failing `upload.saveFilePart` with `errors` one by one,
+/// then succeeding. Returns the server and the number of calls.
+fn failing_server(errors: Vec<Option<Error>>) -> (MockServer, Rc<RefCell<usize>>) {
+    let calls = Rc::new(RefCell::new(0));
+    let server = MockServer::new().unwrap();
+    let mut errors = errors.into_iter();
+
+    {
+        let calls = calls.clone();
+        server.on(move |_: schema::rpc::upload::saveFilePart| {
+            *calls.borrow_mut() += 1;
+
+            match errors.next() {
+                Some(Some(error)) => Err(error),
+                Some(None) => Ok(false),
+                None => Ok(true),
+            }
+        });
+    }
+
+    (server, calls)
+}
+
+fn rpc_error(code: i32, message: &str) -> Option<Error> {
+    Some(ErrorKind::RpcError(code, message.to_owned()).into())
+}
+
+/// A reader which returns at most 100 bytes at once and is interrupted
+/// before every read.
+struct ChoppyReader<R> {
+    inner: R,
+    interrupt: bool,
+}
+
+impl<R: Read> Read for ChoppyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+        }
+
+        let len = cmp::min(buf.len(), 100);
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+
+#[test]
+fn test_upload_small_file() {
+    ensure_env_logger_initialized();
+
+    let (server, parts) = recording_server();
+    let data: Vec<u8> = (0..2500).map(|i| i as u8).collect();
+
+    let input_file = Uploader::new().part_size(1024)
+        .upload(&mut connect(&server), "data.bin".to_owned(), data.len() as u64, data.as_slice())
+        .unwrap();
+
+    match input_file {
+        schema::InputFile::inputFile(ref input_file) => {
+            assert_eq!(input_file.parts, 3);
+            assert_eq!(input_file.name, "data.bin");
+        },
+        ref input_file => panic!("unexpected input file: {:?}", input_file),
+    }
+
+    let parts = parts.borrow();
+    assert_eq!(parts.iter().map(|p| (p.0, p.1, p.2.len())).collect::<Vec<_>>(),
+               vec![(0, None, 1024), (1, None, 1024), (2, None, 452)]);
+    assert_eq!(parts.iter().flat_map(|p| p.2.iter().cloned()).collect::<Vec<u8>>(), data);
+}
+
+#[test]
+fn test_upload_md5_checksum() {
+    ensure_env_logger_initialized();
+
+    let (server, _) = recording_server();
+    let input_file = Uploader::new().upload(&mut connect(&server), "abc".to_owned(), 3, &b"abc"[..]).unwrap();
+
+    match input_file {
+        schema::InputFile::inputFile(ref input_file) => {
+            assert_eq!(input_file.md5_checksum, "900150983cd24fb0d6963f7d28e17f72");
+        },
+        ref input_file => panic!("unexpected input file: {:?}", input_file),
+    }
+}
+
+#[test]
+fn test_upload_big_file() {
+    ensure_env_logger_initialized();
+
+    let (server, parts) = recording_server();
+    let size = BIG_FILE_THRESHOLD + 1;
+
+    let input_file = Uploader::new()
+        .upload(&mut connect(&server), "big.bin".to_owned(), size, io::repeat(7).take(size))
+        .unwrap();
+
+    match input_file {
+        schema::InputFile::inputFileBig(ref input_file) => assert_eq!(input_file.parts, 21),
+        ref input_file => panic!("unexpected input file: {:?}", input_file),
+    }
+
+    let parts = parts.borrow();
+    assert_eq!(parts.len(), 21);
+    assert!(parts.iter().all(|p| p.1 == Some(21)));
+    assert_eq!(parts[20].2, vec![7]);
+}
+
+#[test]
+fn test_upload_threshold_is_inclusive() {
+    ensure_env_logger_initialized();
+
+    let (server, _) = recording_server();
+    let size = BIG_FILE_THRESHOLD;
+
+    let input_file = Uploader::new()
+        .upload(&mut connect(&server), "small.bin".to_owned(), size, io::repeat(7).take(size))
+        .unwrap();
+
+    match input_file {
+        schema::InputFile::inputFile(ref input_file) => assert_eq!(input_file.parts, 20),
+        ref input_file => panic!("unexpected input file: {:?}", input_file),
+    }
+}
+
+#[test]
+fn test_upload_too_many_parts() {
+    ensure_env_logger_initialized();
+
+    let (server, parts) = recording_server();
+    let uploader = Uploader::new().part_size(1024);
+
+    let max_size = MAX_PARTS * 1024;
+    let error = uploader.upload(&mut connect(&server), "huge.bin".to_owned(), max_size + 1, io::empty())
+        .unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::FileTooBig(size, limit) => assert_eq!((size, limit), (max_size + 1, max_size)),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+    assert!(parts.borrow().is_empty());
+}
+
+#[test]
+fn test_upload_choppy_reader() {
+    ensure_env_logger_initialized();
+
+    let (server, parts) = recording_server();
+    let data: Vec<u8> = (0..1500).map(|i| (i * 7) as u8).collect();
+    let reader = ChoppyReader { inner: data.as_slice(), interrupt: false };
+
+    Uploader::new().part_size(1024)
+        .upload(&mut connect(&server), "data.bin".to_owned(), data.len() as u64, reader)
+        .unwrap();
+
+    // Parts are filled completely despite short and interrupted reads
+    let parts = parts.borrow();
+    assert_eq!(parts.iter().map(|p| p.2.len()).collect::<Vec<_>>(), vec![1024, 476]);
+    assert_eq!(parts.iter().flat_map(|p| p.2.iter().cloned()).collect::<Vec<u8>>(), data);
+}
+
+#[test]
+fn test_upload_size_mismatch() {
+    ensure_env_logger_initialized();
+
+    let (server, _) = recording_server();
+    let error = Uploader::new().upload(&mut connect(&server), "abc".to_owned(), 10, &b"abc"[..]).unwrap_err();
+
+    match *error.kind() {
+        ErrorKind::FileSizeMismatch(10, 3) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn test_upload_retries() {
+    ensure_env_logger_initialized();
+
+    // Server errors, flood waits and unsaved parts are retried
+    let (server, calls) = failing_server(vec![rpc_error(500, "INTERNAL"), rpc_error(420, "FLOOD_WAIT_0"), None]);
+    Uploader::new().max_retries(4).upload(&mut connect(&server), "abc".to_owned(), 3, &b"abc"[..]).unwrap();
+    assert_eq!(*calls.borrow(), 4);
+
+    // Up to `max_retries` attempts are made
+    let (server, calls) = failing_server((0..5).map(|_| rpc_error(500, "INTERNAL")).collect());
+    let error = Uploader::new().max_retries(2).upload(&mut connect(&server), "abc".to_owned(), 3, &b"abc"[..])
+        .unwrap_err();
+    assert_eq!(*calls.borrow(), 2);
+    match *error.kind() {
+        ErrorKind::RpcError(500, _) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    // Client errors aren't retried at all
+    let (server, calls) = failing_server(vec![rpc_error(400, "FILE_PART_INVALID")]);
+    let error = Uploader::new().upload(&mut connect(&server), "abc".to_owned(), 3, &b"abc"[..]).unwrap_err();
+    assert_eq!(*calls.borrow(), 1);
+    match *error.kind() {
+        ErrorKind::RpcError(400, _) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
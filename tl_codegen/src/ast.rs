@@ -5,7 +5,7 @@ use syn;
 #[cfg(feature = "parsing")]
 use synom;
 
-use error::{self, ErrorKind};
+use error::{self, ErrorKind, Location};
 use analyzer::TypeckKind;
 
 
@@ -16,7 +16,7 @@ pub enum Type {
     Int,
     Named(Vec<String>),
     TypeParameter(String),
-    Generic(Vec<String>, Box<Type>),
+    Generic(Vec<String>, Vec<Type>),
     Flagged(String, u32, Box<Type>),
     Repeated(Vec<Field>),
 }
@@ -34,12 +34,7 @@ impl Type {
     }
 
     pub fn namespace(&self) -> Option<&[String]> {
-        self.names_vec().map(|v| {
-            // FIXME: do we really need assert here?
-            assert!(v.len() >= 1);
-
-            &v[..v.len()-1]
-        })
+        self.names_vec().and_then(|v| v.split_last()).map(|(_, namespace)| namespace)
     }
 
     pub fn name(&self) -> Option<&str> {
@@ -53,6 +48,16 @@ impl Type {
         }
     }
 
+    pub fn contains_type_parameter(&self, name: &str) -> bool {
+        match *self {
+            Type::TypeParameter(ref p) => p == name,
+            Type::Generic(_, ref tys) => tys.iter().any(|ty| ty.contains_type_parameter(name)),
+            Type::Flagged(_, _, ref ty) => ty.contains_type_parameter(name),
+            Type::Repeated(ref fields) => fields.iter().any(|f| f.ty.contains_type_parameter(name)),
+            Type::Int | Type::Named(..) => false,
+        }
+    }
+
     pub fn is_type_parameter(&self) -> bool {
         match *self {
             Type::TypeParameter(..) => true,
@@ -60,18 +65,54 @@ impl Type {
         }
     }
 
+    /// Replace references to type parameters written without `!`
+    /// (e.g. `X` in `Vector<X>`) with `Type::TypeParameter`.
+    fn fixup_type_parameters(&mut self, type_parameters: &HashSet<&str>) {
+        match *self {
+            Type::Named(ref v) if v.len() == 1 && type_parameters.contains(v[0].as_str()) => (),
+            Type::Generic(_, ref mut tys) => {
+                for ty in tys {
+                    ty.fixup_type_parameters(type_parameters);
+                }
+                return;
+            },
+            Type::Flagged(_, _, ref mut ty) => {
+                ty.fixup_type_parameters(type_parameters);
+                return;
+            },
+            Type::Repeated(ref mut fields) => {
+                for f in fields {
+                    f.ty.fixup_type_parameters(type_parameters);
+                }
+                return;
+            },
+            _ => return,
+        }
+
+        let name = self.name().unwrap().to_owned(); // safe to unwrap
+        *self = Type::TypeParameter(name);
+    }
+
     fn fixup(&mut self, fixup_map: &TypeFixupMap) {
         // FIXME: what does `loc` variable mean?
         let loc = match *self {
             Type::Named(ref mut names) => names,
-            Type::Generic(ref mut container, ref mut ty) => {
-                ty.fixup(fixup_map);
+            Type::Generic(ref mut container, ref mut tys) => {
+                for ty in tys {
+                    ty.fixup(fixup_map);
+                }
                 container
             },
             Type::Flagged(_, _, ref mut ty) => {
                 ty.fixup(fixup_map);
                 return;
             },
+            Type::Repeated(ref mut fields) => {
+                for f in fields {
+                    f.ty.fixup(fixup_map);
+                }
+                return;
+            },
             _ => return,
         };
         match fixup_map.get(loc) {
@@ -91,14 +132,39 @@ impl Type {
                 let ty = syn::Ty::Path(None, no_conflict_ident(s).into());
                 TypeIr::noncopyable(ty)
             },
-            Type::Generic(ref container, ref ty) => {
-                // TODO: change this to support multiple type parameters
-                names_to_type_ir(container, &[ty.to_type_ir()?])?
+            Type::Generic(ref container, ref tys) => {
+                let type_irs = tys.iter().map(Type::to_type_ir).collect::<error::Result<Vec<_>>>()?;
+                names_to_type_ir(container, &type_irs)?
             },
+            // Presence of flagged fields is stored in the flags field,
+            // see `Constructor::to_syn_flags_impls`
             Type::Flagged(_, _, ref ty) => {
-                ty.to_type_ir()?
+                let mut type_ir = ty.to_type_ir()?;
+                if type_ir.kind == TypeIrKind::Unit {
+                    type_ir = TypeIr::copyable(syn::Ty::Path(None, "bool".into()));
+                } else {
+                    type_ir.with_option = true;
+                }
+
+                type_ir
+            },
+            Type::Repeated(ref fields) => {
+                // Lengths are serialized in place of the preceding `#`
+                // field, see `Constructor::fixup_repeated_fields`
+                let mut element_type_irs = fields.iter()
+                    .map(|f| f.ty.to_type_ir())
+                    .collect::<error::Result<Vec<_>>>()?;
+                let is_dynamic = element_type_irs.iter().any(|t| t.kind == TypeIrKind::Dynamic);
+
+                let element_ty = if element_type_irs.len() == 1 {
+                    element_type_irs.pop().unwrap().unboxed() // safe to unwrap
+                } else {
+                    syn::Ty::Tup(element_type_irs.into_iter().map(TypeIr::unboxed).collect())
+                };
+
+                let vec_ty = syn_type_from_components(false, vec!["Vec"], vec![element_ty]);
+                if is_dynamic { TypeIr::dynamic(vec_ty) } else { TypeIr::noncopyable(vec_ty) }
             },
-            Type::Repeated(..) => unimplemented!(), // FIXME
         };
 
         Ok(type_ir)
@@ -168,13 +234,7 @@ impl TypeIr {
 
     fn impl_boxed(self) -> syn::Ty {
         if self.kind == TypeIrKind::NeedsBox {
-            let mut path: syn::Path = "Box".into();
-            match path.segments[0].parameters {
-                syn::PathParameters::AngleBracketed(ref mut data) => data.types.push(self.ty),
-                _ => unreachable!(),
-            }
-
-            syn::Ty::Path(None, path)
+            syn_type_from_components(false, vec!["Box"], vec![self.ty])
         } else {
             self.ty
         }
@@ -218,7 +278,22 @@ pub struct Field {
 }
 
 impl Field {
+    /// Name of the field, `None` for anonymous ones.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(String::as_str)
+    }
+
+    pub fn ty(&self) -> &Type {
+        &self.ty
+    }
+
     fn to_syn_field(&self) -> error::Result<syn::Field> {
+        if let Type::Repeated(..) = self.ty {
+            if self.name.is_none() {
+                bail!(ErrorKind::RepeatedFieldWithoutCount(self.clone()));
+            }
+        }
+
         let ty = self.ty.to_type_ir()?.boxed();
 
         let mut field = syn::Field {
@@ -243,15 +318,76 @@ pub struct Constructor {
     pub(crate) type_parameters: Vec<Field>,
     pub(crate) fields: Vec<Field>,
     pub(crate) output: Type,
+    pub(crate) location: Option<Location>,
+    pub(crate) docs: Documentation,
+}
+
+/// Descriptions of a constructor and its fields, used for rustdoc of
+/// generated items.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Documentation {
+    pub(crate) description: Option<String>,
+    pub(crate) fields: BTreeMap<String, String>,
+}
+
+impl Documentation {
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_ref().map(String::as_str)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.description.is_none() && self.fields.is_empty()
+    }
 }
 
 impl Constructor {
+    /// Name of the constructor as written in the schema, e.g.
+    /// `Type::Named(["auth", "sentCode"])`.
+    pub fn variant(&self) -> &Type {
+        &self.variant
+    }
+
+    pub fn tl_id(&self) -> Option<u32> {
+        self.tl_id
+    }
+
+    /// Type parameters in curly braces, e.g. `{X:Type}`.
+    pub fn type_parameters(&self) -> &[Field] {
+        &self.type_parameters
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// The type this constructor belongs to (or the return type of a
+    /// function).
+    pub fn output(&self) -> &Type {
+        &self.output
+    }
+
+    /// Where the constructor is defined, if it was parsed from a schema.
+    pub fn location(&self) -> Option<&Location> {
+        self.location.as_ref()
+    }
+
+    /// Descriptions from schema comments or a doc file.
+    pub fn docs(&self) -> &Documentation {
+        &self.docs
+    }
+
     pub fn fixup(&mut self, which: Delimiter, fixup_map: &TypeFixupMap) {
         if which == Delimiter::Functions {
             self.fixup_output();
         }
 
+        self.fixup_type_parameters();
         self.fixup_fields(fixup_map);
+        self.fixup_repeated_fields();
         self.fixup_variant();
     }
 
@@ -272,7 +408,29 @@ impl Constructor {
         };
 
         if is_output_a_type_parameter(self) {
-            self.output = Type::TypeParameter(self.output.name().unwrap().into()) // FIXME
+            let name = match self.output {
+                Type::Named(ref v) => v[0].clone(),
+                _ => return,
+            };
+
+            self.output = Type::TypeParameter(name);
+        }
+    }
+
+    fn fixup_type_parameters(&mut self) {
+        let type_parameters: HashSet<&str> = self.type_parameters.iter()
+            .filter_map(|p| p.name.as_ref().map(String::as_str))
+            .collect();
+
+        for f in &mut self.fields {
+            f.ty.fixup_type_parameters(&type_parameters);
+        }
+
+        // `Pair X Y` of a generic type, the output of functions was fixed up above
+        if let Type::Generic(_, ref mut tys) = self.output {
+            for ty in tys {
+                ty.fixup_type_parameters(&type_parameters);
+            }
         }
     }
 
@@ -281,6 +439,12 @@ impl Constructor {
             f.ty.fixup(fixup_map);
         }
 
+        self.fixup_binary_strings();
+    }
+
+    /// Treat `string` fields of key exchange constructors as `bytes`
+    /// since they hold binary data.
+    pub fn fixup_binary_strings(&mut self) {
         match self.variant.name() {
             Some("resPQ") |
             Some("p_q_inner_data") |
@@ -303,6 +467,42 @@ impl Constructor {
         }
     }
 
+    /// Merge repeated fields with the `#` fields preceding them.
+    ///
+    /// A `#` field holds the number of elements of the repeated field
+    /// following it, which is exactly how a bare `Vec` is serialized.
+    /// The merged field takes the name of the `#` field. If it is
+    /// anonymous, the field is named `items`, or `items_2`, `items_3`
+    /// etc. if that name is already taken.
+    pub fn fixup_repeated_fields(&mut self) {
+        let flag_fields: HashSet<String> = self.flag_field_names().into_iter().map(Into::into).collect();
+        let mut used_names: HashSet<String> = self.fields.iter().filter_map(|f| f.name.clone()).collect();
+        let mut fields: Vec<Field> = Vec::with_capacity(self.fields.len());
+
+        for field in self.fields.drain(..) {
+            let count_field_name = match (&field, fields.last()) {
+                (&Field { name: None, ty: Type::Repeated(..) }, Some(&Field { ref name, ty: Type::Int })) => {
+                    match *name {
+                        Some(ref name) if flag_fields.contains(name) => None,
+                        Some(ref name) => Some(name.clone()),
+                        None => Some(unused_name("items", &mut used_names)),
+                    }
+                },
+                _ => None,
+            };
+
+            match count_field_name {
+                Some(name) => {
+                    fields.pop();
+                    fields.push(Field { name: Some(name), ty: field.ty });
+                },
+                None => fields.push(field),
+            }
+        }
+
+        self.fields = fields;
+    }
+
     fn fixup_variant(&mut self) {
         match self.variant.name() {
             // The 'updates' variant struct conflicts with the module.
@@ -336,56 +536,87 @@ impl Constructor {
         })
     }
 
+    /// Type parameters referenced by fields. Rust doesn't allow unused
+    /// ones, e.g. `Y` in `left {X:Type} {Y:Type} value:X = Either X Y`.
+    pub(crate) fn used_type_parameters(&self) -> Vec<&str> {
+        self.type_parameters.iter()
+            .filter_map(|field| field.name.as_ref().map(String::as_str))
+            .filter(|name| self.fields.iter().any(|f| f.ty.contains_type_parameter(name)))
+            .collect()
+    }
+
     fn syn_generics(&self) -> syn::Generics {
-        let ty_params = self.type_parameters.iter()
-            .map(|field| syn::Ident::new(field.name.clone().unwrap()).into()) // FIXME
-            .collect();
+        syn_generics_from_names(&self.used_type_parameters())
+    }
 
-        syn::Generics {
-            lifetimes: vec![],
-            ty_params: ty_params,
-            where_clause: Default::default(),
-        }
+    /// Type parameters as types to be used in a path, e.g. `X` in `Foo<X>`.
+    fn syn_generic_types(&self) -> Vec<syn::Ty> {
+        self.syn_generics().ty_params
+            .into_iter()
+            .map(|ty_param| syn::Ty::Path(None, ty_param.ident.into()))
+            .collect()
     }
 
-    fn syn_rpc_generics(&self) -> syn::Generics {
+    fn syn_rpc_generics(&self) -> error::Result<syn::Generics> {
         if self.type_parameters.is_empty() {
-            return syn::Generics::default();
+            return Ok(syn::Generics::default());
         }
 
-        let ty_params = self.type_parameters.iter()
-            .map(|field| syn::TyParam {
-                attrs: vec![],
-                ident: syn::Ident::new(field.name.clone().unwrap()), // FIXME
-                bounds: vec![
-                    syn::parse_ty_param_bound(quote! {
+        let ty_params = self.used_type_parameters().into_iter()
+            .map(|name| {
+                let name = name.to_owned();
+
+                let mut bounds = vec![];
+                // The reply type is taken from the wrapped function
+                if self.output == Type::TypeParameter(name.clone()) {
+                    bounds.push(parse_generated(syn::parse_ty_param_bound, quote! {
                         ::rpc::RpcFunction
-                    }.as_str()).unwrap(),
-                    syn::parse_ty_param_bound(quote! {
-                        ::serde::Serialize
-                    }.as_str()).unwrap(),
-                ],
-                default: None,
+                    }.as_str())?);
+                }
+                bounds.push(parse_generated(syn::parse_ty_param_bound, quote! {
+                    ::serde::Serialize
+                }.as_str())?);
+
+                Ok(syn::TyParam {
+                    attrs: vec![],
+                    ident: syn::Ident::new(name),
+                    bounds: bounds,
+                    default: None,
+                })
             })
-            .collect();
+            .collect::<error::Result<_>>()?;
 
-        syn::Generics {
+        Ok(syn::Generics {
             lifetimes: vec![],
             ty_params: ty_params,
             where_clause: Default::default(),
-        }
+        })
     }
 
-    fn to_syn_struct<'a>(&self, name: &syn::Ident, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<syn::Item> {
+    /// The struct for this constructor followed by impls it needs.
+    fn to_syn_struct<'a>(&self, name: &syn::Ident, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<Vec<syn::Item>> {
         let syn_generics = self.syn_generics();
-        let syn_fields = self.fields
-            .iter()
-            .map(Field::to_syn_field)
+        let syn_fields = self.non_flag_fields()
+            .map(|field| {
+                let mut syn_field = field.to_syn_field()?;
+                if let Some(doc) = field.name().and_then(|name| self.docs.field(name)) {
+                    syn_field.attrs.push(doc_attr(doc));
+                }
+
+                Ok(syn_field)
+            })
             .collect::<error::Result<_>>()?;
 
         let typeck_kind = ctors_typeck_info[self];
         let mut derives = typeck_kind.infer_basic_derives();
         let mut id_attr = None;
+        let mut impl_items = vec![];
+
+        if !self.flag_field_names().is_empty() {
+            derives.retain(|&d| d != "Serialize" && d != "Deserialize" && d != "MtProtoSized");
+            impl_items = self.to_syn_flags_impls(name, typeck_kind)?;
+            impl_items.extend(self.to_syn_builder(name)?);
+        }
 
         if let Some(tl_id) = self.tl_id {
             derives.push("MtProtoIdentifiable");
@@ -412,11 +643,9 @@ impl Constructor {
             is_sugared_doc: false,
         };
 
-        let attrs = if let Some(id_attr) = id_attr {
-            vec![derive_attr, id_attr]
-        } else {
-            vec![derive_attr]
-        };
+        let mut attrs: Vec<_> = self.docs.description().map(doc_attr).into_iter().collect();
+        attrs.push(derive_attr);
+        attrs.extend(id_attr);
 
         let syn_struct = syn::Item {
             ident: name.clone(),
@@ -425,17 +654,286 @@ impl Constructor {
             node: syn::ItemKind::Struct(syn::VariantData::Struct(syn_fields), syn_generics),
         };
 
-        Ok(syn_struct)
+        Ok(iter::once(syn_struct).chain(impl_items).collect())
     }
 
-    pub fn variant_name(&self) -> syn::Ident {
-        self.variant.name().map(no_conflict_ident).unwrap() // FIXME: .unwrap()
+    /// `Serialize`, `Deserialize` and `MtProtoSized` impls for a
+    /// constructor with flags fields.
+    ///
+    /// Flags fields are not stored in the struct: they are computed from
+    /// the presence of optional fields during serialization and decide
+    /// which fields are read during deserialization.
+    fn to_syn_flags_impls(&self, name: &syn::Ident, typeck_kind: TypeckKind) -> error::Result<Vec<syn::Item>> {
+        let flag_field_names = self.flag_field_names();
+        let name_str = name.as_ref();
+        let expecting = format!("struct {}", name_str);
+
+        let mut compute_flags = vec![];
+        let mut serialize_fields = vec![];
+        let mut size_hints = vec![];
+        let mut deserialize_fields = vec![];
+        let mut wire_field_names = vec![];
+        let mut struct_field_names = vec![];
+        // Flags fields must come before the fields they refer to, since
+        // their values are needed for deserialization
+        let mut declared_flags = HashSet::new();
+
+        for field in &self.fields {
+            let (field_name, ident) = match field.name {
+                Some(ref field_name) => (field_name.as_str(), no_conflict_ident(field_name)),
+                None => bail!(ErrorKind::UnnamedField(field.clone())),
+            };
+            let index = wire_field_names.len();
+
+            match field.ty {
+                Type::Int if flag_field_names.contains(field_name) => {
+                    let flags = self.fields.iter().filter_map(|f| match (&f.name, &f.ty) {
+                        (&Some(ref name), &Type::Flagged(ref flags, bit, ref ty)) if flags == field_name => {
+                            Some((no_conflict_ident(name), bit, ty.to_type_ir().map(|ir| ir.kind == TypeIrKind::Unit)))
+                        },
+                        _ => None,
+                    });
+
+                    compute_flags.push(quote! { let mut #ident: i32 = 0; });
+                    for (flagged_ident, bit, is_unit) in flags {
+                        let is_set = if is_unit? {
+                            quote! { self.#flagged_ident }
+                        } else {
+                            quote! { self.#flagged_ident.is_some() }
+                        };
+                        compute_flags.push(quote! { if #is_set { #ident |= 1 << #bit; } });
+                    }
+
+                    serialize_fields.push(quote! { __state.serialize_field(#field_name, &#ident)?; });
+                    size_hints.push(quote! { __size += ::serde_mtproto::MtProtoSized::size_hint(&0i32)?; });
+                    deserialize_fields.push(quote! {
+                        let #ident: i32 = __seq.next_element()?
+                            .ok_or_else(|| ::serde::de::Error::invalid_length(#index, &self))?;
+                    });
+                    wire_field_names.push(field_name);
+                    declared_flags.insert(field_name);
+                },
+                Type::Flagged(ref flags, bit, ref ty) => {
+                    if !declared_flags.contains(flags.as_str()) {
+                        bail!(ErrorKind::UndeclaredFlags(flags.clone(), bit));
+                    }
+                    let flags = no_conflict_ident(flags);
+
+                    if ty.to_type_ir()?.kind == TypeIrKind::Unit {
+                        deserialize_fields.push(quote! { let #ident = #flags & (1 << #bit) != 0; });
+                    } else {
+                        serialize_fields.push(quote! {
+                            if let Some(ref __value) = self.#ident {
+                                __state.serialize_field(#field_name, __value)?;
+                            }
+                        });
+                        size_hints.push(quote! {
+                            if let Some(ref __value) = self.#ident {
+                                __size += ::serde_mtproto::MtProtoSized::size_hint(__value)?;
+                            }
+                        });
+                        deserialize_fields.push(quote! {
+                            let #ident = if #flags & (1 << #bit) != 0 {
+                                Some(__seq.next_element()?
+                                    .ok_or_else(|| ::serde::de::Error::invalid_length(#index, &self))?)
+                            } else {
+                                None
+                            };
+                        });
+                        wire_field_names.push(field_name);
+                    }
+
+                    struct_field_names.push(ident);
+                },
+                _ => {
+                    serialize_fields.push(quote! { __state.serialize_field(#field_name, &self.#ident)?; });
+                    size_hints.push(quote! { __size += ::serde_mtproto::MtProtoSized::size_hint(&self.#ident)?; });
+                    deserialize_fields.push(quote! {
+                        let #ident = __seq.next_element()?
+                            .ok_or_else(|| ::serde::de::Error::invalid_length(#index, &self))?;
+                    });
+                    wire_field_names.push(field_name);
+                    struct_field_names.push(ident);
+                },
+            }
+        }
+
+        let wire_fields_count = wire_field_names.len();
+        let struct_fields_init = struct_field_names.iter().map(|ident| quote! { #ident: #ident });
+        let generics = self.syn_generics();
+        let (impl_generics, ty_generics, _) = generics.split_for_impl();
+        let bounded_generics = |bound: &str, lifetime: Option<&str>| -> error::Result<syn::Generics> {
+            let mut generics = generics.clone();
+            for ty_param in &mut generics.ty_params {
+                ty_param.bounds.push(parse_generated(syn::parse_ty_param_bound, bound)?);
+            }
+            if let Some(lifetime) = lifetime {
+                generics.lifetimes.insert(0, syn::LifetimeDef::new(lifetime));
+            }
+
+            Ok(generics)
+        };
+
+        let ser_generics = bounded_generics("::serde::Serialize", None)?;
+        let (ser_impl_generics, _, _) = ser_generics.split_for_impl();
+        let sized_generics = bounded_generics("::serde_mtproto::MtProtoSized", None)?;
+        let (sized_impl_generics, _, _) = sized_generics.split_for_impl();
+
+        let mut impls = vec![
+            quote! {
+                impl #ser_impl_generics ::serde::Serialize for #name #ty_generics {
+                    fn serialize<__S>(&self, __serializer: __S) -> ::std::result::Result<__S::Ok, __S::Error>
+                        where __S: ::serde::Serializer
+                    {
+                        use ::serde::ser::SerializeStruct;
+
+                        #(#compute_flags)*
+
+                        let mut __state = __serializer.serialize_struct(#name_str, #wire_fields_count)?;
+                        #(#serialize_fields)*
+                        __state.end()
+                    }
+                }
+            },
+            quote! {
+                impl #sized_impl_generics ::serde_mtproto::MtProtoSized for #name #ty_generics {
+                    fn size_hint(&self) -> ::serde_mtproto::Result<usize> {
+                        let mut __size = 0;
+                        #(#size_hints)*
+                        Ok(__size)
+                    }
+                }
+            },
+        ];
+
+        if typeck_kind == TypeckKind::Static {
+            let de_generics = bounded_generics("::serde::Deserialize<'de>", Some("'de"))?;
+            let (de_impl_generics, _, _) = de_generics.split_for_impl();
+
+            impls.push(quote! {
+                impl #de_impl_generics ::serde::Deserialize<'de> for #name #ty_generics {
+                    fn deserialize<__D>(__deserializer: __D) -> ::std::result::Result<Self, __D::Error>
+                        where __D: ::serde::Deserializer<'de>
+                    {
+                        struct __Visitor #impl_generics(::std::marker::PhantomData<#name #ty_generics>);
+
+                        impl #de_impl_generics ::serde::de::Visitor<'de> for __Visitor #ty_generics {
+                            type Value = #name #ty_generics;
+
+                            fn expecting(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                                f.write_str(#expecting)
+                            }
+
+                            fn visit_seq<__A>(self, mut __seq: __A) -> ::std::result::Result<Self::Value, __A::Error>
+                                where __A: ::serde::de::SeqAccess<'de>
+                            {
+                                #(#deserialize_fields)*
+
+                                Ok(#name { #(#struct_fields_init),* })
+                            }
+                        }
+
+                        const __FIELDS: &'static [&'static str] = &[#(#wire_field_names),*];
+                        __deserializer.deserialize_struct(#name_str, __FIELDS, __Visitor(::std::marker::PhantomData))
+                    }
+                }
+            });
+        }
+
+        impls.into_iter().map(|tokens| parse_generated(syn::parse_item, tokens.as_str())).collect()
+    }
+
+    /// A builder for a constructor with flags fields, used as
+    /// `user::builder(id).bot(true).access_hash(hash).build()`.
+    ///
+    /// Required fields are arguments of `builder()`, optional fields are
+    /// `None` and `true` flags are `false` unless set.
+    fn to_syn_builder(&self, name: &syn::Ident) -> error::Result<Vec<syn::Item>> {
+        let builder_name = syn::Ident::new(format!("{}Builder", name.as_ref()));
+
+        let mut required_params = vec![];
+        let mut fields_init = vec![];
+        let mut setters = vec![];
+
+        for field in self.non_flag_fields() {
+            let ident = match field.name {
+                Some(ref field_name) => no_conflict_ident(field_name),
+                None => bail!(ErrorKind::UnnamedField(field.clone())),
+            };
+
+            match field.ty {
+                Type::Flagged(_, _, ref ty) => {
+                    let type_ir = ty.to_type_ir()?;
+
+                    if type_ir.kind == TypeIrKind::Unit {
+                        fields_init.push(quote! { #ident: false });
+                        setters.push(quote! {
+                            pub fn #ident(mut self, value: bool) -> Self {
+                                self.value.#ident = value;
+                                self
+                            }
+                        });
+                    } else {
+                        let ty = type_ir.boxed();
+                        fields_init.push(quote! { #ident: None });
+                        setters.push(quote! {
+                            pub fn #ident(mut self, value: #ty) -> Self {
+                                self.value.#ident = Some(value);
+                                self
+                            }
+                        });
+                    }
+                },
+                _ => {
+                    let ty = field.ty.to_type_ir()?.boxed();
+                    required_params.push(quote! { #ident: #ty });
+                    fields_init.push(quote! { #ident: #ident });
+                },
+            }
+        }
+
+        let generics = self.syn_generics();
+        let (impl_generics, ty_generics, _) = generics.split_for_impl();
+
+        let items = vec![
+            quote! {
+                #[derive(Clone, Debug)]
+                pub struct #builder_name #impl_generics {
+                    value: #name #ty_generics,
+                }
+            },
+            quote! {
+                impl #impl_generics #name #ty_generics {
+                    pub fn builder(#(#required_params),*) -> #builder_name #ty_generics {
+                        #builder_name {
+                            value: #name { #(#fields_init),* },
+                        }
+                    }
+                }
+            },
+            quote! {
+                impl #impl_generics #builder_name #ty_generics {
+                    #(#setters)*
+
+                    pub fn build(self) -> #name #ty_generics {
+                        self.value
+                    }
+                }
+            },
+        ];
+
+        items.into_iter().map(|tokens| parse_generated(syn::parse_item, tokens.as_str())).collect()
+    }
+
+    pub fn variant_name(&self) -> error::Result<syn::Ident> {
+        self.variant.name().map(no_conflict_ident)
+            .ok_or_else(|| ErrorKind::UnnamedType(self.variant.clone()).into())
     }
 
-    pub fn to_syn_variant(&self) -> syn::Variant {
-        let variant_name = self.variant_name();
+    pub fn to_syn_variant(&self) -> error::Result<syn::Variant> {
+        let variant_name = self.variant_name()?;
 
-        let mut attrs = vec![];
+        let mut attrs: Vec<_> = self.docs.description().map(doc_attr).into_iter().collect();
         if let Some(tl_id) = self.tl_id {
             let id_attr = syn::Attribute {
                 // Docs for syn 0.11.11 contain a bug: we need Outer for #[..], not Inner
@@ -458,45 +956,43 @@ impl Constructor {
                     ident: None,
                     vis: syn::Visibility::Inherited,
                     attrs: vec![],
-                    ty: syn::Ty::Path(None, variant_name.clone().into()),
+                    ty: syn_type_from_components(false, vec![variant_name.clone()], self.syn_generic_types()),
                 }
             ])
         };
 
-        syn::Variant {
+        Ok(syn::Variant {
             ident: syn::Ident::new(variant_name),
             attrs: attrs,
             data: variant_data,
             discriminant: None,
-        }
+        })
     }
 
-    pub fn to_syn_variant_type_struct<'a>(&self, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<Option<syn::Item>> {
+    pub fn to_syn_variant_type_struct<'a>(&self, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<Vec<syn::Item>> {
         if self.fields.is_empty() {
-            Ok(None)
+            Ok(vec![])
         } else {
-            self.to_syn_type_struct_base(self.variant_name(), ctors_typeck_info).map(Some)
+            self.to_syn_type_struct_base(self.variant_name()?, ctors_typeck_info)
         }
     }
 
-    fn to_syn_type_struct_base<'a>(&self, name: syn::Ident, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<syn::Item> {
+    fn to_syn_type_struct_base<'a>(&self, name: syn::Ident, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<Vec<syn::Item>> {
         self.to_syn_struct(&name, ctors_typeck_info)
     }
 
-    pub fn to_syn_single_type_struct<'a>(&self, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<syn::Item> {
-        let name = self.output.name().map(no_conflict_ident).unwrap(); // FIXME
+    pub fn to_syn_single_type_struct<'a>(&self, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<Vec<syn::Item>> {
+        let name = self.output.name().map(no_conflict_ident)
+            .ok_or_else(|| ErrorKind::UnnamedType(self.output.clone()))?;
         self.to_syn_type_struct_base(name, ctors_typeck_info)
     }
 
     pub fn to_syn_function_struct<'a>(&self, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<Vec<syn::Item>> {
-        let name = self.variant_name();
-        let syn_rpc_generics = self.syn_rpc_generics();
-        let syn_generics = self.syn_generics();
-        let generic_types = syn_generics.ty_params
-            .into_iter()
-            .map(|ty_param| syn::Ty::Path(None, ty_param.ident.into()));
+        let name = self.variant_name()?;
+        let syn_rpc_generics = self.syn_rpc_generics()?;
+        let generic_types = self.syn_generic_types();
 
-        let struct_block = self.to_syn_struct(&name, ctors_typeck_info)?;
+        let mut items = self.to_syn_struct(&name, ctors_typeck_info)?;
         let mut output_ty = self.output.to_type_ir()?.unboxed();
         if self.output.is_type_parameter() {
             match output_ty {
@@ -504,18 +1000,20 @@ impl Constructor {
                     path.segments.push("Reply".into());
                     output_ty = syn::Ty::Path(None, path);
                 },
-                _ => unreachable!(),
+                ty => bail!(ErrorKind::UnsupportedType(format!("{} as function output", quote! { #ty }))),
             }
         }
 
         let name = syn::Ident::new(name);
-        let impl_item = syn::parse_item(quote! {
+        let impl_item = parse_generated(syn::parse_item, quote! {
             impl #syn_rpc_generics ::rpc::RpcFunction for #name<#(#generic_types),*> {
                 type Reply = #output_ty;
             }
-        }.as_str()).unwrap();
+        }.as_str())?;
+
+        items.push(impl_item);
 
-        Ok(vec![struct_block, impl_item])
+        Ok(items)
     }
 }
 
@@ -533,15 +1031,30 @@ pub enum Item {
 }
 
 
+/// Parse generated code with one of `syn::parse_*` functions.
+///
+/// Failures are bugs of the generator, but are reported as errors
+/// rather than panics, so they end up in diagnostics of the schema.
+pub(crate) fn parse_generated<T, F>(parse: F, code: &str) -> error::Result<T>
+    where F: FnOnce(&str) -> Result<T, String>
+{
+    parse(code).map_err(|e| ErrorKind::InvalidGeneratedCode(e, code.to_owned()).into())
+}
+
+pub fn doc_attr(doc: &str) -> syn::Attribute {
+    syn::Attribute {
+        style: syn::AttrStyle::Outer,
+        value: syn::MetaItem::NameValue(
+            syn::Ident::new("doc"),
+            syn::Lit::Str(format!(" {}", doc), syn::StrStyle::Cooked),
+        ),
+        is_sugared_doc: false,
+    }
+}
+
 pub fn wrap_option_type(wrap: bool, ty: syn::Ty) -> syn::Ty {
     if wrap {
-        let mut path: syn::Path = "Option".into();
-        match path.segments[0].parameters {
-            syn::PathParameters::AngleBracketed(ref mut data) => data.types.push(ty),
-            _ => unreachable!(),
-        }
-
-        syn::Ty::Path(None, path)
+        syn_type_from_components(false, vec!["Option"], vec![ty])
     } else {
         ty
     }
@@ -582,6 +1095,29 @@ pub fn no_conflict_ident(s: &str) -> syn::Ident {
     candidate.into()
 }
 
+pub fn syn_generics_from_names(names: &[&str]) -> syn::Generics {
+    syn::Generics {
+        lifetimes: vec![],
+        ty_params: names.iter().map(|&name| syn::Ident::new(name).into()).collect(),
+        where_clause: Default::default(),
+    }
+}
+
+/// `base`, or `base` with the smallest suffix starting from `_2` which
+/// is not in `used_names`. The chosen name is added to `used_names`.
+fn unused_name(base: &str, used_names: &mut HashSet<String>) -> String {
+    let mut candidate = base.to_owned();
+    let mut suffix = 2;
+
+    while used_names.contains(&candidate) {
+        candidate = format!("{}_{}", base, suffix);
+        suffix += 1;
+    }
+
+    used_names.insert(candidate.clone());
+    candidate
+}
+
 fn names_to_type_ir(names: &[String], type_parameters: &[TypeIr]) -> error::Result<TypeIr> {
     if names.len() == 1 {
         let get_ty_param = || -> error::Result<_> {
@@ -684,12 +1220,11 @@ fn syn_type_from_components<PS, PSI, TPI>(global: bool,
         segments: path_segments.into_iter().map(Into::into).collect(),
     };
 
-    let last_index = path.segments.len() - 1;
-    match path.segments[last_index].parameters {
-        syn::PathParameters::AngleBracketed(ref mut data) => {
-            data.types.extend(type_parameters.into_iter().map(Into::into));
-        },
-        _ => unreachable!(),
+    if let Some(last_segment) = path.segments.last_mut() {
+        last_segment.parameters = syn::PathParameters::AngleBracketed(syn::AngleBracketedParameterData {
+            types: type_parameters.into_iter().collect(),
+            ..Default::default()
+        });
     }
 
     syn::Ty::Path(None, path)
@@ -1,18 +1,18 @@
 extern crate tl_codegen;
 
 use std::io::Read;
+use std::process;
 
 
 fn main() {
     let mut input = String::new();
     std::io::stdin().read_to_string(&mut input).unwrap();
-    println!("{}", generate_from_input(&input));
-}
-
-fn generate_from_input(input: &str) -> String {
-    tl_codegen::generate_code_for(input).into_string()
-}
 
-fn generate_from_input(input: &str) -> String {
-    format!("{:?}", tl_codegen::generate_items_for(input))
+    match tl_codegen::generate_code_for(&input) {
+        Ok(code) => println!("{}", code.as_str()),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        },
+    }
 }
@@ -0,0 +1,69 @@
+//! Compare two TL schemas, e.g. before updating to a new layer.
+//!
+//! Usage: `tl_diff [--json] OLD.tl NEW.tl`
+//!
+//! Schemas may also be Telegram's `.json` files, see
+//! `tl_codegen::parser::parse_files`.
+//!
+//! Like `diff`, exits with 0 if the schemas are equal, 1 if they differ
+//! and 2 on errors.
+
+extern crate tl_codegen;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+use tl_codegen::diff::SchemaDiff;
+use tl_codegen::parser;
+
+
+fn read_file(path: &str) -> io::Result<String> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Compare `(file name, contents)` pairs, so that errors point to files.
+fn diff_files(old: (&str, &str), new: (&str, &str)) -> tl_codegen::Result<SchemaDiff> {
+    let old = parser::parse_files(vec![old])?;
+    let new = parser::parse_files(vec![new])?;
+
+    tl_codegen::diff_items(old, new)
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let json = args.iter().position(|arg| arg == "--json").map(|i| args.remove(i)).is_some();
+
+    if args.len() != 2 {
+        eprintln!("Usage: tl_diff [--json] OLD.tl NEW.tl");
+        process::exit(2);
+    }
+
+    let schemas = read_file(&args[0]).and_then(|old| read_file(&args[1]).map(|new| (old, new)));
+    let diff = match schemas {
+        Ok((old, new)) => diff_files((&args[0], &old), (&args[1], &new)),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        },
+    };
+
+    match diff {
+        Ok(diff) => {
+            if json {
+                println!("{}", diff.to_json());
+            } else {
+                print!("{}", diff);
+            }
+
+            process::exit(if diff.is_empty() { 0 } else { 1 });
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        },
+    }
+}
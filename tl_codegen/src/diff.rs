@@ -0,0 +1,321 @@
+//! Comparison of two schemas, e.g. of consecutive layers.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use ast::{Constructor, Field, Item, Type};
+use error;
+use generator::{self, AllConstructors};
+use json::Value;
+use parser;
+
+
+/// Differences between an old and a new schema.
+#[derive(Clone, Debug)]
+pub struct SchemaDiff {
+    pub old_layer: u32,
+    pub new_layer: u32,
+    pub constructors: Vec<Change>,
+    pub functions: Vec<Change>,
+}
+
+/// A constructor or function which differs between schemas.
+#[derive(Clone, Debug)]
+pub enum Change {
+    Added(Constructor),
+    Removed(Constructor),
+    Changed {
+        old: Constructor,
+        new: Constructor,
+        kinds: Vec<ChangeKind>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    IdChanged(Option<u32>, Option<u32>),
+    /// An optional `flags.N?` field was added, which doesn't break
+    /// existing code, but changes the id.
+    FlagFieldAdded(Field),
+    FieldAdded(Field),
+    FieldRemoved(Field),
+    FieldTypeChanged(String, Type, Type),
+    /// The type of a constructor or the return type of a function.
+    TypeChanged(Type, Type),
+}
+
+
+/// Compare two schemas in `.tl` format.
+pub fn diff_schemas(old: &str, new: &str) -> error::Result<SchemaDiff> {
+    diff_items(parser::parse_string(old)?, parser::parse_string(new)?)
+}
+
+/// Compare two parsed schemas.
+pub fn diff_items(old: Vec<Item>, new: Vec<Item>) -> error::Result<SchemaDiff> {
+    let old = partition(old)?;
+    let new = partition(new)?;
+
+    Ok(SchemaDiff {
+        old_layer: old.layer,
+        new_layer: new.layer,
+        constructors: diff_constructors(type_constructors(&old), type_constructors(&new)),
+        functions: diff_constructors(functions(&old), functions(&new)),
+    })
+}
+
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.constructors.is_empty() && self.functions.is_empty()
+    }
+
+    /// The diff as a JSON object:
+    ///
+    /// ```json
+    /// {
+    ///     "old_layer": 67,
+    ///     "new_layer": 68,
+    ///     "constructors": [
+    ///         { "change": "added", "name": "foo", "definition": "foo#12345678 bar:int = Foo;" },
+    ///         { "change": "changed", "name": "baz", "old": "...", "new": "...",
+    ///           "kinds": [{ "kind": "id_changed", "old": "1a2b3c4d", "new": "5e6f7a8b" }] }
+    ///     ],
+    ///     "functions": []
+    /// }
+    /// ```
+    pub fn to_json(&self) -> String {
+        let changes_to_json = |changes: &[Change]| Value::Array(changes.iter().map(Change::to_json).collect());
+
+        Value::Object(vec![
+            ("old_layer".to_owned(), Value::Number(self.old_layer as f64)),
+            ("new_layer".to_owned(), Value::Number(self.new_layer as f64)),
+            ("constructors".to_owned(), changes_to_json(&self.constructors)),
+            ("functions".to_owned(), changes_to_json(&self.functions)),
+        ]).to_string()
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    /// Human-readable report, one line per added or removed item and an
+    /// indented line per change of a changed one.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Layer {} -> {}", self.old_layer, self.new_layer)?;
+
+        for &(title, changes) in &[("Constructors", &self.constructors), ("Functions", &self.functions)] {
+            if changes.is_empty() {
+                continue;
+            }
+
+            writeln!(f, "\n{}:", title)?;
+            for change in changes.iter() {
+                write!(f, "{}", change)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Change {
+    /// Full name of the constructor or function, e.g. `auth.sentCode`.
+    pub fn name(&self) -> String {
+        match *self {
+            Change::Added(ref c) | Change::Removed(ref c) | Change::Changed { new: ref c, .. } => full_name(c),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut members = vec![];
+
+        match *self {
+            Change::Added(ref c) => {
+                members.push(("change".to_owned(), Value::String("added".to_owned())));
+                members.push(("name".to_owned(), Value::String(self.name())));
+                members.push(("definition".to_owned(), Value::String(c.to_string())));
+            },
+            Change::Removed(ref c) => {
+                members.push(("change".to_owned(), Value::String("removed".to_owned())));
+                members.push(("name".to_owned(), Value::String(self.name())));
+                members.push(("definition".to_owned(), Value::String(c.to_string())));
+            },
+            Change::Changed { ref old, ref new, ref kinds } => {
+                members.push(("change".to_owned(), Value::String("changed".to_owned())));
+                members.push(("name".to_owned(), Value::String(self.name())));
+                members.push(("old".to_owned(), Value::String(old.to_string())));
+                members.push(("new".to_owned(), Value::String(new.to_string())));
+                members.push(("kinds".to_owned(), Value::Array(kinds.iter().map(ChangeKind::to_json).collect())));
+            },
+        }
+
+        Value::Object(members)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Added(ref c) => writeln!(f, "+ {}", c),
+            Change::Removed(ref c) => writeln!(f, "- {}", c),
+            Change::Changed { ref kinds, .. } => {
+                writeln!(f, "~ {}", self.name())?;
+                for kind in kinds {
+                    writeln!(f, "    {}", kind)?;
+                }
+
+                Ok(())
+            },
+        }
+    }
+}
+
+impl ChangeKind {
+    fn to_json(&self) -> Value {
+        let string = |s: &str| Value::String(s.to_owned());
+        let id = |id: Option<u32>| id.map_or(Value::Null, |id| Value::String(format!("{:08x}", id)));
+
+        let members = match *self {
+            ChangeKind::IdChanged(old, new) => vec![
+                ("kind", string("id_changed")),
+                ("old", id(old)),
+                ("new", id(new)),
+            ],
+            ChangeKind::FlagFieldAdded(ref field) => vec![
+                ("kind", string("flag_field_added")),
+                ("field", string(&field.to_string())),
+            ],
+            ChangeKind::FieldAdded(ref field) => vec![
+                ("kind", string("field_added")),
+                ("field", string(&field.to_string())),
+            ],
+            ChangeKind::FieldRemoved(ref field) => vec![
+                ("kind", string("field_removed")),
+                ("field", string(&field.to_string())),
+            ],
+            ChangeKind::FieldTypeChanged(ref name, ref old, ref new) => vec![
+                ("kind", string("field_type_changed")),
+                ("field", string(name)),
+                ("old", string(&old.to_string())),
+                ("new", string(&new.to_string())),
+            ],
+            ChangeKind::TypeChanged(ref old, ref new) => vec![
+                ("kind", string("type_changed")),
+                ("old", string(&old.to_string())),
+                ("new", string(&new.to_string())),
+            ],
+        };
+
+        Value::Object(members.into_iter().map(|(key, value)| (key.to_owned(), value)).collect())
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let id = |id: Option<u32>| id.map_or("none".to_owned(), |id| format!("#{:08x}", id));
+
+        match *self {
+            ChangeKind::IdChanged(old, new) => write!(f, "id changed: {} -> {}", id(old), id(new)),
+            ChangeKind::FlagFieldAdded(ref field) => write!(f, "flag field added: {}", field),
+            ChangeKind::FieldAdded(ref field) => write!(f, "field added: {}", field),
+            ChangeKind::FieldRemoved(ref field) => write!(f, "field removed: {}", field),
+            ChangeKind::FieldTypeChanged(ref name, ref old, ref new) => {
+                write!(f, "type of field `{}` changed: {} -> {}", name, old, new)
+            },
+            ChangeKind::TypeChanged(ref old, ref new) => write!(f, "type changed: {} -> {}", old, new),
+        }
+    }
+}
+
+
+fn partition(items: Vec<Item>) -> error::Result<AllConstructors> {
+    let mut diagnostics = vec![];
+    let constructors = generator::partition_by_delimiter_and_namespace(items, &mut diagnostics);
+    error::check_diagnostics(diagnostics)?;
+
+    Ok(constructors)
+}
+
+fn type_constructors(all: &AllConstructors) -> BTreeMap<String, &Constructor> {
+    all.types.values()
+        .flat_map(|types| types.values())
+        .flat_map(|constructors| constructors.0.iter())
+        .map(|c| (full_name(c), c))
+        .collect()
+}
+
+fn functions(all: &AllConstructors) -> BTreeMap<String, &Constructor> {
+    all.functions.values()
+        .flat_map(|functions| functions.iter())
+        .map(|c| (full_name(c), c))
+        .collect()
+}
+
+fn full_name(constructor: &Constructor) -> String {
+    constructor.variant.to_string()
+}
+
+fn diff_constructors(old: BTreeMap<String, &Constructor>, new: BTreeMap<String, &Constructor>) -> Vec<Change> {
+    let mut changes = vec![];
+
+    for (name, old_constructor) in &old {
+        match new.get(name) {
+            None => changes.push(Change::Removed((*old_constructor).clone())),
+            Some(new_constructor) => {
+                let kinds = change_kinds(old_constructor, new_constructor);
+                if !kinds.is_empty() {
+                    changes.push(Change::Changed {
+                        old: (*old_constructor).clone(),
+                        new: (*new_constructor).clone(),
+                        kinds: kinds,
+                    });
+                }
+            },
+        }
+    }
+
+    for (name, new_constructor) in &new {
+        if !old.contains_key(name) {
+            changes.push(Change::Added((*new_constructor).clone()));
+        }
+    }
+
+    changes.sort_by_key(Change::name);
+    changes
+}
+
+fn change_kinds(old: &Constructor, new: &Constructor) -> Vec<ChangeKind> {
+    // Anonymous fields are told apart by their definitions
+    fn field_key(field: &Field) -> String {
+        field.name.clone().unwrap_or_else(|| field.to_string())
+    }
+
+    let mut kinds = vec![];
+
+    if old.tl_id != new.tl_id {
+        kinds.push(ChangeKind::IdChanged(old.tl_id, new.tl_id));
+    }
+
+    for old_field in &old.fields {
+        match new.fields.iter().find(|f| field_key(f) == field_key(old_field)) {
+            None => kinds.push(ChangeKind::FieldRemoved(old_field.clone())),
+            Some(new_field) if new_field.ty != old_field.ty => {
+                kinds.push(ChangeKind::FieldTypeChanged(field_key(old_field), old_field.ty.clone(), new_field.ty.clone()));
+            },
+            Some(_) => (),
+        }
+    }
+
+    for new_field in &new.fields {
+        if !old.fields.iter().any(|f| field_key(f) == field_key(new_field)) {
+            match new_field.ty {
+                Type::Flagged(..) => kinds.push(ChangeKind::FlagFieldAdded(new_field.clone())),
+                _ => kinds.push(ChangeKind::FieldAdded(new_field.clone())),
+            }
+        }
+    }
+
+    if old.output != new.output {
+        kinds.push(ChangeKind::TypeChanged(old.output.clone(), new.output.clone()));
+    }
+
+    kinds
+}
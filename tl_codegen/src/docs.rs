@@ -0,0 +1,84 @@
+//! Documentation of TL items from external doc files.
+
+use ast::{Delimiter, Documentation, Item, Type};
+use error::{self, ErrorKind};
+use json::{self, Value};
+
+
+/// Add descriptions from a JSON doc file, e.g. scraped from
+/// core.telegram.org, to constructors and functions in `items`:
+///
+/// ```json
+/// {
+///     "constructors": {
+///         "user": {
+///             "description": "Indicates info about a certain user",
+///             "params": { "id": "ID of the user" }
+///         }
+///     },
+///     "methods": {
+///         "auth.sendCode": { "description": "Send the verification code for login" }
+///     }
+/// }
+/// ```
+///
+/// Descriptions from schema comments take precedence over the doc file.
+/// Entries for items which aren't in the schema are ignored.
+pub fn apply_doc_file(items: &mut [Item], input: &str) -> error::Result<()> {
+    let doc_file = json::parse(input)?;
+    let constructors = section(&doc_file, "constructors")?;
+    let methods = section(&doc_file, "methods")?;
+
+    let mut delimiter = Delimiter::Types;
+    for item in items {
+        let constructor = match *item {
+            Item::Delimiter(d) => {
+                delimiter = d;
+                continue;
+            },
+            Item::Constructor(ref mut c) => c,
+            Item::Layer(_) => continue,
+        };
+
+        let name = match constructor.variant {
+            Type::Named(ref v) => v.join("."),
+            _ => continue,
+        };
+        let section = if delimiter == Delimiter::Functions { methods } else { constructors };
+
+        if let Some(&(_, ref entry)) = section.iter().find(|&&(ref n, _)| *n == name) {
+            merge_docs(&mut constructor.docs, &name, entry)?;
+        }
+    }
+
+    Ok(())
+}
+
+
+fn section<'a>(doc_file: &'a Value, key: &str) -> error::Result<&'a [(String, Value)]> {
+    match doc_file.get(key) {
+        Some(value) => value.as_object()
+            .ok_or_else(|| ErrorKind::UnexpectedJson(format!("`{}` is not an object", key)).into()),
+        None => Ok(&[]),
+    }
+}
+
+fn merge_docs(docs: &mut Documentation, name: &str, entry: &Value) -> error::Result<()> {
+    let unexpected = |what: &str| ErrorKind::UnexpectedJson(format!("{} of `{}`", what, name));
+
+    if docs.description.is_none() {
+        if let Some(description) = entry.get("description") {
+            let description = description.as_str().ok_or_else(|| unexpected("description"))?;
+            docs.description = Some(description.to_owned());
+        }
+    }
+
+    if let Some(params) = entry.get("params") {
+        for &(ref field, ref description) in params.as_object().ok_or_else(|| unexpected("params"))? {
+            let description = description.as_str().ok_or_else(|| unexpected("param description"))?;
+            docs.fields.entry(field.clone()).or_insert_with(|| description.to_owned());
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,6 @@
-use ast::TypeIr;
+use std::fmt;
+
+use ast::{Constructor, Field, Type, TypeIr};
 
 error_chain! {
     errors {
@@ -7,5 +9,175 @@ error_chain! {
             display("wrong number of type parameters: {:?} (need {}, found {})",
                 &ty_params, needed_count, ty_params.len())
         }
+
+        RepeatedFieldWithoutCount(field: Field) {
+            description("repeated field is not preceded by a `#` field holding its length")
+            display("repeated field is not preceded by a `#` field holding its length: {:?}", field)
+        }
+
+        UnnamedField(field: Field) {
+            description("field of a constructor with flags must be named")
+            display("field of a constructor with flags must be named: {:?}", field)
+        }
+
+        UnnamedType(ty: Type) {
+            description("expected a named type")
+            display("expected a named type, found {:?}", ty)
+        }
+
+        UnsupportedType(description: String) {
+            description("type is not supported")
+            display("type is not supported: {}", description)
+        }
+
+        WrongTlId(tl_id: u32, computed: u32) {
+            description("constructor id doesn't match its definition")
+            display("constructor id #{:08x} doesn't match its definition, expected #{:08x}", tl_id, computed)
+        }
+
+        MissingTlId(computed: u32) {
+            description("constructor id is missing")
+            display("constructor id is missing, expected #{:08x}", computed)
+        }
+
+        DuplicateTlId(tl_id: u32, other: String) {
+            description("duplicate constructor id")
+            display("constructor id #{:08x} is already used by `{}`", tl_id, other)
+        }
+
+        DuplicateName(name: String) {
+            description("duplicate constructor name")
+            display("constructor `{}` is already defined", name)
+        }
+
+        UndefinedType(name: String) {
+            description("undefined type")
+            display("undefined type `{}`", name)
+        }
+
+        UndeclaredFlags(flags: String, bit: u32) {
+            description("flags field is not declared")
+            display("`{}.{}?` refers to a field which isn't declared before as `{}:#`", flags, bit, flags)
+        }
+
+        InvalidGeneratedCode(description: String, code: String) {
+            description("generated code doesn't parse")
+            display("generated code doesn't parse: {}\n{}", description, code)
+        }
+
+        InvalidJson(description: String) {
+            description("invalid JSON")
+            display("invalid JSON: {}", description)
+        }
+
+        UnexpectedJson(description: String) {
+            description("unexpected JSON value")
+            display("unexpected JSON value: {}", description)
+        }
+
+        Diagnostics(diagnostics: Vec<Diagnostic>) {
+            description("errors in TL schema")
+            display("{} error(s) in TL schema:\n{}",
+                diagnostics.len(),
+                diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))
+        }
+    }
+}
+
+
+/// Position of an item in a TL schema file.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    /// `None` if the schema didn't come from a file.
+    pub file: Option<String>,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column number in characters.
+    pub column: usize,
+}
+
+impl Location {
+    /// Compute line and column of a byte `offset` in `input`.
+    pub fn from_offset(file: Option<&str>, input: &str, offset: usize) -> Location {
+        let before = &input[..offset];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+        Location {
+            file: file.map(ToOwned::to_owned),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.as_ref().map(String::as_str).unwrap_or("<input>"), self.line, self.column)
+    }
+}
+
+
+/// A human-readable error message attached to a place in a TL schema.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub location: Option<Location>,
+    /// Full name of the offending constructor, e.g. `auth.sentCode`.
+    pub constructor: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(location: Option<Location>, message: String) -> Diagnostic {
+        Diagnostic {
+            location: location,
+            constructor: None,
+            message: message,
+        }
+    }
+
+    /// Report `error` which occurred while processing `constructor`.
+    pub fn for_constructor(constructor: &Constructor, error: &Error) -> Diagnostic {
+        Diagnostic {
+            location: constructor.location().cloned(),
+            constructor: constructor.variant().names_vec().map(|names| names.join(".")),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref location) = self.location {
+            write!(f, "{}: ", location)?;
+        }
+
+        write!(f, "{}", self.message)?;
+
+        if let Some(ref constructor) = self.constructor {
+            write!(f, " (in `{}`)", constructor)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Turn errors into diagnostics and merge those which already are.
+pub(crate) fn collect_diagnostics(diagnostics: &mut Vec<Diagnostic>, error: Error, constructor: Option<&Constructor>) {
+    match error {
+        Error(ErrorKind::Diagnostics(more), _) => diagnostics.extend(more),
+        error => diagnostics.push(match constructor {
+            Some(constructor) => Diagnostic::for_constructor(constructor, &error),
+            None => Diagnostic::new(None, error.to_string()),
+        }),
+    }
+}
+
+/// Fail with all `diagnostics` if there are any.
+pub(crate) fn check_diagnostics(diagnostics: Vec<Diagnostic>) -> Result<()> {
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        bail!(ErrorKind::Diagnostics(diagnostics))
     }
 }
@@ -6,22 +6,50 @@ use syn;
 use analyzer::{ConstructorInputData, PathGlobalSegmentsTyParams, TypeckKind,
                build_transform_dag, analyze_dag};
 use ast::{Constructor, Delimiter, Item, Type, TypeFixupMap, TypeIrKind,
-          no_conflict_ident, wrap_option_type, wrap_option_value};
-use error;
+          no_conflict_ident, parse_generated, syn_generics_from_names, wrap_option_type,
+          wrap_option_value};
+use error::{self, Diagnostic, ErrorKind};
 use parser;
 
 
-pub fn generate_code_for(input: &str) -> quote::Tokens {
-    let krate = generate_ast_for(input);
+pub fn generate_code_for(input: &str) -> error::Result<quote::Tokens> {
+    generate_code_for_items(parser::parse_string(input)?)
+}
+
+pub fn generate_code_for_items(items: Vec<Item>) -> error::Result<quote::Tokens> {
+    let krate = generate_ast_for_items(items)?;
+
+    Ok(quote! { #krate })
+}
 
-    quote! { #krate }
+pub fn generate_ast_for(input: &str) -> error::Result<syn::Crate> {
+    generate_ast_for_items(parser::parse_string(input)?)
+}
+
+/// Generate code for parsed items, e.g. from `parser::parse_files`.
+///
+/// Errors are collected for all constructors and returned together as
+/// `ErrorKind::Diagnostics`.
+pub fn generate_ast_for_items(items: Vec<Item>) -> error::Result<syn::Crate> {
+    let mut diagnostics = vec![];
+
+    match generate_krate(items, &mut diagnostics) {
+        Ok(krate) => {
+            error::check_diagnostics(diagnostics)?;
+            Ok(krate)
+        },
+        Err(e) => {
+            error::collect_diagnostics(&mut diagnostics, e, None);
+            bail!(ErrorKind::Diagnostics(diagnostics));
+        },
+    }
 }
 
-pub fn generate_ast_for(input: &str) -> syn::Crate {
+/// Errors which don't stop the generation are pushed to `diagnostics`.
+fn generate_krate(mut items: Vec<Item>, diagnostics: &mut Vec<Diagnostic>) -> error::Result<syn::Crate> {
     let mut constructors = {
-        let mut items = parser::parse_string(input).unwrap();
         filter_items(&mut items);
-        partition_by_delimiter_and_namespace(items)
+        partition_by_delimiter_and_namespace(items, diagnostics)
     };
 
     let layer = constructors.layer as i32;
@@ -36,12 +64,12 @@ pub fn generate_ast_for(input: &str) -> syn::Crate {
         ),
         is_sugared_doc: true,
     };
-    let mut krate = syn::parse_crate(quote! {
+    let mut krate = parse_generated(syn::parse_crate, quote! {
         #module_level_docs
         #![allow(non_camel_case_types)]
 
         pub const LAYER: i32 = #layer;
-    }.as_str()).unwrap();
+    }.as_str())?;
 
     let variants_to_outputs: TypeFixupMap = constructors.types.iter()
         .flat_map(|(namespaces, constructor_map)| {
@@ -61,7 +89,7 @@ pub fn generate_ast_for(input: &str) -> syn::Crate {
         })
         .collect();
 
-    fn process_namespaces<I>(items_buf: &mut Vec<syn::Item>, namespaces: &[String], items: I)
+    fn process_namespaces<I>(items_buf: &mut Vec<syn::Item>, namespaces: &[String], items: I) -> error::Result<()>
         where I: IntoIterator<Item=syn::Item>
     {
         if namespaces.is_empty() {
@@ -70,23 +98,25 @@ pub fn generate_ast_for(input: &str) -> syn::Crate {
             let mut namespaces_rev_iter = namespaces.iter().cloned().rev();
 
             let namespace = syn::Ident::new(namespaces_rev_iter.next().unwrap()); // safe to unwrap
-            let mut syn_mod = syn::parse_item(quote! {
+            let mut syn_mod = parse_generated(syn::parse_item, quote! {
                 pub mod #namespace {
                     #(#items)*
                 }
-            }.as_str()).unwrap();
+            }.as_str())?;
 
             for namespace in namespaces_rev_iter {
                 let namespace = syn::Ident::new(namespace);
-                syn_mod = syn::parse_item(quote! {
+                syn_mod = parse_generated(syn::parse_item, quote! {
                     pub mod #namespace {
                         #syn_mod
                     }
-                }.as_str()).unwrap();
+                }.as_str())?;
             }
 
             items_buf.push(syn_mod);
         }
+
+        Ok(())
     };
 
     for (_namespaces, constructor_map) in &mut constructors.types {
@@ -102,17 +132,25 @@ pub fn generate_ast_for(input: &str) -> syn::Crate {
         }
     }
 
-    let ctors_typeck_info = constructors.ctors_typeck_info();
+    let ctors_typeck_info = constructors.ctors_typeck_info()?;
 
     let mut dynamic_ctors: Vec<(Vec<String>, u32, syn::Stmt)> = vec![];
     for (namespaces, constructor_map) in &constructors.types {
-        dynamic_ctors.extend(constructor_map.values()
-            .flat_map(|c| c.to_syn_dynamic_ctors(&ctors_typeck_info).unwrap())); // FIXME
+        let mut substructs = vec![];
+
+        for c in constructor_map.values() {
+            match c.to_syn_dynamic_ctors(&ctors_typeck_info) {
+                Ok(stmts) => dynamic_ctors.extend(stmts),
+                Err(e) => error::collect_diagnostics(diagnostics, e, None),
+            }
 
-        let substructs = constructor_map.values()
-            .flat_map(|c| c.to_syn_data_type_items(&ctors_typeck_info).unwrap()); // FIXME
+            match c.to_syn_data_type_items(&ctors_typeck_info) {
+                Ok(items) => substructs.extend(items),
+                Err(e) => error::collect_diagnostics(diagnostics, e, None),
+            }
+        }
 
-        process_namespaces(&mut krate.items, namespaces, substructs);
+        process_namespaces(&mut krate.items, namespaces, substructs)?;
     }
 
     dynamic_ctors.sort_by(|&(ref names1, tl_id1, ref _stmt1), &(ref names2, tl_id2, ref _stmt2)| {
@@ -120,31 +158,35 @@ pub fn generate_ast_for(input: &str) -> syn::Crate {
     });
 
     let stmts: Vec<syn::Stmt> = dynamic_ctors.into_iter().map(|(_, _, stmt)| stmt).collect();
-    let register_ctors = syn::parse_item(quote! {
+    let register_ctors = parse_generated(syn::parse_item, quote! {
         /// Registers all generated deserializable constructors to the provided constructors map
         pub fn register_ctors(cstore: &mut ::tl::dynamic::TLConstructorsMap) {
             #(#stmts)*
         }
-    }.as_str()).unwrap();
+    }.as_str())?;
     krate.items.push(register_ctors);
 
     let mut rpc_items = vec![];
     for (namespaces, substructs) in &constructors.functions {
-        let substructs = substructs.into_iter()
-            .flat_map(|c| {
-                c.to_syn_function_struct(&ctors_typeck_info).unwrap() // FIXME
-            });
+        let mut function_items = vec![];
+
+        for c in substructs {
+            match c.to_syn_function_struct(&ctors_typeck_info) {
+                Ok(items) => function_items.extend(items),
+                Err(e) => error::collect_diagnostics(diagnostics, e, Some(c)),
+            }
+        }
 
-        process_namespaces(&mut rpc_items, namespaces, substructs);
+        process_namespaces(&mut rpc_items, namespaces, function_items)?;
     }
 
-    krate.items.push(syn::parse_item(quote! {
+    krate.items.push(parse_generated(syn::parse_item, quote! {
         pub mod rpc {
             #(#rpc_items)*
         }
-    }.as_str()).unwrap());
+    }.as_str())?);
 
-    krate
+    Ok(krate)
 }
 
 fn filter_items(items: &mut Vec<Item>) {
@@ -154,7 +196,10 @@ fn filter_items(items: &mut Vec<Item>) {
             _ => return true,
         };
 
-        // Blacklist some annoying inconsistencies.
+        // Blacklist some annoying inconsistencies. `vector` lowers to
+        // `struct Vector<t> { items: Vec<t> }` just fine, but `Vector t`
+        // is mapped to `serde_mtproto::Boxed<Vec<t>>` everywhere, so
+        // the generated type would never be used.
         match c.variant.name() {
             Some("true") |
             Some("vector") => false,
@@ -163,7 +208,7 @@ fn filter_items(items: &mut Vec<Item>) {
     });
 }
 
-fn partition_by_delimiter_and_namespace(items: Vec<Item>) -> AllConstructors {
+pub(crate) fn partition_by_delimiter_and_namespace(items: Vec<Item>, diagnostics: &mut Vec<Diagnostic>) -> AllConstructors {
     let mut current = Delimiter::Types;
     let mut result = AllConstructors {
         types: BTreeMap::new(),
@@ -175,16 +220,29 @@ fn partition_by_delimiter_and_namespace(items: Vec<Item>) -> AllConstructors {
         match item {
             Item::Delimiter(d) => current = d,
             Item::Constructor(c) => {
+                let named = match current {
+                    Delimiter::Types => &c.output,
+                    Delimiter::Functions => &c.variant,
+                };
+                let (namespace, name) = match (named.namespace(), named.name()) {
+                    (Some(namespace), Some(name)) => (namespace.to_vec(), name.to_owned()),
+                    _ => {
+                        let error = ErrorKind::UnnamedType(named.clone()).into();
+                        diagnostics.push(Diagnostic::for_constructor(&c, &error));
+                        continue;
+                    },
+                };
+
                 match current {
                     Delimiter::Types => {
-                        result.types.entry(c.output.namespace().unwrap().to_vec()) // FIXME
+                        result.types.entry(namespace)
                             .or_insert_with(Default::default)
-                            .entry(c.output.name().map(Into::into).unwrap()) // FIXME
+                            .entry(name)
                             .or_insert_with(Default::default)
                             .0.push(c);
                     },
                     Delimiter::Functions => {
-                        result.functions.entry(c.variant.namespace().unwrap().to_vec()) // FIXME
+                        result.functions.entry(namespace)
                             .or_insert_with(Default::default)
                             .push(c);
                     },
@@ -199,7 +257,7 @@ fn partition_by_delimiter_and_namespace(items: Vec<Item>) -> AllConstructors {
 
 
 #[derive(Debug, Default)]
-struct Constructors(Vec<Constructor>);
+pub(crate) struct Constructors(pub(crate) Vec<Constructor>);
 
 impl Constructors {
     fn fixup(&mut self, delim: Delimiter, fixup_map: &TypeFixupMap) {
@@ -208,21 +266,30 @@ impl Constructors {
         }
     }
 
+    /// Errors are reported as `ErrorKind::Diagnostics` pointing to the
+    /// offending constructor.
     fn to_syn_data_type_items<'a>(&self, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>) -> error::Result<Vec<syn::Item>> {
-        if self.0.len() == 1 {
-            return self.0[0].to_syn_single_type_struct(ctors_typeck_info).map(|s| vec![s]);
-        }
-
-        assert!(self.0.len() >= 2); // FIXME: return errors instead of assert
+        let first = match self.0.len() {
+            0 => return Ok(vec![]),
+            1 => {
+                let ctor = &self.0[0];
+                return ctor.to_syn_single_type_struct(ctors_typeck_info)
+                    .map_err(|e| in_constructor(ctor, e));
+            },
+            _ => &self.0[0],
+        };
 
-        let name = self.0[0].output.name().map(no_conflict_ident).unwrap(); // FIXME
-        let variants: Vec<syn::Variant> = self.0.iter().map(Constructor::to_syn_variant).collect();
-        let methods = self.determine_methods(&name)?;
+        let name = first.output.name().map(no_conflict_ident)
+            .ok_or_else(|| in_constructor(first, ErrorKind::UnnamedType(first.output.clone()).into()))?;
+        let variants = self.0.iter()
+            .map(|ctor| ctor.to_syn_variant().map_err(|e| in_constructor(ctor, e)))
+            .collect::<error::Result<Vec<syn::Variant>>>()?;
+        let methods = self.determine_methods(&name).map_err(|e| in_constructor(first, e))?;
         let structs = self.0.iter()
-            .map(|ctor| ctor.to_syn_variant_type_struct(&ctors_typeck_info))
+            .map(|ctor| ctor.to_syn_variant_type_struct(&ctors_typeck_info).map_err(|e| in_constructor(ctor, e)))
             .collect::<error::Result<Vec<_>>>()?
             .into_iter()
-            .filter_map(|maybe_struct| maybe_struct);
+            .flat_map(|items| items);
 
         let is_static_typeck_kind = self.0.iter()
             .map(|ctor| ctors_typeck_info[ctor])
@@ -233,12 +300,13 @@ impl Constructors {
         derives.push("MtProtoIdentifiable");
 
         let derives = derives.into_iter().map(syn::Ident::new);
-        let syn_enum = syn::parse_item(quote! {
+        let syn_generics = self.syn_generics();
+        let syn_enum = parse_generated(syn::parse_item, quote! {
             #[derive( #(#derives),* )]
-            pub enum #name {
+            pub enum #name #syn_generics {
                 #(#variants,)*
             }
-        }.as_str()).unwrap();
+        }.as_str())?;
 
         let syn_data_type_items = {
             // enum & impl & structs; structs.len() == self.0.len()
@@ -254,6 +322,20 @@ impl Constructors {
         Ok(syn_data_type_items)
     }
 
+    /// Type parameters used by any of the constructors.
+    fn syn_generics(&self) -> syn::Generics {
+        let mut names: Vec<&str> = vec![];
+        for ctor in &self.0 {
+            for name in ctor.used_type_parameters() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+
+        syn_generics_from_names(&names)
+    }
+
     fn determine_methods(&self, enum_name: &syn::Ident) -> error::Result<Option<syn::Item>> {
         let all_constructors_count = self.0.len();
         let mut methods = vec![];
@@ -263,10 +345,8 @@ impl Constructors {
                 continue;
             }
 
-            // FIXME: handle case when typemap.len() == 0
-            let (output_type, constructors) = typemap.into_iter().next().unwrap();
+            let (output_type, constructors) = typemap.into_iter().next().unwrap(); // safe to unwrap
             if constructors.len() <= 1 {
-                //panic!("{:#?}", constructors);
                 continue;
             }
 
@@ -301,7 +381,7 @@ impl Constructors {
             let ty = wrap_option_type(force_option, type_ir.ref_type());
             let mut constructors_match_arms: Vec<syn::Arm> = constructors.into_iter()
                 .map(|c| {
-                    syn::Arm {
+                    Ok(syn::Arm {
                         attrs: vec![],
                         pats: vec![
                             syn::Pat::TupleStruct(
@@ -309,7 +389,7 @@ impl Constructors {
                                     global: false,
                                     segments: vec![
                                         enum_name.clone().into(),
-                                        c.variant_name().into(),
+                                        c.variant_name()?.into(),
                                     ],
                                 },
                                 vec![
@@ -324,9 +404,9 @@ impl Constructors {
                         ],
                         guard: None,
                         body: Box::new(value.clone()),
-                    }
+                    })
                 })
-                .collect();
+                .collect::<error::Result<_>>()?;
 
             if !exhaustive {
                 let arm_ignore = syn::Arm {
@@ -384,11 +464,13 @@ impl Constructors {
         let maybe_item = if methods.is_empty() {
             None
         } else {
-            let item = syn::parse_item(quote! {
-                impl #enum_name {
+            let syn_generics = self.syn_generics();
+            let (impl_generics, ty_generics, where_clause) = syn_generics.split_for_impl();
+            let item = parse_generated(syn::parse_item, quote! {
+                impl #impl_generics #enum_name #ty_generics #where_clause {
                     #(#methods)*
                 }
-            }.as_str()).unwrap();
+            }.as_str())?;
 
             Some(item)
         };
@@ -420,50 +502,64 @@ impl Constructors {
     fn to_syn_dynamic_ctors<'a>(&self, ctors_typeck_info: &BTreeMap<&'a Constructor, TypeckKind>)
         -> error::Result<Vec<(Vec<String>, u32, syn::Stmt)>>
     {
-        let syn_output_ty = self.0[0].output.to_type_ir()?.unboxed();
-        let ty_name = self.0[0].output.names_vec().unwrap();  // FIXME
+        let first = match self.0.first() {
+            Some(first) => first,
+            None => return Ok(vec![]),
+        };
+
+        let syn_output_ty = first.output.to_type_ir().map_err(|e| in_constructor(first, e))?.unboxed();
+        let ty_name = first.output.names_vec()
+            .ok_or_else(|| in_constructor(first, ErrorKind::UnnamedType(first.output.clone()).into()))?;
 
         let dynamic_ctors = self.0.iter().filter_map(|c| {
-            if ctors_typeck_info[c] == TypeckKind::Dynamic {
+            // Generic types can't be registered without knowing their
+            // type arguments
+            if ctors_typeck_info[c] == TypeckKind::Dynamic || !c.type_parameters.is_empty() {
                 return None;
             }
 
             c.tl_id.map(|tl_id| {
-                let syn_add = syn::Stmt::Semi(
-                    Box::new(syn::parse_expr(quote! {
-                        cstore.add::<#syn_output_ty>(#tl_id)
-                    }.as_str()).unwrap())
-                );
+                let syn_add_expr = parse_generated(syn::parse_expr, quote! {
+                    cstore.add::<#syn_output_ty>(#tl_id)
+                }.as_str()).map_err(|e| in_constructor(c, e))?;
 
-                (ty_name.clone(), tl_id, syn_add)
+                Ok((ty_name.clone(), tl_id, syn::Stmt::Semi(Box::new(syn_add_expr))))
             })
-        }).collect();
+        }).collect::<error::Result<_>>()?;
 
         Ok(dynamic_ctors)
     }
 }
 
 
+fn in_constructor(ctor: &Constructor, error: error::Error) -> error::Error {
+    let mut diagnostics = vec![];
+    error::collect_diagnostics(&mut diagnostics, error, Some(ctor));
+
+    ErrorKind::Diagnostics(diagnostics).into()
+}
+
+
 #[derive(Debug)]
-struct AllConstructors {
-    types: BTreeMap<Vec<String>, BTreeMap<String, Constructors>>,
-    functions: BTreeMap<Vec<String>, Vec<Constructor>>,
-    layer: u32,
+pub(crate) struct AllConstructors {
+    pub(crate) types: BTreeMap<Vec<String>, BTreeMap<String, Constructors>>,
+    pub(crate) functions: BTreeMap<Vec<String>, Vec<Constructor>>,
+    pub(crate) layer: u32,
 }
 
 impl AllConstructors {
-    fn ctors_typeck_info<'a>(&'a self) -> BTreeMap<&'a Constructor, TypeckKind> {
-        let all_ctors_input_data = self.prepare_input_data_for_dag();
+    fn ctors_typeck_info<'a>(&'a self) -> error::Result<BTreeMap<&'a Constructor, TypeckKind>> {
+        let all_ctors_input_data = self.prepare_input_data_for_dag()?;
         let dag = build_transform_dag(all_ctors_input_data);
         let ctors_typeck_kinds = analyze_dag(dag);
 
-        ctors_typeck_kinds
+        Ok(ctors_typeck_kinds)
     }
 
-    fn prepare_input_data_for_dag<'a>(&'a self) -> Vec<ConstructorInputData<'a>> {
-        fn get_unrolled_syn_ty(namespaces: &[String], ty: &Type) -> PathGlobalSegmentsTyParams {
+    fn prepare_input_data_for_dag<'a>(&'a self) -> error::Result<Vec<ConstructorInputData<'a>>> {
+        fn get_unrolled_syn_ty(namespaces: &[String], ty: &Type) -> error::Result<PathGlobalSegmentsTyParams> {
             fn split_path_to_global_segments(namespaces: &[String], path: syn::Path)
-                -> PathGlobalSegmentsTyParams
+                -> error::Result<PathGlobalSegmentsTyParams>
             {
                 let mut split = vec![vec![]];
 
@@ -476,78 +572,93 @@ impl AllConstructors {
 
                     match seg.parameters {
                         syn::PathParameters::AngleBracketed(data) => {
-                            assert!(data.lifetimes.is_empty());
-                            assert!(data.bindings.is_empty());
-
-                            split.extend(data.types.into_iter().flat_map(|ty| {
-                                match ty {
-                                    syn::Ty::Path(None, path) => split_path_to_global_segments(namespaces, path),
-                                    _ => unreachable!(),
-                                }
-                            }));
+                            if !data.lifetimes.is_empty() || !data.bindings.is_empty() {
+                                bail!(ErrorKind::UnsupportedType("lifetimes or associated type bindings".to_owned()));
+                            }
+
+                            for ty in data.types {
+                                split.extend(split_ty_to_global_segments(namespaces, ty)?);
+                            }
+                        },
+                        syn::PathParameters::Parenthesized(_) => {
+                            bail!(ErrorKind::UnsupportedType("parenthesized type parameters".to_owned()));
                         },
-                        syn::PathParameters::Parenthesized(_) => unreachable!(),
                     }
                 }
 
-                split
+                Ok(split)
             };
 
-            match ty.to_type_ir().unwrap().unboxed() {  // FIXME
-                syn::Ty::Path(None, path) => split_path_to_global_segments(namespaces, path),
-                syn::Ty::Tup(tys) => {
-                    tys.into_iter().flat_map(|syn_ty| {
-                        match syn_ty {
-                            syn::Ty::Path(None, path) => split_path_to_global_segments(namespaces, path),
-                            ty => unreachable!("`syn::Ty` encountered: {:#?}", ty),
+            fn split_ty_to_global_segments(namespaces: &[String], ty: syn::Ty)
+                -> error::Result<PathGlobalSegmentsTyParams>
+            {
+                match ty {
+                    syn::Ty::Path(None, path) => split_path_to_global_segments(namespaces, path),
+                    // Tuples come from `int256` and repeated fields
+                    syn::Ty::Tup(tys) => {
+                        let mut split = vec![];
+                        for syn_ty in tys {
+                            split.extend(split_ty_to_global_segments(namespaces, syn_ty)?);
                         }
-                    }).collect()
-                },
-                ty => unreachable!("`syn::Ty` encountered: {:#?}", ty),
+
+                        Ok(split)
+                    },
+                    ty => bail!(ErrorKind::UnsupportedType(format!("{}", quote! { #ty }))),
+                }
             }
+
+            split_ty_to_global_segments(namespaces, ty.to_type_ir()?.unboxed())
         }
 
         fn get_input_data<'a>(namespaces: &[String], ctor: &'a Constructor)
-            -> ConstructorInputData<'a>
+            -> error::Result<ConstructorInputData<'a>>
         {
-            let unrolled_output_syn_ty = get_unrolled_syn_ty(namespaces, &ctor.output);
-            let kind = match ctor.output.to_type_ir().unwrap().kind { // FIXME
+            let unrolled_output_syn_ty = get_unrolled_syn_ty(namespaces, &ctor.output)?;
+            let kind = match ctor.output.to_type_ir()?.kind {
                 TypeIrKind::Dynamic => TypeckKind::Dynamic,
                 _ => {
-                    let is_dynamic = ctor.fields.iter()
-                        .any(|f| f.ty.to_type_ir().unwrap().kind == TypeIrKind::Dynamic); // FIXME
+                    let mut is_dynamic = false;
+                    for f in &ctor.fields {
+                        is_dynamic |= f.ty.to_type_ir()?.kind == TypeIrKind::Dynamic;
+                    }
 
                     if is_dynamic { TypeckKind::Dynamic } else { TypeckKind::Static }
                 },
             };
-            let deps = ctor.fields.iter().map(|f| get_unrolled_syn_ty(namespaces, &f.ty)).collect();
+            let deps = ctor.fields.iter()
+                .map(|f| get_unrolled_syn_ty(namespaces, &f.ty))
+                .collect::<error::Result<_>>()?;
 
-            ConstructorInputData {
+            Ok(ConstructorInputData {
                 ty: unrolled_output_syn_ty,
                 kind: kind,
                 deps: deps,
                 ctor: ctor,
-            }
+            })
         }
 
         let mut all_ctors_input_data = vec![];
+        let mut diagnostics = vec![];
 
-        for (namespaces, ty_data) in &self.types {
-            for (_ty_name, ty_ctors) in ty_data {
-                for ty_ctor in &ty_ctors.0 {
-                    let input_data = get_input_data(&namespaces, ty_ctor);
-                    all_ctors_input_data.push(input_data);
+        {
+            let all_ctors = self.types.iter()
+                .flat_map(|(namespaces, ty_data)| {
+                    ty_data.values().flat_map(move |ty_ctors| ty_ctors.0.iter().map(move |c| (namespaces, c)))
+                })
+                .chain(self.functions.iter().flat_map(|(namespaces, fn_ctors)| {
+                    fn_ctors.iter().map(move |c| (namespaces, c))
+                }));
+
+            for (namespaces, ctor) in all_ctors {
+                match get_input_data(namespaces, ctor) {
+                    Ok(input_data) => all_ctors_input_data.push(input_data),
+                    Err(e) => error::collect_diagnostics(&mut diagnostics, e, Some(ctor)),
                 }
             }
         }
 
-        for (namespaces, fn_ctors) in &self.functions {
-            for fn_ctor in fn_ctors {
-                let input_data = get_input_data(&namespaces, fn_ctor);
-                all_ctors_input_data.push(input_data);
-            }
-        }
+        error::check_diagnostics(diagnostics)?;
 
-        all_ctors_input_data
+        Ok(all_ctors_input_data)
     }
 }
@@ -0,0 +1,159 @@
+//! Minimal JSON reader for schema and documentation files.
+
+use std::char::{self, REPLACEMENT_CHARACTER};
+use std::fmt;
+
+use pom::{DataInput, Parser};
+use pom::char_class::hex_digit;
+use pom::parser::*;
+
+use error::{self, ErrorKind};
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in the order they appear in the input.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref members) => members.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self {
+            Value::Array(ref values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Value)]> {
+        match *self {
+            Value::Object(ref members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// Compact JSON text of the value.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(ref s) => write_string(f, s),
+            Value::Array(ref values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Value::Object(ref members) => {
+                write!(f, "{{")?;
+                for (i, &(ref key, ref value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+
+fn space() -> Parser<u8, ()> {
+    one_of(b" \t\r\n").repeat(0..).discard()
+}
+
+fn number() -> Parser<u8, f64> {
+    let integer = one_of(b"123456789") - one_of(b"0123456789").repeat(0..) | sym(b'0');
+    let frac = sym(b'.') + one_of(b"0123456789").repeat(1..);
+    let exp = one_of(b"eE") + one_of(b"+-").opt() + one_of(b"0123456789").repeat(1..);
+    let number = sym(b'-').opt() + integer + frac.opt() + exp.opt();
+    number.collect().convert(String::from_utf8).convert(|s| s.parse::<f64>())
+}
+
+fn string() -> Parser<u8, String> {
+    let special_char = sym(b'\\') | sym(b'/') | sym(b'"')
+        | sym(b'b').map(|_| b'\x08') | sym(b'f').map(|_| b'\x0C')
+        | sym(b'n').map(|_| b'\n') | sym(b'r').map(|_| b'\r') | sym(b't').map(|_| b'\t');
+    let escape_sequence = sym(b'\\') * special_char;
+    let char_string = (none_of(b"\\\"") | escape_sequence).repeat(1..).convert(String::from_utf8);
+    let utf16_char = seq(b"\\u") * is_a(hex_digit).repeat(4)
+        .convert(String::from_utf8)
+        .convert(|digits| u16::from_str_radix(&digits, 16));
+    let utf16_string = utf16_char.repeat(1..).map(|chars| {
+        char::decode_utf16(chars).map(|r| r.unwrap_or(REPLACEMENT_CHARACTER)).collect::<String>()
+    });
+
+    (sym(b'"') * (char_string | utf16_string).repeat(0..) - sym(b'"')).map(|strings| strings.concat())
+}
+
+fn array() -> Parser<u8, Vec<Value>> {
+    let elems = list(call(value), sym(b',') * space());
+    sym(b'[') * space() * elems - sym(b']')
+}
+
+fn object() -> Parser<u8, Vec<(String, Value)>> {
+    let member = string() - space() - sym(b':') - space() + call(value);
+    let members = list(member, sym(b',') * space());
+    sym(b'{') * space() * members - sym(b'}')
+}
+
+fn value() -> Parser<u8, Value> {
+    ( seq(b"null").map(|_| Value::Null) |
+      seq(b"true").map(|_| Value::Bool(true)) |
+      seq(b"false").map(|_| Value::Bool(false)) |
+      number().map(Value::Number) |
+      string().map(Value::String) |
+      array().map(Value::Array) |
+      object().map(Value::Object)
+    ) - space()
+}
+
+pub fn parse(input: &str) -> error::Result<Value> {
+    let mut data = DataInput::new(input.as_bytes());
+
+    (space() * value() - end()).parse(&mut data)
+        .map_err(|e| ErrorKind::InvalidJson(e.to_string()).into())
+}
@@ -1,3 +1,4 @@
+extern crate crc;
 #[macro_use]
 extern crate error_chain;
 extern crate petgraph;
@@ -10,11 +11,22 @@ extern crate synom;
 
 
 mod analyzer;
-mod ast;
-mod error;
+pub mod ast;
+pub mod diff;
+pub mod docs;
+pub mod error;
 mod generator;
-mod parser;
+mod json;
+pub mod parser;
+pub mod printer;
+pub mod validator;
 
 
+pub use diff::{diff_items, diff_schemas};
+pub use docs::apply_doc_file;
 pub use generator::generate_ast_for;
 pub use generator::generate_code_for;
+pub use generator::{generate_ast_for_items, generate_code_for_items};
+pub use error::{Diagnostic, Error, ErrorKind, Location, Result};
+pub use printer::print_items;
+pub use validator::{MissingIds, validate_items};
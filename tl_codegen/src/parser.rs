@@ -1,16 +1,21 @@
-use pom::{self, Parser};
+use std::cmp;
+
+use pom::{self, Input, Parser};
 use pom::char_class::{alphanum, digit, hex_digit};
 use pom::parser::*;
 
-use ast::{Constructor, Delimiter, Field, Item, Type};
+use ast::{Constructor, Delimiter, Documentation, Field, Item, Type};
+use error::{self, Diagnostic, ErrorKind, Location};
+use json::{self, Value};
 
 
-fn utf8(v: Vec<u8>) -> String {
-    String::from_utf8(v).unwrap()
+/// Failures are reported as syntax errors by `convert`.
+fn utf8(v: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(v).map_err(|e| e.to_string())
 }
 
 fn ident() -> Parser<u8, String> {
-    (is_a(alphanum) | sym(b'_')).repeat(1..).map(utf8)
+    (is_a(alphanum) | sym(b'_')).repeat(1..).convert(utf8)
 }
 
 fn dotted_ident() -> Parser<u8, Vec<String>> {
@@ -22,11 +27,11 @@ fn dotted_ident() -> Parser<u8, Vec<String>> {
 }
 
 fn tl_id() -> Parser<u8, u32> {
-    sym(b'#') * is_a(hex_digit).repeat(0..9).convert(|s| u32::from_str_radix(&utf8(s), 16))
+    sym(b'#') * is_a(hex_digit).repeat(0..9).convert(|s| utf8(s).and_then(|s| u32::from_str_radix(&s, 16).map_err(|e| e.to_string())))
 }
 
 fn decimal() -> Parser<u8, u32> {
-    is_a(digit).repeat(0..).convert(|s| utf8(s).parse())
+    is_a(digit).repeat(0..).convert(|s| utf8(s).and_then(|s| s.parse().map_err(|e: ::std::num::ParseIntError| e.to_string())))
 }
 
 fn ty_flag() -> Parser<u8, Type> {
@@ -35,8 +40,8 @@ fn ty_flag() -> Parser<u8, Type> {
 }
 
 fn ty_generic() -> Parser<u8, Type> {
-    (dotted_ident() - sym(b'<') + call(ty) - sym(b'>'))
-        .map(|(name, ty)| Type::Generic(name, Box::new(ty)))
+    (dotted_ident() - sym(b'<') + list(call(ty), sym(b',')) - sym(b'>'))
+        .map(|(name, tys)| Type::Generic(name, tys))
 }
 
 fn ty() -> Parser<u8, Type> {
@@ -49,8 +54,8 @@ fn ty() -> Parser<u8, Type> {
 }
 
 fn ty_space_generic() -> Parser<u8, Type> {
-    let space_generic = dotted_ident() - sym(b' ') + ty();
-    (space_generic.map(|(name, ty)| Type::Generic(name, Box::new(ty))) |
+    let space_generic = dotted_ident() + (sym(b' ') * ty()).repeat(1..);
+    (space_generic.map(|(name, tys)| Type::Generic(name, tys)) |
      ty())
 }
 
@@ -93,6 +98,8 @@ fn constructor() -> Parser<u8, Constructor> {
             type_parameters: type_parameters,
             fields: fields,
             output: output,
+            location: None,
+            docs: Documentation::default(),
         })
         .name("constructor")
 }
@@ -116,18 +123,286 @@ fn space() -> Parser<u8, ()> {
 }
 
 fn item() -> Parser<u8, Item> {
-    ( delimiter().map(Item::Delimiter) |
-      constructor().map(Item::Constructor) |
-      layer().map(Item::Layer)
-    ) - space()
+    delimiter().map(Item::Delimiter) |
+        constructor().map(Item::Constructor) |
+        layer().map(Item::Layer)
 }
 
-fn lines() -> Parser<u8, Vec<Item>> {
-    space() * item().repeat(0..) - end()
+/// Documentation from the comment lines directly preceding a constructor:
+///
+/// ```text
+/// // @description Object defines a user. @id User identifier
+/// // @access_hash Access hash of the user
+/// user#2e13f4c3 flags:# id:int access_hash:flags.0?long ... = User;
+/// ```
+///
+/// TDLib-style `@param_<field>` keys and `//-` continuation lines are
+/// accepted as well.
+fn documentation(preceding: &str) -> Documentation {
+    let mut lines: Vec<&str> = preceding.lines().rev()
+        .map(str::trim)
+        .take_while(|line| line.starts_with("//"))
+        .collect();
+    lines.reverse();
+
+    let text = lines.iter()
+        .map(|line| line.find(|c| c != '/' && c != '-').map_or("", |i| line[i..].trim()))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut docs = Documentation::default();
+    let mut annotations = vec![];
+    for word in text.split_whitespace() {
+        if word.len() > 1 && word.starts_with('@') {
+            annotations.push((&word[1..], vec![]));
+        } else if let Some(&mut (_, ref mut words)) = annotations.last_mut() {
+            words.push(word);
+        }
+    }
+
+    for (key, words) in annotations {
+        if words.is_empty() {
+            continue;
+        }
+
+        let value = words.join(" ");
+        match key {
+            "description" => docs.description = Some(value),
+            "class" => (),
+            key if key.starts_with("param_") => { docs.fields.insert(key["param_".len()..].to_owned(), value); },
+            key => { docs.fields.insert(key.to_owned(), value); },
+        }
+    }
+
+    docs
+}
+
+/// Parse a schema which didn't come from a file.
+pub fn parse_string(input: &str) -> error::Result<Vec<Item>> {
+    parse_file(None, input)
+}
+
+/// Parse a schema read from `file`, which is used in diagnostics.
+///
+/// Parsing continues on the next line after a syntax error, so that all
+/// errors are reported at once.
+pub fn parse_file(file: Option<&str>, input: &str) -> error::Result<Vec<Item>> {
+    let bytes = input.as_bytes();
+    let mut data = pom::DataInput::new(bytes);
+    let mut items = vec![];
+    let mut diagnostics = vec![];
+
+    // Comments before the next item, which may document it
+    let mut comments_start = 0;
+    // Never fails
+    let _ = space().parse(&mut data);
+
+    while data.position() < bytes.len() {
+        let start = data.position();
+
+        match item().parse(&mut data) {
+            Ok(Item::Constructor(mut constructor)) => {
+                constructor.location = Some(Location::from_offset(file, input, start));
+                constructor.docs = documentation(&input[comments_start..start]);
+                items.push(Item::Constructor(constructor));
+            },
+            Ok(item) => items.push(item),
+            Err(_) => {
+                let line_end = bytes[start..].iter().position(|&b| b == b'\n')
+                    .map(|i| start + i)
+                    .unwrap_or(bytes.len());
+                let line = String::from_utf8_lossy(&bytes[start..line_end]);
+
+                diagnostics.push(Diagnostic::new(
+                    Some(Location::from_offset(file, input, start)),
+                    format!("syntax error in `{}`", line.trim()),
+                ));
+
+                data.jump_to(cmp::max(line_end, start + 1));
+            },
+        }
+
+        comments_start = data.position();
+        let _ = space().parse(&mut data);
+    }
+
+    error::check_diagnostics(diagnostics)?;
+
+    Ok(items)
 }
 
+/// Parse a schema in Telegram's JSON format, which produces the same
+/// items as the equivalent `.tl` text:
+///
+/// ```json
+/// {
+///     "constructors": [
+///         { "id": "-1132882121", "predicate": "boolFalse", "params": [], "type": "Bool" }
+///     ],
+///     "methods": [
+///         { "id": "-1502141361", "method": "auth.sendCode",
+///           "params": [{ "name": "phone_number", "type": "string" }], "type": "auth.SentCode" }
+///     ]
+/// }
+/// ```
+///
+/// Type parameters aren't declared in this format, so `{X:Type}` is added
+/// for each `!X` parameter.
+pub fn parse_json_file(file: Option<&str>, input: &str) -> error::Result<Vec<Item>> {
+    let file_name = file.unwrap_or("<input>");
+    let schema = json::parse(input).map_err(|e| {
+        ErrorKind::Diagnostics(vec![Diagnostic::new(None, format!("{}: {}", file_name, e))])
+    })?;
+
+    let mut items = vec![];
+    let mut diagnostics = vec![];
+
+    if let Some(layer) = schema.get("layer") {
+        match *layer {
+            Value::Number(layer) => items.push(Item::Layer(layer as u32)),
+            _ => diagnostics.push(Diagnostic::new(None, format!("{}: `layer` is not a number", file_name))),
+        }
+    }
+
+    let sections = [
+        (Delimiter::Types, "constructors", "predicate"),
+        (Delimiter::Functions, "methods", "method"),
+    ];
+
+    for &(delimiter, section, name_key) in &sections {
+        let entries = match schema.get(section).map(Value::as_array) {
+            Some(Some(entries)) => entries,
+            Some(None) => {
+                diagnostics.push(Diagnostic::new(None, format!("{}: `{}` is not an array", file_name, section)));
+                continue;
+            },
+            None => continue,
+        };
+
+        items.push(Item::Delimiter(delimiter));
+        for (index, entry) in entries.iter().enumerate() {
+            match json_constructor(entry, name_key) {
+                Ok(constructor) => items.push(Item::Constructor(constructor)),
+                Err(e) => diagnostics.push(Diagnostic::new(None, format!("{}: {}[{}]: {}", file_name, section, index, e))),
+            }
+        }
+    }
+
+    error::check_diagnostics(diagnostics)?;
+
+    Ok(items)
+}
+
+/// Definition of the core `vector` type. Telegram's JSON schemas declare
+/// it as `{"predicate": "vector", "params": [], "type": "Vector t"}`,
+/// which lacks its type parameter and repeated field.
+const JSON_VECTOR_DEFINITION: &'static str = "vector {t:Type} # [ t ] = Vector t;";
+
+fn json_constructor(entry: &Value, name_key: &str) -> error::Result<Constructor> {
+    fn unexpected(description: String) -> error::Error {
+        ErrorKind::UnexpectedJson(description).into()
+    }
+
+    fn string<'a>(value: &'a Value, key: &str) -> error::Result<&'a str> {
+        value.get(key).and_then(Value::as_str).ok_or_else(|| unexpected(format!("`{}` is not a string", key)))
+    }
+
+    fn parse_type(parser: Parser<u8, Type>, input: &str) -> error::Result<Type> {
+        (parser - end()).parse(&mut pom::DataInput::new(input.as_bytes()))
+            .map_err(|_| unexpected(format!("invalid type `{}`", input)))
+    }
+
+    fn add_type_parameters(ty: &Type, type_parameters: &mut Vec<Field>) {
+        match *ty {
+            Type::TypeParameter(ref name) => {
+                if !type_parameters.iter().any(|p| p.name.as_ref() == Some(name)) {
+                    type_parameters.push(Field {
+                        name: Some(name.clone()),
+                        ty: Type::Named(vec!["Type".to_owned()]),
+                    });
+                }
+            },
+            Type::Generic(_, ref tys) => for ty in tys {
+                add_type_parameters(ty, type_parameters);
+            },
+            Type::Flagged(_, _, ref ty) => add_type_parameters(ty, type_parameters),
+            Type::Repeated(ref fields) => for field in fields {
+                add_type_parameters(&field.ty, type_parameters);
+            },
+            Type::Int | Type::Named(_) => (),
+        }
+    }
+
+    let name = string(entry, name_key)?;
+    let tl_id = match entry.get("id") {
+        // Ids are signed in JSON schemas
+        Some(&Value::String(ref id)) => {
+            Some(id.parse::<i32>().map_err(|_| unexpected(format!("invalid id `{}`", id)))? as u32)
+        },
+        Some(&Value::Number(id)) => Some(id as i64 as u32),
+        Some(_) => bail!(unexpected("`id` is not a string or a number".to_owned())),
+        None => None,
+    };
+
+    let params = entry.get("params").map_or(Some(&[][..]), Value::as_array)
+        .ok_or_else(|| unexpected("`params` is not an array".to_owned()))?;
+
+    if name_key == "predicate" && name == "vector" && params.is_empty() {
+        let mut vector = (constructor() - end())
+            .parse(&mut pom::DataInput::new(JSON_VECTOR_DEFINITION.as_bytes()))
+            .map_err(|_| unexpected(format!("invalid `vector` definition `{}`", JSON_VECTOR_DEFINITION)))?;
+        vector.tl_id = tl_id;
+
+        return Ok(vector);
+    }
+
+    let mut fields = vec![];
+    let mut type_parameters = vec![];
+
+    for param in params {
+        let ty = parse_type(ty(), string(param, "type")?)?;
+        add_type_parameters(&ty, &mut type_parameters);
+        fields.push(Field {
+            name: Some(string(param, "name")?.to_owned()),
+            ty: ty,
+        });
+    }
+
+    Ok(Constructor {
+        variant: Type::Named(name.split('.').map(ToOwned::to_owned).collect()),
+        tl_id: tl_id,
+        type_parameters: type_parameters,
+        fields: fields,
+        output: parse_type(ty_space_generic(), string(entry, "type")?)?,
+        location: None,
+        docs: Documentation::default(),
+    })
+}
+
+/// Parse several schema files, reporting errors in all of them.
+///
+/// Files with the `.json` extension are parsed with `parse_json_file`,
+/// others as `.tl` text.
+pub fn parse_files<'a, I>(files: I) -> error::Result<Vec<Item>>
+    where I: IntoIterator<Item = (&'a str, &'a str)>
+{
+    let mut items = vec![];
+    let mut diagnostics = vec![];
+
+    for (file, input) in files {
+        let parsed = if file.ends_with(".json") {
+            parse_json_file(Some(file), input)
+        } else {
+            parse_file(Some(file), input)
+        };
+
+        match parsed {
+            Ok(file_items) => items.extend(file_items),
+            Err(e) => error::collect_diagnostics(&mut diagnostics, e, None),
+        }
+    }
+
+    error::check_diagnostics(diagnostics)?;
 
-pub fn parse_string(input: &str) -> pom::Result<Vec<Item>> {
-    let mut input = pom::DataInput::new(input.as_bytes());
-    lines().parse(&mut input)
+    Ok(items)
 }
@@ -0,0 +1,124 @@
+//! Printing of parsed schemas back as TL text.
+//!
+//! The output is canonical: one item per line, single spaces between
+//! fields, generics in angle brackets and ids without leading zeros, so
+//! that `parse_string(&print_items(&items))` gives the same items.
+
+use std::fmt;
+
+use ast::{Constructor, Delimiter, Documentation, Field, Item, Type};
+
+
+/// Print `items` as a schema file, including `// @description` comments
+/// for documented constructors.
+pub fn print_items(items: &[Item]) -> String {
+    let mut output = String::new();
+
+    for item in items {
+        match *item {
+            Item::Delimiter(delimiter) => {
+                let delimiter = match delimiter {
+                    Delimiter::Types => "---types---",
+                    Delimiter::Functions => "---functions---",
+                };
+
+                if !output.is_empty() {
+                    output.push('\n');
+                }
+                output.push_str(delimiter);
+                output.push_str("\n\n");
+            },
+            Item::Constructor(ref constructor) => {
+                if let Some(docs) = doc_comment(constructor.docs()) {
+                    output.push_str(&docs);
+                    output.push('\n');
+                }
+                output.push_str(&constructor.to_string());
+                output.push('\n');
+            },
+            Item::Layer(layer) => {
+                if !output.is_empty() {
+                    output.push('\n');
+                }
+                output.push_str(&format!("// LAYER {}\n", layer));
+            },
+        }
+    }
+
+    output
+}
+
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Type::Int => write!(f, "#"),
+            Type::Named(ref v) => write!(f, "{}", v.join(".")),
+            Type::TypeParameter(ref name) => write!(f, "!{}", name),
+            Type::Generic(ref v, ref tys) => {
+                let tys: Vec<String> = tys.iter().map(ToString::to_string).collect();
+                write!(f, "{}<{}>", v.join("."), tys.join(","))
+            },
+            Type::Flagged(ref flags, bit, ref ty) => write!(f, "{}.{}?{}", flags, bit, ty),
+            Type::Repeated(ref fields) => {
+                let fields: Vec<String> = fields.iter().map(ToString::to_string).collect();
+                write!(f, "[ {} ]", fields.join(" "))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "{}:{}", name, self.ty),
+            None => write!(f, "{}", self.ty),
+        }
+    }
+}
+
+impl fmt::Display for Constructor {
+    /// The constructor as a single line, e.g.
+    /// `auth.sentCode#5e002502 flags:# phone_registered:flags.0?true ... = auth.SentCode;`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.variant)?;
+        if let Some(tl_id) = self.tl_id {
+            write!(f, "#{:x}", tl_id)?;
+        }
+
+        for param in &self.type_parameters {
+            write!(f, " {{{}}}", param)?;
+        }
+        for field in &self.fields {
+            write!(f, " {}", field)?;
+        }
+
+        write!(f, " = {};", self.output)
+    }
+}
+
+
+fn doc_comment(docs: &Documentation) -> Option<String> {
+    if docs.is_empty() {
+        return None;
+    }
+
+    // Descriptions from doc files may span several lines
+    let one_line = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut comment = "//".to_owned();
+    if let Some(description) = docs.description() {
+        comment.push_str(&format!(" @description {}", one_line(description)));
+    }
+    for (field, description) in &docs.fields {
+        // Keep keys with a special meaning from being taken for fields
+        let key = match field.as_str() {
+            "description" | "class" => format!("param_{}", field),
+            field if field.starts_with("param_") => format!("param_{}", field),
+            field => field.to_owned(),
+        };
+        comment.push_str(&format!(" @{} {}", key, one_line(description)));
+    }
+
+    Some(comment)
+}
@@ -0,0 +1,245 @@
+//! Semantic checks of parsed TL schemas.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crc::crc32;
+
+use ast::{Constructor, Delimiter, Field, Item, Type};
+use error::{self, ErrorKind};
+
+
+/// Types which aren't defined in schemas, but are known to the generator.
+const BUILTIN_TYPES: &'static [&'static str] = &[
+    "#", "Bool", "true", "int", "long", "int128", "int256", "double", "bytes", "string",
+    "vector", "Vector", "WithSize", "Object", "Type",
+];
+
+/// What to do with constructors without an explicit `#id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingIds {
+    /// Leave them as they are, e.g. for manually (de)serialized types.
+    Allow,
+    /// Report them as errors.
+    Deny,
+    /// Set them to the computed CRC32 id.
+    Fill,
+}
+
+
+/// Check `items` for wrong or duplicate ids, duplicate names, undefined
+/// types and undeclared flags fields.
+///
+/// All problems are returned at once as `ErrorKind::Diagnostics`.
+pub fn validate_items(items: &mut [Item], missing_ids: MissingIds) -> error::Result<()> {
+    let mut diagnostics = vec![];
+    let defined_types = defined_types(items);
+    let mut ids: BTreeMap<u32, String> = BTreeMap::new();
+    let mut names: BTreeSet<(Delimiter, String)> = BTreeSet::new();
+    let mut delimiter = Delimiter::Types;
+
+    for item in items {
+        let constructor = match *item {
+            Item::Delimiter(d) => {
+                delimiter = d;
+                continue;
+            },
+            Item::Constructor(ref mut c) => c,
+            Item::Layer(_) => continue,
+        };
+
+        let mut errors = vec![];
+        let name = full_name(&constructor.variant);
+        let computed_id = compute_tl_id(constructor);
+
+        match constructor.tl_id {
+            Some(tl_id) if tl_id != computed_id => {
+                errors.push(ErrorKind::WrongTlId(tl_id, computed_id).into());
+            },
+            Some(_) => (),
+            None => match missing_ids {
+                MissingIds::Allow => (),
+                MissingIds::Deny => errors.push(ErrorKind::MissingTlId(computed_id).into()),
+                MissingIds::Fill => constructor.tl_id = Some(computed_id),
+            },
+        }
+
+        if let Some(tl_id) = constructor.tl_id {
+            if let Some(other) = ids.get(&tl_id) {
+                errors.push(ErrorKind::DuplicateTlId(tl_id, other.clone()).into());
+            }
+            ids.entry(tl_id).or_insert_with(|| name.clone());
+        }
+
+        if !names.insert((delimiter, name.clone())) {
+            errors.push(ErrorKind::DuplicateName(name).into());
+        }
+
+        check_fields(constructor, &defined_types, &mut errors);
+
+        for e in errors {
+            error::collect_diagnostics(&mut diagnostics, e, Some(constructor));
+        }
+    }
+
+    error::check_diagnostics(diagnostics)
+}
+
+/// Compute the id of `constructor` as CRC32 of its canonical form.
+pub fn compute_tl_id(constructor: &Constructor) -> u32 {
+    crc32::checksum_ieee(canonical_form(constructor).as_bytes())
+}
+
+/// Normalized definition of `constructor` which its id is computed from,
+/// e.g. `vector t:Type # [ t ] = Vector t` for
+/// `vector#1cb5c415 {t:Type} # [ t ] = Vector t;`.
+///
+/// Fields of type `true` are omitted and `bytes` is written as `string`
+/// like in the official implementation.
+pub fn canonical_form(constructor: &Constructor) -> String {
+    let mut parts = vec![full_name(&constructor.variant)];
+
+    for param in &constructor.type_parameters {
+        parts.push(canonical_field(param, false));
+    }
+
+    for field in &constructor.fields {
+        match field.ty {
+            Type::Flagged(_, _, ref ty) if is_true(ty) => (),
+            _ => parts.push(canonical_field(field, true)),
+        }
+    }
+
+    parts.push("=".to_owned());
+    parts.push(canonical_type(&constructor.output));
+
+    parts.join(" ")
+}
+
+
+fn canonical_field(field: &Field, bytes_as_string: bool) -> String {
+    let ty = match field.ty {
+        Type::Named(ref v) if bytes_as_string && is_bytes(v) => "string".to_owned(),
+        Type::Flagged(ref flags, bit, ref ty) if bytes_as_string && ty.names_vec().map_or(false, |v| is_bytes(v)) => {
+            format!("{}.{}?string", flags, bit)
+        },
+        ref ty => canonical_type(ty),
+    };
+
+    match field.name {
+        Some(ref name) => format!("{}:{}", name, ty),
+        None => ty,
+    }
+}
+
+fn canonical_type(ty: &Type) -> String {
+    match *ty {
+        Type::Int => "#".to_owned(),
+        Type::Named(ref v) => v.join("."),
+        Type::TypeParameter(ref name) => format!("!{}", name),
+        Type::Generic(ref v, ref tys) => {
+            let mut parts = vec![v.join(".")];
+            parts.extend(tys.iter().map(canonical_type));
+            parts.join(" ")
+        },
+        Type::Flagged(ref flags, bit, ref ty) => format!("{}.{}?{}", flags, bit, canonical_type(ty)),
+        Type::Repeated(ref fields) => {
+            let fields: Vec<String> = fields.iter().map(|f| canonical_field(f, false)).collect();
+            format!("[ {} ]", fields.join(" "))
+        },
+    }
+}
+
+fn is_bytes(names: &[String]) -> bool {
+    names.len() == 1 && names[0] == "bytes"
+}
+
+fn is_true(ty: &Type) -> bool {
+    match *ty {
+        Type::Named(ref v) => v.len() == 1 && v[0] == "true",
+        _ => false,
+    }
+}
+
+fn full_name(ty: &Type) -> String {
+    match *ty {
+        Type::Named(ref v) | Type::Generic(ref v, _) => v.join("."),
+        ref ty => canonical_type(ty),
+    }
+}
+
+/// Names which can be referenced in fields: boxed types and bare
+/// constructors.
+fn defined_types(items: &[Item]) -> HashSet<String> {
+    let mut defined: HashSet<String> = BUILTIN_TYPES.iter().map(|&t| t.to_owned()).collect();
+    let mut delimiter = Delimiter::Types;
+
+    for item in items {
+        match *item {
+            Item::Delimiter(d) => delimiter = d,
+            Item::Constructor(ref c) if delimiter == Delimiter::Types => {
+                defined.insert(full_name(&c.variant));
+                if let Some(names) = c.output.names_vec() {
+                    defined.insert(names.join("."));
+                }
+            },
+            _ => (),
+        }
+    }
+
+    defined
+}
+
+fn check_fields(constructor: &Constructor, defined_types: &HashSet<String>, errors: &mut Vec<error::Error>) {
+    let type_parameters: HashSet<&str> = constructor.type_parameters.iter()
+        .filter_map(|p| p.name.as_ref().map(String::as_str))
+        .collect();
+    let mut flag_fields = HashSet::new();
+
+    for field in &constructor.fields {
+        check_type(&field.ty, &type_parameters, &flag_fields, defined_types, errors);
+
+        if let (&Some(ref name), &Type::Int) = (&field.name, &field.ty) {
+            flag_fields.insert(name.as_str());
+        }
+    }
+
+    check_type(&constructor.output, &type_parameters, &flag_fields, defined_types, errors);
+}
+
+fn check_type(ty: &Type,
+              type_parameters: &HashSet<&str>,
+              flag_fields: &HashSet<&str>,
+              defined_types: &HashSet<String>,
+              errors: &mut Vec<error::Error>) {
+    match *ty {
+        Type::Int => (),
+        Type::Named(ref v) => {
+            let name = v.join(".");
+            if !type_parameters.contains(name.as_str()) && !defined_types.contains(&name) {
+                errors.push(ErrorKind::UndefinedType(name).into());
+            }
+        },
+        Type::TypeParameter(ref name) => {
+            if !type_parameters.contains(name.as_str()) {
+                errors.push(ErrorKind::UndefinedType(name.clone()).into());
+            }
+        },
+        Type::Generic(ref v, ref tys) => {
+            check_type(&Type::Named(v.clone()), type_parameters, flag_fields, defined_types, errors);
+            for ty in tys {
+                check_type(ty, type_parameters, flag_fields, defined_types, errors);
+            }
+        },
+        Type::Flagged(ref flags, bit, ref ty) => {
+            if !flag_fields.contains(flags.as_str()) {
+                errors.push(ErrorKind::UndeclaredFlags(flags.clone(), bit).into());
+            }
+            check_type(ty, type_parameters, flag_fields, defined_types, errors);
+        },
+        Type::Repeated(ref fields) => {
+            for f in fields {
+                check_type(&f.ty, type_parameters, flag_fields, defined_types, errors);
+            }
+        },
+    }
+}
@@ -0,0 +1,164 @@
+extern crate tl_codegen;
+
+
+use tl_codegen::ast::{Constructor, Item, Type};
+use tl_codegen::{Error, ErrorKind};
+use tl_codegen::parser;
+
+
+/// Generated code with whitespace removed, since token streams are
+/// printed with spaces between all tokens.
+fn generate_compact(input: &str) -> String {
+    let code = tl_codegen::generate_code_for(input).unwrap();
+    code.as_str().chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+fn parse_constructor(input: &str) -> Constructor {
+    match parser::parse_string(input).unwrap().pop() {
+        Some(Item::Constructor(c)) => c,
+        item => panic!("expected a constructor, got {:?}", item),
+    }
+}
+
+fn field_names(constructor: &Constructor) -> Vec<Option<&str>> {
+    constructor.fields().iter().map(|f| f.name()).collect()
+}
+
+fn repeated_field_names(ty: &Type) -> Vec<Option<&str>> {
+    match *ty {
+        Type::Repeated(ref fields) => fields.iter().map(|f| f.name()).collect(),
+        ref ty => panic!("expected a repeated field, got {:?}", ty),
+    }
+}
+
+
+#[test]
+fn test_repeated_fields_lowering() {
+    let mut pairs = parse_constructor("pairs#aabbccdd x:int n:# [ a:int b:long ] y:int = Pairs;");
+    assert_eq!(field_names(&pairs), vec![Some("x"), Some("n"), None, Some("y")]);
+
+    // The count takes the place of the `#` field, which is how a bare
+    // `Vec` is serialized: `x`, the count, `a` and `b` of each element,
+    // then `y`
+    pairs.fixup_repeated_fields();
+    assert_eq!(field_names(&pairs), vec![Some("x"), Some("n"), Some("y")]);
+    assert_eq!(repeated_field_names(pairs.fields()[1].ty()), vec![Some("a"), Some("b")]);
+
+    let code = generate_compact("pairs#aabbccdd x:int n:# [ a:int b:long ] y:int = Pairs;");
+    assert!(code.contains("pubstructPairs{pubx:i32,pubn:Vec<(i32,i64)>,puby:i32}"), "{}", code);
+
+    // A single field isn't wrapped in a tuple
+    let code = generate_compact("ints#aabbccde n:# [ int ] = Ints;");
+    assert!(code.contains("pubstructInts{pubn:Vec<i32>}"), "{}", code);
+}
+
+#[test]
+fn test_repeated_fields_anonymous_count() {
+    let code = generate_compact("numbers#aabbccde # [ int ] = Numbers;");
+    assert!(code.contains("pubstructNumbers{pubitems:Vec<i32>}"), "{}", code);
+
+    // Existing fields keep their names
+    let code = generate_compact("numbers#aabbccde items:int # [ int ] items_2:int # [ long ] = Numbers;");
+    assert!(code.contains("pubstructNumbers{pubitems:i32,pubitems_3:Vec<i32>,pubitems_2:i32,pubitems_4:Vec<i64>}"),
+            "{}", code);
+}
+
+#[test]
+fn test_repeated_fields_vector() {
+    let mut vector = parse_constructor("vector#1cb5c415 {t:Type} # [ t ] = Vector t;");
+    vector.fixup_repeated_fields();
+    assert_eq!(field_names(&vector), vec![Some("items")]);
+    assert_eq!(repeated_field_names(vector.fields()[0].ty()), vec![None]);
+
+    // `Vector t` is mapped to `Vec` instead
+    let code = generate_compact("---types---\nvector#1cb5c415 {t:Type} # [ t ] = Vector t;\n\
+                                 wrapper#aabbccdd ids:Vector<int> = Wrapper;");
+    assert!(!code.contains("structVector"), "{}", code);
+    assert!(code.contains("pubids:::serde_mtproto::Boxed<Vec<i32>>"), "{}", code);
+}
+
+#[test]
+fn test_nested_vector_field() {
+    let code = generate_compact("\
+---types---
+vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+matrix#aabbccdd rows:Vector<Vector<long>> = Matrix;
+");
+
+    // Both vectors are boxed, so each of them is serialized with the
+    // `vector` constructor id and its length
+    assert!(code.contains("\
+        #[derive(Clone,Debug,PartialEq,PartialOrd,Serialize,Deserialize,MtProtoSized,MtProtoIdentifiable)]\
+        #[id=\"0xaabbccdd\"]\
+        pubstructMatrix{pubrows:::serde_mtproto::Boxed<Vec<::serde_mtproto::Boxed<Vec<i64>>>>}"), "{}", code);
+}
+
+#[test]
+fn test_multiple_type_parameters() {
+    let code = generate_compact("\
+---functions---
+invokeBoth#aabbccde {X:Type} {Y:Type} first:!X second:!Y = X;
+");
+
+    assert!(code.contains("\
+        #[derive(Clone,Debug,PartialEq,PartialOrd,Serialize,Deserialize,MtProtoSized,MtProtoIdentifiable)]\
+        #[id=\"0xaabbccde\"]\
+        pubstructinvokeBoth<X,Y>{pubfirst:X,pubsecond:Y}"), "{}", code);
+    // Only the parameter used as the result type has to be a function
+    assert!(code.contains("\
+        impl<X:::rpc::RpcFunction+::serde::Serialize,Y:::serde::Serialize>::rpc::RpcFunctionforinvokeBoth<X,Y>{\
+            typeReply=X::Reply;\
+        }"), "{}", code);
+}
+
+#[test]
+fn test_undeclared_flags() {
+    for input in &["foo#1 x:flags.0?int = Foo;", "foo#1 x:flags.0?int flags:# = Foo;"] {
+        let error = tl_codegen::generate_code_for(input).unwrap_err();
+        let message = match error {
+            Error(ErrorKind::Diagnostics(ref diagnostics), _) if diagnostics.len() == 1 => &diagnostics[0].message,
+            ref error => panic!("expected a single diagnostic, got {}", error),
+        };
+
+        assert_eq!(message, "`flags.0?` refers to a field which isn't declared before as `flags:#`");
+    }
+}
+
+#[test]
+fn test_generic_function_builder() {
+    let code = generate_compact("\
+---functions---
+invokeWithFlags#aabbccdd {X:Type} flags:# silent:flags.0?true timeout:flags.1?int query:!X = X;
+");
+
+    // Only fields which aren't flagged are required
+    assert!(code.contains("\
+        impl<X>invokeWithFlags<X>{\
+            pubfnbuilder(query:X)->invokeWithFlagsBuilder<X>{\
+                invokeWithFlagsBuilder{value:invokeWithFlags{silent:false,timeout:None,query:query}}\
+            }\
+        }"), "{}", code);
+    assert!(code.contains("\
+        impl<X>invokeWithFlagsBuilder<X>{\
+            pubfnsilent(mutself,value:bool)->Self{self.value.silent=value;self}\
+            pubfntimeout(mutself,value:i32)->Self{self.value.timeout=Some(value);self}\
+            pubfnbuild(self)->invokeWithFlags<X>{self.value}\
+        }"), "{}", code);
+}
+
+#[test]
+fn test_doc_attributes() {
+    let code = generate_compact("\
+// @description Object defines a user. @id User identifier
+user#aabbccdd id:int access_hash:long = User;
+---functions---
+// @description Returns a user @id Identifier of the user
+users.getUser#aabbccde id:int = User;
+");
+
+    // Fields without a description get no attribute
+    assert!(code.contains("#[doc=\"Objectdefinesauser.\"]#[derive("), "{}", code);
+    assert!(code.contains("pubstructUser{#[doc=\"Useridentifier\"]pubid:i32,pubaccess_hash:i64}"), "{}", code);
+    assert!(code.contains("#[doc=\"Returnsauser\"]#[derive("), "{}", code);
+    assert!(code.contains("pubstructgetUser{#[doc=\"Identifieroftheuser\"]pubid:i32}"), "{}", code);
+}
@@ -0,0 +1,83 @@
+extern crate tl_codegen;
+
+
+use tl_codegen::diff::{Change, ChangeKind};
+
+
+const OLD_LAYER: &'static str = "\
+---types---
+
+thing#11111111 flags:# count:flags.0?int title:string = Thing;
+thingEmpty#22222222 id:int = Thing;
+oldThing#33333333 = OldThing;
+
+---functions---
+
+things.get#44444444 id:int = Thing;
+
+// LAYER 1
+";
+
+const NEW_LAYER: &'static str = "\
+---types---
+
+thing#55555555 flags:# count:flags.0?int pinned:flags.1?true title:string = Thing;
+thingEmpty#22222222 id:long = Thing;
+newThing#66666666 = NewThing;
+
+---functions---
+
+things.get#44444444 id:int = Thing;
+
+// LAYER 2
+";
+
+
+#[test]
+fn test_diff_schemas() {
+    let diff = tl_codegen::diff_schemas(OLD_LAYER, NEW_LAYER).unwrap();
+    assert_eq!((diff.old_layer, diff.new_layer), (1, 2));
+    assert!(diff.functions.is_empty());
+
+    let names: Vec<String> = diff.constructors.iter().map(Change::name).collect();
+    assert_eq!(names, vec!["newThing", "oldThing", "thing", "thingEmpty"]);
+
+    match diff.constructors[2] {
+        Change::Changed { ref kinds, .. } => {
+            assert_eq!(kinds[0], ChangeKind::IdChanged(Some(0x11111111), Some(0x55555555)));
+            match kinds[1] {
+                ChangeKind::FlagFieldAdded(ref field) => assert_eq!(field.name(), Some("pinned")),
+                ref kind => panic!("unexpected change: {:?}", kind),
+            }
+            assert_eq!(kinds.len(), 2);
+        },
+        ref change => panic!("unexpected change: {:?}", change),
+    }
+
+    assert_eq!(diff.to_string(), "\
+Layer 1 -> 2
+
+Constructors:
++ newThing#66666666 = NewThing;
+- oldThing#33333333 = OldThing;
+~ thing
+    id changed: #11111111 -> #55555555
+    flag field added: pinned:flags.1?true
+~ thingEmpty
+    type of field `id` changed: int -> long
+");
+}
+
+#[test]
+fn test_diff_schemas_json() {
+    let diff = tl_codegen::diff_schemas(OLD_LAYER, OLD_LAYER).unwrap();
+    assert!(diff.is_empty());
+    assert_eq!(diff.to_json(), r#"{"old_layer":1,"new_layer":1,"constructors":[],"functions":[]}"#);
+
+    let diff = tl_codegen::diff_schemas("a#1 = A;", "a#1 = B;").unwrap();
+    assert_eq!(diff.to_json(), concat!(
+        r#"{"old_layer":0,"new_layer":0,"constructors":[{"change":"changed","name":"a","#,
+        r#""old":"a#1 = A;","new":"a#1 = B;","kinds":[{"kind":"type_changed","old":"A","new":"B"}]}],"#,
+        r#""functions":[]}"#,
+    ));
+}
@@ -0,0 +1,68 @@
+extern crate tl_codegen;
+
+
+use tl_codegen::ast::{Documentation, Item};
+use tl_codegen::parser;
+
+
+fn docs(items: &[Item]) -> Vec<&Documentation> {
+    items.iter()
+        .filter_map(|item| match *item {
+            Item::Constructor(ref c) => Some(c.docs()),
+            _ => None,
+        })
+        .collect()
+}
+
+
+const DOC_FILE: &'static str = r#"{
+    "constructors": {
+        "user": {
+            "description": "Indicates info about a certain user",
+            "params": {"id": "ID of the user", "access_hash": "Access hash of the user"}
+        },
+        "auth.authorization": {"description": "Contains user authorization info"},
+        "unknown": {"description": "Not in the schema"}
+    },
+    "methods": {
+        "users.getUsers": {"description": "Returns basic user info", "params": {"id": "List of user identifiers"}}
+    }
+}"#;
+
+
+#[test]
+fn test_apply_doc_file() {
+    let mut items = parser::parse_string("\
+// @description From the schema @id User identifier
+user#aabbccdd id:int access_hash:long = User;
+auth.authorization#aabbccde user:User = auth.Authorization;
+users.getUsers#aabbccdf id:int = Vector<User>;
+---functions---
+users.getUsers#aabbcce0 id:int = Vector<User>;
+").unwrap();
+    tl_codegen::apply_doc_file(&mut items, DOC_FILE).unwrap();
+    let docs = docs(&items);
+
+    // Comments in the schema take precedence over the doc file
+    assert_eq!(docs[0].description(), Some("From the schema"));
+    assert_eq!(docs[0].field("id"), Some("User identifier"));
+    assert_eq!(docs[0].field("access_hash"), Some("Access hash of the user"));
+
+    // Namespaced names are matched in full
+    assert_eq!(docs[1].description(), Some("Contains user authorization info"));
+
+    // Methods are only looked up for functions
+    assert!(docs[2].is_empty());
+    assert_eq!(docs[3].description(), Some("Returns basic user info"));
+    assert_eq!(docs[3].field("id"), Some("List of user identifiers"));
+}
+
+#[test]
+fn test_apply_doc_file_errors() {
+    let mut items = parser::parse_string("user#aabbccdd id:int = User;").unwrap();
+
+    assert!(tl_codegen::apply_doc_file(&mut items, "{").is_err());
+    assert!(tl_codegen::apply_doc_file(&mut items, r#"{"constructors": []}"#).is_err());
+    assert!(tl_codegen::apply_doc_file(&mut items, r#"{"constructors": {"user": {"description": 1}}}"#).is_err());
+    assert!(docs(&items)[0].is_empty());
+}
@@ -0,0 +1,166 @@
+extern crate tl_codegen;
+
+
+use tl_codegen::{Diagnostic, Error, ErrorKind, Location, MissingIds};
+use tl_codegen::ast::{Constructor, Item};
+use tl_codegen::parser;
+
+
+fn constructors(items: &[Item]) -> Vec<&Constructor> {
+    items.iter()
+        .filter_map(|item| match *item {
+            Item::Constructor(ref c) => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+fn diagnostics(error: Error) -> Vec<Diagnostic> {
+    match error {
+        Error(ErrorKind::Diagnostics(diagnostics), _) => diagnostics,
+        error => panic!("expected diagnostics, got {}", error),
+    }
+}
+
+
+#[test]
+fn test_syntax_errors() {
+    let input = "\
+boolFalse#bc799737 = Bool;
+boolTrue#997275b5 = = Bool;
+
+  error#c4b9f9bb code:int text:string Error;
+null#56730bcc = Null;
+";
+    let error = parser::parse_file(Some("test.tl"), input).unwrap_err();
+    let diagnostics = diagnostics(error);
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].location, Some(Location { file: Some("test.tl".to_owned()), line: 2, column: 1 }));
+    assert_eq!(diagnostics[0].to_string(), "test.tl:2:1: syntax error in `boolTrue#997275b5 = = Bool;`");
+    assert_eq!(diagnostics[1].location, Some(Location { file: Some("test.tl".to_owned()), line: 4, column: 3 }));
+    assert_eq!(diagnostics[1].to_string(), "test.tl:4:3: syntax error in `error#c4b9f9bb code:int text:string Error;`");
+}
+
+#[test]
+fn test_generator_errors() {
+    let input = "\
+---types---
+foo#aabbccdd x:int = Foo;
+bar#aabbccde x:int [ long ] = Bar;
+---functions---
+baz#aabbccdf [ int ] = Foo;
+";
+    let items = parser::parse_file(Some("test.tl"), input).unwrap();
+    let error = tl_codegen::generate_ast_for_items(items).unwrap_err();
+    let locations: Vec<String> = diagnostics(error).iter()
+        .map(|d| format!("{}", d.location.as_ref().unwrap()))
+        .collect();
+
+    assert_eq!(locations, vec!["test.tl:3:1", "test.tl:5:1"]);
+}
+
+const JSON_SCHEMA: &'static str = r##"{
+    "constructors": [
+        {"id": "481674261", "predicate": "vector", "params": [], "type": "Vector t"},
+        {"id": "-1132882121", "predicate": "boolFalse", "params": [], "type": "Bool"},
+        {"id": "-1720552011", "predicate": "boolTrue", "params": [], "type": "Bool"},
+        {"id": "-2122045747", "predicate": "peerSettings", "params": [
+            {"name": "flags", "type": "#"},
+            {"name": "report_spam", "type": "flags.0?true"}
+        ], "type": "PeerSettings"}
+    ],
+    "methods": [
+        {"id": "-627372787", "method": "invokeWithLayer", "params": [
+            {"name": "layer", "type": "int"},
+            {"name": "query", "type": "!X"}
+        ], "type": "X"},
+        {"id": "-32911747", "method": "auth.checkIds", "params": [
+            {"name": "hash", "type": "string"},
+            {"name": "ids", "type": "Vector<long>"}
+        ], "type": "Bool"}
+    ],
+    "layer": 71
+}"##;
+
+const TL_SCHEMA: &'static str = "\
+// LAYER 71
+---types---
+vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+boolFalse#bc799737 = Bool;
+boolTrue#997275b5 = Bool;
+peerSettings#818426cd flags:# report_spam:flags.0?true = PeerSettings;
+---functions---
+invokeWithLayer#da9b0d0d {X:Type} layer:int query:!X = X;
+auth.checkIds#fe09ce7d hash:string ids:Vector<long> = Bool;
+";
+
+#[test]
+fn test_json_schema() {
+    let mut json_items = parser::parse_json_file(Some("test.json"), JSON_SCHEMA).unwrap();
+    let tl_items = parser::parse_file(Some("test.tl"), TL_SCHEMA).unwrap();
+
+    // `vector` is declared without its type parameter in JSON
+    assert_eq!(tl_codegen::print_items(&json_items), tl_codegen::print_items(&tl_items));
+    tl_codegen::validate_items(&mut json_items, MissingIds::Deny).unwrap();
+}
+
+#[test]
+fn test_mixed_schema_files() {
+    let json_types = r#"{"constructors": [{"id": "-1132882121", "predicate": "boolFalse", "params": [], "type": "Bool"}]}"#;
+    let tl_functions = "---functions---\nhelp.getConfig#c4f9186b = Config;";
+    let items = parser::parse_files(vec![("types.json", json_types), ("functions.tl", tl_functions)]).unwrap();
+
+    assert_eq!(tl_codegen::print_items(&items), "\
+---types---
+
+boolFalse#bc799737 = Bool;
+
+---functions---
+
+help.getConfig#c4f9186b = Config;
+");
+
+    // Errors of all files are reported together
+    let error = parser::parse_files(vec![
+        ("types.json", r#"{"constructors": [{"predicate": "boolFalse", "params": [], "type": 1}]}"#),
+        ("functions.tl", "---functions---\nhelp.getConfig#c4f9186b Config;"),
+    ]).unwrap_err();
+    let messages: Vec<String> = diagnostics(error).iter().map(ToString::to_string).collect();
+
+    assert_eq!(messages, vec![
+        "types.json: constructors[0]: unexpected JSON value: `type` is not a string",
+        "functions.tl:2:1: syntax error in `help.getConfig#c4f9186b Config;`",
+    ]);
+}
+
+#[test]
+fn test_comment_docs() {
+    let input = "\
+// @description Object defines a user. @id User identifier
+// @access_hash Access hash
+//- of the user
+user#aabbccdd id:int access_hash:long = User;
+// @class Chat @description Not separated from the previous item
+// @param_title Title of the chat
+chat#aabbccde id:int title:string = Chat;
+// @description Separated by a blank line
+
+channel#aabbccdf id:int = Chat;
+";
+    let items = parser::parse_string(input).unwrap();
+    let constructors = constructors(&items);
+
+    let user = constructors[0].docs();
+    assert_eq!(user.description(), Some("Object defines a user."));
+    assert_eq!(user.field("id"), Some("User identifier"));
+    assert_eq!(user.field("access_hash"), Some("Access hash of the user"));
+
+    // `@class` is dropped and `@param_` prefixes are stripped
+    let chat = constructors[1].docs();
+    assert_eq!(chat.description(), Some("Not separated from the previous item"));
+    assert_eq!(chat.field("class"), None);
+    assert_eq!(chat.field("title"), Some("Title of the chat"));
+
+    assert!(constructors[2].docs().is_empty());
+}
@@ -0,0 +1,72 @@
+extern crate tl_codegen;
+
+
+use std::fs::File;
+use std::io::Read;
+
+use tl_codegen::ast::Item;
+use tl_codegen::parser;
+
+
+fn read_schema(path: &str) -> String {
+    let mut input = String::new();
+    File::open(path).unwrap().read_to_string(&mut input).unwrap();
+    input
+}
+
+
+#[test]
+fn test_print_round_trip() {
+    let items = parser::parse_string(&read_schema("../tl/000-scheme.tl")).unwrap();
+    let printed = tl_codegen::print_items(&items);
+    let reparsed_items = parser::parse_string(&printed).unwrap();
+
+    assert_eq!(items.len(), reparsed_items.len());
+    for (item, reparsed_item) in items.iter().zip(&reparsed_items) {
+        match (item, reparsed_item) {
+            (&Item::Constructor(ref c), &Item::Constructor(ref reparsed)) => {
+                assert_eq!(c.variant(), reparsed.variant());
+                assert_eq!(c.tl_id(), reparsed.tl_id());
+                assert_eq!(c.type_parameters(), reparsed.type_parameters());
+                assert_eq!(c.fields(), reparsed.fields());
+                assert_eq!(c.output(), reparsed.output());
+                assert_eq!(c.docs(), reparsed.docs());
+            },
+            (&Item::Delimiter(d), &Item::Delimiter(reparsed)) => assert_eq!(d, reparsed),
+            (&Item::Layer(layer), &Item::Layer(reparsed)) => assert_eq!(layer, reparsed),
+            (item, reparsed_item) => panic!("{:?} was reparsed as {:?}", item, reparsed_item),
+        }
+    }
+
+    // Printed schemas are already canonical
+    assert_eq!(tl_codegen::print_items(&reparsed_items), printed);
+}
+
+#[test]
+fn test_print_items() {
+    let input = "\
+// LAYER 12
+---types---
+// @description   Some   thing
+//- @param_description Its description
+thing#00abcdef {X:Type} flags:# description:string pinned:flags.0?true value:flags.1?!X = Thing X;
+vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+---functions---
+things.get#1234 ids:Vector<int> = Vector<Thing<int>>;
+";
+
+    let items = parser::parse_string(input).unwrap();
+    assert_eq!(tl_codegen::print_items(&items), "\
+// LAYER 12
+
+---types---
+
+// @description Some thing @param_description Its description
+thing#abcdef {X:Type} flags:# description:string pinned:flags.0?true value:flags.1?!X = Thing<X>;
+vector#1cb5c415 {t:Type} # [ t ] = Vector<t>;
+
+---functions---
+
+things.get#1234 ids:Vector<int> = Vector<Thing<int>>;
+");
+}
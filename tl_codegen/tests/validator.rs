@@ -0,0 +1,128 @@
+extern crate tl_codegen;
+
+
+use tl_codegen::{Error, ErrorKind, MissingIds};
+use tl_codegen::ast::Item;
+use tl_codegen::{parser, validator};
+
+
+fn validate(input: &str, missing_ids: MissingIds) -> Result<Vec<Item>, Vec<String>> {
+    let mut items = parser::parse_string(input).unwrap();
+
+    match validator::validate_items(&mut items, missing_ids) {
+        Ok(()) => Ok(items),
+        Err(Error(ErrorKind::Diagnostics(diagnostics), _)) => {
+            Err(diagnostics.iter().map(|d| d.message.clone()).collect())
+        },
+        Err(e) => panic!("expected diagnostics, got {}", e),
+    }
+}
+
+fn tl_ids(items: &[Item]) -> Vec<Option<u32>> {
+    items.iter()
+        .filter_map(|item| match *item {
+            Item::Constructor(ref c) => Some(c.tl_id()),
+            _ => None,
+        })
+        .collect()
+}
+
+
+#[test]
+fn test_known_ids() {
+    let input = "\
+boolFalse#bc799737 = Bool;
+vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+peerSettings#818426cd flags:# report_spam:flags.0?true = PeerSettings;
+auth.exportedAuthorization#df969c2d id:int bytes:bytes = auth.ExportedAuthorization;
+documentAttributeAudio#9852f9c6 flags:# voice:flags.10?true duration:int title:flags.0?string performer:flags.1?string waveform:flags.2?bytes = DocumentAttribute;
+---functions---
+invokeWithLayer#da9b0d0d {X:Type} layer:int query:!X = X;
+";
+    validate(input, MissingIds::Deny).unwrap();
+}
+
+#[test]
+fn test_canonical_form() {
+    let items = parser::parse_string("\
+vector#1cb5c415 {t:Type} # [ t ] = Vector t;
+documentAttributeAudio#9852f9c6 flags:# voice:flags.10?true duration:int waveform:flags.2?bytes = DocumentAttribute;
+").unwrap();
+    let forms: Vec<String> = items.iter()
+        .filter_map(|item| match *item {
+            Item::Constructor(ref c) => Some(validator::canonical_form(c)),
+            _ => None,
+        })
+        .collect();
+
+    assert_eq!(forms, vec![
+        "vector t:Type # [ t ] = Vector t",
+        "documentAttributeAudio flags:# duration:int waveform:flags.2?string = DocumentAttribute",
+    ]);
+}
+
+#[test]
+fn test_wrong_id() {
+    let errors = validate("boolFalse#bc799738 = Bool;", MissingIds::Deny).unwrap_err();
+    assert_eq!(errors, vec!["constructor id #bc799738 doesn't match its definition, expected #bc799737"]);
+}
+
+#[test]
+fn test_missing_ids() {
+    let input = "boolFalse = Bool;\nboolTrue#997275b5 = Bool;";
+
+    let items = validate(input, MissingIds::Allow).unwrap();
+    assert_eq!(tl_ids(&items), vec![None, Some(0x997275b5)]);
+
+    let errors = validate(input, MissingIds::Deny).unwrap_err();
+    assert_eq!(errors, vec!["constructor id is missing, expected #bc799737"]);
+
+    let items = validate(input, MissingIds::Fill).unwrap();
+    assert_eq!(tl_ids(&items), vec![Some(0xbc799737), Some(0x997275b5)]);
+}
+
+#[test]
+fn test_duplicates() {
+    let input = "\
+boolFalse#bc799737 = Bool;
+boolFalse = Bool;
+---functions---
+boolFalse = Bool;
+";
+    // The same name is allowed for a type and a function, but the
+    // filled id is used by the type already
+    let errors = validate(input, MissingIds::Fill).unwrap_err();
+    assert_eq!(errors, vec![
+        "constructor id #bc799737 is already used by `boolFalse`",
+        "constructor `boolFalse` is already defined",
+        "constructor id #bc799737 is already used by `boolFalse`",
+    ]);
+}
+
+#[test]
+fn test_undefined_types() {
+    let input = "\
+user id:int photo:UserPhoto = User;
+users list:Vector<User> other:Vector<Chat> = Users;
+wrapper {X:Type} query:!X other:!Y = Wrapper;
+";
+    let errors = validate(input, MissingIds::Allow).unwrap_err();
+    assert_eq!(errors.len(), 3);
+    assert!(errors[0].contains("undefined type `UserPhoto`"), "{:?}", errors);
+    assert!(errors[1].contains("undefined type `Chat`"), "{:?}", errors);
+    assert!(errors[2].contains("undefined type `Y`"), "{:?}", errors);
+}
+
+#[test]
+fn test_undeclared_flags() {
+    let input = "\
+foo x:flags.0?int = Foo;
+bar x:flags.0?int flags:# = Bar;
+baz flags:# x:flags.0?int = Baz;
+";
+    let errors = validate(input, MissingIds::Allow).unwrap_err();
+    assert_eq!(errors, vec![
+        "`flags.0?` refers to a field which isn't declared before as `flags:#`",
+        "`flags.0?` refers to a field which isn't declared before as `flags:#`",
+    ]);
+}